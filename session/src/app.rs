@@ -7,7 +7,7 @@ use common::log::{error, info};
 use common::logger;
 use common::tokio;
 
-use crate::gb::SessionConf;
+use crate::gb::{DialogSession, SessionConf};
 use crate::general::http::Http;
 use crate::general::runner::{PicsRunner, Runner};
 
@@ -42,6 +42,8 @@ impl Daemon<(std::net::TcpListener, (Option<std::net::TcpListener>, Option<UdpSo
             .unwrap()
             .block_on(async {
                 mysqlx::init_conn_pool()?;
+                //遗留媒体对话待设备上线后BYE
+                DialogSession::rehydrate()?;
                 let web = tokio::spawn(async move {
                     info!("Web server start running...");
                     http.run(http_listener).await?;
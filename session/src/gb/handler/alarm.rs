@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::Stream;
+use parking_lot::Mutex;
+
+use common::exception::GlobalResult;
+use common::once_cell::sync::Lazy;
+
+use crate::gb::handler::buffer::{self, NotifyReceiver, NotifySender, OverflowPolicy};
+use crate::gb::handler::parser::xml::*;
+
+//报警订阅接收端：device_id
+static ALARM_WATCHERS: Lazy<Mutex<HashMap<String, Vec<NotifySender<AlarmRecord>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 报警记录：对应 Response/AlarmList/Item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlarmRecord {
+    pub channel_id: String,
+    pub priority: Option<u8>,
+    pub method: Option<u8>,
+    pub time: Option<String>,
+    pub description: Option<String>,
+    pub longitude: Option<f32>,
+    pub latitude: Option<f32>,
+    pub alarm_type: Option<u8>,
+}
+
+impl AlarmRecord {
+    /// 解析报警查询响应体
+    pub fn parse_list(body: &[u8]) -> GlobalResult<Vec<Self>> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Vec<Self> {
+        let mut records = Vec::new();
+        let mut record: Option<AlarmRecord> = None;
+        for (k, v) in vs {
+            match &k[..] {
+                //以Item下DeviceID作为新记录起始
+                RESPONSE_ALARM_LIST_ITEM_DEVICE_ID => {
+                    if let Some(ar) = record.take() {
+                        records.push(ar);
+                    }
+                    record = Some(AlarmRecord { channel_id: v, ..Default::default() });
+                }
+                _ => {
+                    if let Some(ar) = record.as_mut() {
+                        Self::fill_record(ar, &k, v);
+                    }
+                }
+            }
+        }
+        if let Some(ar) = record.take() {
+            records.push(ar);
+        }
+        records
+    }
+
+    /// 解析报警通知(Notify,CmdType=Alarm);无DeviceID时为None
+    pub fn parse_notify(vs: &[(String, String)]) -> Option<Self> {
+        let mut ar = AlarmRecord::default();
+        for (k, v) in vs {
+            match &k[..] {
+                NOTIFY_DEVICE_ID => { ar.channel_id = v.clone(); }
+                NOTIFY_ALARM_PRIORITY => { ar.priority = v.parse::<u8>().ok(); }
+                NOTIFY_ALARM_METHOD => { ar.method = v.parse::<u8>().ok(); }
+                NOTIFY_ALARM_TIME => { ar.time = Some(v.clone()); }
+                NOTIFY_ALARM_DESCRIPTION => { ar.description = Some(v.clone()); }
+                NOTIFY_LONGITUDE => { ar.longitude = v.parse::<f32>().ok(); }
+                NOTIFY_LATITUDE => { ar.latitude = v.parse::<f32>().ok(); }
+                NOTIFY_INFO_ALARM_TYPE => { ar.alarm_type = v.parse::<u8>().ok(); }
+                _ => {}
+            }
+        }
+        if ar.channel_id.is_empty() { None } else { Some(ar) }
+    }
+
+    /// 推送报警通知至该设备的订阅接收端,返回送达数
+    pub fn dispatch_notify(device_id: &str, vs: &[(String, String)]) -> usize {
+        let Some(record) = Self::parse_notify(vs) else { return 0; };
+        let mut guard = ALARM_WATCHERS.lock();
+        let Some(watchers) = guard.get_mut(device_id) else { return 0; };
+        watchers.retain(|tx| !tx.is_closed() && tx.send(record.clone()).is_ok());
+        let delivered = watchers.len();
+        if watchers.is_empty() {
+            guard.remove(device_id);
+        }
+        delivered
+    }
+
+    fn fill_record(ar: &mut AlarmRecord, k: &str, v: String) {
+        match k {
+            RESPONSE_ALARM_LIST_ITEM_ALARM_PRIORITY => { ar.priority = v.parse::<u8>().ok(); }
+            RESPONSE_ALARM_LIST_ITEM_ALARM_METHOD => { ar.method = v.parse::<u8>().ok(); }
+            RESPONSE_ALARM_LIST_ITEM_ALARM_TIME => { ar.time = Some(v); }
+            RESPONSE_ALARM_LIST_ITEM_ALARM_DESCRIPTION => { ar.description = Some(v); }
+            RESPONSE_ALARM_LIST_ITEM_LONGITUDE => { ar.longitude = v.parse::<f32>().ok(); }
+            RESPONSE_ALARM_LIST_ITEM_LATITUDE => { ar.latitude = v.parse::<f32>().ok(); }
+            RESPONSE_ALARM_LIST_ITEM_INFO_ALARM_TYPE => { ar.alarm_type = v.parse::<u8>().ok(); }
+            _ => {}
+        }
+    }
+}
+
+/// 报警订阅：以Stream接收设备报警通知,缓冲有界,消费过慢时按溢出策略丢弃并计数
+pub struct AlarmSubscription {
+    device_id: String,
+    rx: NotifyReceiver<AlarmRecord>,
+}
+
+impl AlarmSubscription {
+    /// 按配置的缓冲容量与溢出策略订阅
+    pub fn watch(device_id: &str) -> Self {
+        Self::register(device_id, buffer::channel_by_conf())
+    }
+
+    pub fn watch_with(device_id: &str, capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::register(device_id, buffer::channel(capacity, policy))
+    }
+
+    fn register(device_id: &str, (tx, rx): (NotifySender<AlarmRecord>, NotifyReceiver<AlarmRecord>)) -> Self {
+        ALARM_WATCHERS.lock().entry(device_id.to_string()).or_default().push(tx);
+        Self { device_id: device_id.to_string(), rx }
+    }
+
+    /// 消费过慢被丢弃的报警数
+    pub fn dropped(&self) -> u64 {
+        self.rx.dropped()
+    }
+
+    /// error策略下是否因溢出被关闭
+    pub fn overflowed(&self) -> bool {
+        self.rx.overflowed()
+    }
+}
+
+impl Stream for AlarmSubscription {
+    type Item = AlarmRecord;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+impl Drop for AlarmSubscription {
+    fn drop(&mut self) {
+        self.rx.close();
+        let mut guard = ALARM_WATCHERS.lock();
+        if let Some(watchers) = guard.get_mut(&self.device_id) {
+            watchers.retain(|tx| !tx.is_closed());
+            if watchers.is_empty() {
+                guard.remove(&self.device_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AlarmRecord;
+
+    #[test]
+    fn test_parse_alarm_list() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>Alarm</CmdType>
+<SN>17431</SN>
+<DeviceID>34020000001110000001</DeviceID>
+<AlarmList Num="2">
+<Item>
+<DeviceID>34020000001340000101</DeviceID>
+<AlarmPriority>1</AlarmPriority>
+<AlarmMethod>2</AlarmMethod>
+<AlarmTime>2024-01-01T08:00:00</AlarmTime>
+<AlarmDescription>motion</AlarmDescription>
+<Info>
+<AlarmType>2</AlarmType>
+</Info>
+</Item>
+<Item>
+<DeviceID>34020000001340000102</DeviceID>
+<AlarmPriority>3</AlarmPriority>
+<AlarmTime>2024-01-01T09:00:00</AlarmTime>
+</Item>
+</AlarmList>
+</Response>
+"#;
+        let records = AlarmRecord::parse_list(xml.as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].channel_id, "34020000001340000101");
+        assert_eq!(records[0].priority, Some(1));
+        assert_eq!(records[0].method, Some(2));
+        assert_eq!(records[0].alarm_type, Some(2));
+        assert_eq!(records[0].description.as_deref(), Some("motion"));
+        assert_eq!(records[1].priority, Some(3));
+        assert_eq!(records[1].method, None);
+        assert_eq!(records[1].time.as_deref(), Some("2024-01-01T09:00:00"));
+    }
+
+    #[test]
+    fn test_alarm_storm_drop_oldest() {
+        use futures_util::{FutureExt, StreamExt};
+        use crate::gb::handler::buffer::OverflowPolicy;
+        use crate::gb::handler::parser::xml::parse_xlm_to_vec;
+        use super::AlarmSubscription;
+
+        let notify = |sn: u32| {
+            let xml = format!(r#"<?xml version="1.0" encoding="GB2312"?>
+<Notify>
+<CmdType>Alarm</CmdType>
+<SN>{sn}</SN>
+<DeviceID>34020000001340000200</DeviceID>
+<AlarmPriority>1</AlarmPriority>
+<AlarmMethod>5</AlarmMethod>
+<AlarmTime>2024-01-01T08:00:0{sn}</AlarmTime>
+<Info>
+<AlarmType>2</AlarmType>
+</Info>
+</Notify>
+"#);
+            parse_xlm_to_vec(xml.as_bytes()).unwrap()
+        };
+        let device_id = "34020000001110000200";
+        let mut subscription = AlarmSubscription::watch_with(device_id, 2, OverflowPolicy::DropOldest);
+        for sn in 1..=3 {
+            assert_eq!(AlarmRecord::dispatch_notify(device_id, &notify(sn)), 1);
+        }
+        //缓冲满后丢弃最早的报警,保留最新
+        assert_eq!(subscription.dropped(), 1);
+        assert!(!subscription.overflowed());
+        let rt = common::tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let first = rt.block_on(subscription.next()).unwrap();
+        assert_eq!(first.channel_id, "34020000001340000200");
+        assert_eq!(first.time.as_deref(), Some("2024-01-01T08:00:02"));
+        assert_eq!(first.method, Some(5));
+        assert_eq!(first.alarm_type, Some(2));
+        assert_eq!(rt.block_on(subscription.next()).unwrap().time.as_deref(), Some("2024-01-01T08:00:03"));
+        assert!(subscription.next().now_or_never().is_none());
+
+        assert_eq!(AlarmRecord::dispatch_notify(device_id, &notify(4)), 1);
+        assert_eq!(subscription.dropped(), 1);
+        drop(subscription);
+        assert_eq!(AlarmRecord::dispatch_notify(device_id, &notify(5)), 0);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use futures_util::Stream;
+use parking_lot::Mutex;
+
+use common::exception::GlobalResult;
+use common::serde::Deserialize;
+
+use crate::gb::SessionConf;
+use crate::gb::handler::error_code::CmdErrorCode;
+
+/// 通知缓冲已满时的处理策略
+/// drop_oldest : 丢弃最早的通知,保留最新
+/// error : 拒收新通知并关闭接收端,接收端取完已缓冲的通知后结束
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Deserialize)]
+#[serde(crate = "common::serde", rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    #[default]
+    DropOldest,
+    Error,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    closed: bool,
+    overflowed: bool,
+    waker: Option<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    dropped: AtomicU64,
+}
+
+/// 有界通知缓冲：一发一收,消费跟不上时按OverflowPolicy处理并累计丢弃数
+pub fn channel<T>(capacity: usize, policy: OverflowPolicy) -> (NotifySender<T>, NotifyReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State { queue: VecDeque::new(), closed: false, overflowed: false, waker: None }),
+        dropped: AtomicU64::new(0),
+    });
+    (NotifySender { shared: shared.clone(), capacity: capacity.max(1), policy }, NotifyReceiver { shared })
+}
+
+/// 按配置的容量与溢出策略创建
+pub fn channel_by_conf<T>() -> (NotifySender<T>, NotifyReceiver<T>) {
+    channel(SessionConf::notify_buffer_capacity(), SessionConf::notify_overflow())
+}
+
+pub struct NotifySender<T> {
+    shared: Arc<Shared<T>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T> NotifySender<T> {
+    /// 接收端已丢弃或因溢出关闭
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().closed
+    }
+
+    /// error策略下缓冲已满返回错误;接收端已关闭时丢弃通知
+    pub fn send(&self, item: T) -> GlobalResult<()> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Ok(());
+        }
+        if state.queue.len() >= self.capacity {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            match self.policy {
+                OverflowPolicy::DropOldest => { state.queue.pop_front(); }
+                OverflowPolicy::Error => {
+                    state.closed = true;
+                    state.overflowed = true;
+                    if let Some(waker) = state.waker.take() {
+                        waker.wake();
+                    }
+                    return Err(CmdErrorCode::NotifyOverflow.warn(&format!("通知缓冲已满: capacity = {},接收端已关闭", self.capacity)));
+                }
+            }
+        }
+        state.queue.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for NotifySender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct NotifyReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> NotifyReceiver<T> {
+    /// 因消费过慢被丢弃(drop_oldest)或拒收(error)的通知数
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// error策略下是否因溢出被关闭
+    pub fn overflowed(&self) -> bool {
+        self.shared.state.lock().overflowed
+    }
+
+    pub fn close(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.closed = true;
+        state.queue.clear();
+    }
+}
+
+impl<T> Stream for NotifyReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.state.lock();
+        if let Some(item) = state.queue.pop_front() {
+            return Poll::Ready(Some(item));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for NotifyReceiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures_util::StreamExt;
+
+    use crate::gb::handler::error_code::CmdErrorCode;
+    use super::{channel, OverflowPolicy};
+
+    #[test]
+    fn test_error_policy_closes_receiver() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::Error);
+        assert!(tx.send(1).is_ok());
+        assert!(tx.send(2).is_ok());
+        match tx.send(3) {
+            Err(ref e) => assert_eq!(CmdErrorCode::of(e), Some(CmdErrorCode::NotifyOverflow)),
+            Ok(_) => panic!("expect overflow"),
+        }
+        assert!(tx.is_closed());
+        assert!(rx.overflowed());
+        assert_eq!(rx.dropped(), 1);
+        //已缓冲的通知仍可取出,随后结束
+        let items = common::tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+            let mut items = Vec::new();
+            while let Some(item) = rx.next().await {
+                items.push(item);
+            }
+            items
+        });
+        assert_eq!(items, vec![1, 2]);
+    }
+}
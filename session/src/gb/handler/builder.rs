@@ -1,5 +1,6 @@
-use std::net::SocketAddr;
+use std::net::{Ipv6Addr, SocketAddr};
 use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
 use common::log::{error};
 use rsip::{Error, Header, header, headers, Method, Param, param, Request, Response, SipMessage, uri, Uri};
 use rsip::Header::Via;
@@ -11,20 +12,27 @@ use rsip::prelude::*;
 use uuid::Uuid;
 
 use common::anyhow::anyhow;
-use common::chrono::Local;
+use common::chrono::{Local, TimeZone};
 use common::exception::{GlobalResult, TransError};
 use common::exception::GlobalError::SysErr;
 use common::log::warn;
 use common::rand::prelude::StdRng;
 use common::rand::{Rng, SeedableRng, thread_rng};
 
+use crate::gb::handler::catalog::CatalogFilter;
 use crate::gb::handler::parser;
+use crate::gb::handler::profile::MAIN_STREAM;
+use crate::gb::handler::quirks::DeviceQuirks;
+use crate::gb::handler::snapshot::SnapshotUri;
+use crate::gb::handler::status::DeviceClock;
 use crate::gb::SessionConf;
 use crate::gb::shared::event::Ident;
 use crate::gb::shared::rw::RWSession;
+use crate::gb::shared::ws::WsSession;
 use crate::storage::entity::GmvOauth;
 use crate::storage::mapper;
-use crate::general::model::{PtzControlModel, StreamMode};
+use crate::general::model::{InviteOption, PtzControlModel, SignalTransport, StreamMode, TransportProfile};
+use crate::gb::handler::error_code::CmdErrorCode;
 
 pub struct ResponseBuilder;
 
@@ -80,6 +88,18 @@ impl ResponseBuilder {
         }.into())
     }
 
+    /// 420 Bad Extension：Unsupported列出不支持的option-tag
+    pub fn build_bad_extension_response(req: &Request, socket_addr: &SocketAddr, unsupported: &[String]) -> GlobalResult<SipMessage> {
+        let mut response_header = Self::build_response_header(req, socket_addr)?;
+        response_header.push(rsip::headers::Unsupported::new(unsupported.join(", ")).into());
+        Ok(rsip::Response {
+            status_code: 420.into(),
+            headers: response_header,
+            version: rsip::Version::V2,
+            body: Default::default(),
+        }.into())
+    }
+
     pub fn build_register_ok_response(req: &Request, socket_addr: &SocketAddr) -> GlobalResult<SipMessage> {
         let mut response_header = Self::build_response_header(req, socket_addr)?;
         let other_header = Header::Other(String::from("X-GB-Ver"), String::from("3.0"));
@@ -107,6 +127,37 @@ impl ResponseBuilder {
     }
 
 
+    /// 设备发起INVITE(语音广播/对讲)的200 OK：携带本端SDP,Contact为本端地址
+    pub fn build_invite_ok_response(req: &Request, socket_addr: &SocketAddr, sdp: &str) -> GlobalResult<SipMessage> {
+        let conf = SessionConf::get_session_by_conf();
+        let local_id = req.to_header().hand_log(|msg| warn!("{msg}"))?
+            .uri().hand_log(|msg| warn!("{msg}"))?
+            .auth.ok_or(SysErr(anyhow!("to user is none")))?
+            .user;
+        let contact = RequestBuilder::local_contact(&local_id, &conf.get_wan_ip().to_string(), conf.advertised_port());
+        Self::invite_ok_response(req, socket_addr, contact, sdp)
+    }
+
+    fn invite_ok_response(req: &Request, socket_addr: &SocketAddr, contact: Header, sdp: &str) -> GlobalResult<SipMessage> {
+        let mut headers: rsip::Headers = Default::default();
+        //应答头沿用请求的Via/From/Call-ID/CSeq,To补本端tag;Contact与Content-Length替换为本端
+        for header in Self::build_response_header(req, socket_addr)?.iter() {
+            match header {
+                Header::Contact(_) | Header::ContentLength(_) => {}
+                other => headers.push(other.clone()),
+            }
+        }
+        headers.push(contact);
+        headers.push(rsip::headers::ContentType::new("Application/SDP").into());
+        headers.push(rsip::headers::ContentLength::from(sdp.len() as u32).into());
+        Ok(rsip::Response {
+            status_code: 200.into(),
+            headers,
+            version: rsip::Version::V2,
+            body: sdp.as_bytes().to_vec(),
+        }.into())
+    }
+
     fn build_response_header(req: &Request, socket_addr: &SocketAddr) -> GlobalResult<rsip::Headers> {
         let via_header = parser::header::get_via_header(req)?;
         let mut params = via_header.params().hand_log(|msg| warn!("{msg}"))?;
@@ -156,17 +207,83 @@ impl RequestBuilder {
         let message_request = Self::build_message_request(None, device_id, xml).await;
         message_request
     }
+    pub async fn query_device_catalog_filtered(device_id: &String, filter: &CatalogFilter) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_device_catalog_filtered(device_id, filter);
+        let message_request = Self::build_message_request(None, device_id, xml).await;
+        message_request
+    }
+    pub async fn query_device_status(device_id: &String, channel_id_opt: Option<&String>) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_device_status(channel_id_opt.unwrap_or(device_id));
+        let message_request = Self::build_message_request(channel_id_opt, device_id, xml).await;
+        message_request
+    }
+    pub async fn query_storage_status(device_id: &String, channel_id_opt: Option<&String>) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_storage_status(channel_id_opt.unwrap_or(device_id));
+        let message_request = Self::build_message_request(channel_id_opt, device_id, xml).await;
+        message_request
+    }
+    pub async fn query_alarm(device_id: &String, start_alarm_priority: Option<u8>, end_alarm_priority: Option<u8>, st: Option<u32>, et: Option<u32>, alarm_method: Option<&String>) -> GlobalResult<(Ident, SipMessage)> {
+        let (st, et) = (st.map(|st| DeviceClock::to_device_time(device_id, st)), et.map(|et| DeviceClock::to_device_time(device_id, et)));
+        let xml = XmlBuilder::query_alarm(device_id, start_alarm_priority, end_alarm_priority, st, et, alarm_method);
+        let message_request = Self::build_message_request(None, device_id, xml).await;
+        message_request
+    }
+    pub async fn query_position_track(device_id: &String, channel_id: &String, st: u32, et: u32) -> GlobalResult<(Ident, SipMessage)> {
+        let (st, et) = Self::device_range(device_id, st, et);
+        let xml = XmlBuilder::query_position_track(channel_id, st, et)?;
+        let message_request = Self::build_message_request(Some(channel_id), device_id, xml).await;
+        message_request
+    }
+    pub async fn query_record_info(device_id: &String, channel_id: &String, st: u32, et: u32) -> GlobalResult<(Ident, SipMessage)> {
+        let (st, et) = Self::device_range(device_id, st, et);
+        let xml = XmlBuilder::query_record_info(channel_id, st, et)?;
+        let message_request = Self::build_message_request(Some(channel_id), device_id, xml).await;
+        message_request
+    }
     pub async fn subscribe_device_catalog(device_id: &String) -> GlobalResult<(Ident, SipMessage)> {
         let xml = XmlBuilder::query_device_catalog(device_id);
         let message_request = Self::build_subscribe_request(device_id, xml).await;
         message_request
     }
+    //指定订阅时长(秒)的目录订阅
+    pub async fn subscribe_device_catalog_expires(device_id: &String, expires: u32) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_device_catalog(device_id);
+        let message_request = Self::build_subscribe_request_with(device_id, xml, &Self::catalog_event(), Some(expires)).await;
+        message_request
+    }
+    //报警订阅：不限优先级、时间与报警方式
+    pub async fn subscribe_alarm(device_id: &String) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_alarm(device_id, None, None, None, None, None);
+        let message_request = Self::build_subscribe_request_with(device_id, xml, "presence", None).await;
+        message_request
+    }
+    //expires为0即退订
+    pub async fn subscribe_mobile_position(device_id: &String, channel_id: &String, expires: u32, interval: u32) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::subscribe_mobile_position(channel_id, interval);
+        let message_request = Self::build_subscribe_request_with(device_id, xml, "presence", Some(expires)).await;
+        message_request
+    }
 
-    pub async fn control_snapshot_image(device_id: &String, channel_id: &String, num: u8, interval: u8, uri: &String, session_id: &String) -> GlobalResult<(Ident, SipMessage)> {
+    pub async fn control_snapshot_image(device_id: &String, channel_id: &String, num: u8, interval: u8, uri: &SnapshotUri, session_id: &String) -> GlobalResult<(Ident, SipMessage)> {
         let xml = XmlBuilder::control_snapshot_image(channel_id, num, interval, uri, session_id);
         let message_request = Self::build_message_request(Some(channel_id), device_id, xml).await;
         message_request
     }
+    pub async fn control_osd(device_id: &String, channel_id: &String, text: &str, show_time: bool) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::control_osd(channel_id, text, show_time);
+        let message_request = Self::build_message_request(Some(channel_id), device_id, xml).await;
+        message_request
+    }
+    pub async fn query_osd(device_id: &String, channel_id: &String) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_config_download(channel_id, "OSDConfig");
+        let message_request = Self::build_message_request(Some(channel_id), device_id, xml).await;
+        message_request
+    }
+    pub async fn query_stream_profiles(device_id: &String, channel_id: &String) -> GlobalResult<(Ident, SipMessage)> {
+        let xml = XmlBuilder::query_config_download(channel_id, "VideoParamOpt");
+        let message_request = Self::build_message_request(Some(channel_id), device_id, xml).await;
+        message_request
+    }
     pub async fn control_ptz(ptz_control_model: &PtzControlModel) -> GlobalResult<(Ident, SipMessage)> {
         let xml = XmlBuilder::control_ptz(ptz_control_model);
         let message_request = Self::build_message_request(Some(&ptz_control_model.channelId), &ptz_control_model.deviceId, xml).await;
@@ -195,14 +312,25 @@ impl RequestBuilder {
     }
 
     async fn build_subscribe_request(device_id: &String, body: String) -> GlobalResult<(Ident, SipMessage)> {
-        let (mut headers, uri) = Self::build_request_header(None, device_id, true, true, None, None).await?;
+        Self::build_subscribe_request_with(device_id, body, &Self::catalog_event(), None).await
+    }
+
+    fn catalog_event() -> String {
+        format!("Catalog;id={}", thread_rng().gen_range(123456789u32..987654321u32))
+    }
+
+    //expires：订阅时长(秒),None时取设备心跳过期时长
+    async fn build_subscribe_request_with(device_id: &String, body: String, event: &str, expires: Option<u32>) -> GlobalResult<(Ident, SipMessage)> {
+        let (mut headers, uri) = Self::build_request_header(None, device_id, expires.is_none(), true, None, None).await?;
         let call_id_str = Uuid::new_v4().as_simple().to_string();
         headers.push(rsip::headers::CallId::new(&call_id_str).into());
         let mut rng = thread_rng();
         let cs_eq_str = format!("{} SUBSCRIBE", rng.gen_range(12u8..255u8));
         let cs_eq = rsip::headers::CSeq::new(&cs_eq_str).into();
         headers.push(cs_eq);
-        headers.push(rsip::headers::Event::new(format!("Catalog;id={}", rng.gen_range(123456789u32..987654321u32))).into());
+        for header in Self::subscription_headers(event, expires) {
+            headers.push(header);
+        }
         headers.push(rsip::headers::ContentType::new("Application/MANSCDP+xml").into());
         headers.push(rsip::headers::ContentLength::from(body.len() as u32).into());
         let request_msg: SipMessage = Request {
@@ -245,7 +373,26 @@ impl RequestBuilder {
     /// 构建下发请求头
     async fn build_request_header(channel_id: Option<&String>, device_id: &String, expires: bool, contact: bool, from_tag: Option<&str>, to_tag: Option<&str>)
                                   -> GlobalResult<(rsip::Headers, Uri)> {
-        let bill = RWSession::get_bill_by_device_id(device_id).ok_or(SysErr(anyhow!("设备：{device_id}，未注册或已离线"))).hand_log(|msg| warn!("{msg}"))?;
+        Self::build_request_header_on(channel_id, device_id, expires, contact, from_tag, to_tag, None).await
+    }
+
+    //signal_transport:覆盖信令传输,设备须已建立该传输的通道;出口按Via传输选择
+    async fn build_request_header_on(channel_id: Option<&String>, device_id: &String, expires: bool, contact: bool, from_tag: Option<&str>, to_tag: Option<&str>, signal_transport: Option<SignalTransport>)
+                                     -> GlobalResult<(rsip::Headers, Uri)> {
+        //WS会话Via传输为WS/WSS,否则取网络连接协议
+        let transport = match (WsSession::get_transport(device_id), signal_transport) {
+            (Some(transport), _) => transport.to_string(),
+            (None, Some(signal_transport)) => {
+                if !RWSession::supports_transport(device_id, signal_transport) {
+                    return Err(CmdErrorCode::InvalidParam.warn(&format!("设备：{device_id}，未建立{}信令通道", signal_transport.via_name())));
+                }
+                signal_transport.via_name().to_string()
+            }
+            (None, None) => {
+                let bill = RWSession::get_bill_by_device_id(device_id).ok_or(SysErr(anyhow!("设备：{device_id}，未注册或已离线"))).hand_log(|msg| warn!("{msg}"))?;
+                bill.get_protocol().get_value().to_string()
+            }
+        };
         let mut dst_id = device_id;
         if channel_id.is_some() {
             let channel_id = channel_id.unwrap();
@@ -259,7 +406,7 @@ impl RequestBuilder {
         }
         let conf = SessionConf::get_session_by_conf();
         let server_ip = &conf.get_wan_ip().to_string();
-        let server_port = conf.get_wan_port();
+        let server_port = conf.advertised_port();
         //domain宜采用ID统一编码的前十位编码,扩展支持十位编码加“.spvmn.cn”后缀格式,或采用IP:port格式,port宜采用5060;这里统一使用device_id的前十位,不再调用DB进行判断原设备的使用方式
         // let gmv_device = GmvDevice::query_gmv_device_by_device_id(&device_id.to_string())?.ok_or(SysErr(anyhow!("设备：{device_id}-{channel_id:?}未注册或已离线。")))?;
         let oauth = GmvOauth::read_gmv_oauth_by_device_id(device_id).await?
@@ -271,12 +418,11 @@ impl RequestBuilder {
         let domain_id = oauth.get_domain_id();
         let domain = &format!("{}.spvmn.cn", oauth.get_domain());
 
-        let transport = bill.get_protocol().get_value();
         let uri_str = format!("sip:{}@{}", dst_id, domain);
         let uri = uri::Uri::try_from(uri_str).hand_log(|msg| warn!("{msg}"))?;
         let mut rng = StdRng::from_entropy();
         let mut headers: rsip::Headers = Default::default();
-        headers.push(rsip::headers::Via::new(format!("SIP/2.0/{} {}:{};rport;branch=z9hG4bK{}", transport, server_ip, server_port, rng.gen_range(123456789u32..987654321u32))).into());
+        headers.push(Self::local_via(&transport, server_ip, server_port, rng.gen_range(123456789u32..987654321u32)));
         headers.push(rsip::headers::From::new(format!("<sip:{}@{}>;tag={}", domain_id, domain, from_tag.unwrap_or(&rng.gen_range(123456789u32..987654321u32).to_string()))).into());
         to_tag.map(|tag| {
             headers.push(rsip::headers::To::new(format!("<sip:{}@{}>;tag={}", dst_id, domain, tag)).into())
@@ -288,29 +434,76 @@ impl RequestBuilder {
             headers.push(rsip::headers::Expires::new(expires.as_secs().to_string()).into());
         }
         if contact {
-            headers.push(rsip::headers::Contact::new(format!("<sip:{}@{}:{}>", domain_id, server_ip, server_port)).into());
+            headers.push(Self::local_contact(domain_id, server_ip, server_port));
         }
         headers.push(rsip::headers::MaxForwards::new("70").into());
         headers.push(rsip::headers::UserAgent::new("GMV 0.1").into());
+        if let Some(supported) = Self::supported_header(SessionConf::supported_tags()) {
+            headers.push(supported);
+        }
         Ok((headers, uri))
     }
 
-    pub async fn play_live_request(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::play_live(channel_id, dst_ip, dst_port, stream_mode, ssrc)?;
-        Self::build_stream_request(device_id, channel_id, ssrc, sdp).await
+    fn subscription_headers(event: &str, expires: Option<u32>) -> Vec<Header> {
+        let mut headers: Vec<Header> = vec![rsip::headers::Event::new(event).into()];
+        if let Some(expires) = expires {
+            headers.push(rsip::headers::Expires::new(expires.to_string()).into());
+        }
+        headers
+    }
+
+    //未配置option-tag时不携带Supported,保持原有请求头
+    fn supported_header(tags: &[String]) -> Option<Header> {
+        if tags.is_empty() {
+            return None;
+        }
+        Some(rsip::headers::Supported::new(tags.join(", ")).into())
+    }
+
+    //本端Via/Contact：端口为对外宣告端口,可与实际监听端口不同
+    fn local_via(transport: &str, server_ip: &str, port: u16, branch: u32) -> Header {
+        rsip::headers::Via::new(format!("SIP/2.0/{} {}:{};rport;branch=z9hG4bK{}", transport, server_ip, port, branch)).into()
+    }
+
+    fn local_contact(domain_id: &str, server_ip: &str, port: u16) -> Header {
+        rsip::headers::Contact::new(format!("<sip:{}@{}:{}>", domain_id, server_ip, port)).into()
+    }
+
+    // stream_number: 码流编号,1主码流(设备缺省),2子码流...
+    // 点播请求均返回规范化后的SSRC(y=行所用),供应答校验与流登记
+    pub async fn play_live_request(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, stream_number: u8, option: &InviteOption) -> GlobalResult<(Ident, SipMessage, String)> {
+        let option = &DeviceQuirks::of(device_id).apply(option);
+        let ssrc = SdpBuilder::normalize_ssrc(ssrc)?;
+        let sdp = SdpBuilder::play_live(channel_id, dst_ip, dst_port, stream_mode, &ssrc, stream_number, option)?;
+        let (ident, msg) = Self::build_stream_request(device_id, channel_id, &ssrc, sdp, option).await?;
+        Ok((ident, msg, ssrc))
     }
 
 
     // 点播历史视频
-    pub async fn playback(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::playback(channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et)?;
-        Self::build_stream_request(device_id, channel_id, ssrc, sdp).await
+    pub async fn playback(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, option: &InviteOption) -> GlobalResult<(Ident, SipMessage, String)> {
+        let option = &DeviceQuirks::of(device_id).apply(option);
+        let (st, et) = Self::device_range(device_id, st, et);
+        let ssrc = SdpBuilder::normalize_ssrc(ssrc)?;
+        let sdp = SdpBuilder::playback(channel_id, dst_ip, dst_port, stream_mode, &ssrc, st, et, option)?;
+        let (ident, msg) = Self::build_stream_request(device_id, channel_id, &ssrc, sdp, option).await?;
+        Ok((ident, msg, ssrc))
     }
 
     // 云端录像
-    pub async fn download(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, speed: u8) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::download(channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, speed)?;
-        Self::build_stream_request(device_id, channel_id, ssrc, sdp).await
+    // speed: 下载倍速;0表示不限速,由设备按链路能力尽快传输
+    pub async fn download(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, speed: u8, option: &InviteOption) -> GlobalResult<(Ident, SipMessage, String)> {
+        let option = &DeviceQuirks::of(device_id).apply(option);
+        let (st, et) = Self::device_range(device_id, st, et);
+        let ssrc = SdpBuilder::normalize_ssrc(ssrc)?;
+        let sdp = SdpBuilder::download(channel_id, dst_ip, dst_port, stream_mode, &ssrc, st, et, speed, option)?;
+        let (ident, msg) = Self::build_stream_request(device_id, channel_id, &ssrc, sdp, option).await?;
+        Ok((ident, msg, ssrc))
+    }
+
+    //服务端时间区间换算为设备时间,见DeviceClock
+    fn device_range(device_id: &String, st: u32, et: u32) -> (u32, u32) {
+        (DeviceClock::to_device_time(device_id, st), DeviceClock::to_device_time(device_id, et))
     }
 
     pub async fn build_bye_request(seq: u32, call_id: String, device_id: &String, channel_id: &String, from_tag: &str, to_tag: &str) -> GlobalResult<(Ident, SipMessage)> {
@@ -335,31 +528,35 @@ impl RequestBuilder {
     // 查询硬盘录像情况
     // 拖动播放
     pub async fn seek(device_id: &String, channel_id: &String, seek: u32, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::info_seek(seek);
+        let sdp = Mansrtsp::seek(Mansrtsp::next_cseq(), seek).encode();
         Self::common_info_request(device_id, channel_id, &sdp, from_tag, to_tag, Some(seq), Some(call_id)).await
     }
 
     // 倍速播放
     pub async fn speed(device_id: &String, channel_id: &String, speed: f32, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::info_speed(speed);
+        let sdp = Mansrtsp::speed(Mansrtsp::next_cseq(), speed).encode();
         Self::common_info_request(device_id, channel_id, &sdp, from_tag, to_tag, Some(seq), Some(call_id)).await
     }
 
     // 暂停回放
     pub async fn pause(device_id: &String, channel_id: &String, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::info_pause();
+        let sdp = Mansrtsp::pause(Mansrtsp::next_cseq()).encode();
         Self::common_info_request(device_id, channel_id, &sdp, from_tag, to_tag, Some(seq), Some(call_id)).await
     }
 
     // 恢复回放
     pub async fn replay(device_id: &String, channel_id: &String, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<(Ident, SipMessage)> {
-        let sdp = SdpBuilder::info_replay();
+        let sdp = Mansrtsp::resume(Mansrtsp::next_cseq()).encode();
         Self::common_info_request(device_id, channel_id, &sdp, from_tag, to_tag, Some(seq), Some(call_id)).await
     }
 
-    async fn build_stream_request(device_id: &String, channel_id: &String, ssrc: &str, body: String) -> GlobalResult<(Ident, SipMessage)> {
-        let (mut headers, uri) = Self::build_request_header(Some(channel_id), device_id, false, true, None, None).await?;
-        let call_id_str = Uuid::new_v4().as_simple().to_string();
+    async fn build_stream_request(device_id: &String, channel_id: &String, ssrc: &str, body: String, option: &InviteOption) -> GlobalResult<(Ident, SipMessage)> {
+        let call_id_str = Self::stream_call_id(option.call_id.as_deref())?;
+        let (headers, uri) = Self::build_request_header_on(Some(channel_id), device_id, false, true, None, None, option.signal_transport).await?;
+        Self::assemble_stream_request(headers, uri, device_id, channel_id, ssrc, body, call_id_str)
+    }
+
+    fn assemble_stream_request(mut headers: rsip::Headers, uri: Uri, device_id: &String, channel_id: &String, ssrc: &str, body: String, call_id_str: String) -> GlobalResult<(Ident, SipMessage)> {
         headers.push(rsip::headers::CallId::new(&call_id_str).into());
         let mut rng = thread_rng();
         let cs_eq_str = format!("{} INVITE", rng.gen_range(12u8..255u8));
@@ -385,6 +582,142 @@ impl RequestBuilder {
         Ok((ident, msg))
     }
 
+    //调用方指定Call-ID时校验语法：word [ "@" word ] (RFC3261 25.1)
+    fn stream_call_id(call_id: Option<&str>) -> GlobalResult<String> {
+        match call_id {
+            None => Ok(Uuid::new_v4().as_simple().to_string()),
+            Some(call_id) => {
+                let is_word = |word: &str| !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "-.!%*_+`'~()<>:\\\"/[]?{}".contains(c));
+                let valid = match call_id.split_once('@') {
+                    None => is_word(call_id),
+                    Some((local, host)) => is_word(local) && is_word(host),
+                };
+                if !valid {
+                    return Err(CmdErrorCode::InvalidParam.error(&format!("无效的Call-ID:{call_id}")));
+                }
+                Ok(call_id.to_string())
+            }
+        }
+    }
+
+    //3xx重定向：Contact作为新Request-URI,Call-ID/From/To不变,CSeq递增,新事务重新生成Via branch(RFC3261 8.1.3.4)
+    pub fn build_redirect_request(ident: &Ident, req: &Request, res: &Response) -> GlobalResult<(Ident, SipMessage)> {
+        let contact: typed::Contact = res.contact_header().hand_log(|msg| warn!("{msg}"))?.typed().hand_log(|msg| warn!("{msg}"))?;
+        let seq = req.cseq_header().hand_log(|msg| warn!("{msg}"))?.seq().hand_log(|msg| warn!("{msg}"))?;
+        let cs_eq_str = format!("{} INVITE", seq + 1);
+        let branch = format!("branch=z9hG4bK{}", thread_rng().gen_range(123456789u32..987654321u32));
+        let mut headers: rsip::Headers = Default::default();
+        for header in req.headers.iter() {
+            match header {
+                Header::Via(via) => {
+                    let value = via.value().split(';')
+                        .map(|param| if param.trim().starts_with("branch=") { branch.as_str() } else { param })
+                        .collect::<Vec<&str>>()
+                        .join(";");
+                    headers.push(rsip::headers::Via::new(value).into());
+                }
+                Header::CSeq(_) => headers.push(rsip::headers::CSeq::new(&cs_eq_str).into()),
+                other => headers.push(other.clone()),
+            }
+        }
+        let msg = Request {
+            method: Method::Invite,
+            uri: contact.uri,
+            headers,
+            version: rsip::common::version::Version::V2,
+            body: req.body.clone(),
+        }.into();
+        let ident = Ident::new(ident.get_device_id().clone(), ident.get_call_id().clone(), cs_eq_str);
+        Ok((ident, msg))
+    }
+
+    //CANCEL：Request-URI、Via(branch)、From、To、Call-ID与被取消的INVITE一致,CSeq序号相同(RFC3261 9.1)
+    pub fn build_cancel_request(req: &Request) -> GlobalResult<SipMessage> {
+        let mut headers: rsip::Headers = Default::default();
+        headers.push(req.via_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        headers.push(req.from_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        headers.push(req.to_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        headers.push(req.call_id_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        let seq = req.cseq_header().hand_log(|msg| warn!("{msg}"))?.seq().hand_log(|msg| warn!("{msg}"))?;
+        headers.push(rsip::headers::CSeq::new(format!("{seq} CANCEL")).into());
+        headers.push(rsip::headers::MaxForwards::new("70").into());
+        headers.push(rsip::headers::UserAgent::new("GMV 0.1").into());
+        headers.push(rsip::headers::ContentLength::default().into());
+        Ok(Request {
+            method: Method::Cancel,
+            uri: req.uri.clone(),
+            headers,
+            version: rsip::common::version::Version::V2,
+            body: Default::default(),
+        }.into())
+    }
+
+    //非2xx最终应答的ACK：属于INVITE事务,Request-URI、Via(branch)与Route沿用原INVITE,To取应答(含tag)(RFC3261 17.1.1.3)
+    pub fn build_ack_request_by_failure_response(req: &Request, res: &Response) -> GlobalResult<SipMessage> {
+        let mut headers: rsip::Headers = Default::default();
+        headers.push(req.via_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        for route in req.headers.iter().filter(|header| matches!(header, rsip::Header::Route(_))) {
+            headers.push(route.clone());
+        }
+        headers.push(res.to_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        headers.push(req.from_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        headers.push(req.call_id_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+        let seq = req.cseq_header().hand_log(|msg| warn!("{msg}"))?.seq().hand_log(|msg| warn!("{msg}"))?;
+        headers.push(rsip::headers::CSeq::new(format!("{seq} ACK")).into());
+        headers.push(rsip::headers::MaxForwards::new("70").into());
+        headers.push(rsip::headers::UserAgent::new("GMV 0.1").into());
+        headers.push(rsip::headers::ContentLength::default().into());
+        Ok(rsip::Request {
+            method: Method::Ack,
+            uri: req.uri.clone(),
+            headers,
+            version: rsip::common::version::Version::V2,
+            body: Default::default(),
+        }.into())
+    }
+
+    /// 目录订阅对话内的re-SUBSCRIBE：由设备NOTIFY取对话(Call-ID、双方tag、Event),seq须大于对话内前次SUBSCRIBE
+    pub fn build_resubscribe_by_notify(device_id: &String, notify: &Request, seq: u32) -> GlobalResult<(Ident, SipMessage)> {
+        let event = notify.headers.iter().find_map(|header| match header {
+            Header::Event(event) => Some(event.clone()),
+            _ => None,
+        }).ok_or_else(|| CmdErrorCode::InvalidParam.warn(&format!("device id = [{device_id}] 目录NOTIFY缺少Event")))?;
+        let transport = notify.via_header().hand_log(|msg| warn!("{msg}"))?.typed().hand_log(|msg| warn!("{msg}"))?.transport.to_string();
+        let conf = SessionConf::get_session_by_conf();
+        let server_ip = &conf.get_wan_ip().to_string();
+        let server_port = conf.advertised_port();
+        let from = notify.from_header().hand_log(|msg| warn!("{msg}"))?;
+        let uri = from.uri().hand_log(|msg| warn!("{msg}"))?;
+        let domain_id = notify.uri.auth.as_ref().map(|auth| auth.user.clone()).unwrap_or_default();
+        let call_id = notify.call_id_header().hand_log(|msg| warn!("{msg}"))?.value().to_string();
+        let cs_eq_str = format!("{seq} SUBSCRIBE");
+        let mut headers: rsip::Headers = Default::default();
+        headers.push(Self::local_via(&transport, server_ip, server_port, thread_rng().gen_range(123456789u32..987654321u32)));
+        //NOTIFY由设备发起：其To为本端,From为设备
+        headers.push(rsip::headers::From::new(notify.to_header().hand_log(|msg| warn!("{msg}"))?.value()).into());
+        headers.push(rsip::headers::To::new(from.value()).into());
+        headers.push(rsip::headers::CallId::new(&call_id).into());
+        headers.push(rsip::headers::CSeq::new(&cs_eq_str).into());
+        headers.push(event.into());
+        if let Some(expires) = RWSession::get_expires_by_device_id(device_id) {
+            headers.push(rsip::headers::Expires::new(expires.as_secs().to_string()).into());
+        }
+        headers.push(Self::local_contact(&domain_id, server_ip, server_port));
+        headers.push(rsip::headers::MaxForwards::new("70").into());
+        headers.push(rsip::headers::UserAgent::new("GMV 0.1").into());
+        let body = XmlBuilder::query_device_catalog(device_id);
+        headers.push(rsip::headers::ContentType::new("Application/MANSCDP+xml").into());
+        headers.push(rsip::headers::ContentLength::from(body.len() as u32).into());
+        let request_msg: SipMessage = Request {
+            method: Method::Subscribe,
+            uri,
+            headers,
+            version: rsip::common::version::Version::V2,
+            body: body.as_bytes().to_vec(),
+        }.into();
+        Ok((Ident::new(device_id.to_string(), call_id, cs_eq_str), request_msg))
+    }
+
     pub fn build_ack_request_by_response(res: &Response) -> GlobalResult<SipMessage> {
         let mut headers: rsip::Headers = Default::default();
         headers.push(res.to_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
@@ -468,7 +801,7 @@ impl XmlBuilder {
         }
         cmd_line
     }
-    pub fn control_snapshot_image(channel_id: &String, num: u8, interval: u8, uri: &String, session_id: &String) -> String {
+    pub fn control_snapshot_image(channel_id: &String, num: u8, interval: u8, uri: &SnapshotUri, session_id: &String) -> String {
         let mut xml = String::new();
         xml.push_str("<?xml version=\"1.0\"?>\r\n");
         xml.push_str("<Control>\r\n");
@@ -485,6 +818,42 @@ impl XmlBuilder {
         xml
     }
 
+    //OSD叠加：单项标题文字,时间叠加可选
+    pub fn control_osd(channel_id: &String, text: &str, show_time: bool) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB18030\"?>\r\n");
+        xml.push_str("<Control>\r\n");
+        xml.push_str("<CmdType>DeviceConfig</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", channel_id));
+        xml.push_str("<OSDConfig>\r\n");
+        xml.push_str(&*format!("<TimeEnable>{}</TimeEnable>\r\n", show_time as u8));
+        xml.push_str("<TextEnable>1</TextEnable>\r\n");
+        xml.push_str("<SumNum>1</SumNum>\r\n");
+        xml.push_str("<Item>\r\n");
+        xml.push_str(&*format!("<Text>{}</Text>\r\n", Self::escape(text)));
+        xml.push_str("</Item>\r\n");
+        xml.push_str("</OSDConfig>\r\n");
+        xml.push_str("</Control>\r\n");
+        xml
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    }
+
+    pub fn query_config_download(device_id: &String, config_type: &str) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>ConfigDownload</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
+        xml.push_str(&*format!("<ConfigType>{}</ConfigType>\r\n", config_type));
+        xml.push_str("</Query>\r\n");
+        xml
+    }
+
     pub fn query_preset(device_id: &String) -> String {
         let mut xml = String::new();
         xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
@@ -507,116 +876,420 @@ impl XmlBuilder {
     }
 
     pub fn query_device_catalog(device_id: &String) -> String {
+        Self::query_device_catalog_filtered(device_id, &CatalogFilter::default())
+    }
+
+    //过滤元素为扩展元素,见CatalogFilter
+    pub fn query_device_catalog_filtered(device_id: &String, filter: &CatalogFilter) -> String {
         let mut xml = String::new();
         xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
         xml.push_str("<Query>\r\n");
         xml.push_str("<CmdType>Catalog</CmdType>\r\n");
         xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
         xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
+        if let Some(online) = filter.online {
+            xml.push_str(&*format!("<Status>{}</Status>\r\n", if online { "ON" } else { "OFF" }));
+        }
+        if let Some(type_code) = &filter.type_code {
+            xml.push_str(&*format!("<Type>{}</Type>\r\n", Self::escape(type_code)));
+        }
+        xml.push_str("</Query>\r\n");
+        xml
+    }
+
+    pub fn query_device_status(device_id: &String) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>DeviceStatus</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
+        xml.push_str("</Query>\r\n");
+        xml
+    }
+
+    pub fn query_storage_status(device_id: &String) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>SDCardStatus</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
         xml.push_str("</Query>\r\n");
         xml
     }
+
+    //报警查询：过滤条件均为可选,未指定时不输出对应元素
+    pub fn query_alarm(device_id: &String, start_alarm_priority: Option<u8>, end_alarm_priority: Option<u8>, st: Option<u32>, et: Option<u32>, alarm_method: Option<&String>) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>Alarm</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
+        if let Some(priority) = start_alarm_priority {
+            xml.push_str(&*format!("<StartAlarmPriority>{}</StartAlarmPriority>\r\n", priority));
+        }
+        if let Some(priority) = end_alarm_priority {
+            xml.push_str(&*format!("<EndAlarmPriority>{}</EndAlarmPriority>\r\n", priority));
+        }
+        if let Some(method) = alarm_method {
+            xml.push_str(&*format!("<AlarmMethod>{}</AlarmMethod>\r\n", method));
+        }
+        if let Some(st) = st {
+            xml.push_str(&*format!("<StartAlarmTime>{}</StartAlarmTime>\r\n", Self::format_time(st)));
+        }
+        if let Some(et) = et {
+            xml.push_str(&*format!("<EndAlarmTime>{}</EndAlarmTime>\r\n", Self::format_time(et)));
+        }
+        xml.push_str("</Query>\r\n");
+        xml
+    }
+
+    //移动设备位置订阅：Interval为上报间隔(秒)
+    pub fn subscribe_mobile_position(channel_id: &String, interval: u32) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>MobilePosition</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", channel_id));
+        xml.push_str(&*format!("<Interval>{}</Interval>\r\n", interval));
+        xml.push_str("</Query>\r\n");
+        xml
+    }
+
+    //轨迹查询：时间区间校验同回放
+    pub fn query_position_track(device_id: &String, st: u32, et: u32) -> GlobalResult<String> {
+        SdpBuilder::history_range(st, et)?;
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>MobilePosition</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
+        xml.push_str(&*format!("<StartTime>{}</StartTime>\r\n", Self::format_time(st)));
+        xml.push_str(&*format!("<EndTime>{}</EndTime>\r\n", Self::format_time(et)));
+        xml.push_str("</Query>\r\n");
+        Ok(xml)
+    }
+
+    //录像检索：Type取all,不区分定时/报警/手动录像
+    pub fn query_record_info(device_id: &String, st: u32, et: u32) -> GlobalResult<String> {
+        SdpBuilder::history_range(st, et)?;
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n");
+        xml.push_str("<Query>\r\n");
+        xml.push_str("<CmdType>RecordInfo</CmdType>\r\n");
+        xml.push_str(&*format!("<SN>{}</SN>\r\n", Local::now().timestamp_subsec_millis()));
+        xml.push_str(&*format!("<DeviceID>{}</DeviceID>\r\n", device_id));
+        xml.push_str(&*format!("<StartTime>{}</StartTime>\r\n", Self::format_time(st)));
+        xml.push_str(&*format!("<EndTime>{}</EndTime>\r\n", Self::format_time(et)));
+        xml.push_str("<Type>all</Type>\r\n");
+        xml.push_str("</Query>\r\n");
+        Ok(xml)
+    }
+
+    fn format_time(ts: u32) -> String {
+        Local.timestamp_opt(ts as i64, 0).unwrap().format("%Y-%m-%dT%H:%M:%S").to_string()
+    }
 }
 
-struct SdpBuilder;
+static MANSRTSP_CSEQ: AtomicU32 = AtomicU32::new(1);
 
-#[allow(unused)]
-impl SdpBuilder {
-    pub fn info_pause() -> String {
-        let mut sdp = String::with_capacity(100);
-        sdp.push_str("PAUSE RTSP/1.0\r\n");
-        sdp.push_str(&format!("CSeq: {}\r\n", Local::now().timestamp()));
-        sdp.push_str("PauseTime: now\r\n");
-        sdp
+/// MANSRTSP 回放控制消息体(INFO)：CSeq 独立于 SIP CSeq 递增
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mansrtsp {
+    cseq: u32,
+    method: MansrtspMethod,
+    //倍速
+    scale: Option<f32>,
+    //npt起始位置,None表示now
+    range: Option<Option<u32>>,
+    pause_time: bool,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MansrtspMethod {
+    Play,
+    Pause,
+}
+
+impl Mansrtsp {
+    pub fn next_cseq() -> u32 {
+        MANSRTSP_CSEQ.fetch_add(1, Ordering::Relaxed)
     }
 
-    //暂停后恢复播放
-    pub fn info_replay() -> String {
-        let mut sdp = String::with_capacity(100);
-        sdp.push_str("PLAY RTSP/1.0\r\n");
-        sdp.push_str(&format!("CSeq: {}\r\n", Local::now().timestamp()));
-        sdp.push_str("Range: npt=now-\r\n");
-        sdp
+    fn new(cseq: u32, method: MansrtspMethod) -> Self {
+        Self { cseq, method, scale: None, range: None, pause_time: false }
     }
 
     //倍速播放
-    pub fn info_speed(speed: f32) -> String {
-        let mut sdp = String::with_capacity(100);
-        sdp.push_str("PLAY RTSP/1.0\r\n");
-        sdp.push_str(&format!("CSeq: {}\r\n", Local::now().timestamp()));
-        sdp.push_str(&format!("Scale: {}.000000\r\n", speed));
-        sdp
+    pub fn speed(cseq: u32, speed: f32) -> Self {
+        Self { scale: Some(speed), ..Self::new(cseq, MansrtspMethod::Play) }
     }
 
-    //拖动播放
-    pub fn info_seek(seek: u32) -> String {
-        let mut sdp = String::with_capacity(100);
-        sdp.push_str("PLAY RTSP/1.0\r\n");
-        sdp.push_str(&format!("CSeq: {}\r\n", Local::now().timestamp()));
-        sdp.push_str(&format!("Range: npt={}-\r\n", seek));
-        sdp
+    //拖动播放：相对回放起始的秒数
+    pub fn seek(cseq: u32, seek: u32) -> Self {
+        Self { range: Some(Some(seek)), ..Self::new(cseq, MansrtspMethod::Play) }
     }
 
-    pub fn playback(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32) -> GlobalResult<String> {
-        let st_et = format!("{} {}", st, et);
-        let sdp = Self::build_common_play(channel_id, media_ip, media_port, stream_mode, ssrc, "Playback", &st_et, true, None)?;
-        Ok(sdp)
+    //暂停回放
+    pub fn pause(cseq: u32) -> Self {
+        Self { pause_time: true, ..Self::new(cseq, MansrtspMethod::Pause) }
+    }
+
+    //暂停后恢复播放
+    pub fn resume(cseq: u32) -> Self {
+        Self { range: Some(None), ..Self::new(cseq, MansrtspMethod::Play) }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut body = String::with_capacity(100);
+        match self.method {
+            MansrtspMethod::Play => { body.push_str("PLAY RTSP/1.0\r\n"); }
+            MansrtspMethod::Pause => { body.push_str("PAUSE RTSP/1.0\r\n"); }
+        }
+        body.push_str(&format!("CSeq: {}\r\n", self.cseq));
+        if self.pause_time {
+            body.push_str("PauseTime: now\r\n");
+        }
+        if let Some(scale) = self.scale {
+            body.push_str(&format!("Scale: {:.6}\r\n", scale));
+        }
+        match self.range {
+            Some(Some(npt)) => { body.push_str(&format!("Range: npt={}-\r\n", npt)); }
+            Some(None) => { body.push_str("Range: npt=now-\r\n"); }
+            None => {}
+        }
+        body
     }
+}
+
+/// 点播会话类型：决定s=会话名、是否携带u=取流地址及a=downloadspeed
+/// Playback为按录像时间实时推流(可倍速控制),Download为文件下载(按downloadspeed尽快传输)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum SdpSession {
+    Play,
+    Playback,
+    Download(u8),
+}
 
-    pub fn download(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, download_speed: u8) -> GlobalResult<String> {
-        let st_et = format!("{} {}", st, et);
-        let sdp = Self::build_common_play(channel_id, media_ip, media_port, stream_mode, ssrc, "Download", &st_et, true, Some(download_speed))?;
+impl SdpSession {
+    fn name(self) -> &'static str {
+        match self {
+            SdpSession::Play => "Play",
+            SdpSession::Playback => "Playback",
+            SdpSession::Download(_) => "Download",
+        }
+    }
+
+    //仅下载携带downloadspeed;回放带该属性时部分设备按下载处理,忽略倍速控制
+    fn download_speed(self) -> Option<u8> {
+        match self {
+            SdpSession::Download(speed) => Some(speed),
+            _ => None,
+        }
+    }
+}
+
+//GB28181约定的视频payload:96 PS封装,其余为裸流(ES)
+pub(super) const VIDEO_RTPMAPS: [(u8, &str); 5] = [(96, "PS/90000"), (97, "MPEG4/90000"), (98, "H264/90000"), (99, "SVAC/90000"), (100, "H265/90000")];
+
+struct SdpBuilder;
+
+#[allow(unused)]
+impl SdpBuilder {
+    pub fn playback(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, option: &InviteOption) -> GlobalResult<String> {
+        let st_et = Self::history_range(st, et)?;
+        let sdp = Self::build_common_play(channel_id, media_ip, media_port, stream_mode, ssrc, SdpSession::Playback, &st_et, option)?;
         Ok(sdp)
     }
-    pub fn play_live(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String) -> GlobalResult<String> {
-        let sdp = Self::build_common_play(channel_id, media_ip, media_port, stream_mode, ssrc, "Play", "0 0", false, None)?;
+
+    /// download_speed = 0 时原样输出 a=downloadspeed:0，部分设备据此按最快速度传输
+    pub fn download(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, download_speed: u8, option: &InviteOption) -> GlobalResult<String> {
+        let st_et = Self::history_range(st, et)?;
+        let sdp = Self::build_common_play(channel_id, media_ip, media_port, stream_mode, ssrc, SdpSession::Download(download_speed), &st_et, option)?;
         Ok(sdp)
     }
+    pub fn play_live(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, stream_number: u8, option: &InviteOption) -> GlobalResult<String> {
+        let sdp = Self::build_common_play(channel_id, media_ip, media_port, stream_mode, ssrc, SdpSession::Play, "0 0", option)?;
+        Ok(Self::select_stream(sdp, stream_number))
+    }
+
+    //码流选择：a=streamnumber:<编号-1>(0主码流,1子码流...),置于y=行前;主码流为设备缺省,不声明
+    fn select_stream(mut sdp: String, stream_number: u8) -> String {
+        if stream_number <= MAIN_STREAM {
+            return sdp;
+        }
+        let attr = format!("a=streamnumber:{}\r\n", stream_number - 1);
+        match sdp.find("\r\ny=") {
+            Some(i) => sdp.insert_str(i + 2, &attr),
+            None => sdp.push_str(&attr),
+        }
+        sdp
+    }
+
+    //回放/下载 t=开始时间 结束时间(秒级时间戳),与 u=通道ID:0 同时下发;
+    //t=0 0 会被设备当作实时点播,故不允许为0或区间无效
+    fn history_range(st: u32, et: u32) -> GlobalResult<String> {
+        if st == 0 || et == 0 || st >= et {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("回放时间区间无效: st = {st},et = {et}")));
+        }
+        Ok(format!("{} {}", st, et))
+    }
 
     ///缺s:Play/Playback/Download; t:开始时间戳 结束时间戳; u:回放与下载时的取流地址
-    fn build_common_play(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, name: &str, st_et: &str, u: bool, download_speed: Option<u8>) -> GlobalResult<String> {
+    fn build_common_play(channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, session: SdpSession, st_et: &str, option: &InviteOption) -> GlobalResult<String> {
+        Self::check_fmtp(&option.fmtp)?;
+        Self::check_video_payloads(&option.video_payloads)?;
+        if option.strict_offer && option.srtp.is_some() {
+            return Err(CmdErrorCode::InvalidParam.warn("最简offer不支持SRTP"));
+        }
         let conf = SessionConf::get_session_by_conf();
         let session_ip = &conf.get_wan_ip().to_string();
+        Ok(Self::build_session_sdp(session_ip, channel_id, media_ip, media_port, stream_mode, ssrc, session, st_et, option))
+    }
+
+    fn build_session_sdp(session_ip: &str, channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, session: SdpSession, st_et: &str, option: &InviteOption) -> String {
+        let u = session != SdpSession::Play;
+        Self::build_sdp(session_ip, channel_id, media_ip, media_port, stream_mode, ssrc, session.name(), st_et, u, session.download_speed(), option)
+    }
+
+    //y=行SSRC：10位十进制(首位0实时/1历史);不足10位时保留首位、其后补0,不改变实时/历史标识;
+    //非数字、超长或超出32位的视为无效
+    fn normalize_ssrc(ssrc: &str) -> GlobalResult<String> {
+        let ssrc = ssrc.trim();
+        if ssrc.is_empty() || ssrc.len() > 10 || !ssrc.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("SSRC无效: {ssrc:?}")));
+        }
+        let (flag, rest) = ssrc.split_at(1);
+        let normalized = format!("{flag}{rest:0>9}");
+        if normalized.parse::<u64>().map(|v| v > u32::MAX as u64).unwrap_or(true) {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("SSRC无效: {ssrc:?}")));
+        }
+        Ok(normalized)
+    }
+
+    //fmtp仅可用于offer中的payload(96-100),参数不可为空或含换行
+    fn check_fmtp(fmtp: &[(u8, String)]) -> GlobalResult<()> {
+        for (tp, params) in fmtp {
+            if !(96..=100).contains(tp) {
+                return Err(CmdErrorCode::InvalidParam.warn(&format!("fmtp payload无效: {tp}")));
+            }
+            if params.trim().is_empty() || params.contains(['\r', '\n']) {
+                return Err(CmdErrorCode::InvalidParam.warn(&format!("fmtp参数无效: {tp} {params:?}")));
+            }
+        }
+        Ok(())
+    }
+
+    //offer的payload须为GB28181约定的视频payload(96-100),不可重复
+    fn check_video_payloads(payloads: &[u8]) -> GlobalResult<()> {
+        for (i, tp) in payloads.iter().enumerate() {
+            if !VIDEO_RTPMAPS.iter().any(|(known, _)| known == tp) || payloads[..i].contains(tp) {
+                return Err(CmdErrorCode::InvalidParam.warn(&format!("offer payload无效: {tp}")));
+            }
+        }
+        Ok(())
+    }
+
+    //按指定顺序取payload与rtpmap;未指定则为全部
+    fn video_rtpmaps(payloads: &[u8]) -> Vec<(u8, &'static str)> {
+        if payloads.is_empty() {
+            return VIDEO_RTPMAPS.to_vec();
+        }
+        payloads.iter().filter_map(|tp| VIDEO_RTPMAPS.iter().find(|(known, _)| known == tp).copied()).collect()
+    }
+
+    //地址类型按地址解析结果：IPv6为IP6,其余(IPv4或域名)为IP4
+    fn addr_type(addr: &str) -> &'static str {
+        if addr.parse::<Ipv6Addr>().is_ok() { "IP6" } else { "IP4" }
+    }
+
+    fn build_sdp(session_ip: &str, channel_id: &String, media_ip: &String, media_port: u16, stream_mode: StreamMode, ssrc: &String, name: &str, st_et: &str, u: bool, download_speed: Option<u8>, option: &InviteOption) -> String {
         let mut sdp = String::with_capacity(300);
         sdp.push_str("v=0\r\n");
-        sdp.push_str(&format!("o={} 0 0 IN IP4 {}\r\n", channel_id, session_ip));
+        sdp.push_str(&format!("o={} 0 0 IN {} {}\r\n", channel_id, Self::addr_type(session_ip), session_ip));
         sdp.push_str(&format!("s={}\r\n", name));
         if u {
             sdp.push_str(&format!("u={}:0\r\n", channel_id));
         }
-        sdp.push_str(&format!("c=IN IP4 {}\r\n", media_ip));
+        let media_ip = media_ip.trim_start_matches('[').trim_end_matches(']');
+        sdp.push_str(&format!("c=IN {} {}\r\n", Self::addr_type(media_ip), media_ip));
         sdp.push_str(&format!("t={}\r\n", st_et));
+        let strict = option.strict_offer;
+        let proto = if strict {
+            TransportProfile::Avp.proto()
+        } else if option.srtp.is_some() {
+            option.profile.secure_proto()
+        } else {
+            option.profile.proto()
+        };
+        let rtpmaps = if strict { Self::video_rtpmaps(&[96]) } else { Self::video_rtpmaps(&option.video_payloads) };
+        let payloads = rtpmaps.iter().map(|(tp, _)| tp.to_string()).collect::<Vec<_>>().join(" ");
         match stream_mode {
             StreamMode::Udp => {
-                sdp.push_str(&format!("m=video {} RTP/AVP 96 97 98 99 100\r\n", media_port))
+                sdp.push_str(&format!("m=video {} {} {}\r\n", media_port, proto, payloads))
             }
             StreamMode::TcpActive => {
-                sdp.push_str(&format!("m=video {} TCP/RTP/AVP 96 97 98 99 100\r\n", media_port));
+                sdp.push_str(&format!("m=video {} TCP/{} {}\r\n", media_port, proto, payloads));
                 sdp.push_str("a=setup:active\r\n");
                 sdp.push_str("a=connection:new\r\n");
             }
             StreamMode::TcpPassive => {
-                sdp.push_str(&format!("m=video {} TCP/RTP/AVP 96 97 98 99 100\r\n", media_port));
+                sdp.push_str(&format!("m=video {} TCP/{} {}\r\n", media_port, proto, payloads));
                 sdp.push_str("a=setup:passive\r\n");
                 sdp.push_str("a=connection:new\r\n");
             }
         }
         sdp.push_str("a=recvonly\r\n");
-        sdp.push_str("a=rtpmap:96 PS/90000\r\n");
-        sdp.push_str("a=rtpmap:97 MPEG4/90000\r\n");
-        sdp.push_str("a=rtpmap:98 H264/90000\r\n");
-        sdp.push_str("a=rtpmap:99 SVAC/90000\r\n");
-        sdp.push_str("a=rtpmap:100 H265/90000\r\n");
+        for (tp, rtpmap) in rtpmaps {
+            sdp.push_str(&format!("a=rtpmap:{} {}\r\n", tp, rtpmap));
+        }
+        if !strict {
+            Self::push_media_attrs(&mut sdp, option);
+        }
         download_speed.map(|speed| sdp.push_str(&format!("a=downloadspeed:{}\r\n", speed)));
         sdp.push_str(&format!("y={}\r\n", ssrc));
-        Ok(sdp)
+        sdp
+    }
+
+    //附加媒体属性：格式参数、SRTP密钥与RTCP相关声明
+    fn push_media_attrs(sdp: &mut String, option: &InviteOption) {
+        for (tp, params) in &option.fmtp {
+            sdp.push_str(&format!("a=fmtp:{} {}\r\n", tp, params.trim()));
+        }
+        if let Some(crypto) = &option.srtp {
+            sdp.push_str(&format!("a=crypto:{}\r\n", crypto.to_attr()));
+        }
+        //AVPF：声明可接收NACK重传请求与PLI关键帧请求
+        if option.profile == TransportProfile::Avpf {
+            sdp.push_str("a=rtcp-fb:* nack\r\n");
+            sdp.push_str("a=rtcp-fb:* nack pli\r\n");
+        }
+        if option.rtcp_mux {
+            sdp.push_str("a=rtcp-mux\r\n");
+        }
+        if let Some(port) = option.rtcp_port {
+            sdp.push_str(&format!("a=rtcp:{}\r\n", port));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use common::chrono::Local;
-    use crate::general::model::PtzControlModel;
+    use crate::general::model::{Direction, InviteOption, PtzControlModel, StreamMode, TransportProfile, ZoomDir};
+
+    //实时点播offer(UDP):通道、收流地址与SSRC取缺省,各用例仅给出option
+    fn offer(option: &InviteOption) -> String {
+        offer_over(StreamMode::Udp, option)
+    }
+
+    fn offer_over(stream_mode: StreamMode, option: &InviteOption) -> String {
+        super::SdpBuilder::build_sdp("10.64.49.44", &String::from("34020000001320000101"), &String::from("10.64.49.218"), 5514, stream_mode, &String::from("0100000001"), "Play", "0 0", false, None, option)
+    }
 
     #[test]
     fn test_date_format() {
@@ -637,6 +1310,107 @@ mod tests {
         println!("{}", msg);
     }
 
+    #[test]
+    fn test_advertised_contact_port() {
+        use rsip::Header;
+        use rsip::prelude::UntypedHeader;
+        match super::RequestBuilder::local_contact("34020000002000000001", "203.0.113.10", 15060) {
+            Header::Contact(contact) => assert_eq!(contact.value(), "<sip:34020000002000000001@203.0.113.10:15060>"),
+            _ => unreachable!(),
+        }
+        match super::RequestBuilder::local_via("UDP", "203.0.113.10", 15060, 123456789) {
+            Header::Via(via) => assert!(via.value().starts_with("SIP/2.0/UDP 203.0.113.10:15060;")),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_query_position_track_xml() {
+        let channel_id = "34020000001320000101".to_string();
+        let xml = super::XmlBuilder::query_position_track(&channel_id, 1704067200, 1704070800).unwrap();
+        assert!(xml.contains("<CmdType>MobilePosition</CmdType>"));
+        assert!(xml.contains("<StartTime>"));
+        assert!(xml.contains("<EndTime>"));
+        assert!(super::XmlBuilder::query_position_track(&channel_id, 1704070800, 1704067200).is_err());
+        assert!(super::XmlBuilder::query_position_track(&channel_id, 0, 1704067200).is_err());
+    }
+
+    #[test]
+    fn test_query_catalog_filtered_xml() {
+        use crate::gb::handler::catalog::CatalogFilter;
+        let device_id = "34020000001110000001".to_string();
+        let plain = super::XmlBuilder::query_device_catalog(&device_id);
+        assert!(!plain.contains("<Status>") && !plain.contains("<Type>"));
+        let filter = CatalogFilter { online: Some(true), type_code: Some("132".to_string()) };
+        let xml = super::XmlBuilder::query_device_catalog_filtered(&device_id, &filter);
+        assert!(xml.contains("<CmdType>Catalog</CmdType>\r\n"));
+        assert!(xml.contains("<DeviceID>34020000001110000001</DeviceID>\r\n<Status>ON</Status>\r\n<Type>132</Type>\r\n</Query>"));
+        let offline = CatalogFilter { online: Some(false), ..Default::default() };
+        assert!(super::XmlBuilder::query_device_catalog_filtered(&device_id, &offline).contains("<Status>OFF</Status>"));
+    }
+
+    #[test]
+    fn test_control_osd_xml() {
+        let channel_id = "34020000001320000101".to_string();
+        let xml = super::XmlBuilder::control_osd(&channel_id, "Gate <1> & 2", true);
+        assert!(xml.contains("<CmdType>DeviceConfig</CmdType>"));
+        assert!(xml.contains("<DeviceID>34020000001320000101</DeviceID>"));
+        assert!(xml.contains("<TimeEnable>1</TimeEnable>"));
+        assert!(xml.contains("<Text>Gate &lt;1&gt; &amp; 2</Text>"));
+        let xml = super::XmlBuilder::control_osd(&channel_id, "Gate", false);
+        assert!(xml.contains("<TimeEnable>0</TimeEnable>"));
+        //生成的配置可被解析回读
+        let osd = crate::gb::handler::osd::OsdConfig::parse(xml.replace("Control>", "Response>").as_bytes()).unwrap();
+        assert_eq!(osd.text.as_deref(), Some("Gate"));
+        assert!(!osd.time_enable);
+
+        let query = super::XmlBuilder::query_config_download(&channel_id, "OSDConfig");
+        assert!(query.contains("<CmdType>ConfigDownload</CmdType>"));
+        assert!(query.contains("<ConfigType>OSDConfig</ConfigType>"));
+    }
+
+    #[test]
+    fn test_ptz_constructors() {
+        let left = PtzControlModel::pan(Direction::Left, 5).unwrap();
+        assert_eq!(super::XmlBuilder::build_cmd_ptz_line(&left), "A50F0102050000BC");
+        let up = PtzControlModel::tilt(Direction::Up, 8).unwrap();
+        assert_eq!(super::XmlBuilder::build_cmd_ptz_line(&up), "A50F0108000800C5");
+        let zoom_in = PtzControlModel::zoom(ZoomDir::In, 3).unwrap();
+        assert_eq!(super::XmlBuilder::build_cmd_ptz_line(&zoom_in), "A50F0110000030F5");
+        //左上斜向
+        let diagonal = PtzControlModel::pan(Direction::Left, 5).unwrap()
+            .combine(PtzControlModel::tilt(Direction::Up, 5).unwrap()).unwrap()
+            .target("34020000001320000001".to_string(), "34020000001310000001".to_string());
+        assert_eq!(super::XmlBuilder::build_cmd_ptz_line(&diagonal), "A50F010A050500C9");
+        assert_eq!(diagonal.channelId, "34020000001310000001");
+
+        assert!(PtzControlModel::pan(Direction::Up, 5).is_err());
+        assert!(PtzControlModel::tilt(Direction::Right, 5).is_err());
+        assert!(PtzControlModel::zoom(ZoomDir::Out, 16).is_err());
+        assert!(PtzControlModel::pan(Direction::Left, 5).unwrap()
+            .combine(PtzControlModel::pan(Direction::Right, 5).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_ptz_describe() {
+        let model = PtzControlModel::pan(Direction::Left, 5).unwrap()
+            .combine(PtzControlModel::tilt(Direction::Up, 3).unwrap()).unwrap();
+        assert_eq!(model.describe(), "pan left speed 5 + tilt up speed 3");
+        assert_eq!(model.to_string(), model.describe());
+        //经指令串还原后描述不变
+        let cmd_line = super::XmlBuilder::build_cmd_ptz_line(&model);
+        assert_eq!(PtzControlModel::from_cmd_line(&cmd_line).unwrap().describe(), "pan left speed 5 + tilt up speed 3");
+        let zoom_in = PtzControlModel::zoom(ZoomDir::In, 3).unwrap();
+        assert_eq!(PtzControlModel::from_cmd_line(&super::XmlBuilder::build_cmd_ptz_line(&zoom_in)).unwrap().describe(), "zoom in speed 3");
+        assert_eq!(PtzControlModel::default().describe(), "stop");
+        assert_eq!(PtzControlModel::from_cmd_line("A50F0100000000B5").unwrap().describe(), "stop");
+
+        //校验码错误、左右位同时置位
+        assert!(PtzControlModel::from_cmd_line("A50F0102050000BD").is_err());
+        assert!(PtzControlModel::from_cmd_line("A50F0103050000BD").is_err());
+        assert!(PtzControlModel::from_cmd_line("A50F01").is_err());
+    }
+
     #[test]
     fn test_ptz_cmd() {
         let mut model = PtzControlModel {
@@ -679,4 +1453,393 @@ mod tests {
         println!("L2 {}", (sec+2)>>1);
         println!("L3 {}", (sec+3)>>1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sdp_transport_profile() {
+        let avp = offer(&InviteOption::default());
+        assert!(avp.contains("m=video 5514 RTP/AVP 96 97 98 99 100\r\n"));
+        assert!(!avp.contains("a=rtcp-fb"));
+
+        let option = InviteOption { profile: TransportProfile::Avpf, ..Default::default() };
+        let avpf = offer(&option);
+        assert!(avpf.contains("m=video 5514 RTP/AVPF 96 97 98 99 100\r\n"));
+        assert!(avpf.contains("a=rtcp-fb:* nack\r\n"));
+        assert!(avpf.contains("a=rtcp-fb:* nack pli\r\n"));
+
+        let tcp = offer_over(StreamMode::TcpPassive, &option);
+        assert!(tcp.contains("m=video 5514 TCP/RTP/AVPF 96 97 98 99 100\r\n"));
+    }
+
+    #[test]
+    fn test_sdp_fmtp() {
+        let option = InviteOption { fmtp: vec![(98, "profile-level-id=42e01f;packetization-mode=1".to_string())], ..Default::default() };
+        let sdp = offer(&option);
+        assert!(sdp.contains("a=rtpmap:98 H264/90000\r\na=rtpmap:99 SVAC/90000\r\na=rtpmap:100 H265/90000\r\na=fmtp:98 profile-level-id=42e01f;packetization-mode=1\r\n"));
+        let plain = offer(&InviteOption::default());
+        assert!(!plain.contains("a=fmtp"));
+
+        assert!(super::SdpBuilder::check_fmtp(&option.fmtp).is_ok());
+        assert!(super::SdpBuilder::check_fmtp(&[(8, "x=1".to_string())]).is_err());
+        assert!(super::SdpBuilder::check_fmtp(&[(98, " ".to_string())]).is_err());
+        assert!(super::SdpBuilder::check_fmtp(&[(98, "a=1\r\na=sendonly".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_device_quirks_offer() {
+        use crate::gb::handler::quirks::DeviceQuirks;
+        let quirky = "34020000001110000193";
+        let other = "34020000001110000194";
+        DeviceQuirks::set(quirky, Some(DeviceQuirks { strict_offer: true, ..Default::default() }));
+        let option = InviteOption { rtcp_mux: true, ..Default::default() };
+        let build = |device_id: &str| {
+            let option = DeviceQuirks::of(device_id).apply(&option);
+            offer(&option)
+        };
+        let strict = build(quirky);
+        assert!(strict.contains("m=video 5514 RTP/AVP 96\r\n"));
+        assert!(!strict.contains("a=rtcp-mux"));
+        let normal = build(other);
+        assert!(normal.contains("m=video 5514 RTP/AVP 96 97 98 99 100\r\n"));
+        assert!(normal.contains("a=rtcp-mux\r\n"));
+        DeviceQuirks::set(quirky, None);
+    }
+
+    #[test]
+    fn test_sdp_strict_offer() {
+        let option = InviteOption {
+            strict_offer: true,
+            profile: TransportProfile::Avpf,
+            rtcp_mux: true,
+            rtcp_port: Some(5515),
+            video_payloads: vec![96, 98],
+            fmtp: vec![(98, "profile-level-id=42e01f".to_string())],
+            ..Default::default()
+        };
+        let sdp = offer(&option);
+        assert!(sdp.contains("m=video 5514 RTP/AVP 96\r\n"));
+        assert_eq!(sdp.matches("a=rtpmap:").count(), 1);
+        assert!(sdp.contains("a=rtpmap:96 PS/90000\r\n"));
+        assert!(!sdp.contains("a=fmtp"));
+        assert!(!sdp.contains("a=rtcp"));
+        assert!(sdp.ends_with("a=recvonly\r\na=rtpmap:96 PS/90000\r\ny=0100000001\r\n"));
+    }
+
+    #[test]
+    fn test_sdp_video_payloads() {
+        let option = InviteOption { video_payloads: vec![96, 98], ..Default::default() };
+        let sdp = offer(&option);
+        assert!(sdp.contains("m=video 5514 RTP/AVP 96 98\r\n"));
+        assert!(sdp.contains("a=rtpmap:96 PS/90000\r\na=rtpmap:98 H264/90000\r\n"));
+        assert!(!sdp.contains("a=rtpmap:100"));
+        assert!(super::SdpBuilder::check_video_payloads(&option.video_payloads).is_ok());
+        assert!(super::SdpBuilder::check_video_payloads(&[96, 8]).is_err());
+        assert!(super::SdpBuilder::check_video_payloads(&[98, 98]).is_err());
+    }
+
+    #[test]
+    fn test_sdp_srtp_crypto() {
+        use crate::general::model::SrtpCrypto;
+        let crypto = SrtpCrypto::generate();
+        let (key, salt) = crypto.master_key_salt().unwrap();
+        assert_eq!((key.len(), salt.len()), (16, 14));
+        let option = InviteOption { srtp: Some(crypto.clone()), ..Default::default() };
+        let sdp = offer(&option);
+        assert!(sdp.contains("m=video 5514 RTP/SAVP 96 97 98 99 100\r\n"));
+        assert!(sdp.contains(&format!("a=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:{}\r\n", crypto.key_params)));
+        let tcp = offer_over(StreamMode::TcpPassive, &option);
+        assert!(tcp.contains("m=video 5514 TCP/RTP/SAVP 96 97 98 99 100\r\n"));
+        //缺省明文
+        let plain = offer(&InviteOption::default());
+        assert!(plain.contains("RTP/AVP 96") && !plain.contains("a=crypto"));
+    }
+
+    #[test]
+    fn test_playback_range_clock_drift() {
+        use crate::gb::handler::status::DeviceClock;
+        let device_id = String::from("34020000001110000258");
+        let channel_id = String::from("34020000001320000101");
+        let media_ip = String::from("10.64.49.218");
+        let ssrc = String::from("1100000001");
+        let emitted = |device_id: &String| {
+            let (st, et) = super::RequestBuilder::device_range(device_id, 1700000000, 1700003600);
+            let st_et = super::SdpBuilder::history_range(st, et).unwrap();
+            super::SdpBuilder::build_sdp("10.64.49.44", &channel_id, &media_ip, 5514, StreamMode::Udp, &ssrc, "Playback", &st_et, true, None, &InviteOption::default())
+        };
+        //未知偏差：原样下发
+        assert!(emitted(&device_id).contains("t=1700000000 1700003600\r\n"));
+        //设备快30秒
+        DeviceClock::set_drift(&device_id, 30);
+        assert!(emitted(&device_id).contains("t=1700000030 1700003630\r\n"));
+        //设备慢30秒
+        DeviceClock::set_drift(&device_id, -30);
+        assert!(emitted(&device_id).contains("t=1699999970 1700003570\r\n"));
+        DeviceClock::forget(&device_id);
+    }
+
+    #[test]
+    fn test_cancel_request_matches_invite() {
+        use rsip::{Method, SipMessage};
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        let invite = "INVITE sip:34020000001320000169@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK169169169\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=169\r\n\
+To: <sip:34020000001320000169@3402000000.spvmn.cn>\r\n\
+Call-ID: cancel-call-id\r\n\
+CSeq: 21 INVITE\r\n\
+Content-Type: Application/SDP\r\n\
+Content-Length: 0\r\n\r\n";
+        let req = match SipMessage::try_from(invite).unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        let cancel = match super::RequestBuilder::build_cancel_request(&req).unwrap() {
+            SipMessage::Request(cancel) => cancel,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        assert_eq!(cancel.method, Method::Cancel);
+        assert_eq!(cancel.uri, req.uri);
+        assert_eq!(cancel.cseq_header().unwrap().value(), "21 CANCEL");
+        assert!(cancel.via_header().unwrap().value().contains("branch=z9hG4bK169169169"));
+        assert_eq!(cancel.call_id_header().unwrap().value(), "cancel-call-id");
+        assert!(cancel.body.is_empty());
+    }
+
+    #[test]
+    fn test_invite_ok_response_sdp_and_tags() {
+        use rsip::SipMessage;
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        let invite = "INVITE sip:34020000002000000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 10.64.49.44:5060;rport;branch=z9hG4bK159159159\r\n\
+From: <sip:34020000001370000001@3402000000>;tag=device-tag\r\n\
+To: <sip:34020000002000000001@3402000000>\r\n\
+Call-ID: talk-call-id\r\n\
+CSeq: 1 INVITE\r\n\
+Contact: <sip:34020000001370000001@10.64.49.44:5060>\r\n\
+Content-Length: 0\r\n\r\n";
+        let req = match SipMessage::try_from(invite).unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        let sdp = "v=0\r\no=34020000002000000001 0 0 IN IP4 10.64.49.218\r\ns=Play\r\nc=IN IP4 10.64.49.218\r\nt=0 0\r\nm=audio 8000 RTP/AVP 8\r\na=sendonly\r\na=rtpmap:8 PCMA/8000\r\ny=0100000159\r\n";
+        let contact = super::RequestBuilder::local_contact("34020000002000000001", "203.0.113.10", 15060);
+        let socket_addr: std::net::SocketAddr = "10.64.49.44:5060".parse().unwrap();
+        let res = match super::ResponseBuilder::invite_ok_response(&req, &socket_addr, contact, sdp).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        assert_eq!(res.status_code.code(), 200);
+        assert_eq!(res.body, sdp.as_bytes());
+        //From沿用设备tag,To补本端tag
+        assert_eq!(res.from_header().unwrap().tag().unwrap().unwrap().to_string(), "device-tag");
+        assert!(res.to_header().unwrap().tag().unwrap().is_some());
+        assert_eq!(res.call_id_header().unwrap().value(), "talk-call-id");
+        assert_eq!(res.cseq_header().unwrap().value(), "1 INVITE");
+        assert_eq!(res.contact_header().unwrap().value(), "<sip:34020000002000000001@203.0.113.10:15060>");
+        assert!(res.via_header().unwrap().value().contains("branch=z9hG4bK159159159"));
+    }
+
+    #[test]
+    fn test_sdp_download_speed_zero() {
+        let channel_id = String::from("34020000001320000101");
+        let media_ip = String::from("10.64.49.218");
+        let ssrc = String::from("1100000001");
+        let sdp = super::SdpBuilder::build_sdp("10.64.49.44", &channel_id, &media_ip, 5514, StreamMode::Udp, &ssrc, "Download", "1700000000 1700003600", true, Some(0), &InviteOption::default());
+        assert!(sdp.contains("s=Download\r\n"));
+        assert!(sdp.contains("a=downloadspeed:0\r\n"));
+        assert!(sdp.ends_with("y=1100000001\r\n"));
+    }
+
+    #[test]
+    fn test_supported_header() {
+        let header = super::RequestBuilder::supported_header(&["timer".to_string(), "100rel".to_string()]).unwrap();
+        assert_eq!(header.to_string(), "Supported: timer, 100rel");
+        //未配置时不携带
+        assert!(super::RequestBuilder::supported_header(&[]).is_none());
+    }
+
+    #[test]
+    fn test_subscribe_mobile_position() {
+        let xml = super::XmlBuilder::subscribe_mobile_position(&"34020000001320000187".to_string(), 5);
+        assert!(xml.contains("<CmdType>MobilePosition</CmdType>"));
+        assert!(xml.contains("<Interval>5</Interval>"));
+        let headers: Vec<String> = super::RequestBuilder::subscription_headers("presence", Some(3600)).iter().map(|h| h.to_string()).collect();
+        assert_eq!(headers, vec!["Event: presence".to_string(), "Expires: 3600".to_string()]);
+        //目录订阅沿用设备心跳过期时长,由请求头构建时携带
+        assert_eq!(super::RequestBuilder::subscription_headers("Catalog;id=1", None).len(), 1);
+    }
+
+    #[test]
+    fn test_sdp_playback_vs_download() {
+        use super::SdpSession;
+        let channel_id = String::from("34020000001320000101");
+        let media_ip = String::from("10.64.49.218");
+        let ssrc = String::from("1100000001");
+        let st_et = "1700000000 1700003600";
+        let playback = super::SdpBuilder::build_session_sdp("10.64.49.44", &channel_id, &media_ip, 5514, StreamMode::Udp, &ssrc, SdpSession::Playback, st_et, &InviteOption::default());
+        assert!(playback.contains("s=Playback\r\n"));
+        assert!(playback.contains("u=34020000001320000101:0\r\n"));
+        assert!(!playback.contains("downloadspeed"));
+
+        let download = super::SdpBuilder::build_session_sdp("10.64.49.44", &channel_id, &media_ip, 5514, StreamMode::Udp, &ssrc, SdpSession::Download(4), st_et, &InviteOption::default());
+        assert!(download.contains("s=Download\r\n"));
+        assert!(download.contains("u=34020000001320000101:0\r\n"));
+        assert!(download.contains("a=downloadspeed:4\r\n"));
+
+        let live = super::SdpBuilder::build_session_sdp("10.64.49.44", &channel_id, &media_ip, 5514, StreamMode::Udp, &ssrc, SdpSession::Play, "0 0", &InviteOption::default());
+        assert!(live.contains("s=Play\r\n"));
+        assert!(!live.contains("u="));
+        assert!(!live.contains("downloadspeed"));
+    }
+
+    #[test]
+    fn test_sdp_playback_range() {
+        let channel_id = String::from("34020000001320000101");
+        let media_ip = String::from("10.64.49.218");
+        let ssrc = String::from("1100000001");
+        let st_et = super::SdpBuilder::history_range(1700000000, 1700003600).unwrap();
+        let sdp = super::SdpBuilder::build_sdp("10.64.49.44", &channel_id, &media_ip, 5514, StreamMode::Udp, &ssrc, "Playback", &st_et, true, None, &InviteOption::default());
+        assert!(sdp.contains("s=Playback\r\n"));
+        assert!(sdp.contains("u=34020000001320000101:0\r\n"));
+        assert!(sdp.contains("t=1700000000 1700003600\r\n"));
+
+        assert!(super::SdpBuilder::history_range(0, 0).is_err());
+        assert!(super::SdpBuilder::history_range(1700003600, 1700000000).is_err());
+    }
+
+    #[test]
+    fn test_query_alarm_xml() {
+        let device_id = String::from("34020000001110000001");
+        let xml = super::XmlBuilder::query_alarm(&device_id, None, None, None, None, None);
+        assert!(xml.contains("<CmdType>Alarm</CmdType>\r\n"));
+        assert!(xml.contains("<DeviceID>34020000001110000001</DeviceID>\r\n"));
+        assert!(!xml.contains("AlarmPriority"));
+        assert!(!xml.contains("AlarmMethod"));
+        assert!(!xml.contains("AlarmTime"));
+
+        let method = String::from("2");
+        let xml = super::XmlBuilder::query_alarm(&device_id, Some(1), Some(4), Some(1700000000), Some(1700003600), Some(&method));
+        assert!(xml.contains("<StartAlarmPriority>1</StartAlarmPriority>\r\n"));
+        assert!(xml.contains("<EndAlarmPriority>4</EndAlarmPriority>\r\n"));
+        assert!(xml.contains("<AlarmMethod>2</AlarmMethod>\r\n"));
+        assert!(xml.contains(&format!("<StartAlarmTime>{}</StartAlarmTime>\r\n", super::XmlBuilder::format_time(1700000000))));
+        assert!(xml.contains(&format!("<EndAlarmTime>{}</EndAlarmTime>\r\n", super::XmlBuilder::format_time(1700003600))));
+        assert!(xml.ends_with("</Query>\r\n"));
+    }
+
+    #[test]
+    fn test_sdp_rtcp_mux() {
+        let sdp = offer(&InviteOption::default());
+        assert!(!sdp.contains("a=rtcp-mux"));
+        let option = InviteOption { rtcp_mux: true, ..Default::default() };
+        let sdp = offer(&option);
+        assert!(sdp.contains("a=recvonly\r\n"));
+        assert!(sdp.contains("a=rtcp-mux\r\n"));
+    }
+
+    #[test]
+    fn test_sdp_select_sub_stream() {
+        let sdp = offer(&InviteOption::default());
+        assert_eq!(super::SdpBuilder::select_stream(sdp.clone(), 1), sdp);
+        let sub = super::SdpBuilder::select_stream(sdp, 2);
+        assert!(sub.ends_with("a=streamnumber:1\r\ny=0100000001\r\n"));
+    }
+
+    #[test]
+    fn test_sdp_ipv6_destination() {
+        let channel_id = String::from("34020000001320000101");
+        let ssrc = String::from("0100000001");
+        let sdp = super::SdpBuilder::build_sdp("10.64.49.44", &channel_id, &String::from("2001:db8::218"), 5514, StreamMode::Udp, &ssrc, "Play", "0 0", false, None, &InviteOption::default());
+        assert!(sdp.contains("o=34020000001320000101 0 0 IN IP4 10.64.49.44\r\n"));
+        assert!(sdp.contains("c=IN IP6 2001:db8::218\r\n"));
+        let sdp = super::SdpBuilder::build_sdp("10.64.49.44", &channel_id, &String::from("[2001:db8::218]"), 5514, StreamMode::Udp, &ssrc, "Play", "0 0", false, None, &InviteOption::default());
+        assert!(sdp.contains("c=IN IP6 2001:db8::218\r\n"));
+        let sdp = super::SdpBuilder::build_sdp("10.64.49.44", &channel_id, &String::from("10.64.49.218"), 5514, StreamMode::Udp, &ssrc, "Play", "0 0", false, None, &InviteOption::default());
+        assert!(sdp.contains("c=IN IP4 10.64.49.218\r\n"));
+    }
+
+    #[test]
+    fn test_normalize_ssrc() {
+        assert_eq!(super::SdpBuilder::normalize_ssrc("0100000001").unwrap(), "0100000001");
+        assert_eq!(super::SdpBuilder::normalize_ssrc(" 1100000002 ").unwrap(), "1100000002");
+        //不足10位：保留首位标识,其后补0
+        assert_eq!(super::SdpBuilder::normalize_ssrc("1001").unwrap(), "1000000001");
+        assert_eq!(super::SdpBuilder::normalize_ssrc("0101").unwrap(), "0000000101");
+        assert_eq!(super::SdpBuilder::normalize_ssrc("1").unwrap(), "1000000000");
+        assert!(super::SdpBuilder::normalize_ssrc("01000000a1").is_err());
+        assert!(super::SdpBuilder::normalize_ssrc("").is_err());
+        assert!(super::SdpBuilder::normalize_ssrc("01000000001").is_err());
+        assert!(super::SdpBuilder::normalize_ssrc("9999999999").is_err());
+    }
+
+    #[test]
+    fn test_sdp_rtcp_port() {
+        let sdp = offer(&InviteOption::default());
+        assert!(!sdp.contains("a=rtcp:"));
+        let option = InviteOption { rtcp_port: Some(6020), ..Default::default() };
+        let sdp = offer(&option);
+        assert!(sdp.contains("a=rtcp:6020\r\n"));
+    }
+
+    #[test]
+    fn test_query_channel_status_xml() {
+        let channel_id = String::from("34020000001320000101");
+        let xml = super::XmlBuilder::query_device_status(&channel_id);
+        assert!(xml.contains("<CmdType>DeviceStatus</CmdType>\r\n"));
+        assert!(xml.contains("<DeviceID>34020000001320000101</DeviceID>\r\n"));
+    }
+
+    #[test]
+    fn test_invite_explicit_call_id() {
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use rsip::SipMessage;
+        assert!(super::RequestBuilder::stream_call_id(None).unwrap().len() > 0);
+        assert_eq!(super::RequestBuilder::stream_call_id(Some("trace-4bf92f3577b34da6@gmv")).unwrap(), "trace-4bf92f3577b34da6@gmv");
+        assert!(super::RequestBuilder::stream_call_id(Some("trace id")).is_err());
+        assert!(super::RequestBuilder::stream_call_id(Some("@gmv")).is_err());
+        assert!(super::RequestBuilder::stream_call_id(Some("")).is_err());
+
+        let call_id = super::RequestBuilder::stream_call_id(Some("trace-4bf92f3577b34da6")).unwrap();
+        let mut headers: rsip::Headers = Default::default();
+        headers.push(rsip::headers::Via::new("SIP/2.0/UDP 172.18.38.186:25600;rport;branch=z9hG4bK123456789").into());
+        headers.push(rsip::headers::From::new("<sip:34020000002000000001@3402000000.spvmn.cn>;tag=123456789").into());
+        headers.push(rsip::headers::To::new("<sip:34020000001320000101@3402000000.spvmn.cn>").into());
+        let uri = rsip::Uri::try_from("sip:34020000001320000101@3402000000.spvmn.cn".to_string()).unwrap();
+        let (ident, invite) = super::RequestBuilder::assemble_stream_request(headers, uri, &"34020000001110000001".to_string(),
+                                                                            &"34020000001320000101".to_string(), "0100000001", "v=0\r\n".to_string(), call_id).unwrap();
+        assert_eq!(ident.get_call_id(), "trace-4bf92f3577b34da6");
+        let invite = match invite {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => panic!("expect request"),
+        };
+        assert_eq!(invite.call_id_header().unwrap().value(), "trace-4bf92f3577b34da6");
+
+        //设备200 OK沿用INVITE的Call-ID,ACK随之
+        let ok = "SIP/2.0 200 OK\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:25600;rport=25600;branch=z9hG4bK123456789\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=123456789\r\n\
+To: <sip:34020000001320000101@3402000000.spvmn.cn>;tag=987654321\r\n\
+Call-ID: trace-4bf92f3577b34da6\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let res = match SipMessage::try_from(ok).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => panic!("expect response"),
+        };
+        match super::RequestBuilder::build_ack_request_by_response(&res).unwrap() {
+            SipMessage::Request(ack) => assert_eq!(ack.call_id_header().unwrap().value(), "trace-4bf92f3577b34da6"),
+            SipMessage::Response(_) => panic!("expect request"),
+        }
+    }
+
+    #[test]
+    fn test_mansrtsp_body() {
+        use super::Mansrtsp;
+        assert_eq!(Mansrtsp::speed(3, 2.0).encode(), "PLAY RTSP/1.0\r\nCSeq: 3\r\nScale: 2.000000\r\n");
+        assert_eq!(Mansrtsp::speed(4, 0.5).encode(), "PLAY RTSP/1.0\r\nCSeq: 4\r\nScale: 0.500000\r\n");
+        assert_eq!(Mansrtsp::seek(5, 120).encode(), "PLAY RTSP/1.0\r\nCSeq: 5\r\nRange: npt=120-\r\n");
+        assert_eq!(Mansrtsp::pause(6).encode(), "PAUSE RTSP/1.0\r\nCSeq: 6\r\nPauseTime: now\r\n");
+        assert_eq!(Mansrtsp::resume(7).encode(), "PLAY RTSP/1.0\r\nCSeq: 7\r\nRange: npt=now-\r\n");
+        let cseq = Mansrtsp::next_cseq();
+        assert!(Mansrtsp::next_cseq() > cseq);
+    }
+}
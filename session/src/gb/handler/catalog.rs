@@ -0,0 +1,605 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{self, Stream};
+use parking_lot::{Mutex, RwLock};
+
+use common::exception::GlobalResult;
+use common::once_cell::sync::Lazy;
+use common::tokio::sync::mpsc;
+use common::tokio::time;
+
+use crate::gb::handler::parser::xml::*;
+
+//分包目录等待尾包超时
+pub const ASSEMBLE_TIMEOUT: Duration = Duration::from_secs(30);
+//超时部分目录的巡检间隔
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+static CATALOG_ASSEMBLER: Lazy<Mutex<CatalogAssembler>> = Lazy::new(|| Mutex::new(CatalogAssembler::new(ASSEMBLE_TIMEOUT)));
+//最近一次目录：device_id:(channel_id:通道项)
+static LAST_CATALOG: Lazy<RwLock<HashMap<String, HashMap<String, ChannelItem>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+//分页订阅：(device_id,查询SN):(订阅编号,订阅方)
+static PAGE_WATCHERS: Lazy<Mutex<HashMap<(String, u32), (u64, mpsc::UnboundedSender<CatalogSnapshot>)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static WATCHER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 通道云台能力：由目录PTZType推断
+/// 1-球机;2-半球;3-固定枪机;4-遥控枪机
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PtzCapabilities {
+    pub pan: bool,
+    pub tilt: bool,
+    pub zoom: bool,
+    pub focus: bool,
+    pub iris: bool,
+    pub preset: bool,
+}
+
+impl PtzCapabilities {
+    //未知类型返回None
+    pub fn from_ptz_type(ptz_type: u8) -> Option<Self> {
+        match ptz_type {
+            1 => Some(Self { pan: true, tilt: true, zoom: true, focus: true, iris: true, preset: true }),
+            2 | 4 => Some(Self { zoom: true, focus: true, iris: true, ..Default::default() }),
+            3 => Some(Self::default()),
+            _ => None,
+        }
+    }
+
+    pub fn has_any(&self) -> bool {
+        self.pan || self.tilt || self.zoom || self.focus || self.iris || self.preset
+    }
+}
+
+/// 目录通道项：对应 Response/DeviceList/Item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelItem {
+    pub channel_id: String,
+    pub name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub owner: Option<String>,
+    pub civil_code: Option<String>,
+    pub block: Option<String>,
+    pub address: Option<String>,
+    pub parental: Option<u8>,
+    pub parent_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub port: Option<u16>,
+    pub longitude: Option<f32>,
+    pub latitude: Option<f32>,
+    pub ptz_type: Option<u8>,
+    //PTZType缺省或未知时为None
+    pub ptz_caps: Option<PtzCapabilities>,
+    pub status: String,
+    //厂商扩展元素：相对Item的路径:值
+    pub extensions: HashMap<String, String>,
+}
+
+/// 一次目录查询的结果
+#[derive(Debug, Clone, Default)]
+pub struct CatalogSnapshot {
+    pub device_id: String,
+    pub sn: Option<u32>,
+    pub sum_num: Option<u32>,
+    pub items: Vec<ChannelItem>,
+}
+
+/// 通道变化前后
+#[derive(Debug, Clone)]
+pub struct ChannelChange {
+    pub old: ChannelItem,
+    pub new: ChannelItem,
+}
+
+impl ChannelChange {
+    /// 仅状态变化（如 ON -> OFF）
+    pub fn is_status_only(&self) -> bool {
+        if self.old.status == self.new.status {
+            return false;
+        }
+        let mut old = self.old.clone();
+        old.status = self.new.status.clone();
+        old == self.new
+    }
+}
+
+/// 目录查询过滤条件
+/// 查询XML附带 <Status>ON|OFF</Status>、<Type>类型码</Type>,非GB28181标准元素:
+/// 仅部分上级/级联平台(自研或扩展信令的平台)按此过滤,IPC/NVR通常忽略;
+/// 故对解析结果总是再做一次本地过滤,结果与平台是否支持无关
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CatalogFilter {
+    //Some(true)-仅在线,Some(false)-仅离线
+    pub online: Option<bool>,
+    //编码第11-13位类型码,如131摄像机、132网络摄像机、215业务分组
+    pub type_code: Option<String>,
+}
+
+impl CatalogFilter {
+    pub fn is_empty(&self) -> bool {
+        self.online.is_none() && self.type_code.is_none()
+    }
+
+    pub fn matches(&self, item: &ChannelItem) -> bool {
+        let online = matches!(&item.status.to_ascii_uppercase()[..], "ON" | "ONLINE");
+        self.online.map(|want| want == online).unwrap_or(true)
+            && self.type_code.as_deref().map(|code| item.channel_id.get(10..13) == Some(code)).unwrap_or(true)
+    }
+
+    /// 本地过滤：平台未按查询条件过滤时兜底
+    pub fn apply(&self, mut snapshot: CatalogSnapshot) -> CatalogSnapshot {
+        if !self.is_empty() {
+            snapshot.items.retain(|item| self.matches(item));
+        }
+        snapshot
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CatalogDelta {
+    pub added: Vec<ChannelItem>,
+    pub removed: Vec<ChannelItem>,
+    pub changed: Vec<ChannelChange>,
+}
+
+impl CatalogDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl CatalogSnapshot {
+    /// 解析目录响应体
+    pub fn parse(body: &[u8]) -> GlobalResult<Self> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Self {
+        let mut snapshot = CatalogSnapshot::default();
+        let mut item: Option<ChannelItem> = None;
+        for (k, v) in vs {
+            match &k[..] {
+                RESPONSE_DEVICE_ID => { snapshot.device_id = v; }
+                RESPONSE_SN => { snapshot.sn = v.parse::<u32>().ok(); }
+                RESPONSE_SUM_NUM => { snapshot.sum_num = v.parse::<u32>().ok(); }
+                //以Item下DeviceID作为新通道起始
+                RESPONSE_DEVICE_LIST_ITEM_DEVICE_ID => {
+                    if let Some(dc) = item.take() {
+                        snapshot.items.push(dc);
+                    }
+                    item = Some(ChannelItem { channel_id: v, ..Default::default() });
+                }
+                _ => {
+                    if let Some(dc) = item.as_mut() {
+                        Self::fill_item(dc, &k, v);
+                    }
+                }
+            }
+        }
+        if let Some(dc) = item.take() {
+            snapshot.items.push(dc);
+        }
+        snapshot
+    }
+
+    fn fill_item(dc: &mut ChannelItem, k: &str, v: String) {
+        match k {
+            RESPONSE_DEVICE_LIST_ITEM_NAME => { dc.name = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_MANUFACTURER => { dc.manufacturer = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_MODEL => { dc.model = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_OWNER => { dc.owner = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_CIVIL_CODE => { dc.civil_code = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_BLOCK => { dc.block = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_ADDRESS => { dc.address = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_PARENTAL => { dc.parental = v.parse::<u8>().ok(); }
+            RESPONSE_DEVICE_LIST_ITEM_PARENT_ID => { dc.parent_id = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_IP_ADDRESS => { dc.ip_address = Some(v); }
+            RESPONSE_DEVICE_LIST_ITEM_PORT => { dc.port = v.parse::<u16>().ok(); }
+            RESPONSE_DEVICE_LIST_ITEM_LONGITUDE => { dc.longitude = v.parse::<f32>().ok(); }
+            RESPONSE_DEVICE_LIST_ITEM_LATITUDE => { dc.latitude = v.parse::<f32>().ok(); }
+            RESPONSE_DEVICE_LIST_ITEM_PTZ_TYPE => {
+                dc.ptz_type = v.parse::<u8>().ok();
+                dc.ptz_caps = dc.ptz_type.and_then(PtzCapabilities::from_ptz_type);
+            }
+            RESPONSE_DEVICE_LIST_ITEM_STATUS => { dc.status = v; }
+            _ => {
+                if let Some(key) = extension_key(k, RESPONSE_DEVICE_LIST_ITEM, CATALOG_ITEM_ELEMENTS) {
+                    dc.extensions.insert(key, v);
+                }
+            }
+        }
+    }
+
+    /// 记录为最近目录：按通道合并,部分目录不覆盖未返回的通道
+    pub fn remember(&self) {
+        let mut guard = LAST_CATALOG.write();
+        let channels = guard.entry(self.device_id.clone()).or_default();
+        for item in &self.items {
+            channels.insert(item.channel_id.clone(), item.clone());
+        }
+    }
+
+    pub fn last_item(device_id: &str, channel_id: &str) -> Option<ChannelItem> {
+        LAST_CATALOG.read().get(device_id).and_then(|channels| channels.get(channel_id)).cloned()
+    }
+
+    /// self为旧目录,other为新目录：新增/移除/变更以通道DeviceID为准
+    pub fn diff(&self, other: &CatalogSnapshot) -> CatalogDelta {
+        let old_map: HashMap<&String, &ChannelItem> = self.items.iter().map(|item| (&item.channel_id, item)).collect();
+        let new_map: HashMap<&String, &ChannelItem> = other.items.iter().map(|item| (&item.channel_id, item)).collect();
+        let mut delta = CatalogDelta::default();
+        for item in &other.items {
+            match old_map.get(&item.channel_id) {
+                None => { delta.added.push(item.clone()); }
+                Some(old) => {
+                    if *old != item {
+                        delta.changed.push(ChannelChange { old: (*old).clone(), new: item.clone() });
+                    }
+                }
+            }
+        }
+        for item in &self.items {
+            if !new_map.contains_key(&item.channel_id) {
+                delta.removed.push(item.clone());
+            }
+        }
+        delta
+    }
+}
+
+/// 分包目录重组：同一设备同一SN的多个响应累计至SumNum后输出完整目录
+/// 超时未收齐时输出已收到的部分
+pub struct CatalogAssembler {
+    timeout: Duration,
+    //(device_id,sn):(累计目录,截止时间)
+    pending: HashMap<(String, u32), (CatalogSnapshot, Instant)>,
+}
+
+impl CatalogAssembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, pending: HashMap::new() }
+    }
+
+    /// 全局重组：返回本次收齐的目录及已超时的部分目录
+    pub fn assemble(snapshot: CatalogSnapshot) -> Vec<CatalogSnapshot> {
+        let now = Instant::now();
+        let mut guard = CATALOG_ASSEMBLER.lock();
+        let mut res = guard.take_expired(now);
+        if let Some(complete) = guard.push(snapshot, now) {
+            res.push(complete);
+        }
+        res
+    }
+
+    /// 全局取出已超时的部分目录：尾包丢失时不会再有目录消息触发assemble,由定时巡检调用
+    pub fn flush_expired() -> Vec<CatalogSnapshot> {
+        CATALOG_ASSEMBLER.lock().take_expired(Instant::now())
+    }
+
+    //无SN或SumNum时视为完整目录
+    pub fn push(&mut self, snapshot: CatalogSnapshot, now: Instant) -> Option<CatalogSnapshot> {
+        let (sn, sum_num) = match (snapshot.sn, snapshot.sum_num) {
+            (Some(sn), Some(sum_num)) => (sn, sum_num as usize),
+            _ => { return Some(snapshot); }
+        };
+        let key = (snapshot.device_id.clone(), sn);
+        let deadline = now + self.timeout;
+        let (acc, _) = self.pending.entry(key.clone())
+            .or_insert_with(|| (CatalogSnapshot { device_id: snapshot.device_id.clone(), sn: Some(sn), sum_num: Some(sum_num as u32), items: Vec::new() }, deadline));
+        //重传分包时按通道去重
+        for item in snapshot.items {
+            match acc.items.iter_mut().find(|dc| dc.channel_id == item.channel_id) {
+                None => { acc.items.push(item); }
+                Some(dc) => { *dc = item; }
+            }
+        }
+        if acc.items.len() >= sum_num {
+            return self.pending.remove(&key).map(|(acc, _)| acc);
+        }
+        None
+    }
+
+    pub fn take_expired(&mut self, now: Instant) -> Vec<CatalogSnapshot> {
+        let keys: Vec<(String, u32)> = self.pending.iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter().filter_map(|key| self.pending.remove(key)).map(|(acc, _)| acc).collect()
+    }
+}
+
+/// 目录分页：每收到一包即产出,不等待重组
+pub struct CatalogPages;
+
+impl CatalogPages {
+    /// 订阅sn对应目录查询的应答分页;累计通道数达SumNum(无SumNum时收到一包即结束)或超时后结束,结束或丢弃时注销
+    pub fn watch(device_id: &String, sn: u32, timeout: Duration) -> impl Stream<Item=Vec<ChannelItem>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let key = (device_id.clone(), sn);
+        let id = WATCHER_ID.fetch_add(1, Ordering::Relaxed);
+        PAGE_WATCHERS.lock().insert(key.clone(), (id, tx));
+        Self::page_stream(rx, PageWatch { key, id }, time::Instant::now() + timeout)
+    }
+
+    fn page_stream(rx: mpsc::UnboundedReceiver<CatalogSnapshot>, watch: PageWatch, deadline: time::Instant) -> impl Stream<Item=Vec<ChannelItem>> {
+        //(接收端,注销守卫,已收通道,SumNum,是否已收齐)
+        let init = (rx, watch, HashSet::new(), None, false);
+        stream::unfold(init, move |(mut rx, watch, mut seen, sum_num, done): (_, PageWatch, HashSet<String>, Option<usize>, bool)| async move {
+            if done {
+                return None;
+            }
+            let page = time::timeout_at(deadline, rx.recv()).await.ok().flatten()?;
+            let sum_num = page.sum_num.map(|num| num as usize).or(sum_num);
+            seen.extend(page.items.iter().map(|item| item.channel_id.clone()));
+            let done = sum_num.map(|num| seen.len() >= num).unwrap_or(true);
+            Some((page.items, (rx, watch, seen, sum_num, done)))
+        })
+    }
+
+    /// 分包到达时按(device_id,SN)分发;无SN的分包无法匹配查询,不分发
+    pub fn publish(device_id: &String, page: &CatalogSnapshot) {
+        let Some(sn) = page.sn else { return; };
+        let key = (device_id.clone(), sn);
+        let mut guard = PAGE_WATCHERS.lock();
+        if let Some((_, tx)) = guard.get(&key) {
+            if tx.send(page.clone()).is_err() {
+                guard.remove(&key);
+            }
+        }
+    }
+
+    fn watching(device_id: &String, sn: u32) -> bool {
+        PAGE_WATCHERS.lock().contains_key(&(device_id.clone(), sn))
+    }
+}
+
+//分页流结束(收齐、超时)或被丢弃时注销订阅;同键已被新订阅替换时不动
+struct PageWatch {
+    key: (String, u32),
+    id: u64,
+}
+
+impl Drop for PageWatch {
+    fn drop(&mut self) {
+        let mut guard = PAGE_WATCHERS.lock();
+        if guard.get(&self.key).map(|(id, _)| *id == self.id).unwrap_or(false) {
+            guard.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{CatalogAssembler, CatalogFilter, CatalogPages, CatalogSnapshot, ChannelItem, PtzCapabilities};
+
+    fn item(channel_id: &str, name: &str, status: &str) -> ChannelItem {
+        ChannelItem {
+            channel_id: channel_id.to_string(),
+            name: Some(name.to_string()),
+            status: status.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn snapshot(items: Vec<ChannelItem>) -> CatalogSnapshot {
+        CatalogSnapshot { device_id: "34020000001110000001".to_string(), items, ..Default::default() }
+    }
+
+    #[test]
+    fn test_catalog_pages_incremental() {
+        use futures_util::StreamExt;
+        use common::tokio;
+
+        let device_id = "34020000001110000009".to_string();
+        let page = |sn: u32, items: Vec<ChannelItem>| CatalogSnapshot { device_id: device_id.clone(), sn: Some(sn), sum_num: Some(3), items };
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let mut pages = Box::pin(CatalogPages::watch(&device_id, 1, Duration::from_secs(5)));
+            //其他查询(SN不同)的分包不分发
+            CatalogPages::publish(&device_id, &page(9, vec![item("34020000001320000109", "Camera 09", "ON")]));
+            CatalogPages::publish(&device_id, &page(1, vec![item("34020000001320000101", "Camera 01", "ON"), item("34020000001320000102", "Camera 02", "ON")]));
+            assert_eq!(pages.next().await.unwrap().len(), 2);
+            CatalogPages::publish(&device_id, &page(1, vec![item("34020000001320000103", "Camera 03", "OFF")]));
+            let second = pages.next().await.unwrap();
+            assert_eq!(second[0].channel_id, "34020000001320000103");
+            //达SumNum结束并注销
+            assert!(pages.next().await.is_none());
+            assert!(!CatalogPages::watching(&device_id, 1));
+
+            //尾包未到：超时结束并注销
+            let mut pages = Box::pin(CatalogPages::watch(&device_id, 2, Duration::from_millis(50)));
+            CatalogPages::publish(&device_id, &page(2, vec![item("34020000001320000101", "Camera 01", "ON")]));
+            assert_eq!(pages.next().await.unwrap().len(), 1);
+            assert!(pages.next().await.is_none());
+            assert!(!CatalogPages::watching(&device_id, 2));
+
+            //未取完即丢弃：注销
+            let pages = CatalogPages::watch(&device_id, 3, Duration::from_secs(5));
+            assert!(CatalogPages::watching(&device_id, 3));
+            drop(pages);
+            assert!(!CatalogPages::watching(&device_id, 3));
+        });
+    }
+
+    #[test]
+    fn test_catalog_filter_fallback() {
+        let catalog = snapshot(vec![
+            item("34020000001320000101", "Camera 01", "ON"),
+            item("34020000001320000102", "Camera 02", "OFF"),
+            item("34020000001310000103", "Camera 03", "ONLINE"),
+            item("34020000002150000001", "Group", "ON"),
+        ]);
+        assert_eq!(CatalogFilter::default().apply(catalog.clone()).items.len(), 4);
+        let online = CatalogFilter { online: Some(true), ..Default::default() }.apply(catalog.clone());
+        assert_eq!(online.items.iter().map(|i| i.channel_id.as_str()).collect::<Vec<_>>(),
+                   vec!["34020000001320000101", "34020000001310000103", "34020000002150000001"]);
+        let offline = CatalogFilter { online: Some(false), ..Default::default() }.apply(catalog.clone());
+        assert_eq!(offline.items.len(), 1);
+        let ipc_online = CatalogFilter { online: Some(true), type_code: Some("132".to_string()) }.apply(catalog);
+        assert_eq!(ipc_online.items.len(), 1);
+        assert_eq!(ipc_online.items[0].channel_id, "34020000001320000101");
+    }
+
+    #[test]
+    fn test_parse_catalog() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>Catalog</CmdType>
+<SN>17430</SN>
+<DeviceID>34020000001110000001</DeviceID>
+<SumNum>2</SumNum>
+<DeviceList Num="2">
+<Item>
+<DeviceID>34020000001320000101</DeviceID>
+<Name>Camera 01</Name>
+<Manufacturer>Hikvision</Manufacturer>
+<ParentID>34020000001110000001</ParentID>
+<Status>ON</Status>
+<Info>
+<PTZType>1</PTZType>
+</Info>
+</Item>
+<Item>
+<DeviceID>34020000001320000102</DeviceID>
+<Name>Camera 02</Name>
+<Status>OFF</Status>
+</Item>
+</DeviceList>
+</Response>
+"#;
+        let snapshot = CatalogSnapshot::parse(xml.as_bytes()).unwrap();
+        assert_eq!(snapshot.device_id, "34020000001110000001");
+        assert_eq!(snapshot.sn, Some(17430));
+        assert_eq!(snapshot.sum_num, Some(2));
+        assert_eq!(snapshot.items.len(), 2);
+        assert_eq!(snapshot.items[0].channel_id, "34020000001320000101");
+        assert_eq!(snapshot.items[0].ptz_type, Some(1));
+        assert!(snapshot.items[0].ptz_caps.unwrap().pan);
+        assert_eq!(snapshot.items[1].ptz_caps, None);
+        assert_eq!(snapshot.items[0].status, "ON");
+        assert_eq!(snapshot.items[1].name.as_deref(), Some("Camera 02"));
+        assert_eq!(snapshot.items[1].status, "OFF");
+        assert!(snapshot.items[0].extensions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_catalog_extensions() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>Catalog</CmdType>
+<SN>17431</SN>
+<DeviceID>34020000001110000001</DeviceID>
+<SumNum>1</SumNum>
+<DeviceList Num="1">
+<Item>
+<DeviceID>34020000001320000101</DeviceID>
+<Name>Camera 01</Name>
+<Status>ON</Status>
+<HikExt>
+<Level>3</Level>
+</HikExt>
+<StreamNumberList>0/1</StreamNumberList>
+</Item>
+</DeviceList>
+</Response>
+"#;
+        let snapshot = CatalogSnapshot::parse(xml.as_bytes()).unwrap();
+        let ext = &snapshot.items[0].extensions;
+        assert_eq!(ext.get("HikExt,Level").map(|s| s.as_str()), Some("3"));
+        assert_eq!(ext.get("StreamNumberList").map(|s| s.as_str()), Some("0/1"));
+        assert_eq!(ext.len(), 2);
+        assert_eq!(snapshot.items[0].status, "ON");
+    }
+
+    #[test]
+    fn test_ptz_caps_from_ptz_type() {
+        let ball = PtzCapabilities::from_ptz_type(1).unwrap();
+        assert!(ball.pan && ball.tilt && ball.zoom && ball.focus && ball.iris && ball.preset);
+        let dome = PtzCapabilities::from_ptz_type(2).unwrap();
+        assert!(!dome.pan && !dome.tilt && dome.zoom);
+        //固定枪机无任何云台能力
+        assert!(!PtzCapabilities::from_ptz_type(3).unwrap().has_any());
+        assert_eq!(PtzCapabilities::from_ptz_type(4), Some(dome));
+        assert_eq!(PtzCapabilities::from_ptz_type(0), None);
+
+        let mut fixed = item("34020000001320000201", "fixed", "ON");
+        fixed.ptz_type = Some(3);
+        fixed.ptz_caps = PtzCapabilities::from_ptz_type(3);
+        CatalogSnapshot { device_id: "34020000001110000002".to_string(), items: vec![fixed], ..Default::default() }.remember();
+        let cached = CatalogSnapshot::last_item("34020000001110000002", "34020000001320000201").unwrap();
+        assert_eq!(cached.ptz_caps, Some(PtzCapabilities::default()));
+    }
+
+    #[test]
+    fn test_diff_added_removed() {
+        let old = snapshot(vec![item("1", "a", "ON"), item("2", "b", "ON")]);
+        let new = snapshot(vec![item("2", "b", "ON"), item("3", "c", "ON")]);
+        let delta = old.diff(&new);
+        assert_eq!(delta.added.len(), 1);
+        assert_eq!(delta.added[0].channel_id, "3");
+        assert_eq!(delta.removed.len(), 1);
+        assert_eq!(delta.removed[0].channel_id, "1");
+        assert!(delta.changed.is_empty());
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn test_diff_status_only() {
+        let old = snapshot(vec![item("1", "a", "ON")]);
+        let new = snapshot(vec![item("1", "a", "OFF")]);
+        let delta = old.diff(&new);
+        assert_eq!(delta.changed.len(), 1);
+        assert!(delta.changed[0].is_status_only());
+        assert_eq!(delta.changed[0].new.status, "OFF");
+    }
+
+    #[test]
+    fn test_diff_name_changed() {
+        let old = snapshot(vec![item("1", "a", "ON")]);
+        let renamed = snapshot(vec![item("1", "a-new", "ON")]);
+        let delta = old.diff(&renamed);
+        assert_eq!(delta.changed.len(), 1);
+        assert!(!delta.changed[0].is_status_only());
+
+        let both = snapshot(vec![item("1", "a-new", "OFF")]);
+        let delta = old.diff(&both);
+        assert!(!delta.changed[0].is_status_only());
+    }
+
+    fn fragment(sn: u32, sum_num: u32, items: Vec<ChannelItem>) -> CatalogSnapshot {
+        CatalogSnapshot { sn: Some(sn), sum_num: Some(sum_num), ..snapshot(items) }
+    }
+
+    #[test]
+    fn test_assemble_three_fragments() {
+        let mut assembler = CatalogAssembler::new(Duration::from_secs(30));
+        let now = Instant::now();
+        assert!(assembler.push(fragment(7, 5, vec![item("1", "a", "ON"), item("2", "b", "ON")]), now).is_none());
+        assert!(assembler.push(fragment(7, 5, vec![item("3", "c", "ON"), item("4", "d", "OFF")]), now).is_none());
+        //其他SN互不影响
+        assert!(assembler.push(fragment(8, 2, vec![item("9", "z", "ON")]), now).is_none());
+        let complete = assembler.push(fragment(7, 5, vec![item("5", "e", "ON")]), now).unwrap();
+        assert_eq!(complete.sn, Some(7));
+        assert_eq!(complete.items.len(), 5);
+        assert_eq!(complete.items[4].channel_id, "5");
+        assert!(assembler.take_expired(now).is_empty());
+    }
+
+    #[test]
+    fn test_assemble_timeout_flush() {
+        let mut assembler = CatalogAssembler::new(Duration::from_secs(30));
+        let now = Instant::now();
+        assert!(assembler.push(fragment(7, 3, vec![item("1", "a", "ON")]), now).is_none());
+        //重传分包不重复计数
+        assert!(assembler.push(fragment(7, 3, vec![item("1", "a", "ON")]), now).is_none());
+        assert!(assembler.take_expired(now + Duration::from_secs(10)).is_empty());
+        let partial = assembler.take_expired(now + Duration::from_secs(30));
+        assert_eq!(partial.len(), 1);
+        assert_eq!(partial[0].items.len(), 1);
+        assert!(assembler.take_expired(now + Duration::from_secs(60)).is_empty());
+    }
+}
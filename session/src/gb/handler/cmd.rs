@@ -1,41 +1,408 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
 
+use futures_util::{stream, Stream, StreamExt};
+use parking_lot::{Mutex, RwLock};
 use regex::Regex;
 use rsip::prelude::{HeadersExt, UntypedHeader};
-use rsip::{Response, SipMessage};
+use rsip::{Request, Response, SipMessage};
 
+use common::chrono::Local;
 use common::exception::{GlobalError, GlobalResult, TransError};
-use common::log::{debug, error, warn};
-use common::tokio::sync::mpsc;
+use common::bytes::Bytes;
+use common::net::state::{Association, Package, Protocol, Zip};
+use common::log::{debug, error, info, log, warn, Level};
+use common::once_cell::sync::Lazy;
+use common::serde::Deserialize;
+use common::tokio::sync::{mpsc, oneshot};
+use common::tokio::sync::mpsc::Sender;
+use common::tokio::time;
 use common::tokio::time::Instant;
 
 use crate::gb::handler::builder::{RequestBuilder, ResponseBuilder};
-use crate::gb::shared::event::{Container, EventSession, Ident};
-use crate::gb::shared::rw::RequestOutput;
-use crate::general::model::{PtzControlModel, StreamMode};
+use crate::gb::handler::alarm::AlarmRecord;
+use crate::gb::handler::parser;
+use crate::gb::handler::catalog::{ASSEMBLE_TIMEOUT, FLUSH_INTERVAL, CatalogFilter, CatalogPages, CatalogSnapshot, ChannelItem, PtzCapabilities};
+use crate::gb::handler::limiter::CmdLimiter;
+use crate::gb::handler::ptz::{PTZ_STOP_ALL_CONCURRENCY, PtzDebouncer, PtzTracker};
+use crate::gb::handler::osd::OsdConfig;
+use crate::gb::handler::profile::StreamProfile;
+use crate::gb::handler::position::{PositionPoint, PositionSubscription};
+use crate::gb::handler::quirks::DeviceQuirks;
+use crate::gb::handler::record::{RecordInfo, RecordSegment};
+use crate::gb::handler::snapshot::{SnapshotHandle, SnapshotReceiver, SnapshotUri};
+use crate::gb::handler::subscription::{RenewalPacer, SubscriptionState, SubscriptionTerminated};
+use crate::gb::handler::status::{DeviceClock, DeviceInfo, DeviceStatus, StorageStatus};
+use crate::gb::shared::dialog::{Dialog, DialogSession, DialogState, StreamKey, StreamMedia};
+pub use crate::gb::shared::dialog::ActiveStream;
+use crate::gb::shared::event::{Container, EventSession, Ident, TapDirection};
+use crate::gb::shared::reply::ReplySession;
+use crate::gb::shared::rw::{RequestOutput, RWSession};
+use crate::general::model::{InviteOption, InviteRequest, PtzControlModel, SrtpCrypto, SsrcCheck, StreamMode, WebStreamDescriptor};
+use crate::gb::SessionConf;
+use crate::general::{RtpPortConf, SipTimerConf};
+use crate::storage::entity::GmvDeviceExt;
+use crate::utils::se_token;
+use crate::gb::handler::error_code::CmdErrorCode;
 
 pub struct CmdResponse;
 
+/// 对设备请求的常规自动应答
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AutoReply {
+    //200 OK：注册成功、保活等MESSAGE、目录等NOTIFY、BYE
+    Ok,
+    //200 OK(带Date)：注销
+    LogoutOk,
+    //401(带WWW-Authenticate)：未鉴权的注册
+    Challenge,
+    //401：注册已过期,需重新注册
+    Expired,
+}
+
+//最近一次目录NOTIFY：device_id:(NOTIFY,对话内前次re-SUBSCRIBE的CSeq),供poke_catalog_subscription刷新订阅
+static LAST_CATALOG_NOTIFY: Lazy<Mutex<HashMap<String, (Request, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+//设备发起会话中等待ACK的200 OK：call_id:通知
+static PENDING_ACK: Lazy<Mutex<HashMap<String, oneshot::Sender<()>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl CmdResponse {
+    pub async fn ok(req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        Self::reply(AutoReply::Ok, req, tx, bill).await
+    }
+
+    pub async fn logout_ok(req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        Self::reply(AutoReply::LogoutOk, req, tx, bill).await
+    }
+
+    pub async fn challenge(req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        Self::reply(AutoReply::Challenge, req, tx, bill).await
+    }
+
+    pub async fn expired(req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        Self::reply(AutoReply::Expired, req, tx, bill).await
+    }
+
+    /// 构建应答并经请求来源的网络出口下发;发送失败仅记录
+    pub async fn reply(kind: AutoReply, req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        let response = Self::build(kind, req, bill.get_remote_addr())?;
+        Self::send_reply(response, req, tx, bill).await
+    }
+
+    /// 应答目录NOTIFY并留存该NOTIFY,平台停止推送时可经poke_catalog_subscription在订阅对话内刷新
+    pub async fn catalog_notify_ok(device_id: &String, req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        let response = Self::build(AutoReply::Ok, req, bill.get_remote_addr())?;
+        Self::remember_catalog_notify(device_id, req);
+        Self::send_reply(response, req, tx, bill).await
+    }
+
+    //同一订阅对话的新NOTIFY沿用已用的CSeq
+    fn remember_catalog_notify(device_id: &String, notify: &Request) {
+        let call_id = notify.call_id_header().ok().map(|call_id| call_id.value().to_string());
+        let mut guard = LAST_CATALOG_NOTIFY.lock();
+        let seq = guard.get(device_id)
+            .filter(|(last, _)| last.call_id_header().ok().map(|call_id| call_id.value().to_string()) == call_id)
+            .map(|(_, seq)| *seq)
+            .unwrap_or(0);
+        guard.insert(device_id.clone(), (notify.clone(), seq));
+    }
+
+    //取最近NOTIFY并分配对话内下一个CSeq：不小于当前时间戳,高于本端初始SUBSCRIBE的随机CSeq
+    fn next_catalog_resubscribe(device_id: &String) -> Option<(Request, u32)> {
+        let mut guard = LAST_CATALOG_NOTIFY.lock();
+        let (notify, seq) = guard.get_mut(device_id)?;
+        *seq = CmdStream::resync_seq(*seq, Local::now().timestamp());
+        Some((notify.clone(), *seq))
+    }
+
+    /// 请求Require了本端不支持的扩展：应答420并返回true,调用方不再处理该请求
+    pub async fn reject_unsupported(req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<bool> {
+        let unsupported = parser::header::unsupported_required(req, SessionConf::supported_tags());
+        if unsupported.is_empty() {
+            return Ok(false);
+        }
+        warn!("请求Require不支持的扩展: {unsupported:?},应答420");
+        let response = ResponseBuilder::build_bad_extension_response(req, bill.get_remote_addr(), &unsupported)?;
+        Self::send_reply(response, req, tx, bill).await?;
+        Ok(true)
+    }
+
+    async fn send_reply(response: SipMessage, req: &Request, tx: &Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        if let Ok(device_id) = parser::header::get_device_id_by_request(req) {
+            EventSession::tap(&device_id, TapDirection::Outbound, &response);
+        }
+        let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(response)));
+        let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+        Ok(())
+    }
+
+    /// 应答沿用请求的Via(补rport/received)、From、To(补tag)、Call-ID、CSeq
+    pub fn build(kind: AutoReply, req: &Request, socket_addr: &SocketAddr) -> GlobalResult<SipMessage> {
+        match kind {
+            AutoReply::Ok => ResponseBuilder::build_register_ok_response(req, socket_addr),
+            AutoReply::LogoutOk => ResponseBuilder::build_logout_ok_response(req, socket_addr),
+            AutoReply::Challenge => ResponseBuilder::unauthorized_register_response(req, socket_addr),
+            AutoReply::Expired => ResponseBuilder::build_401_response(req, socket_addr),
+        }
+    }
+
+    /// 应答设备发起的INVITE(语音广播/对讲)：以本端SDP回200 OK,收到ACK后登记对话
+    /// UDP下未收到ACK前按T1倍增间隔重传200 OK(RFC3261 13.3.1.4),事务超时仍未收到则返回错误
+    pub async fn answer_invite(req: &Request, our_sdp: &str) -> GlobalResult<(Dialog, oneshot::Receiver<Dialog>)> {
+        let device_id = parser::header::get_device_id_by_request(req)?;
+        let bill = RWSession::get_bill_by_device_id(&device_id)
+            .ok_or_else(|| GlobalError::new_sys_error(&format!("设备：{device_id}，未注册或已离线"), |msg| warn!("{msg}")))?;
+        let ok = ResponseBuilder::build_invite_ok_response(req, bill.get_remote_addr(), our_sdp)?;
+        let (call_id, device_tag, local_tag) = match &ok {
+            SipMessage::Response(res) => (
+                res.call_id_header().hand_log(|msg| warn!("{msg}"))?.value().to_string(),
+                ResponseBuilder::get_tag_by_header_from(res)?,
+                ResponseBuilder::get_tag_by_header_to(res)?,
+            ),
+            SipMessage::Request(_) => unreachable!(),
+        };
+        let (tx, mut rx) = oneshot::channel();
+        PENDING_ACK.lock().insert(call_id.clone(), tx);
+        let timer = SipTimerConf::get_sip_timer_conf();
+        let intervals = if &Protocol::UDP == bill.get_protocol() { timer.retransmit_intervals(true) } else { Vec::new() };
+        let acked = Self::await_ack(&device_id, ok, &mut rx, intervals, timer.transaction_timeout(true)).await;
+        PENDING_ACK.lock().remove(&call_id);
+        if !acked? {
+            return Err(CmdErrorCode::AckTimeout.error(&format!("设备未确认200 OK: device_id = {device_id},call_id = {call_id}")));
+        }
+        let channel_id = parser::header::get_channel_id(req).unwrap_or_else(|| device_id.clone());
+        let dialog = Dialog::new(device_id, channel_id, call_id, local_tag, device_tag);
+        let bye_rx = DialogSession::insert(dialog.clone());
+        Ok((dialog, bye_rx))
+    }
+
+    //下发200 OK并等待ACK,按intervals重传
+    async fn await_ack(device_id: &String, ok: SipMessage, rx: &mut oneshot::Receiver<()>, intervals: Vec<Duration>, timeout: Duration) -> GlobalResult<bool> {
+        let deadline = Instant::now() + timeout;
+        RequestOutput::do_send_off(device_id, ok.clone())?;
+        for interval in intervals {
+            if let Ok(acked) = time::timeout_at(deadline.min(Instant::now() + interval), &mut *rx).await {
+                return Ok(acked.is_ok());
+            }
+            let _ = RequestOutput::do_send_off(device_id, ok.clone()).hand_log(|msg| warn!("{msg}"));
+        }
+        Ok(time::timeout_at(deadline, rx).await.map(|acked| acked.is_ok()).unwrap_or(false))
+    }
+
+    /// 设备ACK：匹配到等待确认的200 OK时返回true
+    pub fn on_ack(call_id: &str) -> bool {
+        PENDING_ACK.lock().remove(call_id).map(|tx| tx.send(()).is_ok()).unwrap_or(false)
+    }
+}
+
+//设备发现整体超时
+const DISCOVER_TIMEOUT: Duration = Duration::from_secs(10);
+//目录应答等待：须覆盖分包重组超时与巡检间隔,尾包丢失时仍能取得部分目录
+const CATALOG_TIMEOUT: Duration = Duration::from_secs(ASSEMBLE_TIMEOUT.as_secs() + 2 * FLUSH_INTERVAL.as_secs());
+//SUBSCRIBE等待最终应答
+const SUBSCRIBE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+//INVITE 3xx重定向最大跳数,防止重定向环路
+const MAX_REDIRECT_HOPS: usize = 2;
+
+/// 设备发现结果：超时未返回的部分为None,complete为false
+#[derive(Debug, Clone, Default)]
+pub struct DeviceDiscovery {
+    pub info: Option<GmvDeviceExt>,
+    pub catalog: Option<CatalogSnapshot>,
+    pub complete: bool,
+}
+
 pub struct CmdQuery;
 
 impl CmdQuery {
     pub async fn query_preset(device_id: &String, channel_id_opt: Option<&String>) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
         let (ident, msg) = RequestBuilder::query_preset(device_id, channel_id_opt).await?;
-        RequestOutput::new(ident, msg, None).do_send()
+        RequestOutput::new(ident, msg, None).do_send().await
     }
     pub async fn query_device_info(device_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
         let (ident, msg) = RequestBuilder::query_device_info(device_id).await?;
-        RequestOutput::new(ident, msg, None).do_send()
+        RequestOutput::new(ident, msg, None).do_send().await
     }
     pub async fn query_device_catalog(device_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
         let (ident, msg) = RequestBuilder::query_device_catalog(device_id).await?;
-        RequestOutput::new(ident, msg, None).do_send()
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    pub async fn query_device_status(device_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_device_status(device_id, None).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 通道状态查询：请求目标为通道DeviceID;不区分通道的设备以设备级状态应答,见DeviceStatus::parse_channel
+    pub async fn query_channel_status(device_id: &String, channel_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_device_status(device_id, Some(channel_id)).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 存储卡状态查询：结果由设备以 SDCardStatus 响应返回,见StorageStatus
+    pub async fn query_storage_status(device_id: &String, channel_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_storage_status(device_id, Some(channel_id)).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 移动设备历史轨迹查询(st/et秒级时间戳)：结果由设备以 MobilePosition 响应返回,见PositionPoint
+    pub async fn query_position_track(device_id: &String, channel_id: &String, st: u32, et: u32) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_position_track(device_id, channel_id, st, et).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// OSD配置查询：结果由设备以 ConfigDownload 响应返回,见OsdConfig
+    pub async fn query_osd(device_id: &String, channel_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_osd(device_id, channel_id).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 码流能力查询：结果由设备以 ConfigDownload 响应返回,见StreamProfile;收到后按通道缓存
+    pub async fn query_stream_profiles(device_id: &String, channel_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_stream_profiles(device_id, channel_id).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 报警查询：优先级/时间/报警方式均为可选过滤条件；结果由设备以 Alarm 响应返回
+    pub async fn query_alarm(device_id: &String, start_alarm_priority: Option<u8>, end_alarm_priority: Option<u8>, st: Option<u32>, et: Option<u32>, alarm_method: Option<&String>) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_alarm(device_id, start_alarm_priority, end_alarm_priority, st, et, alarm_method).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 录像检索并等待应答(分包时为首包)：st/et秒级时间戳
+    pub async fn query_record_info(device_id: &String, channel_id: &String, st: u32, et: u32) -> GlobalResult<RecordInfo> {
+        let record_rx = ReplySession::wait_record_info(channel_id);
+        {
+            let _permit = CmdLimiter::admit().await?;
+            let (ident, msg) = RequestBuilder::query_record_info(device_id, channel_id, st, et).await?;
+            RequestOutput::new(ident, msg, None).do_send().await?;
+        }
+        Self::await_record_info(record_rx).await
+    }
+    /// 回放前置检查：区间内是否有录像;查询失败(如超时)返回错误,由调用方提示回放不可用
+    pub async fn has_recordings(device_id: &String, channel_id: &String, st: u32, et: u32) -> GlobalResult<bool> {
+        Self::query_record_info(device_id, channel_id, st, et).await.map(|info| info.has_segments())
+    }
+
+    async fn await_record_info(record_rx: oneshot::Receiver<RecordInfo>) -> GlobalResult<RecordInfo> {
+        match time::timeout(DISCOVER_TIMEOUT, record_rx).await {
+            Ok(Ok(info)) => Ok(info),
+            _ => Err(CmdErrorCode::ResponseTimeout.warn("录像检索未响应或超时")),
+        }
     }
     pub async fn subscribe_device_catalog(device_id: &String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
         let (ident, msg) = RequestBuilder::subscribe_device_catalog(device_id).await?;
-        RequestOutput::new(ident, msg, None).do_send()
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+    /// 目录订阅并等待SUBSCRIBE 200确认;返回对端实际授予的Expires(可能低于请求值)
+    pub async fn subscribe_device_catalog_confirmed(device_id: &String, expires: u32) -> GlobalResult<u32> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::subscribe_device_catalog_expires(device_id, expires).await?;
+        let (tx, rx) = mpsc::channel(10);
+        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send().await?;
+        let res = Self::await_subscribe_confirm(rx, expires, Instant::now() + SUBSCRIBE_CONFIRM_TIMEOUT).await;
+        EventSession::remove_event(&ident);
+        res
+    }
+
+    //跳过1xx;2xx未带Expires时视为按请求值授予
+    async fn await_subscribe_confirm(mut rx: mpsc::Receiver<(Option<Response>, Instant)>, expires: u32, deadline: Instant) -> GlobalResult<u32> {
+        loop {
+            match time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some((Some(res), _))) => {
+                    let code = res.status_code.code();
+                    if code < 200 {
+                        continue;
+                    }
+                    if code >= 300 {
+                        return Err(CmdErrorCode::SubscribeRejected.warn(&format!("订阅被拒绝: {}", res.status_code)));
+                    }
+                    let granted = res.headers.iter().find_map(|header| match header {
+                        rsip::Header::Expires(value) => value.value().trim().parse::<u32>().ok(),
+                        _ => None,
+                    });
+                    return Ok(granted.unwrap_or(expires));
+                }
+                _ => return Err(CmdErrorCode::ResponseTimeout.warn("订阅未响应或超时")),
+            }
+        }
     }
+
+    /// 并行查询设备信息与目录,在同一超时(覆盖分包重组超时)内等待两者的应答;
+    /// 一项下发失败时仍返回另一项的结果,均失败时返回错误
+    pub async fn discover_device(device_id: &String) -> GlobalResult<DeviceDiscovery> {
+        let deadline = Instant::now() + CATALOG_TIMEOUT;
+        let info_rx = ReplySession::wait_device_info(device_id);
+        let catalog_rx = ReplySession::wait_catalog(device_id);
+        let (info_sent, catalog_sent) = common::tokio::join!(Self::query_device_info(device_id), Self::query_device_catalog(device_id));
+        let (info_rx, catalog_rx) = match (info_sent, catalog_sent) {
+            (Err(err), Err(_)) => return Err(err),
+            (info_sent, catalog_sent) => (info_sent.ok().map(|_| info_rx), catalog_sent.ok().map(|_| catalog_rx)),
+        };
+        let discovery = Self::await_discovery(info_rx, catalog_rx, deadline).await;
+        if !discovery.complete {
+            warn!("device_id = {device_id}: 设备发现超时,info = {},catalog = {}", discovery.info.is_some(), discovery.catalog.is_some());
+        }
+        Ok(discovery)
+    }
+
+    //None：该项下发失败,不再等待其应答
+    async fn await_discovery(info_rx: Option<oneshot::Receiver<GmvDeviceExt>>, catalog_rx: Option<oneshot::Receiver<CatalogSnapshot>>, deadline: Instant) -> DeviceDiscovery {
+        let (info, catalog) = common::tokio::join!(Self::await_reply(info_rx, deadline), Self::await_reply(catalog_rx, deadline));
+        let complete = info.is_some() && catalog.is_some();
+        DeviceDiscovery { info, catalog, complete }
+    }
+
+    async fn await_reply<T>(rx: Option<oneshot::Receiver<T>>, deadline: Instant) -> Option<T> {
+        match rx {
+            Some(rx) => time::timeout_at(deadline, rx).await.ok().and_then(|res| res.ok()),
+            None => None,
+        }
+    }
+
+    /// 按条件查询目录并等待应答;平台未按条件过滤时本地过滤,见CatalogFilter
+    pub async fn query_catalog_filtered(device_id: &String, filter: &CatalogFilter) -> GlobalResult<CatalogSnapshot> {
+        let catalog_rx = ReplySession::wait_catalog(device_id);
+        {
+            let _permit = CmdLimiter::admit().await?;
+            let (ident, msg) = RequestBuilder::query_device_catalog_filtered(device_id, filter).await?;
+            RequestOutput::new(ident, msg, None).do_send().await?;
+        }
+        match time::timeout(CATALOG_TIMEOUT, catalog_rx).await {
+            Ok(Ok(snapshot)) => Ok(filter.apply(snapshot)),
+            _ => Err(CmdErrorCode::ResponseTimeout.warn("目录查询未响应或超时")),
+        }
+    }
+
+    /// 查询目录并按分包逐页返回(按查询SN匹配应答),完整目录仍经CatalogSnapshot重组输出
+    pub async fn query_catalog_pages(device_id: &String) -> GlobalResult<impl Stream<Item=Vec<ChannelItem>>> {
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::query_device_catalog(device_id).await?;
+        let pages = CatalogPages::watch(device_id, Self::query_sn(&msg)?, ASSEMBLE_TIMEOUT);
+        RequestOutput::new(ident, msg, None).do_send().await?;
+        Ok(pages)
+    }
+
+    //查询请求体中的SN
+    fn query_sn(msg: &SipMessage) -> GlobalResult<u32> {
+        parser::xml::parse_xlm_to_vec(msg.body())?.into_iter()
+            .find(|(k, _)| k == parser::xml::QUERY_SN)
+            .and_then(|(_, v)| v.parse::<u32>().ok())
+            .ok_or_else(|| CmdErrorCode::InvalidParam.error("目录查询缺少SN"))
+    }
+
+    /// 通道云台能力(取最近一次目录);目录未含该通道或PTZType未知时为None
+    pub fn channel_ptz_caps(device_id: &String, channel_id: &String) -> Option<PtzCapabilities> {
+        CatalogSnapshot::last_item(device_id, channel_id).and_then(|item| item.ptz_caps)
+    }
+
     pub async fn lazy_query_device_info(device_id: &String) -> GlobalResult<()> {
         let (ident, msg) = RequestBuilder::query_device_info(device_id).await?;
         let when = Instant::now() + Duration::from_secs(2);
@@ -52,154 +419,2949 @@ impl CmdQuery {
         let when = Instant::now() + Duration::from_secs(2);
         EventSession::listen_event(&ident.clone(), when, Container::build_actor(ident, msg, None))
     }
+
+    /// 订阅移动设备位置：expires为订阅时长,interval为上报间隔(秒,不超过expires)
+    /// 返回的订阅流接收位置通知,丢弃时发送退订(Expires为0)
+    pub async fn subscribe_mobile_position(device_id: &String, channel_id: &String, expires: u32, interval: u32) -> GlobalResult<PositionSubscription> {
+        PositionSubscription::check(expires, interval)?;
+        let _permit = CmdLimiter::admit().await?;
+        let (ident, msg) = RequestBuilder::subscribe_mobile_position(device_id, channel_id, expires, interval).await?;
+        RequestOutput::new(ident, msg, None).do_send().await?;
+        let (device, channel) = (device_id.clone(), channel_id.clone());
+        let unsubscribe = move || {
+            if let Ok(handle) = common::tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    let _ = Self::unsubscribe_mobile_position(&device, &channel, interval).await.hand_log(|msg| warn!("位置退订失败: {msg}"));
+                });
+            }
+        };
+        Ok(PositionSubscription::watch(device_id, channel_id, Some(Box::new(unsubscribe))))
+    }
+
+    async fn unsubscribe_mobile_position(device_id: &String, channel_id: &String, interval: u32) -> GlobalResult<()> {
+        let (ident, msg) = RequestBuilder::subscribe_mobile_position(device_id, channel_id, 0, interval).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+
+    /// 唤醒停止推送的目录订阅：沿最近一次目录NOTIFY的订阅对话发送re-SUBSCRIBE;尚无NOTIFY时重新订阅
+    pub async fn poke_catalog_subscription(device_id: &String) -> GlobalResult<CatalogPoke> {
+        match CmdResponse::next_catalog_resubscribe(device_id) {
+            Some((notify, seq)) => {
+                let _permit = CmdLimiter::admit().await?;
+                let (ident, msg) = RequestBuilder::build_resubscribe_by_notify(device_id, &notify, seq)?;
+                RequestOutput::new(ident, msg, None).do_send().await?;
+                Ok(CatalogPoke::Refresh)
+            }
+            None => {
+                Self::subscribe_device_catalog(device_id).await?;
+                Ok(CatalogPoke::Resubscribe)
+            }
+        }
+    }
+
+    /// 平台终止订阅(Subscription-State: terminated)：可重试时按retry-after排定重新订阅,否则上报SubscriptionTerminated
+    pub fn on_subscription_terminated(device_id: &String, kind: &str, state: &SubscriptionState) -> Option<Duration> {
+        let (device, resubscribe_kind) = (device_id.clone(), kind.to_string());
+        SubscriptionTerminated::on_terminated(device_id, kind, state, move |delay| {
+            if let Ok(handle) = common::tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    let _ = Self::schedule_resubscribe(&device, &resubscribe_kind, delay).await.hand_log(|msg| warn!("重新订阅失败: {msg}"));
+                });
+            }
+        })
+    }
+
+    async fn schedule_resubscribe(device_id: &String, kind: &str, delay: Duration) -> GlobalResult<()> {
+        let (ident, msg) = match kind {
+            parser::xml::MESSAGE_ALARM => RequestBuilder::subscribe_alarm(device_id).await?,
+            _ => RequestBuilder::subscribe_device_catalog(device_id).await?,
+        };
+        EventSession::listen_event(&ident.clone(), Instant::now() + delay, Container::build_actor(ident, msg, None))
+    }
+
+    /// 按设备相位排定下次目录订阅续订(见RenewalPacer),设备离线时随延迟事件一并取消
+    pub async fn schedule_catalog_renewal(device_id: &String) -> GlobalResult<Duration> {
+        let expires = RWSession::get_expires_by_device_id(device_id).ok_or_else(|| CmdErrorCode::InvalidParam.warn(&format!("device id = [{device_id}] 未知设备")))?;
+        let delay = RenewalPacer::next_delay_now(device_id, expires);
+        let (ident, msg) = RequestBuilder::subscribe_device_catalog(device_id).await?;
+        EventSession::listen_event(&ident.clone(), Instant::now() + delay, Container::build_actor(ident, msg, None))?;
+        Ok(delay)
+    }
 }
 
 pub struct CmdControl;
 
 impl CmdControl {
     pub async fn control_ptz(ptz_control_model: &PtzControlModel) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
         let (ident, msg) = RequestBuilder::control_ptz(ptz_control_model).await?;
-        RequestOutput::new(ident, msg, None).do_send()
+        RequestOutput::new(ident, msg, None).do_send().await?;
+        info!("云台控制: device_id = {},channel_id = {},{ptz_control_model}", ptz_control_model.deviceId, ptz_control_model.channelId);
+        PtzTracker::record_global(ptz_control_model);
+        Ok(())
+    }
+
+    /// 急停：对最近下发过移动指令的全部通道下发停止,返回下发成功数
+    pub async fn stop_all_ptz() -> usize {
+        let keys = PtzTracker::take_active_global();
+        PtzTracker::stop_each(keys, PTZ_STOP_ALL_CONCURRENCY, |model| async move { Self::control_ptz(&model).await }).await
     }
 
-    pub async fn snapshot_image(device_id: &String, channel_id: &String, num: u8, interval: u8, uri: &String, session_id: &String) -> GlobalResult<()> {
+    /// 设置通道标题OSD及时间叠加;文字超长时拒绝下发
+    pub async fn set_osd(device_id: &String, channel_id: &String, text: &str, show_time: bool) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        OsdConfig::check_text(text)?;
+        let (ident, msg) = RequestBuilder::control_osd(device_id, channel_id, text, show_time).await?;
+        RequestOutput::new(ident, msg, None).do_send().await
+    }
+
+    /// 高频云台输入(如摇杆)：按通道合并后下发,输入停止后自动下发停止指令
+    pub fn control_ptz_debounced(ptz_control_model: PtzControlModel) {
+        PtzDebouncer::submit_global(ptz_control_model);
+    }
+
+    /// 抓图：下发后返回句柄,可等待设备上传完成通知;不关心结果时丢弃即可
+    /// 上传地址须经SnapshotUri校验(协议http/https/ftp)
+    pub async fn snapshot_image(device_id: &String, channel_id: &String, num: u8, interval: u8, uri: &SnapshotUri, session_id: &String) -> GlobalResult<SnapshotHandle> {
+        let _permit = CmdLimiter::admit().await?;
+        let handle = SnapshotHandle::register(session_id.clone(), num);
         let (ident, msg) = RequestBuilder::control_snapshot_image(device_id, channel_id, num, interval, uri, session_id).await?;
-        RequestOutput::new(ident, msg, None).do_send()
+        RequestOutput::new(ident, msg, None).do_send().await?;
+        Ok(handle)
         // let device = GmvDevice::query_gmv_device_by_device_id(device_id).await?.ok_or_else(|| GlobalError::new_sys_error(&format!("未知设备: {device_id}"), |msg| error!("{msg}")))?;
         // match device.get_gb_version().as_deref() {
         //     Some("3.0") => {
         //         let (ident, msg) = RequestBuilder::control_snapshot_image(device_id, channel_id, num, interval, uri, session_id).await?;
-        //         RequestOutput::new(ident, msg, None).do_send()
+        //         RequestOutput::new(ident, msg, None).do_send().await
         //     }
         //     _ => {
         //         Err(GlobalError::new_sys_error(&format!("未知设备: {device_id}"), |msg| error!("{msg}")))
         //     }
         // }
     }
+
+    /// 即时抓图：临时开启上传端口,触发设备单张抓拍并等待上传,返回图片数据(JPEG)
+    pub async fn grab_snapshot(device_id: &String, channel_id: &String, timeout: Duration) -> GlobalResult<Vec<u8>> {
+        let receiver = SnapshotReceiver::bind().await?;
+        let uri = receiver.uri(SessionConf::get_session_by_conf().get_wan_ip());
+        let (_token, session_id) = se_token::build_token_session_id(device_id, channel_id)?;
+        Self::snapshot_image(device_id, channel_id, 1, 1, &uri, &session_id).await?;
+        receiver.recv(timeout).await
+    }
 }
 
+//重传NOTIFY去重窗口：覆盖设备非INVITE事务重传时长(64*T1)
+pub const NOTIFY_DEDUP_WINDOW: Duration = Duration::from_secs(32);
+
+//(device_id,CSeq,SN):首次到达时间
+static NOTIFY_SEEN: Lazy<Mutex<HashMap<(String, String, String), Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 pub struct CmdNotify;
 
-pub struct CmdStream;
+impl CmdNotify {
+    /// 重传去重后分发：同(设备,CSeq,SN)在窗口内仅首次执行dispatch,重复的跳过;
+    /// 无论是否重复均执行ack,设备收到200后才停止重传;dispatch失败时不记录,允许重传再次处理
+    pub async fn dispatch_once<D, A>(device_id: &str, cseq: &str, sn: Option<&str>, dispatch: D, ack: A) -> GlobalResult<()>
+    where
+        D: Future<Output=GlobalResult<()>>,
+        A: Future<Output=GlobalResult<()>>,
+    {
+        let key = (device_id.to_string(), cseq.to_string(), sn.unwrap_or_default().to_string());
+        if Self::first_seen(&key, Instant::now()) {
+            if let Err(err) = dispatch.await {
+                NOTIFY_SEEN.lock().remove(&key);
+                return Err(err);
+            }
+        } else {
+            debug!("重传NOTIFY,仅应答: device_id = {device_id},CSeq = {cseq},SN = {:?}", sn);
+        }
+        ack.await
+    }
 
-impl CmdStream {
-    pub async fn download_invite(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, speed: u8)
-                                 -> GlobalResult<(Response, HashMap<u8, String>, String, String)> {
-        let (ident, msg) = RequestBuilder::download(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, speed)
-            .await.hand_log(|msg| warn!("{msg}"))?;
-        Self::invite_stream(ident, msg).await
+    fn first_seen(key: &(String, String, String), now: Instant) -> bool {
+        let mut guard = NOTIFY_SEEN.lock();
+        guard.retain(|_, at| now.saturating_duration_since(*at) < NOTIFY_DEDUP_WINDOW);
+        if guard.contains_key(key) {
+            return false;
+        }
+        guard.insert(key.clone(), now);
+        true
     }
 
-    pub async fn play_back_invite(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32)
-                                  -> GlobalResult<(Response, HashMap<u8, String>, String, String)> {
-        let (ident, msg) = RequestBuilder::playback(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et)
-            .await.hand_log(|msg| warn!("{msg}"))?;
-        Self::invite_stream(ident, msg).await
+    pub fn parse_device_info(body: &[u8]) -> GlobalResult<DeviceInfo> {
+        DeviceInfo::parse(body)
     }
-    pub async fn play_live_invite(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String)
-                                  -> GlobalResult<(Response, HashMap<u8, String>, String, String)> {
-        let (ident, msg) = RequestBuilder::play_live_request(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc)
-            .await.hand_log(|msg| warn!("{msg}"))?;
-        Self::invite_stream(ident, msg).await
+
+    pub fn parse_catalog(body: &[u8]) -> GlobalResult<CatalogSnapshot> {
+        CatalogSnapshot::parse(body)
     }
 
-    pub fn invite_ack(device_id: &String, response: &Response) -> GlobalResult<(String, u32)> {
-        let ack_request = RequestBuilder::build_ack_request_by_response(response)?;
-        let call_id = ack_request.call_id_header().hand_log(|msg| warn!("{msg}"))?.value().to_string();
-        let seq = ack_request.cseq_header().hand_log(|msg| warn!("{msg}"))?.seq().hand_log(|msg| warn!("{msg}"))?;
-        RequestOutput::do_send_off(device_id, ack_request).hand_log(|msg| warn!("{msg}"))?;
-        Ok((call_id, seq))
+    pub fn parse_channel_status(body: &[u8], channel_id: &str) -> GlobalResult<DeviceStatus> {
+        DeviceStatus::parse_channel(body, channel_id)
     }
-    pub async fn play_speed(device_id: &String, channel_id: &String, speed: f32, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<()> {
-        let (ident, msg) = RequestBuilder::speed(device_id, channel_id, speed, from_tag, to_tag, seq, call_id).await?;
-        let (tx, mut rx) = mpsc::channel(10);
-        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send().hand_log(|msg| error!("未响应：{msg}"))?;
-        if let Some((Some(res), _)) = rx.recv().await {
-            if res.status_code.code() == 200 {
-                EventSession::remove_event(&ident);
-                return Ok(());
-            }
-            error!("speed: ident = {:?},channel_id = {},res = {}",&ident,channel_id,res.status_code);
+
+    pub fn parse_storage_status(body: &[u8]) -> GlobalResult<StorageStatus> {
+        StorageStatus::parse(body)
+    }
+
+    pub fn parse_position_track(body: &[u8]) -> GlobalResult<Vec<PositionPoint>> {
+        PositionPoint::parse_track(body)
+    }
+
+    pub fn parse_osd_config(body: &[u8]) -> GlobalResult<OsdConfig> {
+        OsdConfig::parse(body)
+    }
+
+    pub fn parse_stream_profiles(body: &[u8]) -> GlobalResult<Vec<StreamProfile>> {
+        StreamProfile::parse(body)
+    }
+
+    pub fn parse_alarm_response(body: &[u8]) -> GlobalResult<Vec<AlarmRecord>> {
+        AlarmRecord::parse_list(body)
+    }
+}
+
+/// 目录订阅唤醒方式
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CatalogPoke {
+    //订阅对话内re-SUBSCRIBE刷新
+    Refresh,
+    //尚无目录NOTIFY,新建订阅
+    Resubscribe,
+}
+
+/// 点播成功结果
+pub struct InviteOutcome {
+    pub response: Response,
+    //payload type:编码名
+    pub media_map: HashMap<u8, String>,
+    //m=video行声明的payload顺序,即设备偏好顺序
+    pub payload_order: Vec<u8>,
+    //设备实际选用的媒体,收流端据此选择PS解封装或裸流(ES)处理
+    pub media: Option<MediaChoice>,
+    pub from_tag: String,
+    pub to_tag: String,
+    //设备是否接受RTP/RTCP复用
+    pub rtcp_mux: bool,
+    //应答SDP中a=rtcp:设备RTCP端口;None即RTP端口+1
+    pub rtcp_port: Option<u16>,
+    //应答SDP中o=行的地址
+    pub origin_addr: Option<String>,
+    //应答SDP中m=video端口与y=行SSRC
+    pub media_port: Option<u16>,
+    pub answer_ssrc: Option<String>,
+    //应答SDP中t=行起止时间(秒级时间戳),回放/下载时可能被设备按实际录像裁剪
+    pub time_range: Option<(u64, u64)>,
+    //应答SDP中a=fmtp:payload type:格式参数,即协商的编码profile
+    pub fmtp: HashMap<u8, String>,
+    //应答SDP中a=crypto:设备SRTP密钥,中继据此派生会话密钥;明文RTP时为None
+    pub crypto: Option<SrtpCrypto>,
+    //offer中宣告的收流端口：PortParity::Adjust时可能为调用方请求端口+1
+    pub dst_port: u16,
+    //媒体流实际SSRC：SsrcCheck::Accept且设备不一致时为设备SSRC,否则为offer中的SSRC
+    pub ssrc: String,
+    //媒体流标识,收流端按SSRC解析,见CmdStream::lookup_by_ssrc
+    pub key: StreamKey,
+    //设备主动BYE时通知;本端BYE后不再触发
+    pub bye_rx: oneshot::Receiver<Dialog>,
+}
+
+/// 回放/下载实际播放区间与请求区间的比较
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PlayedRange {
+    Exact,
+    //设备裁剪后的区间
+    Trimmed { st: u64, et: u64 },
+    //t=0 0：设备按实时流应答
+    Live,
+    //应答无t=行
+    Unknown,
+}
+
+impl InviteOutcome {
+    pub fn played_range(&self, st: u32, et: u32) -> PlayedRange {
+        match self.time_range {
+            None => PlayedRange::Unknown,
+            Some((0, 0)) => PlayedRange::Live,
+            Some((a, b)) if a == st as u64 && b == et as u64 => PlayedRange::Exact,
+            Some((a, b)) => PlayedRange::Trimmed { st: a, et: b },
         }
-        EventSession::remove_event(&ident);
-        return Err(GlobalError::new_biz_error(1000, "speed倍速未响应或超时", |msg| error!("{msg}")));
     }
-    pub async fn play_seek(device_id: &String, channel_id: &String, seek: u32, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<()> {
-        let (ident, msg) = RequestBuilder::seek(device_id, channel_id, seek, from_tag, to_tag, seq, call_id).await?;
-        let (tx, mut rx) = mpsc::channel(10);
-        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send().hand_log(|msg| error!("未响应：{msg}"))?;
-        if let Some((Some(res), _)) = rx.recv().await {
-            if res.status_code.code() == 200 {
-                EventSession::remove_event(&ident);
-                return Ok(());
-            }
-            error!("seek: ident = {:?},channel_id = {},res = {}",&ident,channel_id,res.status_code);
+}
+
+/// 进行中的点播：切换/关闭所需的对话序号与媒体接收端
+#[derive(Debug, Clone)]
+pub struct StreamDialog {
+    pub dialog: Dialog,
+    pub seq: u32,
+    pub dst_ip: String,
+    pub dst_port: u16,
+    pub stream_mode: StreamMode,
+    pub ssrc: String,
+}
+
+//设备应答SDP中关注的内容
+#[derive(Debug, Default)]
+struct SdpAnswer {
+    media_map: HashMap<u8, String>,
+    payload_order: Vec<u8>,
+    rtcp_mux: bool,
+    //a=rtcp端口
+    rtcp_port: Option<u16>,
+    //o=行地址
+    origin_addr: Option<String>,
+    //c=行地址(会话级或媒体级)
+    connection_addrs: Vec<String>,
+    //m=video端口
+    media_port: Option<u16>,
+    //y=行SSRC
+    ssrc: Option<String>,
+    //t=行起止时间
+    time_range: Option<(u64, u64)>,
+    //a=fmtp
+    fmtp: HashMap<u8, String>,
+    //a=crypto(取首个可解析项)
+    crypto: Option<SrtpCrypto>,
+    //(首个)m=video行端口非0且含payload;仅音频可用不算
+    usable_media: bool,
+    //m=video方向为a=inactive(媒体级,或媒体级未声明时取会话级)
+    inactive: bool,
+    //a=rtpmap时钟频率
+    clock_rates: HashMap<u8, u32>,
+}
+
+/// 应答中被选作中继主媒体的媒体行及选择依据,用于互通排查
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaChoice {
+    pub kind: &'static str,
+    pub payload_type: u8,
+    pub codec: Option<String>,
+    pub clock_rate: Option<u32>,
+    pub port: u16,
+    pub reason: &'static str,
+}
+
+impl MediaChoice {
+    /// 是否PS封装;否则为裸流(H264/H265等),编码见codec
+    pub fn is_ps(&self) -> bool {
+        self.codec.as_deref().map(|codec| codec.eq_ignore_ascii_case("PS")).unwrap_or(false)
+    }
+}
+
+impl std::fmt::Display for MediaChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kind = {},payload_type = {},codec = {},clock_rate = {},port = {},reason = {}",
+               self.kind, self.payload_type, self.codec.as_deref().unwrap_or("-"),
+               self.clock_rate.map(|rate| rate.to_string()).unwrap_or_else(|| "-".to_string()), self.port, self.reason)
+    }
+}
+
+//在途INVITE：未得到最终应答即被中止(如超出调用方总时限)时,清理事件并CANCEL
+struct PendingInvite {
+    ident: Ident,
+    cancel: Option<SipMessage>,
+}
+
+impl PendingInvite {
+    //已得到最终应答或事务已结束,无需CANCEL
+    fn settle(mut self) {
+        self.cancel = None;
+    }
+}
+
+impl Drop for PendingInvite {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            warn!("{:?} : INVITE未完成即中止,发送CANCEL", self.ident);
+            EventSession::remove_event(&self.ident);
+            let _ = RequestOutput::do_send_off(self.ident.get_device_id(), cancel).hand_log(|msg| warn!("CANCEL发送失败: {msg}"));
         }
-        EventSession::remove_event(&ident);
-        return Err(GlobalError::new_biz_error(1000, "seek拖动未响应或超时", |msg| error!("{msg}")));
     }
+}
 
-    pub async fn play_bye(seq: u32, call_id: String, device_id: &String, channel_id: &String, from_tag: &str, to_tag: &str) -> GlobalResult<()> {
-        let (ident, msg) = RequestBuilder::build_bye_request(seq, call_id, device_id, channel_id, from_tag, to_tag).await?;
-        let (tx, mut rx) = mpsc::channel(10);
+/// 媒体到达探测：由各RTP接收端实现,ssrc对应的流在timeout内到达返回true
+pub trait MediaProbe: Send + Sync {
+    fn await_media(&self, ssrc: String, timeout: Duration) -> Pin<Box<dyn Future<Output=bool> + Send + '_>>;
+}
 
-        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send().hand_log(|msg| error!("未响应：{msg}"))?;
+//call_id:看门狗解除通知
+static ACK_WATCHDOGS: Lazy<Mutex<HashMap<String, oneshot::Sender<()>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static WATCHDOG_PROBE: Lazy<RwLock<Option<Arc<dyn MediaProbe>>>> = Lazy::new(|| RwLock::new(None));
 
-        if let Some((Some(res), _)) = rx.recv().await {
-            if res.status_code.code() == 200 {
-                EventSession::remove_event(&ident);
-                return Ok(());
+static RTP_PORT_POOL: Lazy<RtpPortPool> = Lazy::new(|| {
+    let conf = RtpPortConf::get_rtp_port_conf();
+    RtpPortPool::new(conf.port_start, conf.port_end)
+});
+
+/// 调用方指定的收流端口为奇数时的处理策略(RTP惯用偶数端口,RTCP为端口+1)
+/// off : 不校验
+/// reject : 拒绝点播
+/// adjust : 改用相邻的偶数端口(端口+1),SDP宣告调整后的端口;收流端须按InviteOutcome::dst_port监听
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Deserialize)]
+#[serde(crate = "common::serde", rename_all = "lowercase")]
+pub enum PortParity {
+    #[default]
+    Off,
+    Reject,
+    Adjust,
+}
+
+impl PortParity {
+    pub fn check(self, port: u16) -> GlobalResult<u16> {
+        if port % 2 == 0 || self == PortParity::Off {
+            return Ok(port);
+        }
+        match self {
+            PortParity::Adjust if port < u16::MAX => {
+                warn!("rtp端口 {port} 为奇数,SDP改用 {},收流端须在该端口监听", port + 1);
+                Ok(port + 1)
             }
-            error!("关闭摄像机: ident = {:?},channel_id = {},res = {}",&ident,channel_id,res.status_code);
+            _ => Err(CmdErrorCode::InvalidParam.warn(&format!("rtp端口须为偶数(RTCP使用端口+1,部分设备拒绝奇数端口): dst_port = {port}"))),
         }
-        EventSession::remove_event(&ident);
-        return Err(GlobalError::new_biz_error(1000, "关闭摄像机直播未响应或超时", |msg| error!("{msg}")));
     }
-    async fn invite_stream(ident: Ident, msg: SipMessage) -> GlobalResult<(Response, HashMap<u8, String>, String, String)> {
-        let (tx, mut rx) = mpsc::channel(10);
-        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send()?;
-        while let Some((Some(res), _)) = rx.recv().await {
-            let code = res.status_code.code();
-            let code_msg = res.status_code.to_string();
-            debug!("{ident:?} : {code} => {code_msg}");
-            if code >= 300 {
-                EventSession::remove_event(&ident);
-                return Err(GlobalError::new_biz_error(3000, &code_msg, |msg| error!("{msg}")));
-            }
-            if code == 200 {
-                let session = sdp_types::Session::parse(res.body()).unwrap();
-                debug!("{ident:?} :{:?}",&session);
-                let re = Regex::new(r"\s+").unwrap();
-                let mut media_map = HashMap::new();
-                for media in session.medias {
-                    for attr in media.attributes {
-                        if attr.attribute.eq("rtpmap") {
-                            if let Some(info) = attr.value {
-                                if let Some((key, val)) = re.replace_all(info.trim(), " ").split_once(" ") {
-                                    let tp = key.parse::<u8>().hand_log(|msg| error!("{msg}"))?;
-                                    let i = val.find('/').unwrap_or(val.len());
-                                    media_map.insert(tp, val[0..i].to_uppercase());
-                                }
-                            }
-                        }
-                    }
-                }
-                let from_tag = ResponseBuilder::get_tag_by_header_from(&res)?;
-                let to_tag = ResponseBuilder::get_tag_by_header_to(&res)?;
-                EventSession::remove_event(&ident);
-                return Ok((res, media_map, from_tag, to_tag));
+}
+
+/// 本端rtp端口池：仅分配偶数端口(相邻奇数端口留给rtcp)
+pub struct RtpPortPool {
+    state: Mutex<PortState>,
+}
+
+struct PortState {
+    free: BTreeSet<u16>,
+    //call_id:port
+    bound: HashMap<String, u16>,
+}
+
+impl RtpPortPool {
+    pub fn new(start: u16, end: u16) -> Self {
+        let free = (start..=end).filter(|port| port % 2 == 0).collect();
+        Self { state: Mutex::new(PortState { free, bound: HashMap::new() }) }
+    }
+
+    pub fn alloc(&self) -> Option<u16> {
+        self.state.lock().free.pop_first()
+    }
+
+    //点播成功后关联对话,BYE时按call_id回收
+    pub fn bind(&self, call_id: &str, port: u16) {
+        self.state.lock().bound.insert(call_id.to_string(), port);
+    }
+
+    pub fn release(&self, port: u16) {
+        self.state.lock().free.insert(port);
+    }
+
+    pub fn release_by_call_id(&self, call_id: &str) -> Option<u16> {
+        let mut guard = self.state.lock();
+        let port = guard.bound.remove(call_id)?;
+        guard.free.insert(port);
+        Some(port)
+    }
+}
+
+pub struct CmdStream;
+
+impl CmdStream {
+    //对话tag：To tag(设备端)必须存在;部分设备200 OK的From缺tag,沿用INVITE中本端下发的tag
+    fn dialog_tags(res: &Response, sent_from_tag: Option<&str>) -> GlobalResult<(String, String)> {
+        let to_tag = ResponseBuilder::get_tag_by_header_to(res)?;
+        let from_tag = match (res.from_header().ok().and_then(|from| from.tag().ok().flatten()), sent_from_tag) {
+            (Some(tag), _) => tag.to_string(),
+            (None, Some(tag)) => {
+                warn!("200 OK缺少From tag,沿用INVITE的tag = {tag}");
+                tag.to_string()
+            }
+            (None, None) => return ResponseBuilder::get_tag_by_header_from(res).map(|tag| (tag, to_tag)),
+        };
+        Ok((from_tag, to_tag))
+    }
+
+    /// 收流端按RTP包SSRC解析所属媒体流;对话结束(本端或设备BYE)后不再命中
+    pub fn lookup_by_ssrc(ssrc: u32) -> Option<StreamKey> {
+        DialogSession::lookup_by_ssrc(ssrc)
+    }
+
+    /// speed = 0：不限速下载，设备按链路能力尽快传输（用于批量归档）
+    /// 设备在应答中回显的 downloadspeed 与请求不一致时仅告警，不中断下载
+    pub async fn download_invite(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, speed: u8, option: &InviteOption)
+                                 -> GlobalResult<InviteOutcome> {
+        let dst_port = SessionConf::rtp_port_parity().check(dst_port)?;
+        let res = Self::within_deadline(option.deadline, async {
+            let _permit = CmdLimiter::admit().await?;
+            stream_mode.check_supported(option.supported_modes.as_deref())?;
+            let (ident, msg, ssrc) = RequestBuilder::download(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, speed, option)
+                .await.hand_log(|msg| warn!("{msg}"))?;
+            Self::invite_stream(ident, msg, channel_id, &format!("{dst_ip}:{dst_port}"), StreamMedia { stream_mode, ssrc }, option).await
+        }).await?;
+        Self::log_played_range(device_id, channel_id, &res, st, et);
+        match Self::parse_download_speed(res.response.body()) {
+            Some(accepted) if accepted != speed => {
+                warn!("device_id = {device_id},channel_id = {channel_id}: 请求下载倍速 = {speed},设备接受倍速 = {accepted}");
             }
+            None if speed == 0 => {
+                debug!("device_id = {device_id},channel_id = {channel_id}: 设备未回显downloadspeed,无法确认是否支持不限速下载");
+            }
+            _ => {}
         }
-        EventSession::remove_event(&ident);
-        return Err(GlobalError::new_biz_error(1000, "摄像机响应超时", |msg| error!("{msg}")));
+        Ok(res)
     }
-}
 
+    //设备应答SDP中回显的下载倍速：a=downloadspeed:
+    fn parse_download_speed(body: &[u8]) -> Option<u8> {
+        String::from_utf8_lossy(body).lines()
+            .find_map(|line| line.trim().strip_prefix("a=downloadspeed:").and_then(|speed| speed.trim().parse::<u8>().ok()))
+    }
 
-#[cfg(test)]
-#[allow(unused)]
-mod test {
-    use regex::Regex;
+    pub async fn play_back_invite(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, st: u32, et: u32, option: &InviteOption)
+                                  -> GlobalResult<InviteOutcome> {
+        let dst_port = SessionConf::rtp_port_parity().check(dst_port)?;
+        let res = Self::within_deadline(option.deadline, async {
+            let _permit = CmdLimiter::admit().await?;
+            stream_mode.check_supported(option.supported_modes.as_deref())?;
+            let (ident, msg, ssrc) = RequestBuilder::playback(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, option)
+                .await.hand_log(|msg| warn!("{msg}"))?;
+            Self::invite_stream(ident, msg, channel_id, &format!("{dst_ip}:{dst_port}"), StreamMedia { stream_mode, ssrc }, option).await
+        }).await?;
+        Self::log_played_range(device_id, channel_id, &res, st, et);
+        Ok(res)
+    }
+
+    /// 按录像检索返回的录像段回放：INVITE区间即该段起止时间(设备时钟),不受时钟偏差补偿影响
+    pub async fn play_back_segment(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, segment: &RecordSegment, option: &InviteOption)
+                                   -> GlobalResult<InviteOutcome> {
+        let (st, et) = Self::segment_range(device_id, channel_id, segment)?;
+        Self::play_back_invite(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, option).await
+    }
+
+    //录像段为设备时间,请求构建会再按偏差换算为设备时间,故先换回服务端时间
+    fn segment_range(device_id: &String, channel_id: &String, segment: &RecordSegment) -> GlobalResult<(u32, u32)> {
+        segment.check(channel_id)?;
+        Ok((DeviceClock::to_server_time(device_id, segment.start_time), DeviceClock::to_server_time(device_id, segment.end_time)))
+    }
+
+    /// 回放最近一段时间：et = 当前时间,st = et - window(秒级时间戳)
+    pub async fn play_back_recent(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, window: Duration, option: &InviteOption)
+                                  -> GlobalResult<InviteOutcome> {
+        //按服务端时间计算,设备时钟偏差由请求构建统一换算
+        let (st, et) = Self::recent_range(Local::now().timestamp(), window)?;
+        Self::play_back_invite(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, option).await
+    }
+
+    fn recent_range(now: i64, window: Duration) -> GlobalResult<(u32, u32)> {
+        let window = window.as_secs() as i64;
+        let et = now;
+        let st = et - window;
+        if window <= 0 || st <= 0 || et > u32::MAX as i64 {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("回放时间窗口无效: window = {window}s,now = {now}")));
+        }
+        Ok((st as u32, et as u32))
+    }
+
+    fn log_played_range(device_id: &String, channel_id: &String, res: &InviteOutcome, st: u32, et: u32) {
+        match res.played_range(st, et) {
+            PlayedRange::Live => { warn!("device_id = {device_id},channel_id = {channel_id}: 请求历史区间 {st}-{et},设备应答t=0 0(实时流)"); }
+            PlayedRange::Trimmed { st: a, et: b } => { debug!("device_id = {device_id},channel_id = {channel_id}: 请求区间 {st}-{et},设备裁剪为 {a}-{b}"); }
+            PlayedRange::Exact | PlayedRange::Unknown => {}
+        }
+    }
+    /// stream_number：1主码流,2子码流...;已知通道码流能力(StreamProfile)时校验编号存在
+    pub async fn play_live_invite(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, stream_number: u8, option: &InviteOption)
+                                  -> GlobalResult<InviteOutcome> {
+        let dst_port = SessionConf::rtp_port_parity().check(dst_port)?;
+        Self::within_deadline(option.deadline, async {
+            let _permit = CmdLimiter::admit().await?;
+            stream_mode.check_supported(option.supported_modes.as_deref())?;
+            StreamProfile::check_stream_number(channel_id, stream_number)?;
+            let (ident, msg, ssrc) = RequestBuilder::play_live_request(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, stream_number, option)
+                .await.hand_log(|msg| warn!("{msg}"))?;
+            Self::invite_stream(ident, msg, channel_id, &format!("{dst_ip}:{dst_port}"), StreamMedia { stream_mode, ssrc }, option).await
+        }).await
+    }
+
+    /// Web实时点播：INVITE -> ACK,返回播放器所需描述;关闭时凭dialogId(Call-ID)
+    pub async fn play_live_for_web(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, stream_number: u8, option: &InviteOption)
+                                   -> GlobalResult<WebStreamDescriptor> {
+        let outcome = Self::play_live_invite(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, stream_number, option).await?;
+        Self::ack_for_web(device_id, channel_id, &outcome)
+    }
+
+    fn ack_for_web(device_id: &String, channel_id: &String, outcome: &InviteOutcome) -> GlobalResult<WebStreamDescriptor> {
+        let media_port = outcome.media_port.filter(|port| *port != 0)
+            .ok_or_else(|| CmdErrorCode::MediaRejected.warn("应答SDP无可用m=video端口"))?;
+        let codec = Self::negotiated_codec(outcome)
+            .ok_or_else(|| CmdErrorCode::MediaRejected.warn("应答SDP无可识别的视频编码"))?;
+        let (call_id, _seq) = Self::invite_ack(device_id, &outcome.response)?;
+        let dialog_token = WebStreamDescriptor::dialog_token(device_id, channel_id, &call_id);
+        Ok(WebStreamDescriptor { mediaPort: media_port, codec, ssrc: outcome.ssrc.clone(), dialogId: call_id, dialogToken: dialog_token })
+    }
+
+    //按m=行顺序取首个可识别编码：优先a=rtpmap,缺省时按GB28181约定的动态payload
+    fn negotiated_codec(outcome: &InviteOutcome) -> Option<String> {
+        outcome.payload_order.iter().find_map(|tp| outcome.media_map.get(tp).cloned().or_else(|| Self::default_codec(*tp)))
+    }
+
+    fn default_codec(tp: u8) -> Option<String> {
+        match tp {
+            96 => Some("PS".to_string()),
+            97 => Some("MPEG4".to_string()),
+            98 => Some("H264".to_string()),
+            _ => None,
+        }
+    }
+
+    //中继采用的主媒体：首个m=video,其中按m=行顺序首个可识别编码的payload
+    fn media_choice(answer: &SdpAnswer) -> Option<MediaChoice> {
+        let port = answer.media_port?;
+        let choice = |payload_type: u8, codec: Option<String>, reason: &'static str| MediaChoice {
+            kind: "video",
+            payload_type,
+            codec,
+            clock_rate: answer.clock_rates.get(&payload_type).copied(),
+            port,
+            reason,
+        };
+        let found = answer.payload_order.iter().find_map(|tp| {
+            answer.media_map.get(tp).map(|codec| choice(*tp, Some(codec.clone()), "first video section, first payload with rtpmap"))
+                .or_else(|| Self::default_codec(*tp).map(|codec| choice(*tp, Some(codec), "first video section, GB28181 default payload mapping")))
+        });
+        found.or_else(|| answer.payload_order.first().map(|tp| choice(*tp, None, "first video section, first payload (codec unknown)")))
+    }
+
+    /// 批量实时点播(如电视墙)：至多concurrency路并行,单路失败不影响其余;结果与请求同序
+    pub async fn play_live_many(requests: Vec<InviteRequest>, concurrency: usize) -> Vec<(InviteRequest, GlobalResult<InviteOutcome>)> {
+        Self::invite_many(requests, concurrency, |req| async move {
+            Self::play_live_invite(&req.device_id, &req.channel_id, &req.dst_ip, req.dst_port, req.stream_mode, &req.ssrc, req.stream_number, &req.option).await
+        }).await
+    }
+
+    async fn invite_many<T, F, Fut>(requests: Vec<InviteRequest>, concurrency: usize, invite: F) -> Vec<(InviteRequest, GlobalResult<T>)>
+    where
+        F: Fn(InviteRequest) -> Fut,
+        Fut: Future<Output=GlobalResult<T>>,
+    {
+        stream::iter(requests)
+            .map(|req| {
+                let fut = invite(req.clone());
+                async move { (req, fut.await) }
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    //调用方总时限：超出时中止在途步骤(已下发的INVITE由PendingInvite清理并CANCEL)
+    async fn within_deadline<T, F>(deadline: Option<Instant>, fut: F) -> GlobalResult<T>
+    where
+        F: Future<Output=GlobalResult<T>>,
+    {
+        match deadline {
+            None => fut.await,
+            Some(deadline) => time::timeout_at(deadline, fut).await
+                .unwrap_or_else(|_| Err(CmdErrorCode::DeadlineExceeded.warn("点播超出调用方总时限,已中止"))),
+        }
+    }
+
+    /// 实时点播并确认媒体到达：INVITE -> ACK -> 探测媒体;媒体未到达时BYE并返回错误
+    pub async fn play_live_confirmed(device_id: &String, channel_id: &String, dst_ip: &String, dst_port: u16, stream_mode: StreamMode, ssrc: &String, stream_number: u8, option: &InviteOption,
+                                     media_probe: &dyn MediaProbe, probe_timeout: Duration) -> GlobalResult<(InviteOutcome, StreamDialog)> {
+        let outcome = Self::play_live_invite(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, stream_number, option).await?;
+        let (call_id, seq) = Self::invite_ack(device_id, &outcome.response)?;
+        let dialog = Dialog::new(device_id.clone(), channel_id.clone(), call_id.clone(), outcome.from_tag.clone(), outcome.to_tag.clone());
+        let stream_dialog = StreamDialog { dialog, seq, dst_ip: dst_ip.clone(), dst_port, stream_mode, ssrc: outcome.ssrc.clone() };
+        let bye = Self::play_bye(seq + 1, call_id, device_id, channel_id, &outcome.from_tag, &outcome.to_tag);
+        Self::confirm_media(media_probe, &outcome.ssrc, probe_timeout, bye).await?;
+        Ok((outcome, stream_dialog))
+    }
+
+    //媒体未在probe_timeout内到达：关闭已建立的对话(失败仅告警)
+    async fn confirm_media<B>(media_probe: &dyn MediaProbe, ssrc: &String, probe_timeout: Duration, bye: B) -> GlobalResult<()>
+    where
+        B: Future<Output=GlobalResult<()>>,
+    {
+        if media_probe.await_media(ssrc.clone(), probe_timeout).await {
+            return Ok(());
+        }
+        let _ = bye.await.hand_log(|msg| warn!("媒体未到达,关闭点播失败: {msg}"));
+        Err(CmdErrorCode::MediaTimeout.error("点播成功但媒体流未到达或超时"))
+    }
+
+    /// 实时 -> 回放：复用同一媒体接收端;回放建立(ACK完成)后再BYE实时流,失败时实时流不受影响
+    /// 回放ssrc：首位置1(历史流)
+    pub async fn switch_to_playback(live_dialog: &StreamDialog, st: u32, et: u32, option: &InviteOption) -> GlobalResult<(InviteOutcome, StreamDialog)> {
+        let device_id = live_dialog.dialog.get_device_id();
+        let channel_id = live_dialog.dialog.get_channel_id();
+        let ssrc = format!("1{}", live_dialog.ssrc.get(1..).unwrap_or_default());
+        let playback = async {
+            let outcome = Self::play_back_invite(device_id, channel_id, &live_dialog.dst_ip, live_dialog.dst_port, live_dialog.stream_mode, &ssrc, st, et, option).await?;
+            let (call_id, seq) = Self::invite_ack(device_id, &outcome.response)?;
+            let dialog = Dialog::new(device_id.clone(), channel_id.clone(), call_id, outcome.from_tag.clone(), outcome.to_tag.clone());
+            let stream_dialog = StreamDialog { dialog, seq, dst_ip: live_dialog.dst_ip.clone(), dst_port: live_dialog.dst_port, stream_mode: live_dialog.stream_mode, ssrc: ssrc.clone() };
+            Ok::<_, GlobalError>((outcome, stream_dialog))
+        };
+        let bye_live = Self::play_bye(live_dialog.seq + 1, live_dialog.dialog.get_call_id().clone(), device_id, channel_id,
+                                      live_dialog.dialog.get_from_tag(), live_dialog.dialog.get_to_tag());
+        Self::switch_stream(playback, bye_live).await
+    }
+
+    //先建立新流,成功后才关闭旧流;关闭旧流失败仅告警
+    async fn switch_stream<T, N, O>(open_new: N, close_old: O) -> GlobalResult<T>
+    where
+        N: Future<Output=GlobalResult<T>>,
+        O: Future<Output=GlobalResult<()>>,
+    {
+        let res = open_new.await?;
+        let _ = close_old.await.hand_log(|msg| warn!("切换后关闭原流失败: {msg}"));
+        Ok(res)
+    }
+
+    /// 从端口池分配媒体接收端口;点播失败时需调用release_rtp_port归还
+    pub fn alloc_rtp_port() -> GlobalResult<u16> {
+        RTP_PORT_POOL.alloc().ok_or_else(|| CmdErrorCode::RtpPortExhausted.error("rtp端口已用完,并发达上限,等待释放"))
+    }
+
+    pub fn bind_rtp_port(call_id: &str, port: u16) {
+        RTP_PORT_POOL.bind(call_id, port);
+    }
+
+    pub fn release_rtp_port(port: u16) {
+        RTP_PORT_POOL.release(port);
+    }
+
+    //对话结束时回收端口
+    pub fn release_rtp_port_by_call_id(call_id: &str) -> Option<u16> {
+        RTP_PORT_POOL.release_by_call_id(call_id)
+    }
+
+    pub fn invite_ack(device_id: &String, response: &Response) -> GlobalResult<(String, u32)> {
+        let ack_request = RequestBuilder::build_ack_request_by_response(response)?;
+        let (call_id, seq) = Self::ack_dialog_id(&ack_request, SessionConf::routine_log_level())?;
+        RequestOutput::do_send_off(device_id, ack_request).hand_log(|msg| warn!("ACK发送失败: {msg}"))?;
+        if let Some(dialog) = DialogSession::get(&call_id) {
+            DialogSession::persist(&dialog, seq);
+            if let Some(grace) = SessionConf::ack_watchdog_grace() {
+                Self::arm_ack_watchdog(dialog, seq, grace);
+            }
+        }
+        Ok((call_id, seq))
+    }
+
+    /// ACK看门狗的媒体探测：设置后看门狗在宽限期内据此确认媒体到达;未设置时需调用方disarm_ack_watchdog确认
+    pub fn set_watchdog_probe(probe: Arc<dyn MediaProbe>) {
+        *WATCHDOG_PROBE.write() = Some(probe);
+    }
+
+    /// 调用方确认媒体已到达(如流媒体回调注册成功),解除看门狗
+    pub fn disarm_ack_watchdog(call_id: &str) -> bool {
+        ACK_WATCHDOGS.lock().remove(call_id).map(|tx| tx.send(()).is_ok()).unwrap_or(false)
+    }
+
+    //ACK后宽限期内对话未关闭且媒体未确认：告警并BYE,回收占用的会话与端口
+    fn arm_ack_watchdog(dialog: Dialog, seq: u32, grace: Duration) {
+        let (tx, rx) = oneshot::channel();
+        let call_id = dialog.get_call_id().clone();
+        ACK_WATCHDOGS.lock().insert(call_id.clone(), tx);
+        let ssrc = DialogSession::get_stream(&call_id).and_then(|stream| stream.media).map(|media| media.ssrc);
+        let probe = WATCHDOG_PROBE.read().clone();
+        common::tokio::spawn(async move {
+            let bye = Self::send_bye(seq + 1, call_id.clone(), dialog.get_device_id(), dialog.get_channel_id(), dialog.get_from_tag(), dialog.get_to_tag());
+            Self::ack_watchdog(&call_id, ssrc, grace, probe, rx, bye).await;
+            ACK_WATCHDOGS.lock().remove(&call_id);
+        });
+    }
+
+    //返回是否触发BYE
+    async fn ack_watchdog<B>(call_id: &str, ssrc: Option<String>, grace: Duration, probe: Option<Arc<dyn MediaProbe>>, disarm: oneshot::Receiver<()>, bye: B) -> bool
+    where
+        B: Future<Output=GlobalResult<()>>,
+    {
+        let media = async {
+            match (probe, ssrc) {
+                (Some(probe), Some(ssrc)) => probe.await_media(ssrc, grace).await,
+                _ => {
+                    time::sleep(grace).await;
+                    false
+                }
+            }
+        };
+        let confirmed = async {
+            match disarm.await {
+                Ok(()) => true,
+                Err(_) => std::future::pending().await,
+            }
+        };
+        let confirmed = common::tokio::select! {
+            arrived = media => arrived,
+            disarmed = confirmed => disarmed,
+        };
+        if confirmed || DialogSession::get(call_id).is_none() {
+            return false;
+        }
+        warn!("call_id = {call_id}: ACK后{}s内媒体未确认且对话未关闭,自动BYE", grace.as_secs());
+        let _ = bye.await.hand_log(|msg| warn!("看门狗关闭对话失败: {msg}"));
+        true
+    }
+
+    //ACK头域提取属常规流程,按配置级别记录
+    fn ack_dialog_id(ack_request: &SipMessage, level: Level) -> GlobalResult<(String, u32)> {
+        let call_id = ack_request.call_id_header().hand_log(|msg| log!(level, "{msg}"))?.value().to_string();
+        let seq = ack_request.cseq_header().hand_log(|msg| log!(level, "{msg}"))?.seq().hand_log(|msg| log!(level, "{msg}"))?;
+        Ok((call_id, seq))
+    }
+
+    /// 当前进行中的媒体流(只读),可据dialog与seq关闭指定流
+    pub fn active_streams() -> Vec<ActiveStream> {
+        DialogSession::active()
+    }
+
+    /// 设备上线后BYE其在进程重启前遗留的媒体流,返回成功关闭数
+    pub async fn bye_orphaned_dialogs(device_id: &String) -> usize {
+        Self::bye_each(DialogSession::take_orphans(device_id), |state| async move {
+            Self::play_bye(state.seq + 1, state.call_id.clone(), &state.device_id, &state.channel_id, &state.from_tag, &state.to_tag).await
+        }).await
+    }
+
+    async fn bye_each<F, Fut>(states: Vec<DialogState>, bye: F) -> usize
+    where
+        F: Fn(DialogState) -> Fut,
+        Fut: Future<Output=GlobalResult<()>>,
+    {
+        let mut done = 0;
+        for state in states {
+            let call_id = state.call_id.clone();
+            match bye(state).await {
+                Ok(()) => done += 1,
+                Err(err) => warn!("遗留对话BYE失败: call_id = {call_id},err = {err:?}"),
+            }
+        }
+        done
+    }
+    /// 返回设备实际生效的倍速：应答MANSRTSP回显Scale(可能被设备钳制为支持的倍率),未回显时为请求值
+    pub async fn play_speed(device_id: &String, channel_id: &String, speed: f32, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<f32> {
+        let _permit = CmdLimiter::admit().await?;
+        Self::speed_exchange(channel_id, speed, seq, call_id.clone(), |seq| RequestBuilder::speed(device_id, channel_id, speed, from_tag, to_tag, seq, call_id.clone())).await
+    }
+
+    //build按CSeq构造请求,CSeq重新同步时再次调用
+    async fn speed_exchange<B, Fut>(channel_id: &String, speed: f32, seq: u32, call_id: String, build: B) -> GlobalResult<f32>
+    where
+        B: Fn(u32) -> Fut,
+        Fut: Future<Output=GlobalResult<(Ident, SipMessage)>>,
+    {
+        let (ident, res) = Self::dialog_exchange(&call_id, seq, build).await?;
+        if let Some(res) = res {
+            if res.status_code.code() == 200 {
+                EventSession::remove_event(&ident);
+                let scale = Self::parse_scale(res.body()).unwrap_or(speed);
+                if scale != speed {
+                    warn!("speed: channel_id = {channel_id},请求倍速 = {speed},设备生效倍速 = {scale}");
+                }
+                return Ok(scale);
+            }
+            if res.status_code.code() == 481 {
+                EventSession::remove_event(&ident);
+                return Self::dialog_gone(&call_id, false);
+            }
+            error!("speed: ident = {:?},channel_id = {},res = {}",&ident,channel_id,res.status_code);
+        }
+        EventSession::remove_event(&ident);
+        return Err(CmdErrorCode::SpeedTimeout.error("speed倍速未响应或超时"));
+    }
+    //MANSRTSP应答体中的Scale头,如 Scale: 2.000
+    fn parse_scale(body: &[u8]) -> Option<f32> {
+        let body = String::from_utf8_lossy(body);
+        body.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("Scale"))
+            .and_then(|(_, value)| value.trim().parse::<f32>().ok())
+            .filter(|scale| scale.is_finite() && *scale > 0.0)
+    }
+
+    pub async fn play_seek(device_id: &String, channel_id: &String, seek: u32, from_tag: &str, to_tag: &str, seq: u32, call_id: String) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        Self::seek_exchange(channel_id, seq, call_id.clone(), |seq| RequestBuilder::seek(device_id, channel_id, seek, from_tag, to_tag, seq, call_id.clone())).await
+    }
+
+    async fn seek_exchange<B, Fut>(channel_id: &String, seq: u32, call_id: String, build: B) -> GlobalResult<()>
+    where
+        B: Fn(u32) -> Fut,
+        Fut: Future<Output=GlobalResult<(Ident, SipMessage)>>,
+    {
+        let (ident, res) = Self::dialog_exchange(&call_id, seq, build).await?;
+        if let Some(res) = res {
+            if res.status_code.code() == 200 {
+                EventSession::remove_event(&ident);
+                return Ok(());
+            }
+            if res.status_code.code() == 481 {
+                EventSession::remove_event(&ident);
+                return Self::dialog_gone(&call_id, false);
+            }
+            error!("seek: ident = {:?},channel_id = {},res = {}",&ident,channel_id,res.status_code);
+        }
+        EventSession::remove_event(&ident);
+        return Err(CmdErrorCode::SeekTimeout.error("seek拖动未响应或超时"));
+    }
+
+    pub async fn play_bye(seq: u32, call_id: String, device_id: &String, channel_id: &String, from_tag: &str, to_tag: &str) -> GlobalResult<()> {
+        let _permit = CmdLimiter::admit().await?;
+        Self::send_bye(seq, call_id, device_id, channel_id, from_tag, to_tag).await
+    }
+
+    //不占用命令许可：供已持有许可的流程内部关闭对话
+    async fn send_bye(seq: u32, call_id: String, device_id: &String, channel_id: &String, from_tag: &str, to_tag: &str) -> GlobalResult<()> {
+        Self::bye_exchange(channel_id, seq, call_id.clone(), |seq| RequestBuilder::build_bye_request(seq, call_id.clone(), device_id, channel_id, from_tag, to_tag)).await
+    }
+
+    //本端状态(对话、rtp端口)仅在200/481后移除：超时或其他失败时保留,调用方可重试BYE
+    async fn bye_exchange<B, Fut>(channel_id: &String, seq: u32, call_id: String, build: B) -> GlobalResult<()>
+    where
+        B: Fn(u32) -> Fut,
+        Fut: Future<Output=GlobalResult<(Ident, SipMessage)>>,
+    {
+        let (ident, res) = Self::dialog_exchange(&call_id, seq, build).await?;
+        if let Some(res) = res {
+            if res.status_code.code() == 200 {
+                EventSession::remove_event(&ident);
+                DialogSession::remove(&call_id);
+                Self::release_rtp_port_by_call_id(&call_id);
+                return Ok(());
+            }
+            if res.status_code.code() == 481 {
+                EventSession::remove_event(&ident);
+                return Self::dialog_gone(&call_id, true);
+            }
+            error!("关闭摄像机: ident = {:?},channel_id = {},res = {}",&ident,channel_id,res.status_code);
+        }
+        EventSession::remove_event(&ident);
+        return Err(CmdErrorCode::ByeTimeout.error("关闭摄像机直播未响应或超时"));
+    }
+
+    //对话内请求：按CSeq构造并发送,等待首个应答;CSeq失步时以新序号重新构造,见in_dialog_exchange
+    async fn dialog_exchange<B, Fut>(call_id: &str, seq: u32, build: B) -> GlobalResult<(Ident, Option<Response>)>
+    where
+        B: Fn(u32) -> Fut,
+        Fut: Future<Output=GlobalResult<(Ident, SipMessage)>>,
+    {
+        Self::in_dialog_exchange(call_id, seq, |seq| {
+            let request = build(seq);
+            async move {
+                let (ident, msg) = request.await?;
+                Self::exchange_once(ident, msg).await
+            }
+        }).await
+    }
+
+    //非2xx最终应答(如486/600)：ACK后立即返回错误,不再等待重传
+    fn reject_invite(msg: &SipMessage, res: &Response, code_msg: &str) -> (Option<SipMessage>, GlobalError) {
+        let ack = match msg {
+            SipMessage::Request(req) => RequestBuilder::build_ack_request_by_failure_response(req, res).ok(),
+            SipMessage::Response(_) => None,
+        };
+        (ack, CmdErrorCode::InviteRejected.error(code_msg))
+    }
+
+    //发送对话内请求并等待首个应答;未响应或超时为None
+    async fn exchange_once(ident: Ident, msg: SipMessage) -> GlobalResult<(Ident, Option<Response>)> {
+        let (tx, mut rx) = mpsc::channel(10);
+        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send().await.hand_log(|msg| error!("未响应：{msg}"))?;
+        match rx.recv().await {
+            Some((Some(res), _)) => Ok((ident, Some(res))),
+            _ => Ok((ident, None)),
+        }
+    }
+
+    //对话内请求：设备以CSeq异常拒绝(如本端重启后序号回退)时,以重新同步的CSeq重发一次
+    async fn in_dialog_exchange<F, Fut>(call_id: &str, seq: u32, send: F) -> GlobalResult<(Ident, Option<Response>)>
+    where
+        F: Fn(u32) -> Fut,
+        Fut: Future<Output=GlobalResult<(Ident, Option<Response>)>>,
+    {
+        let (ident, res) = send(seq).await?;
+        match res {
+            Some(res) if Self::is_cseq_reject(&res) => {
+                EventSession::remove_event(&ident);
+                let resynced = Self::resync_seq(seq, Local::now().timestamp());
+                warn!("{ident:?} : 设备以{}拒绝,疑似CSeq失步,以CSeq = {resynced}重发", res.status_code);
+                if let Some(dialog) = DialogSession::get(call_id) {
+                    DialogSession::persist(&dialog, resynced);
+                }
+                send(resynced).await
+            }
+            res => Ok((ident, res)),
+        }
+    }
+
+    //RFC3261 12.2.2 对话内CSeq小于已收序号时应答500,部分设备应答400;
+    //500/400亦是普通设备错误,仅在Warning或应答体提及CSeq时视为失步(rsip不保留原因短语)
+    fn is_cseq_reject(res: &Response) -> bool {
+        match res.status_code.code() {
+            400 | 500 => {
+                let mentions = |text: &str| text.to_ascii_lowercase().contains("cseq");
+                res.headers.iter().any(|header| matches!(header, rsip::Header::Warning(warning) if mentions(warning.value())))
+                    || mentions(&String::from_utf8_lossy(res.body()))
+            }
+            _ => false,
+        }
+    }
+
+    //取秒级时间戳(去符号位)作为新序号：跨重启单调递增,必大于设备此前收到的本端序号
+    fn resync_seq(seq: u32, now: i64) -> u32 {
+        ((now as u32) & 0x7FFF_FFFF).max(seq.saturating_add(1))
+    }
+
+    //481：设备已无此对话,视为对话已结束并清理本端状态(通知流持有方);BYE的目的已达成
+    fn dialog_gone(call_id: &str, is_bye: bool) -> GlobalResult<()> {
+        DialogSession::device_bye(call_id, None, None);
+        RTP_PORT_POOL.release_by_call_id(call_id);
+        if is_bye {
+            return Ok(());
+        }
+        Err(CmdErrorCode::DialogGone.warn("设备对话已不存在(481)"))
+    }
+
+    async fn invite_stream(ident: Ident, msg: SipMessage, channel_id: &String, dst: &str, media: StreamMedia, option: &InviteOption) -> GlobalResult<InviteOutcome> {
+        let option = &DeviceQuirks::of(ident.get_device_id()).apply(option);
+        let sent_from_tag = match &msg {
+            SipMessage::Request(req) => req.from_header().ok().and_then(|from| from.tag().ok().flatten()).map(|tag| tag.to_string()),
+            SipMessage::Response(_) => None,
+        };
+        let (ident, sent, res) = Self::follow_redirect(ident, msg, MAX_REDIRECT_HOPS, Self::invite_exchange).await?;
+        let code = res.status_code.code();
+        let code_msg = res.status_code.to_string();
+        if code >= 300 {
+            EventSession::remove_event(&ident);
+            let (ack, err) = Self::reject_invite(&sent, &res, &code_msg);
+            if let Some(ack) = ack {
+                let _ = RequestOutput::do_send_off(ident.get_device_id(), ack).hand_log(|msg| warn!("{msg}"));
+            }
+            return Err(err);
+        }
+        if let Err(err) = Self::check_sdp_size(res.body(), SessionConf::max_sdp_size()) {
+            EventSession::remove_event(&ident);
+            //仅用200 OK头域关闭对话,不解析应答体
+            Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+            return Err(err);
+        }
+        let answer = match Self::parse_answer(res.body()) {
+            Ok(answer) => answer,
+            Err(err) => {
+                EventSession::remove_event(&ident);
+                Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+                return Err(err);
+            }
+        };
+        debug!("{ident:?} :{:?}",&answer);
+        if let Err(err) = Self::validate_answer(&answer) {
+            EventSession::remove_event(&ident);
+            Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+            return Err(err);
+        }
+        if let Err(err) = Self::verify_crypto(&answer, option) {
+            EventSession::remove_event(&ident);
+            Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+            return Err(err);
+        }
+        if let Err(err) = Self::verify_source(&answer, option) {
+            EventSession::remove_event(&ident);
+            Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+            return Err(err);
+        }
+        let ssrc = match Self::verify_ssrc(answer.ssrc.as_deref(), &media.ssrc, option.ssrc_check) {
+            Ok(ssrc) => ssrc,
+            Err(err) => {
+                EventSession::remove_event(&ident);
+                Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+                return Err(err);
+            }
+        };
+        let (from_tag, to_tag) = match Self::dialog_tags(&res, sent_from_tag.as_deref()) {
+            Ok(tags) => tags,
+            Err(err) => {
+                EventSession::remove_event(&ident);
+                Self::ack_and_bye(ident.get_device_id(), channel_id, &res, sent_from_tag.as_deref()).await;
+                return Err(err);
+            }
+        };
+        EventSession::remove_event(&ident);
+        let choice = Self::media_choice(&answer);
+        match &choice {
+            Some(choice) => { debug!("{ident:?} : 媒体选择 {choice}"); }
+            None => { debug!("{ident:?} : 媒体选择 无m=video"); }
+        }
+        let dialog = Dialog::new(ident.get_device_id().clone(), channel_id.clone(), ident.get_call_id().clone(), from_tag.clone(), to_tag.clone());
+        let key = StreamKey::build(&dialog, &ssrc);
+        //dst为"ip:port"
+        let dst_port = dst.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).unwrap_or_default();
+        let bye_rx = DialogSession::insert_stream(dialog, Some(StreamMedia { ssrc: ssrc.clone(), ..media }));
+        if option.reuse {
+            DialogSession::register_reusable(ident.get_device_id(), channel_id, dst, ident.get_call_id());
+        }
+        Ok(InviteOutcome { response: res, media_map: answer.media_map, payload_order: answer.payload_order, media: choice, from_tag, to_tag, rtcp_mux: answer.rtcp_mux, rtcp_port: answer.rtcp_port, origin_addr: answer.origin_addr, media_port: answer.media_port, answer_ssrc: answer.ssrc, time_range: answer.time_range, fmtp: answer.fmtp, crypto: answer.crypto, dst_port, ssrc, key, bye_rx })
+    }
+
+    //发送INVITE,等待最终应答(200或>=300);其余1xx/2xx忽略
+    async fn invite_exchange(ident: Ident, msg: SipMessage) -> GlobalResult<Response> {
+        let (tx, mut rx) = mpsc::channel(10);
+        let cancel = match &msg {
+            SipMessage::Request(req) => RequestBuilder::build_cancel_request(req).ok(),
+            SipMessage::Response(_) => None,
+        };
+        RequestOutput::new(ident.clone(), msg, Some(tx)).do_send().await?;
+        let pending = PendingInvite { ident: ident.clone(), cancel };
+        while let Some((Some(res), _)) = rx.recv().await {
+            let code = res.status_code.code();
+            debug!("{ident:?} : {code} => {}", res.status_code);
+            if code == 200 || code >= 300 {
+                pending.settle();
+                return Ok(res);
+            }
+        }
+        pending.settle();
+        EventSession::remove_event(&ident);
+        Err(CmdErrorCode::ResponseTimeout.error("摄像机响应超时"))
+    }
+
+    //3xx重定向：ACK后按Contact重发INVITE,对调用方透明;超出跳数或无可用Contact时返回该3xx,按拒绝处理
+    //返回最后一跳的标识、INVITE(非2xx应答的ACK须沿用)与应答
+    async fn follow_redirect<F, Fut>(ident: Ident, msg: SipMessage, max_hops: usize, mut exchange: F) -> GlobalResult<(Ident, SipMessage, Response)>
+    where
+        F: FnMut(Ident, SipMessage) -> Fut,
+        Fut: Future<Output=GlobalResult<Response>>,
+    {
+        let (mut ident, mut msg) = (ident, msg);
+        let mut hops = 0;
+        loop {
+            let res = exchange(ident.clone(), msg.clone()).await?;
+            let code = res.status_code.code();
+            if !(300..400).contains(&code) {
+                return Ok((ident, msg, res));
+            }
+            if hops >= max_hops {
+                warn!("{ident:?} : 重定向超过{max_hops}跳,停止跟随");
+                return Ok((ident, msg, res));
+            }
+            let req = match &msg {
+                SipMessage::Request(req) => req,
+                SipMessage::Response(_) => return Ok((ident, msg, res)),
+            };
+            let (next_ident, next_msg) = match RequestBuilder::build_redirect_request(&ident, req, &res) {
+                Ok(next) => next,
+                Err(_) => return Ok((ident, msg, res)),
+            };
+            //3xx为INVITE事务的最终应答：ACK并结束原事务
+            EventSession::remove_event(&ident);
+            if let Ok(ack) = RequestBuilder::build_ack_request_by_failure_response(req, &res) {
+                let _ = RequestOutput::do_send_off(ident.get_device_id(), ack).hand_log(|msg| warn!("{msg}"));
+            }
+            if let SipMessage::Request(req) = &next_msg {
+                debug!("{ident:?} : {code} 重定向至 {}", req.uri);
+            }
+            hops += 1;
+            ident = next_ident;
+            msg = next_msg;
+        }
+    }
+
+    //200 OK但应答无可用媒体(无m=行、端口为0或无payload)：设备实际拒绝了媒体
+    fn validate_answer(answer: &SdpAnswer) -> GlobalResult<()> {
+        if !answer.usable_media {
+            return Err(CmdErrorCode::MediaRejected.error("设备在SDP中拒绝媒体: 无端口非0且含payload的媒体行"));
+        }
+        //200 OK但媒体不收不发,不会有RTP到达
+        if answer.inactive {
+            return Err(CmdErrorCode::MediaInactive.error("设备在SDP中将媒体置为a=inactive"));
+        }
+        Ok(())
+    }
+
+    //发起SRTP时应答须携带可用密钥,设备以明文应答视为拒绝
+    fn verify_crypto(answer: &SdpAnswer, option: &InviteOption) -> GlobalResult<()> {
+        if option.srtp.is_none() {
+            return Ok(());
+        }
+        match &answer.crypto {
+            Some(crypto) if crypto.master_key_salt().is_some() => Ok(()),
+            Some(crypto) => Err(CmdErrorCode::SrtpRejected.error(&format!("应答SRTP密钥无效: suite = {}", crypto.suite))),
+            None => Err(CmdErrorCode::SrtpRejected.error("发起SRTP但应答无a=crypto")),
+        }
+    }
+
+    //200 OK已建立对话：ACK后立即BYE,失败仅告警;应答缺少From tag时沿用INVITE的tag
+    async fn ack_and_bye(device_id: &String, channel_id: &String, res: &Response, sent_from_tag: Option<&str>) {
+        let tags = Self::dialog_tags(res, sent_from_tag);
+        match (Self::invite_ack(device_id, res), tags) {
+            (Ok((call_id, seq)), Ok((from_tag, to_tag))) => {
+                let _ = Self::send_bye(seq + 1, call_id, device_id, channel_id, &from_tag, &to_tag).await.hand_log(|msg| warn!("应答媒体无效,关闭对话失败: {msg}"));
+            }
+            _ => { warn!("device_id = {device_id},channel_id = {channel_id}: 应答媒体无效,无法关闭对话"); }
+        }
+    }
+
+    //异常设备可能回送超大"SDP",解析前拒绝
+    fn check_sdp_size(body: &[u8], max: usize) -> GlobalResult<()> {
+        if body.len() > max {
+            return Err(CmdErrorCode::SdpTooLarge.error(&format!("SDP过大: {} > {max}字节", body.len())));
+        }
+        Ok(())
+    }
+
+    //开启源地址校验时：o=与c=地址均需落在设备地址范围内
+    fn verify_source(answer: &SdpAnswer, option: &InviteOption) -> GlobalResult<()> {
+        if let Some(range) = &option.source_range {
+            for addr in answer.origin_addr.iter().chain(answer.connection_addrs.iter()) {
+                if !range.contains(addr) {
+                    return Err(CmdErrorCode::SourceMismatch.error(&format!("媒体源地址 {addr} 不在设备地址范围 {range} 内")));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //应答无y=/a=ssrc时沿用offer;数值相同(如前导零差异)视为一致
+    fn verify_ssrc(answer_ssrc: Option<&str>, offered: &str, check: SsrcCheck) -> GlobalResult<String> {
+        let answered = match (check, answer_ssrc) {
+            (SsrcCheck::Off, _) | (_, None) => { return Ok(offered.to_string()); }
+            (_, Some(answered)) => answered.trim(),
+        };
+        let same = answered == offered || matches!((answered.parse::<u32>(), offered.parse::<u32>()), (Ok(a), Ok(b)) if a == b);
+        if same {
+            return Ok(offered.to_string());
+        }
+        if check == SsrcCheck::Accept {
+            warn!("设备未采用offer SSRC: offered = {offered},answered = {answered},采用设备SSRC");
+            return Ok(answered.to_string());
+        }
+        Err(CmdErrorCode::SsrcMismatch.error(&format!("设备SSRC {answered} 与offer {offered} 不一致")))
+    }
+
+    //解析设备应答SDP
+    //sdp_types解析失败时(设备SDP不规范)降级为逐行提取基本信息
+    fn parse_answer(body: &[u8]) -> GlobalResult<SdpAnswer> {
+        let session = match sdp_types::Session::parse(body) {
+            Ok(session) => session,
+            Err(err) => {
+                warn!("应答SDP解析失败,降级提取: {err:?}");
+                return Self::parse_answer_minimal(body);
+            }
+        };
+        let re = Regex::new(r"\s+").unwrap();
+        let mut answer = SdpAnswer::default();
+        answer.ssrc = String::from_utf8_lossy(body).lines()
+            .find_map(|line| line.trim().strip_prefix("y=").map(|ssrc| ssrc.trim().to_string()));
+        answer.time_range = Self::parse_time_range(&String::from_utf8_lossy(body));
+        answer.origin_addr = Some(session.origin.unicast_address.clone());
+        if let Some(connection) = &session.connection {
+            answer.connection_addrs.push(connection.connection_address.clone());
+        }
+        let session_inactive = session.attributes.iter().any(|attr| attr.attribute.eq("inactive"));
+        let mut attr_ssrc = None;
+        for media in session.medias {
+            for connection in &media.connections {
+                answer.connection_addrs.push(connection.connection_address.clone());
+            }
+            if media.media.eq_ignore_ascii_case("video") {
+                if answer.media_port.is_none() {
+                    answer.media_port = Some(media.port);
+                    answer.usable_media = media.port != 0 && media.fmt.split_whitespace().any(|fmt| fmt.parse::<u8>().is_ok());
+                    let direction = media.attributes.iter().find(|attr| Self::is_direction(&attr.attribute));
+                    answer.inactive = direction.map(|attr| attr.attribute.eq("inactive")).unwrap_or(session_inactive);
+                }
+                if attr_ssrc.is_none() {
+                    attr_ssrc = media.attributes.iter().filter(|attr| attr.attribute.eq("ssrc")).find_map(|attr| attr.value.as_deref().and_then(Self::parse_ssrc_attr));
+                }
+                for tp in media.fmt.split_whitespace().filter_map(|fmt| fmt.parse::<u8>().ok()) {
+                    if !answer.payload_order.contains(&tp) {
+                        answer.payload_order.push(tp);
+                    }
+                }
+            }
+            for attr in media.attributes {
+                if attr.attribute.eq("rtpmap") {
+                    if let Some(info) = attr.value {
+                        if let Some((key, val)) = re.replace_all(info.trim(), " ").split_once(" ") {
+                            //payload type非法时仅忽略该rtpmap
+                            let Ok(tp) = key.parse::<u8>() else {
+                                warn!("忽略无效rtpmap: {info}");
+                                continue;
+                            };
+                            let i = val.find('/').unwrap_or(val.len());
+                            answer.media_map.insert(tp, val[0..i].to_uppercase());
+                            if let Some(rate) = Self::parse_clock_rate(val) {
+                                answer.clock_rates.insert(tp, rate);
+                            }
+                        }
+                    }
+                } else if attr.attribute.eq("fmtp") {
+                    if let Some((tp, params)) = attr.value.as_deref().and_then(Self::parse_fmtp) {
+                        answer.fmtp.insert(tp, params);
+                    }
+                } else if attr.attribute.eq("crypto") {
+                    if answer.crypto.is_none() {
+                        answer.crypto = attr.value.as_deref().and_then(SrtpCrypto::parse);
+                    }
+                } else if attr.attribute.eq("rtcp-mux") {
+                    answer.rtcp_mux = true;
+                } else if attr.attribute.eq("rtcp") {
+                    answer.rtcp_port = attr.value.as_deref().and_then(Self::parse_rtcp_port);
+                }
+            }
+        }
+        //兼容旧设备：y=与a=ssrc同时存在时以y=为准
+        answer.ssrc = answer.ssrc.or(attr_ssrc);
+        Ok(answer)
+    }
+
+    //首个t=行：起止时间,格式不符时为None
+    fn parse_time_range(text: &str) -> Option<(u64, u64)> {
+        let value = text.lines().find_map(|line| line.trim().strip_prefix("t="))?;
+        let mut fields = value.split_whitespace().map(|field| field.parse::<u64>());
+        match (fields.next(), fields.next()) {
+            (Some(Ok(st)), Some(Ok(et))) => Some((st, et)),
+            _ => None,
+        }
+    }
+
+    fn is_direction(attr: &str) -> bool {
+        matches!(attr, "sendonly" | "recvonly" | "sendrecv" | "inactive")
+    }
+
+    //a=fmtp值：<payload type> <参数>
+    fn parse_fmtp(value: &str) -> Option<(u8, String)> {
+        let (tp, params) = value.trim().split_once(char::is_whitespace)?;
+        Some((tp.parse::<u8>().ok()?, params.trim().to_string()))
+    }
+
+    //a=rtpmap编码部分：<encoding name>/<clock rate>[/<channels>]
+    fn parse_clock_rate(val: &str) -> Option<u32> {
+        val.split('/').nth(1)?.trim().parse::<u32>().ok()
+    }
+
+    //a=ssrc值(RFC5576)：<ssrc-id> [<attribute>[:<value>]],仅取十进制ssrc-id
+    fn parse_ssrc_attr(value: &str) -> Option<String> {
+        let id = value.split_whitespace().next()?;
+        id.parse::<u32>().ok().map(|_| id.to_string())
+    }
+
+    //a=rtcp值：<port> [IN IP4|IP6 <addr>],仅取端口
+    fn parse_rtcp_port(value: &str) -> Option<u16> {
+        value.split_whitespace().next()?.parse::<u16>().ok()
+    }
+
+    //最小提取：m=video端口与payload、a=rtpmap、y=;无m=video或无可用payload时失败
+    fn parse_answer_minimal(body: &[u8]) -> GlobalResult<SdpAnswer> {
+        let text = String::from_utf8_lossy(body);
+        let re = Regex::new(r"\s+").unwrap();
+        let mut answer = SdpAnswer::default();
+        let mut in_video = false;
+        let mut seen_video = false;
+        let mut seen_media = false;
+        let mut session_inactive = false;
+        let mut video_direction: Option<&str> = None;
+        let mut attr_ssrc = None;
+        for line in text.lines().map(|line| line.trim()) {
+            let Some((key, value)) = line.split_once('=') else { continue; };
+            let value = value.trim();
+            match key {
+                "o" => { answer.origin_addr = value.split_whitespace().nth(5).map(|addr| addr.to_string()); }
+                "c" => {
+                    if let Some(addr) = value.split_whitespace().nth(2) {
+                        answer.connection_addrs.push(addr.to_string());
+                    }
+                }
+                "m" => {
+                    seen_media = true;
+                    let mut fields = value.split_whitespace();
+                    in_video = fields.next().map(|media| media.eq_ignore_ascii_case("video")).unwrap_or(false);
+                    if in_video {
+                        answer.media_port = fields.next().and_then(|port| port.split('/').next()).and_then(|port| port.parse::<u16>().ok());
+                        let payloads: Vec<u8> = fields.skip(1).filter_map(|fmt| fmt.parse::<u8>().ok()).collect();
+                        if !seen_video {
+                            answer.usable_media = answer.media_port.unwrap_or(0) != 0 && !payloads.is_empty();
+                            seen_video = true;
+                        }
+                        for tp in payloads {
+                            if !answer.payload_order.contains(&tp) {
+                                answer.payload_order.push(tp);
+                            }
+                        }
+                    }
+                }
+                "a" if in_video => {
+                    if let Some(info) = value.strip_prefix("rtpmap:") {
+                        if let Some((tp, val)) = re.replace_all(info.trim(), " ").split_once(" ") {
+                            if let Ok(tp) = tp.parse::<u8>() {
+                                let i = val.find('/').unwrap_or(val.len());
+                                answer.media_map.insert(tp, val[0..i].to_uppercase());
+                                if let Some(rate) = Self::parse_clock_rate(&val) {
+                                    answer.clock_rates.insert(tp, rate);
+                                }
+                            }
+                        }
+                    } else if let Some((tp, params)) = value.strip_prefix("fmtp:").and_then(Self::parse_fmtp) {
+                        answer.fmtp.insert(tp, params);
+                    } else if let Some(crypto) = value.strip_prefix("crypto:").filter(|_| answer.crypto.is_none()) {
+                        answer.crypto = SrtpCrypto::parse(crypto);
+                    } else if value == "rtcp-mux" {
+                        answer.rtcp_mux = true;
+                    } else if let Some(rtcp) = value.strip_prefix("rtcp:") {
+                        answer.rtcp_port = Self::parse_rtcp_port(rtcp);
+                    } else if Self::is_direction(value) && video_direction.is_none() {
+                        video_direction = Some(value);
+                    } else if let Some(ssrc) = value.strip_prefix("ssrc:").filter(|_| attr_ssrc.is_none()) {
+                        attr_ssrc = Self::parse_ssrc_attr(ssrc);
+                    }
+                }
+                "a" if !seen_media && value == "inactive" => { session_inactive = true; }
+                "y" => { answer.ssrc = Some(value.to_string()); }
+                "t" => { answer.time_range = Self::parse_time_range(line); }
+                _ => {}
+            }
+        }
+        if answer.media_port.is_none() || (answer.media_map.is_empty() && answer.payload_order.is_empty()) {
+            return Err(GlobalError::new_sys_error("应答SDP无效:缺少m=video或payload", |msg| error!("{msg}")));
+        }
+        answer.inactive = video_direction.map(|direction| direction == "inactive").unwrap_or(session_inactive);
+        answer.ssrc = answer.ssrc.or(attr_ssrc);
+        Ok(answer)
+    }
+}
+
+
+#[cfg(test)]
+#[allow(unused)]
+mod test {
+    use regex::Regex;
+    use crate::gb::handler::cmd::CmdStream;
+    use crate::gb::handler::error_code::CmdErrorCode;
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        common::tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn test_switch_stream_success() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use common::exception::GlobalResult;
+        let closed = AtomicBool::new(false);
+        let res = block_on(CmdStream::switch_stream(async { Ok::<_, common::exception::GlobalError>("playback") }, async {
+            closed.store(true, Ordering::SeqCst);
+            GlobalResult::Ok(())
+        }));
+        assert_eq!(res.unwrap(), "playback");
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_switch_stream_rollback() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use common::exception::GlobalResult;
+        let closed = AtomicBool::new(false);
+        let res: GlobalResult<&str> = block_on(CmdStream::switch_stream(async {
+            Err(CmdErrorCode::InviteRejected.warn("488 Not Acceptable Here"))
+        }, async {
+            closed.store(true, Ordering::SeqCst);
+            GlobalResult::Ok(())
+        }));
+        assert!(res.is_err());
+        //新流未建立时不关闭实时流
+        assert!(!closed.load(Ordering::SeqCst));
+    }
+
+    struct MockProbe {
+        arrive_after: Option<std::time::Duration>,
+    }
+
+    impl super::MediaProbe for MockProbe {
+        fn await_media(&self, _ssrc: String, timeout: std::time::Duration) -> std::pin::Pin<Box<dyn std::future::Future<Output=bool> + Send + '_>> {
+            Box::pin(async move {
+                match self.arrive_after {
+                    Some(delay) if delay < timeout => {
+                        common::tokio::time::sleep(delay).await;
+                        true
+                    }
+                    _ => {
+                        common::tokio::time::sleep(timeout).await;
+                        false
+                    }
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_confirm_media() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+        use common::exception::GlobalResult;
+        let ssrc = "0100000001".to_string();
+        //媒体到达：不BYE
+        let closed = AtomicBool::new(false);
+        let probe = MockProbe { arrive_after: Some(Duration::from_millis(10)) };
+        let res = block_on(CmdStream::confirm_media(&probe, &ssrc, Duration::from_millis(200), async {
+            closed.store(true, Ordering::SeqCst);
+            GlobalResult::Ok(())
+        }));
+        assert!(res.is_ok());
+        assert!(!closed.load(Ordering::SeqCst));
+        //媒体始终未到达：BYE并返回错误
+        let probe = MockProbe { arrive_after: None };
+        let res = block_on(CmdStream::confirm_media(&probe, &ssrc, Duration::from_millis(50), async {
+            closed.store(true, Ordering::SeqCst);
+            GlobalResult::Ok(())
+        }));
+        assert!(res.is_err());
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_bye_rehydrated_dialogs() {
+        use parking_lot::Mutex;
+        use common::exception::GlobalResult;
+        use crate::gb::shared::dialog::{Dialog, DialogState};
+        let state = |call_id: &str, seq: u32| DialogState::build(&Dialog::new("34020000001320000001".to_string(), "34020000001310000001".to_string(),
+                                                                   call_id.to_string(), "local".to_string(), "device".to_string()), seq);
+        let sent = Mutex::new(Vec::new());
+        let done = block_on(CmdStream::bye_each(vec![state("a@gmv", 20), state("b@gmv", 7)], |state| {
+            let sent = &sent;
+            async move {
+                sent.lock().push((state.seq + 1, state.call_id.clone(), state.from_tag.clone(), state.to_tag.clone()));
+                if state.call_id == "b@gmv" {
+                    return Err(CmdErrorCode::ByeTimeout.warn("关闭摄像机直播未响应或超时"));
+                }
+                GlobalResult::Ok(())
+            }
+        }));
+        assert_eq!(done, 1);
+        assert_eq!(*sent.lock(), vec![
+            (21, "a@gmv".to_string(), "local".to_string(), "device".to_string()),
+            (8, "b@gmv".to_string(), "local".to_string(), "device".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_discovery_complete() {
+        use common::tokio::sync::oneshot;
+        use common::tokio::time::{Duration, Instant};
+        use crate::gb::handler::catalog::CatalogSnapshot;
+        use crate::gb::handler::cmd::CmdQuery;
+        use crate::storage::entity::GmvDeviceExt;
+
+        let (info_tx, info_rx) = oneshot::channel();
+        let (catalog_tx, catalog_rx) = oneshot::channel();
+        info_tx.send(GmvDeviceExt::default()).unwrap();
+        catalog_tx.send(CatalogSnapshot { device_id: "34020000001110000001".to_string(), ..Default::default() }).unwrap();
+        let discovery = block_on(async { CmdQuery::await_discovery(Some(info_rx), Some(catalog_rx), Instant::now() + Duration::from_secs(1)).await });
+        assert!(discovery.complete);
+        assert!(discovery.info.is_some());
+        assert_eq!(discovery.catalog.unwrap().device_id, "34020000001110000001");
+    }
+
+    #[test]
+    fn test_discovery_partial_on_timeout() {
+        use common::tokio::sync::oneshot;
+        use common::tokio::time::{Duration, Instant};
+        use crate::gb::handler::catalog::CatalogSnapshot;
+        use crate::gb::handler::cmd::CmdQuery;
+        use crate::storage::entity::GmvDeviceExt;
+
+        let (info_tx, info_rx) = oneshot::channel();
+        //目录始终未返回
+        let (_catalog_tx, catalog_rx) = oneshot::channel::<CatalogSnapshot>();
+        info_tx.send(GmvDeviceExt::default()).unwrap();
+        let discovery = block_on(async { CmdQuery::await_discovery(Some(info_rx), Some(catalog_rx), Instant::now() + Duration::from_millis(50)).await });
+        assert!(!discovery.complete);
+        assert!(discovery.info.is_some());
+        assert!(discovery.catalog.is_none());
+        //目录下发失败：保留设备信息,不等待至超时
+        let (info_tx, info_rx) = oneshot::channel();
+        info_tx.send(GmvDeviceExt::default()).unwrap();
+        let discovery = block_on(async { CmdQuery::await_discovery(Some(info_rx), None, Instant::now() + Duration::from_secs(60)).await });
+        assert!(!discovery.complete);
+        assert!(discovery.info.is_some());
+    }
+
+    #[test]
+    fn test_parse_answer_rtcp_mux() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\na=rtcp-mux\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert!(parsed.rtcp_mux);
+        assert_eq!(parsed.media_map.get(&96).map(|s| s.as_str()), Some("PS"));
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        assert!(!CmdStream::parse_answer(answer.as_bytes()).unwrap().rtcp_mux);
+    }
+
+    #[test]
+    fn test_parse_answer_ipv6() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP6 2001:db8::44\r\ns=Play\r\nc=IN IP6 2001:db8::44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        for parsed in [CmdStream::parse_answer(answer.as_bytes()).unwrap(), CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap()] {
+            assert_eq!(parsed.origin_addr.as_deref(), Some("2001:db8::44"));
+            assert_eq!(parsed.connection_addrs, vec!["2001:db8::44".to_string()]);
+            assert_eq!(parsed.media_port, Some(5514));
+        }
+    }
+
+    #[test]
+    fn test_parse_answer_rtcp_port() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\na=rtcp:6021 IN IP4 10.64.49.44\r\ny=0100000001\r\n";
+        assert_eq!(CmdStream::parse_answer(answer.as_bytes()).unwrap().rtcp_port, Some(6021));
+        assert_eq!(CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap().rtcp_port, Some(6021));
+        let answer = answer.replace("a=rtcp:6021 IN IP4 10.64.49.44\r\n", "");
+        assert_eq!(CmdStream::parse_answer(answer.as_bytes()).unwrap().rtcp_port, None);
+    }
+
+    #[test]
+    fn test_parse_answer_payload_order() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 0 RTP/AVP 96 98\r\na=rtpmap:96 PS/90000\r\na=rtpmap:98 H264/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert_eq!(parsed.payload_order, vec![96, 98]);
+        assert_eq!(parsed.media_map.get(&98).map(|s| s.as_str()), Some("H264"));
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 0 RTP/AVP 98 96\r\na=rtpmap:96 PS/90000\r\na=rtpmap:98 H264/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        assert_eq!(CmdStream::parse_answer(answer.as_bytes()).unwrap().payload_order, vec![98, 96]);
+    }
+
+    #[test]
+    fn test_cseq_desync_recovery() {
+        use std::sync::Mutex;
+        use rsip::{Response, SipMessage};
+        use crate::gb::shared::event::Ident;
+
+        fn response(status: &str) -> Response {
+            let (status, warning) = status.split_once('|').unwrap_or((status, ""));
+            let text = format!("SIP/2.0 {status}\r\nCall-ID: desync\r\nCSeq: 1 INFO\r\n{warning}Content-Length: 0\r\n\r\n");
+            match SipMessage::try_from(text.as_str()).unwrap() {
+                SipMessage::Response(res) => res,
+                SipMessage::Request(_) => unreachable!(),
+            }
+        }
+        assert!(CmdStream::is_cseq_reject(&response("500 Server Internal Error|Warning: 399 ipc \"CSeq too low\"\r\n")));
+        assert!(!CmdStream::is_cseq_reject(&response("500 Server Internal Error")));
+        assert!(CmdStream::is_cseq_reject(&response("400 Bad Request|Warning: 399 ipc \"CSeq out of order\"\r\n")));
+        assert!(!CmdStream::is_cseq_reject(&response("400 Bad Request")));
+        assert!(!CmdStream::is_cseq_reject(&response("481 Call/Transaction Does Not Exist")));
+        assert_eq!(CmdStream::resync_seq(20, 1_700_000_000), 1_700_000_000);
+        assert_eq!(CmdStream::resync_seq(1_800_000_000, 1_700_000_000), 1_800_000_001);
+
+        let exchange = |statuses: Vec<&'static str>| {
+            let sent = Mutex::new(Vec::new());
+            let res = block_on(CmdStream::in_dialog_exchange("desync", 20, |seq| {
+                let mut sent = sent.lock().unwrap();
+                let status = statuses[sent.len().min(statuses.len() - 1)];
+                sent.push(seq);
+                async move { Ok((Ident::new("34020000001110000189".to_string(), "desync".to_string(), format!("{seq} INFO")), Some(response(status)))) }
+            })).unwrap();
+            (res.1.unwrap().status_code.code(), sent.into_inner().unwrap())
+        };
+        //CSeq拒绝后以新序号重发成功
+        let desync = "500 Server Internal Error|Warning: 399 ipc \"CSeq too low\"\r\n";
+        let (code, sent) = exchange(vec![desync, "200 OK"]);
+        assert_eq!(code, 200);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], 20);
+        assert!(sent[1] > 20);
+        //仅重试一次
+        let (code, sent) = exchange(vec![desync]);
+        assert_eq!((code, sent.len()), (500, 2));
+        //非CSeq类失败(含未提及CSeq的500)不重试
+        let (code, sent) = exchange(vec!["500 Server Internal Error"]);
+        assert_eq!((code, sent.len()), (500, 1));
+        let (code, sent) = exchange(vec!["486 Busy Here"]);
+        assert_eq!((code, sent.len()), (486, 1));
+    }
+
+    #[test]
+    fn test_media_choice() {
+        use crate::gb::handler::cmd::MediaChoice;
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=audio 5516 RTP/AVP 8\r\na=rtpmap:8 PCMA/8000\r\nm=video 5514 RTP/AVP 98 96\r\na=rtpmap:98 H264/90000\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        let expected = MediaChoice { kind: "video", payload_type: 98, codec: Some("H264".to_string()), clock_rate: Some(90000), port: 5514, reason: "first video section, first payload with rtpmap" };
+        for parsed in [CmdStream::parse_answer(answer.as_bytes()).unwrap(), CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap()] {
+            assert_eq!(CmdStream::media_choice(&parsed), Some(expected.clone()));
+        }
+        assert_eq!(expected.to_string(), "kind = video,payload_type = 98,codec = H264,clock_rate = 90000,port = 5514,reason = first video section, first payload with rtpmap");
+        //无rtpmap时按GB28181约定
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=sendonly\r\ny=0100000001\r\n";
+        let choice = CmdStream::media_choice(&CmdStream::parse_answer(answer.as_bytes()).unwrap()).unwrap();
+        assert_eq!((choice.codec.as_deref(), choice.clock_rate, choice.reason), (Some("PS"), None, "first video section, GB28181 default payload mapping"));
+    }
+
+    #[test]
+    fn test_parse_answer_ssrc_attr() {
+        use crate::general::model::SsrcCheck;
+
+        //仅a=ssrc(GB28181-2022),无y=
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\na=ssrc:100000001 cname:gb\r\n";
+        for parsed in [CmdStream::parse_answer(answer.as_bytes()).unwrap(), CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap()] {
+            assert_eq!(parsed.ssrc.as_deref(), Some("100000001"));
+            //与offer数值一致,去前导零差异
+            assert_eq!(CmdStream::verify_ssrc(parsed.ssrc.as_deref(), "0100000001", SsrcCheck::Reject).unwrap(), "0100000001");
+        }
+        //两者并存时以y=为准
+        let both = answer.replace("a=ssrc:100000001 cname:gb\r\n", "a=ssrc:100000009\r\ny=0100000001\r\n");
+        for parsed in [CmdStream::parse_answer(both.as_bytes()).unwrap(), CmdStream::parse_answer_minimal(both.as_bytes()).unwrap()] {
+            assert_eq!(parsed.ssrc.as_deref(), Some("0100000001"));
+        }
+        //非数值ssrc-id忽略
+        let invalid = answer.replace("a=ssrc:100000001", "a=ssrc:abc");
+        assert_eq!(CmdStream::parse_answer(invalid.as_bytes()).unwrap().ssrc, None);
+    }
+
+    #[test]
+    fn test_parse_answer_fallback() {
+        //无v=行且o=行字段缺失,sdp_types无法解析
+        let answer = "o=34020000001320000101 0 0\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96  PS/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        assert!(sdp_types::Session::parse(answer.as_bytes()).is_err());
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert_eq!(parsed.media_port, Some(5514));
+        assert_eq!(parsed.payload_order, vec![96]);
+        assert_eq!(parsed.media_map.get(&96).map(|s| s.as_str()), Some("PS"));
+        assert_eq!(parsed.ssrc.as_deref(), Some("0100000001"));
+        assert_eq!(parsed.connection_addrs, vec!["10.64.49.44".to_string()]);
+        //rtpmap的payload type非法时忽略该行,其余应答照常解析
+        let bad_rtpmap = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:PS 96/90000\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\n";
+        let parsed = CmdStream::parse_answer(bad_rtpmap.as_bytes()).unwrap();
+        assert_eq!(parsed.media_map.len(), 1);
+        assert_eq!(parsed.media_map.get(&96).map(|s| s.as_str()), Some("PS"));
+        //连最小信息都缺失时报错
+        assert!(CmdStream::parse_answer(b"s=Play\r\nc=IN IP4 10.64.49.44\r\n").is_err());
+    }
+
+    #[test]
+    fn test_active_streams() {
+        use crate::gb::shared::dialog::{Dialog, DialogSession, StreamMedia};
+        use crate::gb::shared::mem::MemTransport;
+        use crate::general::model::StreamMode;
+        let call_id = "active-131@gmv";
+        let dialog = Dialog::new("34020000001110000131".to_string(), "34020000001320000131".to_string(), call_id.to_string(), "local".to_string(), "device".to_string());
+        let _rx = DialogSession::insert_stream(dialog.clone(), Some(StreamMedia { stream_mode: StreamMode::TcpPassive, ssrc: "0100000131".to_string() }));
+        DialogSession::persist(&dialog, 30);
+        let active = CmdStream::active_streams().into_iter().find(|stream| stream.dialog.get_call_id() == call_id).unwrap();
+        assert_eq!(active.dialog, dialog);
+        assert_eq!(active.seq, Some(30));
+        assert_eq!(active.media.unwrap().ssrc, "0100000131");
+        //BYE收到200后不再列出
+        let device_id = "34020000001110000131";
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id.to_string());
+            let (res, _) = common::tokio::join!(
+                CmdStream::bye_exchange(&"34020000001320000131".to_string(), 31, call_id.to_string(), |seq| async move { in_dialog_request(device_id, call_id, "BYE", seq) }),
+                reply(&mut peer, device_id, 200));
+            assert!(res.is_ok());
+        });
+        assert!(CmdStream::active_streams().iter().all(|stream| stream.dialog.get_call_id() != call_id));
+    }
+
+    fn dialog(device_id: &str, call_id: &str) -> crate::gb::shared::dialog::Dialog {
+        crate::gb::shared::dialog::Dialog::new(device_id.to_string(), "34020000001320000101".to_string(),
+                                               call_id.to_string(), "111".to_string(), "222".to_string())
+    }
+
+    //对话内请求(INFO/BYE),代替依赖数据库的RequestBuilder
+    fn in_dialog_request(device_id: &str, call_id: &str, method: &str, seq: u32) -> common::exception::GlobalResult<(crate::gb::shared::event::Ident, rsip::SipMessage)> {
+        let text = format!("{method} sip:34020000001320000101@10.64.49.44:5060 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK{seq}{seq}\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000101@3402000000.spvmn.cn>;tag=222\r\n\
+Call-ID: {call_id}\r\n\
+CSeq: {seq} {method}\r\n\
+Content-Length: 0\r\n\r\n");
+        let ident = crate::gb::shared::event::Ident::new(device_id.to_string(), call_id.to_string(), format!("{seq} {method}"));
+        Ok((ident, rsip::SipMessage::try_from(text.as_str()).unwrap()))
+    }
+
+    //设备端：取出下发的请求并以code应答
+    async fn reply(peer: &mut crate::gb::shared::mem::MemPeer, device_id: &str, code: u16) {
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        let req = match peer.next_sent().await.unwrap() {
+            rsip::SipMessage::Request(req) => req,
+            rsip::SipMessage::Response(_) => unreachable!(),
+        };
+        let cseq = req.cseq_header().unwrap().value().to_string();
+        let ident = crate::gb::shared::event::Ident::new(device_id.to_string(), req.call_id_header().unwrap().value().to_string(), cseq);
+        peer.respond(&ident, crate::gb::shared::mem::MemPeer::response_to(&req, code, "").unwrap()).await.unwrap();
+    }
+
+    #[test]
+    fn test_481_speed() {
+        use crate::gb::handler::error_code::CmdErrorCode;
+        use crate::gb::shared::dialog::DialogSession;
+        use crate::gb::shared::mem::MemTransport;
+        let device_id = "34020000001110000112";
+        let channel_id = "34020000001320000101".to_string();
+        let mut rx = DialogSession::insert(dialog(device_id, "481-speed"));
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id.to_string());
+            let (res, _) = common::tokio::join!(
+                CmdStream::speed_exchange(&channel_id, 2.0, 20, "481-speed".to_string(), |seq| async move { in_dialog_request(device_id, "481-speed", "INFO", seq) }),
+                reply(&mut peer, device_id, 481));
+            assert_eq!(CmdErrorCode::of(&res.unwrap_err()), Some(CmdErrorCode::DialogGone));
+        });
+        assert!(DialogSession::get("481-speed").is_none());
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_481_seek() {
+        use crate::gb::handler::error_code::CmdErrorCode;
+        use crate::gb::shared::dialog::DialogSession;
+        use crate::gb::shared::mem::MemTransport;
+        let device_id = "34020000001110000212";
+        let channel_id = "34020000001320000101".to_string();
+        let mut rx = DialogSession::insert(dialog(device_id, "481-seek"));
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id.to_string());
+            let (res, _) = common::tokio::join!(
+                CmdStream::seek_exchange(&channel_id, 20, "481-seek".to_string(), |seq| async move { in_dialog_request(device_id, "481-seek", "INFO", seq) }),
+                reply(&mut peer, device_id, 481));
+            assert_eq!(CmdErrorCode::of(&res.unwrap_err()), Some(CmdErrorCode::DialogGone));
+        });
+        assert!(DialogSession::get("481-seek").is_none());
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_481_bye() {
+        use crate::gb::handler::error_code::CmdErrorCode;
+        use crate::gb::shared::dialog::DialogSession;
+        use crate::gb::shared::mem::MemTransport;
+        let device_id = "34020000001110000312";
+        let channel_id = "34020000001320000101".to_string();
+        let _rx = DialogSession::insert(dialog(device_id, "481-bye"));
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id.to_string());
+            //BYE失败时保留本端对话,可重试
+            let (res, _) = common::tokio::join!(
+                CmdStream::bye_exchange(&channel_id, 20, "481-bye".to_string(), |seq| async move { in_dialog_request(device_id, "481-bye", "BYE", seq) }),
+                reply(&mut peer, device_id, 500));
+            assert_eq!(CmdErrorCode::of(&res.unwrap_err()), Some(CmdErrorCode::ByeTimeout));
+            assert!(DialogSession::get("481-bye").is_some());
+            //481：设备端对话已不存在,视为已关闭
+            let (res, _) = common::tokio::join!(
+                CmdStream::bye_exchange(&channel_id, 21, "481-bye".to_string(), |seq| async move { in_dialog_request(device_id, "481-bye", "BYE", seq) }),
+                reply(&mut peer, device_id, 481));
+            assert!(res.is_ok());
+        });
+        assert!(DialogSession::get("481-bye").is_none());
+    }
+
+    #[test]
+    fn test_rtp_port_pool() {
+        use std::sync::Arc;
+        use crate::gb::handler::cmd::RtpPortPool;
+
+        let pool = Arc::new(RtpPortPool::new(30001, 30006));
+        let handles: Vec<_> = (0..2).map(|_| {
+            let pool = pool.clone();
+            std::thread::spawn(move || pool.alloc().unwrap())
+        }).collect();
+        let mut ports: Vec<u16> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        ports.sort();
+        assert_eq!(ports, vec![30002, 30004]);
+        pool.bind("call-a", ports[0]);
+        pool.bind("call-b", ports[1]);
+        assert_eq!(pool.alloc(), Some(30006));
+        assert_eq!(pool.alloc(), None);
+        //BYE后端口归还
+        assert_eq!(pool.release_by_call_id("call-a"), Some(30002));
+        assert_eq!(pool.release_by_call_id("call-a"), None);
+        assert_eq!(pool.alloc(), Some(30002));
+    }
+
+    #[test]
+    fn test_verify_source_mismatch() {
+        use crate::general::model::{AddrRange, InviteOption};
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 203.0.113.9\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert_eq!(parsed.origin_addr.as_deref(), Some("203.0.113.9"));
+        //未开启校验
+        assert!(CmdStream::verify_source(&parsed, &InviteOption::default()).is_ok());
+        let option = InviteOption { source_range: Some(AddrRange::new("10.64.0.0".parse().unwrap(), 16)), ..Default::default() };
+        assert!(CmdStream::verify_source(&parsed, &option).is_err());
+        let answer = answer.replace("203.0.113.9", "10.64.49.44");
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert!(CmdStream::verify_source(&parsed, &option).is_ok());
+    }
+
+    #[test]
+    fn test_parse_answer_time_range() {
+        use crate::gb::handler::cmd::PlayedRange;
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Playback\r\nc=IN IP4 10.64.49.44\r\nt=1704067500 1704069000\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=1100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert_eq!(parsed.time_range, Some((1704067500, 1704069000)));
+        let minimal = CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap();
+        assert_eq!(minimal.time_range, parsed.time_range);
+
+        let mut outcome = outcome_with_range(parsed.time_range);
+        //请求 1704067200-1704070800,设备按录像裁剪
+        assert_eq!(outcome.played_range(1704067200, 1704070800), PlayedRange::Trimmed { st: 1704067500, et: 1704069000 });
+        assert_eq!(outcome.played_range(1704067500, 1704069000), PlayedRange::Exact);
+        outcome.time_range = Some((0, 0));
+        assert_eq!(outcome.played_range(1704067200, 1704070800), PlayedRange::Live);
+        outcome.time_range = None;
+        assert_eq!(outcome.played_range(1704067200, 1704070800), PlayedRange::Unknown);
+    }
+
+    #[test]
+    fn test_validate_answer_port_zero() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 0 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert!(CmdStream::validate_answer(&parsed).is_err());
+        let minimal = CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap();
+        assert!(CmdStream::validate_answer(&minimal).is_err());
+
+        let answer = answer.replace("m=video 0 ", "m=video 5514 ");
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert!(CmdStream::validate_answer(&parsed).is_ok());
+        let minimal = CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap();
+        assert!(CmdStream::validate_answer(&minimal).is_ok());
+
+        //仅音频可用,视频端口为0：视为拒绝
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=audio 5516 RTP/AVP 8\r\na=rtpmap:8 PCMA/8000\r\nm=video 0 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert!(CmdStream::validate_answer(&parsed).is_err());
+        let minimal = CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap();
+        assert!(CmdStream::validate_answer(&minimal).is_err());
+    }
+
+    #[test]
+    fn test_recent_range() {
+        use std::time::Duration;
+        //2024-01-01 01:00:00 +08:00
+        let now = 1704042000;
+        assert_eq!(CmdStream::recent_range(now, Duration::from_secs(600)).unwrap(), (1704041400, 1704042000));
+        assert_eq!(CmdStream::recent_range(now, Duration::from_secs(3600)).unwrap(), (1704038400, 1704042000));
+        assert!(CmdStream::recent_range(now, Duration::from_millis(500)).is_err());
+        assert!(CmdStream::recent_range(600, Duration::from_secs(600)).is_err());
+    }
+
+    #[test]
+    fn test_parse_answer_fmtp() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 98\r\na=rtpmap:98 H264/90000\r\na=fmtp:98 profile-level-id=42e01f;packetization-mode=1\r\na=sendonly\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert_eq!(parsed.fmtp.get(&98).map(|s| s.as_str()), Some("profile-level-id=42e01f;packetization-mode=1"));
+        let minimal = CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap();
+        assert_eq!(minimal.fmtp, parsed.fmtp);
+        assert!(CmdStream::parse_fmtp("x profile-level-id=42e01f").is_none());
+    }
+
+    #[test]
+    fn test_parse_answer_crypto() {
+        use crate::general::model::{InviteOption, SrtpCrypto};
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/SAVP 96\r\na=rtpmap:96 PS/90000\r\na=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:PS1uQCVeeCFCanVmcjkpPywjNWhcYD0mXXtxaVBR|2^20|1:32\r\na=sendonly\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        let crypto = parsed.crypto.clone().unwrap();
+        assert_eq!(crypto.tag, 1);
+        assert_eq!(crypto.suite, SrtpCrypto::DEFAULT_SUITE);
+        assert_eq!(crypto.key_params, "PS1uQCVeeCFCanVmcjkpPywjNWhcYD0mXXtxaVBR");
+        let (key, salt) = crypto.master_key_salt().unwrap();
+        assert_eq!((key.len(), salt.len()), (16, 14));
+        assert_eq!(CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap().crypto, Some(crypto));
+
+        let srtp = InviteOption { srtp: Some(SrtpCrypto::generate()), ..Default::default() };
+        assert!(CmdStream::verify_crypto(&parsed, &srtp).is_ok());
+        //设备以明文应答
+        let plain = CmdStream::parse_answer(answer.replace("a=crypto", "a=x-crypto").as_bytes()).unwrap();
+        assert_eq!(CmdErrorCode::of(&CmdStream::verify_crypto(&plain, &srtp).unwrap_err()), Some(CmdErrorCode::SrtpRejected));
+        assert!(CmdStream::verify_crypto(&plain, &InviteOption::default()).is_ok());
+    }
+
+    fn outcome_with_range(time_range: Option<(u64, u64)>) -> crate::gb::handler::cmd::InviteOutcome {
+        use rsip::SipMessage;
+        let ok = "SIP/2.0 200 OK\r\nCall-ID: playback\r\nCSeq: 1 INVITE\r\nContent-Length: 0\r\n\r\n";
+        let response = match SipMessage::try_from(ok).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        let (_tx, bye_rx) = common::tokio::sync::oneshot::channel();
+        crate::gb::handler::cmd::InviteOutcome {
+            response,
+            media_map: Default::default(),
+            payload_order: vec![96],
+            media: None,
+            from_tag: "local".to_string(),
+            to_tag: "device".to_string(),
+            rtcp_mux: false,
+            origin_addr: None,
+            media_port: Some(5514),
+            answer_ssrc: None,
+            time_range,
+            fmtp: Default::default(),
+            crypto: None,
+            rtcp_port: None,
+            dst_port: 10000,
+            ssrc: "1100000001".to_string(),
+            key: crate::gb::shared::dialog::StreamKey { device_id: "34020000001110000001".to_string(), channel_id: "34020000001320000101".to_string(), ssrc: "1100000001".to_string(), call_id: "playback".to_string() },
+            bye_rx,
+        }
+    }
+
+    #[test]
+    fn test_check_sdp_size() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\n";
+        assert!(CmdStream::check_sdp_size(answer.as_bytes(), 64 * 1024).is_ok());
+        let mut junk = answer.to_string();
+        junk.push_str(&"a=x-junk:0000000000\r\n".repeat(4096));
+        let err = CmdStream::check_sdp_size(junk.as_bytes(), 64 * 1024).unwrap_err();
+        assert!(matches!(err, common::exception::GlobalError::BizErr(ref e) if e.msg.contains("SDP过大")));
+    }
+
+    #[test]
+    fn test_verify_ssrc() {
+        use crate::general::model::SsrcCheck;
+        let offered = "0100000001";
+        //一致
+        assert_eq!(CmdStream::verify_ssrc(Some("0100000001"), offered, SsrcCheck::Reject).unwrap(), offered);
+        assert_eq!(CmdStream::verify_ssrc(None, offered, SsrcCheck::Reject).unwrap(), offered);
+        //不一致：采用设备SSRC
+        assert_eq!(CmdStream::verify_ssrc(Some("0200000009"), offered, SsrcCheck::Accept).unwrap(), "0200000009");
+        //不一致：拒绝
+        assert!(CmdStream::verify_ssrc(Some("0200000009"), offered, SsrcCheck::Reject).is_err());
+        //未开启校验
+        assert_eq!(CmdStream::verify_ssrc(Some("0200000009"), offered, SsrcCheck::Off).unwrap(), offered);
+    }
+
+    #[test]
+    fn test_error_code_per_path() {
+        use std::time::Duration;
+        use crate::general::model::{AddrRange, InviteOption, SsrcCheck};
+        let code = |res: common::exception::GlobalResult<()>| res.err().as_ref().and_then(CmdErrorCode::of);
+
+        assert_eq!(code(CmdStream::verify_ssrc(Some("0200000009"), "0100000001", SsrcCheck::Reject).map(|_| ())), Some(CmdErrorCode::SsrcMismatch));
+        assert_eq!(code(CmdStream::check_sdp_size(&[0u8; 16], 8)), Some(CmdErrorCode::SdpTooLarge));
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 203.0.113.9\r\ns=Play\r\nc=IN IP4 203.0.113.9\r\nt=0 0\r\nm=video 0 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert_eq!(code(CmdStream::validate_answer(&parsed)), Some(CmdErrorCode::MediaRejected));
+        let option = InviteOption { source_range: Some(AddrRange::new("10.64.0.0".parse().unwrap(), 16)), ..Default::default() };
+        assert_eq!(code(CmdStream::verify_source(&parsed, &option)), Some(CmdErrorCode::SourceMismatch));
+        assert_eq!(code(CmdStream::dialog_gone("code-dialog-gone", false)), Some(CmdErrorCode::DialogGone));
+        assert_eq!(code(CmdStream::recent_range(1704042000, Duration::ZERO).map(|_| ())), Some(CmdErrorCode::InvalidParam));
+        let probe = MockProbe { arrive_after: None };
+        let res = block_on(CmdStream::confirm_media(&probe, &"0100000001".to_string(), Duration::from_millis(10), async { Ok(()) }));
+        assert_eq!(code(res), Some(CmdErrorCode::MediaTimeout));
+    }
+
+    #[test]
+    fn test_486_ack_and_error() {
+        use rsip::{Method, SipMessage};
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        let invite = SipMessage::try_from("INVITE sip:34020000001320000101@10.64.49.44:5060 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:25600;rport;branch=z9hG4bK123456789\r\n\
+From: <sip:34020000002000000001@3402000000>;tag=111\r\n\
+To: <sip:34020000001320000101@3402000000>\r\n\
+Call-ID: busy-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n").unwrap();
+        let invite_uri = match &invite {
+            SipMessage::Request(req) => req.uri.clone(),
+            SipMessage::Response(_) => unreachable!(),
+        };
+        let busy = "SIP/2.0 486 Busy Here\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:25600;rport=25600;branch=z9hG4bK123456789\r\n\
+From: <sip:34020000002000000001@3402000000>;tag=111\r\n\
+To: <sip:34020000001320000101@3402000000>;tag=222\r\n\
+Call-ID: busy-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let res = match SipMessage::try_from(busy).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        let (ack, err) = CmdStream::reject_invite(&invite, &res, &res.status_code.to_string());
+        assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::InviteRejected));
+        let ack = match ack.unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        assert_eq!(ack.method, Method::Ack);
+        //Request-URI同原INVITE,而非本端From地址
+        assert_eq!(ack.uri, invite_uri);
+        assert_eq!(ack.cseq_header().unwrap().value(), "20 ACK");
+        assert!(ack.via_header().unwrap().value().contains("branch=z9hG4bK123456789"));
+        assert!(ack.to_header().unwrap().value().contains("tag=222"));
+    }
+
+    #[test]
+    fn test_302_redirect_followed() {
+        use rsip::{Response, SipMessage};
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use crate::gb::shared::event::Ident;
+        let invite = "INVITE sip:34020000001320000101@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK111111111\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000101@3402000000.spvmn.cn>\r\n\
+Call-ID: redirect-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Type: Application/SDP\r\n\
+Content-Length: 0\r\n\r\n";
+        let response = |code: &str, extra: &str, cseq: u32| {
+            let res = format!("SIP/2.0 {code}\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport=15060;branch=z9hG4bK111111111\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000101@3402000000.spvmn.cn>;tag=222\r\n\
+Call-ID: redirect-call-id\r\n\
+CSeq: {cseq} INVITE\r\n{extra}\
+Content-Length: 0\r\n\r\n");
+            match SipMessage::try_from(res.as_str()).unwrap() {
+                SipMessage::Response(res) => res,
+                SipMessage::Request(_) => unreachable!(),
+            }
+        };
+        let moved = "Contact: <sip:34020000001320000101@10.0.0.9:5062>\r\n";
+        let ident = Ident::new("34020000001110000001".to_string(), "redirect-call-id".to_string(), "20 INVITE".to_string());
+
+        //302 -> 重发至Contact -> 200
+        let mut replies: Vec<Response> = vec![response("302 Moved Temporarily", moved, 20), response("200 OK", "", 21)];
+        let mut sent: Vec<(Ident, SipMessage)> = Vec::new();
+        let exchange = |ident: Ident, msg: SipMessage| {
+            sent.push((ident, msg));
+            let res = replies.remove(0);
+            async move { Ok(res) }
+        };
+        let msg = SipMessage::try_from(invite).unwrap();
+        let (final_ident, _, res) = block_on(CmdStream::follow_redirect(ident.clone(), msg, 2, exchange)).unwrap();
+        assert_eq!(res.status_code.code(), 200);
+        assert_eq!(final_ident.get_cs_eq(), "21 INVITE");
+        assert_eq!(final_ident.get_call_id(), "redirect-call-id");
+        assert_eq!(sent.len(), 2);
+        let redirected = match &sent[1].1 {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        assert_eq!(redirected.uri.to_string(), "sip:34020000001320000101@10.0.0.9:5062");
+        assert_eq!(redirected.cseq_header().unwrap().value(), "21 INVITE");
+        assert_eq!(redirected.call_id_header().unwrap().value(), "redirect-call-id");
+        assert!(!redirected.via_header().unwrap().value().contains("branch=z9hG4bK111111111"));
+
+        //重定向环路：跳数用尽后返回3xx
+        let mut count = 0;
+        let looping = |_ident: Ident, _msg: SipMessage| {
+            count += 1;
+            let res = response("302 Moved Temporarily", moved, 19 + count);
+            async move { Ok(res) }
+        };
+        let msg = SipMessage::try_from(invite).unwrap();
+        let (_, last, res) = block_on(CmdStream::follow_redirect(ident, msg, 2, looping)).unwrap();
+        assert_eq!(res.status_code.code(), 302);
+        assert_eq!(count, 3);
+        //最终3xx的ACK沿用最后一跳INVITE的Request-URI
+        let (ack, _) = CmdStream::reject_invite(&last, &res, "302");
+        match ack.unwrap() {
+            SipMessage::Request(ack) => assert_eq!(ack.uri.to_string(), "sip:34020000001320000101@10.0.0.9:5062"),
+            SipMessage::Response(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_mem_transport_invite_success() {
+        use rsip::{Method, SipMessage};
+        use crate::gb::shared::dialog::{DialogSession, StreamMedia};
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MEM_TO_TAG, MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000157".to_string();
+        let channel_id = "34020000001320000157".to_string();
+        let invite = "INVITE sip:34020000001320000157@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK157157157\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000157@3402000000.spvmn.cn>\r\n\
+Call-ID: mem-invite-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let answer = "v=0\r\no=34020000001320000157 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000157\r\n";
+        let ident = Ident::new(device_id.clone(), "mem-invite-call-id".to_string(), "20 INVITE".to_string());
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                assert_eq!(req.method, Method::Invite);
+                peer.respond(&ident, MemPeer::response_to(&req, 100, "").unwrap()).await.unwrap();
+                peer.respond(&ident, MemPeer::response_to(&req, 200, answer).unwrap()).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000157".to_string() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10000", media, &InviteOption::default()), device);
+            let outcome = outcome.unwrap();
+            assert_eq!(outcome.response.status_code.code(), 200);
+            assert_eq!(outcome.to_tag, MEM_TO_TAG);
+            assert_eq!(outcome.media_port, Some(5514));
+            assert_eq!(outcome.dst_port, 10000);
+            assert_eq!(outcome.media_map.get(&96).map(|s| s.as_str()), Some("PS"));
+            assert!(peer.try_sent().is_none());
+            assert!(DialogSession::reusable(&device_id, &channel_id, "10.64.49.218:10000").is_none());
+            DialogSession::remove("mem-invite-call-id");
+        });
+    }
+
+    #[test]
+    fn test_lookup_stream_by_ssrc() {
+        use rsip::SipMessage;
+        use crate::gb::shared::dialog::{DialogSession, StreamKey, StreamMedia};
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000173".to_string();
+        let channel_id = "34020000001320000173".to_string();
+        let invite = "INVITE sip:34020000001320000173@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK173173173\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000173@3402000000.spvmn.cn>\r\n\
+Call-ID: ssrc-lookup-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let answer = "v=0\r\no=34020000001320000173 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000173\r\n";
+        let ident = Ident::new(device_id.clone(), "ssrc-lookup-call-id".to_string(), "20 INVITE".to_string());
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                peer.respond(&ident, MemPeer::response_to(&req, 200, answer).unwrap()).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000173".to_string() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10000", media, &InviteOption::default()), device);
+            let outcome = outcome.unwrap();
+            let expected = StreamKey { device_id: device_id.clone(), channel_id: channel_id.clone(), ssrc: "0100000173".to_string(), call_id: "ssrc-lookup-call-id".to_string() };
+            assert_eq!(outcome.key, expected);
+            assert_eq!(CmdStream::lookup_by_ssrc(100000173), Some(expected));
+            assert!(DialogSession::device_bye("ssrc-lookup-call-id", None, None).is_some());
+            assert_eq!(CmdStream::lookup_by_ssrc(100000173), None);
+        });
+    }
+
+    #[test]
+    fn test_invite_ok_missing_from_tag() {
+        use rsip::SipMessage;
+        use crate::gb::shared::dialog::{DialogSession, StreamMedia};
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MEM_TO_TAG, MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000174".to_string();
+        let channel_id = "34020000001320000174".to_string();
+        let invite = "INVITE sip:34020000001320000174@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK174174174\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000174@3402000000.spvmn.cn>\r\n\
+Call-ID: no-from-tag-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let answer = "v=0\r\no=34020000001320000174 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000174\r\n";
+        let ident = Ident::new(device_id.clone(), "no-from-tag-call-id".to_string(), "20 INVITE".to_string());
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                let mut res = MemPeer::response_to(&req, 200, answer).unwrap();
+                res.headers.retain(|h| !matches!(h, rsip::Header::From(_)));
+                res.headers.push(rsip::headers::From::new("<sip:34020000002000000001@3402000000.spvmn.cn>").into());
+                peer.respond(&ident, res).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000174".to_string() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10000", media, &InviteOption::default()), device);
+            let outcome = outcome.unwrap();
+            assert_eq!(outcome.from_tag, "111");
+            assert_eq!(outcome.to_tag, MEM_TO_TAG);
+            assert_eq!(DialogSession::get("no-from-tag-call-id").map(|d| d.get_from_tag().clone()), Some("111".to_string()));
+            DialogSession::remove("no-from-tag-call-id");
+        });
+    }
+
+    #[test]
+    fn test_answer_media_inactive() {
+        use common::exception::GlobalError;
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=inactive\r\ny=0100000001\r\n";
+        let parsed = CmdStream::parse_answer(answer.as_bytes()).unwrap();
+        assert!(parsed.usable_media && parsed.inactive);
+        let err = CmdStream::validate_answer(&parsed).unwrap_err();
+        assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::MediaInactive));
+        let minimal = CmdStream::parse_answer_minimal(answer.as_bytes()).unwrap();
+        assert!(minimal.inactive);
+
+        //会话级inactive,媒体级未声明方向
+        let session_level = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\na=inactive\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\ny=0100000001\r\n";
+        assert!(CmdStream::parse_answer(session_level.as_bytes()).unwrap().inactive);
+        assert!(CmdStream::parse_answer_minimal(session_level.as_bytes()).unwrap().inactive);
+
+        //媒体级方向优先于会话级
+        let overridden = session_level.replace("a=rtpmap:96 PS/90000\r\n", "a=rtpmap:96 PS/90000\r\na=sendonly\r\n");
+        let parsed = CmdStream::parse_answer(overridden.as_bytes()).unwrap();
+        assert!(!parsed.inactive);
+        assert!(CmdStream::validate_answer(&parsed).is_ok());
+    }
+
+    #[test]
+    fn test_play_live_many_partial() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+        use crate::general::model::{InviteOption, InviteRequest, StreamMode};
+        let requests: Vec<InviteRequest> = (0..6u16).map(|i| InviteRequest {
+            device_id: "34020000001110000178".to_string(),
+            channel_id: format!("3402000000132000{:04}", 100 + i),
+            dst_ip: "10.64.49.218".to_string(),
+            dst_port: 10000 + i * 2,
+            stream_mode: StreamMode::Udp,
+            ssrc: format!("01000001{:02}", i),
+            stream_number: 1,
+            option: InviteOption::default(),
+        }).collect();
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let results = block_on(CmdStream::invite_many(requests, 2, |req| {
+            let (in_flight, peak) = (&in_flight, &peak);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                common::tokio::time::sleep(Duration::from_millis(5)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                //奇数端口序号的通道拒绝
+                if (req.dst_port / 2) % 2 == 1 {
+                    Err(CmdErrorCode::InviteRejected.warn("488 Not Acceptable Here"))
+                } else {
+                    Ok(outcome_with_range(None))
+                }
+            }
+        }));
+        assert_eq!(results.len(), 6);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        for (i, (req, res)) in results.iter().enumerate() {
+            assert_eq!(req.channel_id, format!("3402000000132000{:04}", 100 + i));
+            match res {
+                Ok(_) => assert_eq!(i % 2, 0),
+                Err(err) => {
+                    assert_eq!(i % 2, 1);
+                    assert_eq!(CmdErrorCode::of(err), Some(CmdErrorCode::InviteRejected));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_play_back_segment_range() {
+        use crate::gb::handler::record::RecordSegment;
+        use crate::gb::handler::status::DeviceClock;
+        let device_id = "34020000001110000179".to_string();
+        let channel_id = "34020000001320000179".to_string();
+        let segment = RecordSegment { channel_id: channel_id.clone(), start_time: 1704067200, end_time: 1704070800, ..Default::default() };
+        assert_eq!(CmdStream::segment_range(&device_id, &channel_id, &segment).unwrap(), (1704067200, 1704070800));
+        //设备时钟快30秒：下发时换算回的设备时间与录像段一致
+        DeviceClock::set_drift(&device_id, 30);
+        let (st, et) = CmdStream::segment_range(&device_id, &channel_id, &segment).unwrap();
+        assert_eq!((DeviceClock::to_device_time(&device_id, st), DeviceClock::to_device_time(&device_id, et)), (segment.start_time, segment.end_time));
+        DeviceClock::forget(&device_id);
+
+        assert!(CmdStream::segment_range(&device_id, &"34020000001320000180".to_string(), &segment).is_err());
+        let empty = RecordSegment { channel_id: channel_id.clone(), start_time: 1704070800, end_time: 1704070800, ..Default::default() };
+        assert!(CmdStream::segment_range(&device_id, &channel_id, &empty).is_err());
+    }
+
+    #[test]
+    fn test_parse_speed_scale() {
+        let body = "RTSP/1.0 200 OK\r\nCSeq: 2\r\nScale: 2.000\r\n\r\n";
+        assert_eq!(CmdStream::parse_scale(body.as_bytes()), Some(2.0));
+        assert_eq!(CmdStream::parse_scale(b"RTSP/1.0 200 OK\r\nscale:0.5\r\n"), Some(0.5));
+        assert_eq!(CmdStream::parse_scale(b"RTSP/1.0 200 OK\r\nCSeq: 2\r\n"), None);
+        assert_eq!(CmdStream::parse_scale(b""), None);
+        assert_eq!(CmdStream::parse_scale(b"Scale: abc\r\n"), None);
+    }
+
+    #[test]
+    fn test_play_live_for_web() {
+        use rsip::{Method, SipMessage};
+        use crate::gb::shared::dialog::{DialogSession, StreamMedia};
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode, WebStreamDescriptor};
+        let device_id = "34020000001110000186".to_string();
+        let channel_id = "34020000001320000186".to_string();
+        let invite = "INVITE sip:34020000001320000186@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK186186186\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000186@3402000000.spvmn.cn>\r\n\
+Call-ID: web-invite-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let answer = "v=0\r\no=34020000001320000186 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 98 96\r\na=rtpmap:98 H264/90000\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000186\r\n";
+        let ident = Ident::new(device_id.clone(), "web-invite-call-id".to_string(), "20 INVITE".to_string());
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                peer.respond(&ident, MemPeer::response_to(&req, 200, answer).unwrap()).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000186".to_string() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10000", media, &InviteOption::default()), device);
+            let descriptor = CmdStream::ack_for_web(&device_id, &channel_id, &outcome.unwrap()).unwrap();
+            assert!(matches!(peer.next_sent().await.unwrap(), SipMessage::Request(ref req) if req.method == Method::Ack));
+            assert_eq!(descriptor.mediaPort, 5514);
+            assert_eq!(descriptor.codec, "H264");
+            assert_eq!(descriptor.ssrc, "0100000186");
+            assert_eq!(descriptor.dialogId, "web-invite-call-id");
+            assert_eq!(WebStreamDescriptor::parse_dialog_token(&descriptor.dialogToken),
+                       Some((device_id.clone(), channel_id.clone(), "web-invite-call-id".to_string())));
+            DialogSession::remove("web-invite-call-id");
+        });
+    }
+
+    #[test]
+    fn test_port_parity() {
+        use crate::gb::handler::cmd::PortParity;
+        use crate::gb::handler::error_code::CmdErrorCode;
+        assert_eq!(PortParity::Off.check(10001).unwrap(), 10001);
+        assert_eq!(PortParity::Reject.check(10000).unwrap(), 10000);
+        let err = PortParity::Reject.check(10001).unwrap_err();
+        assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::InvalidParam));
+        assert_eq!(PortParity::Adjust.check(10000).unwrap(), 10000);
+        assert_eq!(PortParity::Adjust.check(10001).unwrap(), 10002);
+        //无相邻偶数端口可用
+        assert!(PortParity::Adjust.check(u16::MAX).is_err());
+    }
+
+    #[test]
+    fn test_poke_catalog_subscription() {
+        use rsip::{Header, Method, SipMessage};
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use crate::gb::handler::cmd::{CatalogPoke, CmdQuery, CmdResponse};
+        use crate::gb::shared::mem::MemTransport;
+        let device_id = "34020000001110000196".to_string();
+        let notify = "NOTIFY sip:34020000002000000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.196:5060;rport;branch=z9hG4bK196196196\r\n\
+From: <sip:34020000001110000196@3402000000>;tag=196\r\n\
+To: <sip:34020000002000000001@3402000000>;tag=local\r\n\
+Call-ID: catalog-subscribe-196\r\n\
+CSeq: 7 NOTIFY\r\n\
+Event: Catalog;id=196\r\n\
+Content-Length: 0\r\n\r\n";
+        let req = match SipMessage::try_from(notify).unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        CmdResponse::remember_catalog_notify(&device_id, &req);
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let mut last_seq = 254;
+            for _ in 0..2 {
+                assert_eq!(CmdQuery::poke_catalog_subscription(&device_id).await.unwrap(), CatalogPoke::Refresh);
+                let sub = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(sub) => sub,
+                    SipMessage::Response(_) => panic!("expect re-SUBSCRIBE"),
+                };
+                assert_eq!(sub.method, Method::Subscribe);
+                assert_eq!(sub.call_id_header().unwrap().value(), "catalog-subscribe-196");
+                //本端tag在前,设备tag在后
+                assert_eq!(sub.from_header().unwrap().tag().unwrap().unwrap().to_string(), "local");
+                assert_eq!(sub.to_header().unwrap().tag().unwrap().unwrap().to_string(), "196");
+                let cseq = sub.cseq_header().unwrap();
+                assert_eq!(cseq.method().unwrap(), Method::Subscribe);
+                //高于初始SUBSCRIBE的随机CSeq(<255)与前次re-SUBSCRIBE
+                let seq = cseq.seq().unwrap();
+                assert!(seq > last_seq);
+                last_seq = seq;
+                let event = sub.headers.iter().find_map(|header| match header {
+                    Header::Event(event) => Some(event.value().to_string()),
+                    _ => None,
+                });
+                assert_eq!(event.as_deref(), Some("Catalog;id=196"));
+            }
+        });
+    }
+
+    #[test]
+    fn test_subscribe_confirmed_lower_expires() {
+        use rsip::SipMessage;
+        use common::tokio::sync::mpsc;
+        use common::tokio::time::Instant;
+        use crate::gb::handler::cmd::CmdQuery;
+        use crate::gb::handler::error_code::CmdErrorCode;
+        let response = |text: &str| match SipMessage::try_from(text).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        let trying = response("SIP/2.0 100 Trying\r\nCall-ID: subscribe-194\r\nCSeq: 20 SUBSCRIBE\r\nContent-Length: 0\r\n\r\n");
+        let ok = response("SIP/2.0 200 OK\r\nCall-ID: subscribe-194\r\nCSeq: 20 SUBSCRIBE\r\nExpires: 600\r\nContent-Length: 0\r\n\r\n");
+        let rejected = response("SIP/2.0 403 Forbidden\r\nCall-ID: subscribe-194\r\nCSeq: 20 SUBSCRIBE\r\nContent-Length: 0\r\n\r\n");
+        block_on(async {
+            let deadline = Instant::now() + std::time::Duration::from_millis(200);
+            let (tx, rx) = mpsc::channel(10);
+            tx.send((Some(trying), Instant::now())).await.unwrap();
+            tx.send((Some(ok), Instant::now())).await.unwrap();
+            //请求3600秒,平台仅授予600秒
+            assert_eq!(CmdQuery::await_subscribe_confirm(rx, 3600, deadline).await.unwrap(), 600);
+
+            let (tx, rx) = mpsc::channel(10);
+            tx.send((Some(rejected), Instant::now())).await.unwrap();
+            let err = CmdQuery::await_subscribe_confirm(rx, 3600, deadline).await.unwrap_err();
+            assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::SubscribeRejected));
+
+            let (_tx, rx) = mpsc::channel(10);
+            let err = CmdQuery::await_subscribe_confirm(rx, 3600, deadline).await.unwrap_err();
+            assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::ResponseTimeout));
+        });
+    }
+
+    #[test]
+    fn test_has_recordings() {
+        use crate::gb::handler::cmd::CmdQuery;
+        use crate::gb::handler::record::RecordInfo;
+        use crate::gb::shared::reply::ReplySession;
+        let empty = "<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n<Response>\r\n<CmdType>RecordInfo</CmdType>\r\n<SN>17430</SN>\r\n<DeviceID>34020000001320000191</DeviceID>\r\n<SumNum>0</SumNum>\r\n<RecordList Num=\"0\">\r\n</RecordList>\r\n</Response>\r\n";
+        let found = "<?xml version=\"1.0\" encoding=\"GB2312\"?>\r\n<Response>\r\n<CmdType>RecordInfo</CmdType>\r\n<SN>17431</SN>\r\n<DeviceID>34020000001320000192</DeviceID>\r\n<SumNum>2</SumNum>\r\n<RecordList Num=\"1\">\r\n<Item>\r\n<DeviceID>34020000001320000192</DeviceID>\r\n<Name>Camera 01</Name>\r\n<StartTime>2024-01-01T08:00:00</StartTime>\r\n<EndTime>2024-01-01T09:00:00</EndTime>\r\n<Type>time</Type>\r\n</Item>\r\n</RecordList>\r\n</Response>\r\n";
+        let empty = RecordInfo::parse(empty.as_bytes()).unwrap();
+        assert_eq!(empty.sum_num, Some(0));
+        assert!(!empty.has_segments());
+        let found = RecordInfo::parse(found.as_bytes()).unwrap();
+        assert_eq!(found.sum_num, Some(2));
+        assert_eq!(found.items.len(), 1);
+        assert_eq!(found.items[0].end_time - found.items[0].start_time, 3600);
+        assert_eq!(found.items[0].record_type.as_deref(), Some("time"));
+        assert!(found.has_segments());
+
+        block_on(async {
+            for info in [empty, found] {
+                let rx = ReplySession::wait_record_info(&info.device_id);
+                let expect = info.has_segments();
+                assert!(ReplySession::reply_record_info(&info.device_id.clone(), info));
+                assert_eq!(CmdQuery::await_record_info(rx).await.unwrap().has_segments(), expect);
+            }
+        });
+    }
+
+    #[test]
+    fn test_invite_selects_es_from_ps_offer() {
+        use rsip::SipMessage;
+        use crate::gb::shared::dialog::{DialogSession, StreamMedia};
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000190".to_string();
+        let channel_id = "34020000001320000190".to_string();
+        let invite = "INVITE sip:34020000001320000190@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK190190190\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000190@3402000000.spvmn.cn>\r\n\
+Call-ID: ps-es-invite-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        //offer 96 PS/98 H264,设备仅应答H264
+        let answer = "v=0\r\no=34020000001320000190 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 98\r\na=rtpmap:98 H264/90000\r\na=sendonly\r\ny=0100000190\r\n";
+        let ident = Ident::new(device_id.clone(), "ps-es-invite-call-id".to_string(), "20 INVITE".to_string());
+        let option = InviteOption { video_payloads: vec![96, 98], ..Default::default() };
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                peer.respond(&ident, MemPeer::response_to(&req, 200, answer).unwrap()).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000190".to_string() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10000", media, &option), device);
+            let choice = outcome.unwrap().media.unwrap();
+            assert_eq!(choice.payload_type, 98);
+            assert_eq!(choice.codec.as_deref(), Some("H264"));
+            assert!(!choice.is_ps());
+            DialogSession::remove("ps-es-invite-call-id");
+        });
+    }
+
+    #[test]
+    fn test_tap_device_invite() {
+        use rsip::{Method, SipMessage};
+        use crate::gb::shared::dialog::{DialogSession, StreamMedia};
+        use crate::gb::shared::event::{EventSession, Ident, TapDirection};
+        use crate::gb::shared::mem::{MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000170".to_string();
+        let channel_id = "34020000001320000170".to_string();
+        let invite = "INVITE sip:34020000001320000170@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK170170170\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=111\r\n\
+To: <sip:34020000001320000170@3402000000.spvmn.cn>\r\n\
+Call-ID: tap-invite-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let answer = "v=0\r\no=34020000001320000170 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000170\r\n";
+        let ident = Ident::new(device_id.clone(), "tap-invite-call-id".to_string(), "20 INVITE".to_string());
+        block_on(async {
+            let mut tap = EventSession::tap_device(&device_id);
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                peer.respond(&ident, MemPeer::response_to(&req, 200, answer).unwrap()).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000170".to_string() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10000", media, &InviteOption::default()), device);
+            assert!(outcome.is_ok());
+            let frame = tap.try_recv().unwrap();
+            assert_eq!(frame.direction, TapDirection::Outbound);
+            assert!(matches!(frame.msg, SipMessage::Request(ref req) if req.method == Method::Invite));
+            let frame = tap.try_recv().unwrap();
+            assert_eq!(frame.direction, TapDirection::Inbound);
+            assert!(matches!(frame.msg, SipMessage::Response(ref res) if res.status_code.code() == 200));
+            assert_eq!(frame.device_id, device_id);
+            //接收端丢弃后不再投递
+            drop(tap);
+            EventSession::tap(&device_id, TapDirection::Outbound, &SipMessage::try_from(invite).unwrap());
+            DialogSession::remove("tap-invite-call-id");
+        });
+    }
+
+    #[test]
+    fn test_invite_reuse_dialog() {
+        use rsip::SipMessage;
+        use crate::gb::shared::dialog::{DialogSession, StreamMedia};
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MEM_TO_TAG, MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000161".to_string();
+        let channel_id = "34020000001320000161".to_string();
+        let dst = "10.64.49.218:10002";
+        let invite = "INVITE sip:34020000001320000161@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK161161161\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=161\r\n\
+To: <sip:34020000001320000161@3402000000.spvmn.cn>\r\n\
+Call-ID: reuse-invite-call-id\r\n\
+CSeq: 30 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let answer = "v=0\r\no=34020000001320000161 0 0 IN IP4 10.64.49.44\r\ns=Play\r\nc=IN IP4 10.64.49.44\r\nt=0 0\r\nm=video 5516 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=sendonly\r\ny=0100000161\r\n";
+        let ident = Ident::new(device_id.clone(), "reuse-invite-call-id".to_string(), "30 INVITE".to_string());
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            let device = async {
+                let req = match peer.next_sent().await.unwrap() {
+                    SipMessage::Request(req) => req,
+                    SipMessage::Response(_) => unreachable!(),
+                };
+                peer.respond(&ident, MemPeer::response_to(&req, 200, answer).unwrap()).await.unwrap();
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000161".to_string() };
+            let option = InviteOption { reuse: true, ..Default::default() };
+            let (outcome, _) = common::tokio::join!(CmdStream::invite_stream(ident.clone(), SipMessage::try_from(invite).unwrap(), &channel_id, dst, media, &option), device);
+            outcome.unwrap();
+        });
+        let stream = DialogSession::reusable(&device_id, &channel_id, dst).unwrap();
+        assert_eq!(stream.dialog.get_call_id(), "reuse-invite-call-id");
+        assert_eq!(stream.dialog.get_from_tag(), "161");
+        assert_eq!(stream.dialog.get_to_tag(), MEM_TO_TAG);
+        //ACK后seq可用于BYE(seq+1)
+        assert_eq!(stream.seq, None);
+        DialogSession::persist(&stream.dialog, 30);
+        assert_eq!(DialogSession::reusable(&device_id, &channel_id, dst).unwrap().seq, Some(30));
+        //其他收流地址不复用
+        assert!(DialogSession::reusable(&device_id, &channel_id, "10.64.49.218:10004").is_none());
+        //BYE后移出复用池
+        assert!(DialogSession::remove(stream.dialog.get_call_id()).is_some());
+        assert!(DialogSession::reusable(&device_id, &channel_id, dst).is_none());
+    }
+
+    #[test]
+    fn test_invite_deadline_spans_redirects() {
+        use std::time::Duration;
+        use rsip::{Method, SipMessage};
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use common::tokio::time::{self, Instant};
+        use crate::gb::shared::dialog::StreamMedia;
+        use crate::gb::shared::event::Ident;
+        use crate::gb::shared::mem::{MemPeer, MemTransport};
+        use crate::general::model::{InviteOption, StreamMode};
+        let device_id = "34020000001110000169".to_string();
+        let channel_id = "34020000001320000169".to_string();
+        let invite = "INVITE sip:34020000001320000169@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:15060;rport;branch=z9hG4bK169169169\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=169\r\n\
+To: <sip:34020000001320000169@3402000000.spvmn.cn>\r\n\
+Call-ID: deadline-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let ident = Ident::new(device_id.clone(), "deadline-call-id".to_string(), "20 INVITE".to_string());
+        let request = |msg: SipMessage| match msg {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            //设备每次延迟30ms回302,两次重定向即超出50ms总时限
+            let device = async {
+                let mut methods = Vec::new();
+                while let Some(msg) = peer.next_sent().await {
+                    let req = request(msg);
+                    methods.push(req.method.clone());
+                    match req.method {
+                        Method::Invite => {
+                            time::sleep(Duration::from_millis(30)).await;
+                            let mut res = MemPeer::response_to(&req, 302, "").unwrap();
+                            res.headers.push(rsip::headers::Contact::new("<sip:34020000001320000169@10.0.0.9:5062>").into());
+                            let cseq = req.cseq_header().unwrap().value().to_string();
+                            let _ = peer.respond(&Ident::new(device_id.clone(), "deadline-call-id".to_string(), cseq), res).await;
+                        }
+                        Method::Cancel => break,
+                        _ => {}
+                    }
+                }
+                methods
+            };
+            let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000169".to_string() };
+            let option = InviteOption { deadline: Some(Instant::now() + Duration::from_millis(50)), ..Default::default() };
+            let invite = CmdStream::within_deadline(option.deadline, CmdStream::invite_stream(ident, SipMessage::try_from(invite).unwrap(), &channel_id, "10.64.49.218:10006", media, &option));
+            let (outcome, methods) = common::tokio::join!(invite, device);
+            match outcome {
+                Err(err) => assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::DeadlineExceeded)),
+                Ok(_) => panic!("expect deadline exceeded"),
+            }
+            //中止时在途的第二次INVITE被CANCEL
+            assert_eq!(methods, vec![Method::Invite, Method::Ack, Method::Invite, Method::Cancel]);
+        });
+    }
+
+    #[test]
+    fn test_ack_watchdog_fires_bye() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+        use common::tokio::sync::oneshot;
+        use crate::gb::shared::dialog::{Dialog, DialogSession};
+        let call_id = "watchdog-call-id";
+        let dialog = Dialog::new("34020000001110000167".to_string(), "34020000001320000167".to_string(), call_id.to_string(), "from-tag".to_string(), "to-tag".to_string());
+        let _bye_rx = DialogSession::insert(dialog.clone());
+        let byed = AtomicBool::new(false);
+        //已ACK,无媒体确认也无BYE：宽限期后看门狗BYE
+        let (_disarm_tx, disarm_rx) = oneshot::channel();
+        let fired = block_on(CmdStream::ack_watchdog(call_id, None, Duration::from_millis(20), None, disarm_rx, async {
+            byed.store(true, Ordering::SeqCst);
+            DialogSession::remove(call_id);
+            Ok(())
+        }));
+        assert!(fired);
+        assert!(byed.load(Ordering::SeqCst));
+        assert!(DialogSession::get(call_id).is_none());
+
+        //媒体已确认：不触发
+        let _bye_rx = DialogSession::insert(dialog);
+        let (disarm_tx, disarm_rx) = oneshot::channel();
+        disarm_tx.send(()).unwrap();
+        byed.store(false, Ordering::SeqCst);
+        let fired = block_on(CmdStream::ack_watchdog(call_id, None, Duration::from_millis(20), None, disarm_rx, async {
+            byed.store(true, Ordering::SeqCst);
+            Ok(())
+        }));
+        assert!(!fired);
+        assert!(!byed.load(Ordering::SeqCst));
+        DialogSession::remove(call_id);
+    }
+
+    #[test]
+    fn test_notify_retransmit_dispatch_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::gb::handler::cmd::CmdNotify;
+        let dispatched = AtomicUsize::new(0);
+        let acked = AtomicUsize::new(0);
+        let notify = || CmdNotify::dispatch_once("34020000001110000164", "20 NOTIFY", Some("164"), async {
+            dispatched.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }, async {
+            acked.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        block_on(async {
+            notify().await.unwrap();
+            notify().await.unwrap();
+        });
+        assert_eq!(dispatched.load(Ordering::SeqCst), 1);
+        assert_eq!(acked.load(Ordering::SeqCst), 2);
+        //SN不同视为新通知
+        block_on(CmdNotify::dispatch_once("34020000001110000164", "20 NOTIFY", Some("165"), async {
+            dispatched.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }, async { Ok(()) })).unwrap();
+        assert_eq!(dispatched.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_keep_alive_auto_reply() {
+        use rsip::SipMessage;
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use crate::gb::handler::cmd::{AutoReply, CmdResponse};
+        let keep_alive = "MESSAGE sip:34020000002000000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 10.64.49.44:5060;rport;branch=z9hG4bK160160160\r\n\
+From: <sip:34020000001110000160@3402000000>;tag=device-tag\r\n\
+To: <sip:34020000002000000001@3402000000>\r\n\
+Call-ID: keepalive-call-id\r\n\
+CSeq: 20 MESSAGE\r\n\
+Content-Type: Application/MANSCDP+xml\r\n\
+Max-Forwards: 70\r\n\
+Content-Length: 0\r\n\r\n";
+        let req = match SipMessage::try_from(keep_alive).unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        let socket_addr: std::net::SocketAddr = "10.64.49.44:5080".parse().unwrap();
+        let res = match CmdResponse::build(AutoReply::Ok, &req, &socket_addr).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        assert_eq!(res.status_code.code(), 200);
+        let via = res.via_header().unwrap().value().to_string();
+        assert!(via.starts_with("SIP/2.0/UDP 10.64.49.44:5060"));
+        assert!(via.contains("branch=z9hG4bK160160160"));
+        //NAT：rport补实际端口,并带received
+        assert!(via.contains("rport=5080") && via.contains("received=10.64.49.44"));
+        assert_eq!(res.from_header().unwrap().value(), req.from_header().unwrap().value());
+        assert!(res.to_header().unwrap().value().starts_with("<sip:34020000002000000001@3402000000>;tag="));
+        assert_eq!(res.call_id_header().unwrap().value(), "keepalive-call-id");
+        assert_eq!(res.cseq_header().unwrap().value(), "20 MESSAGE");
+        match CmdResponse::build(AutoReply::Expired, &req, &socket_addr).unwrap() {
+            SipMessage::Response(res) => assert_eq!(res.status_code.code(), 401),
+            SipMessage::Request(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_require_bad_extension() {
+        use rsip::SipMessage;
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use crate::gb::handler::builder::ResponseBuilder;
+        use crate::gb::handler::parser;
+        let message = "MESSAGE sip:34020000002000000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 10.64.49.44:5060;rport;branch=z9hG4bK180180180\r\n\
+From: <sip:34020000001110000180@3402000000>;tag=device-tag\r\n\
+To: <sip:34020000002000000001@3402000000>\r\n\
+Call-ID: require-call-id\r\n\
+CSeq: 20 MESSAGE\r\n\
+Require: timer, x-gb-unknown\r\n\
+Max-Forwards: 70\r\n\
+Content-Length: 0\r\n\r\n";
+        let req = match SipMessage::try_from(message).unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        let supported = vec!["timer".to_string()];
+        let unsupported = parser::header::unsupported_required(&req, &supported);
+        assert_eq!(unsupported, vec!["x-gb-unknown".to_string()]);
+        let socket_addr: std::net::SocketAddr = "10.64.49.44:5080".parse().unwrap();
+        let res = match ResponseBuilder::build_bad_extension_response(&req, &socket_addr, &unsupported).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        assert_eq!(res.status_code.code(), 420);
+        assert!(res.to_string().contains("Unsupported: x-gb-unknown\r\n"));
+        assert_eq!(res.call_id_header().unwrap().value(), "require-call-id");
+        //全部支持时不拒绝
+        assert!(parser::header::unsupported_required(&req, &["timer".to_string(), "X-GB-Unknown".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_answer_invite_await_ack() {
+        use std::time::Duration;
+        use rsip::SipMessage;
+        use crate::gb::handler::cmd::CmdResponse;
+        use crate::gb::shared::mem::MemTransport;
+        let device_id = "34020000001370000159".to_string();
+        let ok = SipMessage::try_from("SIP/2.0 200 OK\r\nCall-ID: talk-ack-call-id\r\nCSeq: 1 INVITE\r\nContent-Length: 0\r\n\r\n").unwrap();
+        block_on(async {
+            let mut peer = MemTransport::attach(&device_id);
+            //ACK到达前重传200 OK
+            let (tx, mut rx) = common::tokio::sync::oneshot::channel();
+            let device = async {
+                peer.next_sent().await.unwrap();
+                peer.next_sent().await.unwrap();
+                tx.send(()).unwrap();
+            };
+            let intervals = vec![Duration::from_millis(10), Duration::from_millis(20), Duration::from_millis(40)];
+            let (acked, _) = common::tokio::join!(CmdResponse::await_ack(&device_id, ok.clone(), &mut rx, intervals, Duration::from_secs(1)), device);
+            assert!(acked.unwrap());
+            //ACK未到达：事务超时
+            let (_tx, mut rx) = common::tokio::sync::oneshot::channel();
+            let acked = CmdResponse::await_ack(&device_id, ok, &mut rx, vec![Duration::from_millis(10)], Duration::from_millis(50)).await;
+            assert!(!acked.unwrap());
+        });
+        assert!(!CmdResponse::on_ack("unknown-call-id"));
+    }
+
+    #[test]
+    fn test_invite_channel_id() {
+        use rsip::SipMessage;
+        use crate::gb::handler::parser;
+        let invite = |extra: &str| {
+            let text = format!("INVITE sip:34020000001370000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.159:5060;rport;branch=z9hG4bK159159159\r\n\
+From: <sip:34020000001110000159@3402000000>;tag=159\r\n\
+To: <sip:34020000001370000001@3402000000>\r\n\
+Call-ID: talk-159\r\n\
+CSeq: 1 INVITE\r\n\
+{extra}Content-Length: 0\r\n\r\n");
+            match SipMessage::try_from(text.as_str()).unwrap() {
+                SipMessage::Request(req) => req,
+                SipMessage::Response(_) => unreachable!(),
+            }
+        };
+        //Subject发送者ID优先,其次Request-URI
+        let req = invite("Subject: 34020000001370000159:0100000159,34020000002000000001:0\r\n");
+        assert_eq!(parser::header::get_channel_id(&req).as_deref(), Some("34020000001370000159"));
+        assert_eq!(parser::header::get_channel_id(&invite("")).as_deref(), Some("34020000001370000001"));
+    }
+
+    //仅记录当前线程的warn及以上日志,避免并行用例互相干扰
+    struct WarnCapture;
+
+    thread_local! {
+        static WARNS: std::cell::RefCell<Vec<String>> = std::cell::RefCell::new(Vec::new());
+    }
+
+    impl common::log::Log for WarnCapture {
+        fn enabled(&self, _metadata: &common::log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &common::log::Record) {
+            if record.level() <= common::log::Level::Warn {
+                WARNS.with(|warns| warns.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_ack_path_no_warn() {
+        use rsip::SipMessage;
+        static CAPTURE: WarnCapture = WarnCapture;
+        let _ = common::log::set_logger(&CAPTURE);
+        common::log::set_max_level(common::log::LevelFilter::Trace);
+        let ok = "SIP/2.0 200 OK\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:25600;rport=25600;branch=z9hG4bK123456789\r\n\
+From: <sip:34020000002000000001@3402000000>;tag=111\r\n\
+To: <sip:34020000001320000101@3402000000>;tag=222\r\n\
+Call-ID: ok-call-id\r\n\
+CSeq: 20 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let res = match SipMessage::try_from(ok).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => unreachable!(),
+        };
+        let ack = crate::gb::handler::builder::RequestBuilder::build_ack_request_by_response(&res).unwrap();
+        let (call_id, seq) = CmdStream::ack_dialog_id(&ack, common::log::Level::Debug).unwrap();
+        assert_eq!((call_id.as_str(), seq), ("ok-call-id", 20));
+        WARNS.with(|warns| assert!(warns.borrow().is_empty(), "{:?}", warns.borrow()));
+    }
+
+    #[test]
+    fn test_parse_download_speed() {
+        let answer = "v=0\r\no=34020000001320000101 0 0 IN IP4 10.64.49.44\r\ns=Download\r\nc=IN IP4 10.64.49.44\r\nt=1700000000 1700003600\r\nm=video 5514 RTP/AVP 96\r\na=rtpmap:96 PS/90000\r\na=downloadspeed:0\r\ny=1100000001\r\n";
+        assert_eq!(CmdStream::parse_download_speed(answer.as_bytes()), Some(0));
+        let answer = "v=0\r\ns=Download\r\na=downloadspeed:4\r\n";
+        assert_eq!(CmdStream::parse_download_speed(answer.as_bytes()), Some(4));
+        let answer = "v=0\r\ns=Download\r\nm=video 5514 RTP/AVP 96\r\n";
+        assert_eq!(CmdStream::parse_download_speed(answer.as_bytes()), None);
+    }
 
     #[test]
     fn test_parse_sdp() {
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use common::exception::{GlobalError, GlobalResult};
+use common::log::{debug, error};
+use common::once_cell::sync::Lazy;
+use common::serde::Deserialize;
+
+use crate::gb::handler::cmd::{CmdControl, CmdQuery, CmdStream, InviteOutcome};
+use crate::gb::handler::profile::MAIN_STREAM;
+use crate::general::model::{InviteOption, PtzControlModel, StreamMode};
+use crate::gb::handler::error_code::CmdErrorCode;
+
+pub type CmdFuture<'a, T> = Pin<Box<dyn Future<Output=GlobalResult<T>> + 'a>>;
+
+//幂等键记忆窗口
+const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(60);
+
+static IDEMPOTENCY_CACHE: Lazy<Mutex<IdempotencyCache>> = Lazy::new(|| Mutex::new(IdempotencyCache::new(IDEMPOTENCY_WINDOW)));
+
+/// 通用指令：由JSON反序列化后统一校验并分发,如 {"cmd":"query_catalog","device_id":"..."}
+/// 新增指令只需增加变体并在execute_on中分发
+#[derive(Debug, Deserialize)]
+#[serde(crate = "common::serde", tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    QueryDeviceInfo { device_id: String },
+    QueryCatalog { device_id: String },
+    QueryDeviceStatus { device_id: String, channel_id: Option<String> },
+    QueryStorageStatus { device_id: String, channel_id: String },
+    QueryOsd { device_id: String, channel_id: String },
+    QueryStreamProfiles { device_id: String, channel_id: String },
+    //st/et秒级时间戳
+    QueryPositionTrack { device_id: String, channel_id: String, st: u32, et: u32 },
+    SubscribeCatalog { device_id: String },
+    QueryPreset { device_id: String, channel_id: Option<String> },
+    //字段同PtzControlModel(驼峰)
+    Ptz(PtzControlModel),
+    SetOsd { device_id: String, channel_id: String, text: String, show_time: bool },
+    Snapshot { device_id: String, channel_id: String, timeout_ms: u64 },
+    //stream_mode：0-UDP,1-TCP主动,2-TCP被动
+    PlayLive { device_id: String, channel_id: String, dst_ip: String, dst_port: u16, stream_mode: u8, ssrc: String },
+    PlayBack { device_id: String, channel_id: String, dst_ip: String, dst_port: u16, stream_mode: u8, ssrc: String, st: u32, et: u32 },
+    //speed：下载倍速
+    Download { device_id: String, channel_id: String, dst_ip: String, dst_port: u16, stream_mode: u8, ssrc: String, st: u32, et: u32, speed: u8 },
+    //回放对话内控制,对话标识同Bye
+    PlaySpeed { device_id: String, channel_id: String, call_id: String, from_tag: String, to_tag: String, seq: u32, speed: f32 },
+    //seek：相对回放起点的秒数
+    PlaySeek { device_id: String, channel_id: String, call_id: String, from_tag: String, to_tag: String, seq: u32, seek: u32 },
+    Bye { device_id: String, channel_id: String, call_id: String, from_tag: String, to_tag: String, seq: u32 },
+}
+
+/// 指令执行结果
+pub enum CommandOutput {
+    //已下发,结果由设备异步响应
+    Sent,
+    Image(Vec<u8>),
+    Invite(InviteOutcome),
+    //设备应答的倍速
+    Speed(f32),
+    //同一幂等键的指令仍在执行,本次未下发;结果以首次执行为准
+    InFlight,
+}
+
+/// 带幂等键的指令：队列至少一次投递时,同一(设备,键)在窗口内仅执行一次
+/// 如 {"idempotency_key":"k1","cmd":"ptz",...}
+#[derive(Debug, Deserialize)]
+#[serde(crate = "common::serde")]
+pub struct CommandRequest {
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+impl CommandRequest {
+    pub async fn execute(self) -> GlobalResult<CommandOutput> {
+        self.execute_with(&GbCommandHandler, &IDEMPOTENCY_CACHE).await
+    }
+
+    /// 重复指令直接返回缓存结果,不再下发;执行失败时清除键以便重投递重试
+    pub async fn execute_with<H: CommandHandler>(self, handler: &H, cache: &Mutex<IdempotencyCache>) -> GlobalResult<CommandOutput> {
+        let key = match self.idempotency_key {
+            None => { return self.command.execute_on(handler).await; }
+            Some(key) => (self.command.ids().0.clone(), key),
+        };
+        if let Some(cached) = cache.lock().begin(&key, Instant::now()) {
+            debug!("重复指令已忽略: device_id = {},key = {}", key.0, key.1);
+            return cached;
+        }
+        let res = self.command.execute_on(handler).await;
+        cache.lock().finish(&key, &res);
+        res
+    }
+}
+
+//重复指令的返回：点播结果不可复用,返回错误
+#[derive(Debug, Clone)]
+enum CachedOutput {
+    Sent,
+    Image(Vec<u8>),
+    Invite,
+    Speed(f32),
+}
+
+impl CachedOutput {
+    fn output(&self) -> GlobalResult<CommandOutput> {
+        match self {
+            CachedOutput::Sent => Ok(CommandOutput::Sent),
+            CachedOutput::Image(image) => Ok(CommandOutput::Image(image.clone())),
+            CachedOutput::Speed(speed) => Ok(CommandOutput::Speed(*speed)),
+            CachedOutput::Invite => Err(CmdErrorCode::DuplicateCommand.error("重复点播指令,已忽略")),
+        }
+    }
+}
+
+/// 幂等键缓存：(device_id,key):(过期时间,结果);执行中的结果为None
+pub struct IdempotencyCache {
+    window: Duration,
+    entries: HashMap<(String, String), (Instant, Option<CachedOutput>)>,
+}
+
+impl IdempotencyCache {
+    pub fn new(window: Duration) -> Self {
+        Self { window, entries: HashMap::new() }
+    }
+
+    //返回Some表示重复;执行中的重复指令返回InFlight
+    fn begin(&mut self, key: &(String, String), now: Instant) -> Option<GlobalResult<CommandOutput>> {
+        self.entries.retain(|_, (expires, _)| *expires > now);
+        if let Some((_, cached)) = self.entries.get(key) {
+            return Some(cached.as_ref().map(CachedOutput::output).unwrap_or(Ok(CommandOutput::InFlight)));
+        }
+        self.entries.insert(key.clone(), (now + self.window, None));
+        None
+    }
+
+    fn finish(&mut self, key: &(String, String), res: &GlobalResult<CommandOutput>) {
+        let cached = match res {
+            Err(_) => {
+                self.entries.remove(key);
+                return;
+            }
+            Ok(CommandOutput::Sent | CommandOutput::InFlight) => CachedOutput::Sent,
+            Ok(CommandOutput::Image(image)) => CachedOutput::Image(image.clone()),
+            Ok(CommandOutput::Invite(_)) => CachedOutput::Invite,
+            Ok(CommandOutput::Speed(speed)) => CachedOutput::Speed(*speed),
+        };
+        if let Some((_, slot)) = self.entries.get_mut(key) {
+            *slot = Some(cached);
+        }
+    }
+}
+
+/// 指令执行端：默认实现即调用CmdQuery/CmdControl/CmdStream,测试时可替换
+pub trait CommandHandler {
+    fn query(&self, cmd: Command) -> CmdFuture<'_, ()> {
+        Box::pin(async move {
+            match cmd {
+                Command::QueryDeviceInfo { device_id } => CmdQuery::query_device_info(&device_id).await,
+                Command::QueryCatalog { device_id } => CmdQuery::query_device_catalog(&device_id).await,
+                Command::QueryDeviceStatus { device_id, channel_id: None } => CmdQuery::query_device_status(&device_id).await,
+                Command::QueryDeviceStatus { device_id, channel_id: Some(channel_id) } => CmdQuery::query_channel_status(&device_id, &channel_id).await,
+                Command::QueryStorageStatus { device_id, channel_id } => CmdQuery::query_storage_status(&device_id, &channel_id).await,
+                Command::QueryOsd { device_id, channel_id } => CmdQuery::query_osd(&device_id, &channel_id).await,
+                Command::QueryStreamProfiles { device_id, channel_id } => CmdQuery::query_stream_profiles(&device_id, &channel_id).await,
+                Command::QueryPositionTrack { device_id, channel_id, st, et } => CmdQuery::query_position_track(&device_id, &channel_id, st, et).await,
+                Command::SubscribeCatalog { device_id } => CmdQuery::subscribe_device_catalog(&device_id).await,
+                Command::QueryPreset { device_id, channel_id } => CmdQuery::query_preset(&device_id, channel_id.as_ref()).await,
+                _ => Err(GlobalError::new_sys_error("非查询指令", |msg| error!("{msg}"))),
+            }
+        })
+    }
+
+    fn control_ptz(&self, model: PtzControlModel) -> CmdFuture<'_, ()> {
+        Box::pin(async move { CmdControl::control_ptz(&model).await })
+    }
+
+    fn set_osd(&self, device_id: String, channel_id: String, text: String, show_time: bool) -> CmdFuture<'_, ()> {
+        Box::pin(async move { CmdControl::set_osd(&device_id, &channel_id, &text, show_time).await })
+    }
+
+    fn snapshot(&self, device_id: String, channel_id: String, timeout: Duration) -> CmdFuture<'_, Vec<u8>> {
+        Box::pin(async move { CmdControl::grab_snapshot(&device_id, &channel_id, timeout).await })
+    }
+
+    fn play_live(&self, device_id: String, channel_id: String, dst_ip: String, dst_port: u16, stream_mode: StreamMode, ssrc: String) -> CmdFuture<'_, InviteOutcome> {
+        Box::pin(async move {
+            CmdStream::play_live_invite(&device_id, &channel_id, &dst_ip, dst_port, stream_mode, &ssrc, MAIN_STREAM, &InviteOption::default()).await
+        })
+    }
+
+    fn play_back(&self, device_id: String, channel_id: String, dst_ip: String, dst_port: u16, stream_mode: StreamMode, ssrc: String, st: u32, et: u32) -> CmdFuture<'_, InviteOutcome> {
+        Box::pin(async move {
+            CmdStream::play_back_invite(&device_id, &channel_id, &dst_ip, dst_port, stream_mode, &ssrc, st, et, &InviteOption::default()).await
+        })
+    }
+
+    fn download(&self, device_id: String, channel_id: String, dst_ip: String, dst_port: u16, stream_mode: StreamMode, ssrc: String, st: u32, et: u32, speed: u8) -> CmdFuture<'_, InviteOutcome> {
+        Box::pin(async move {
+            CmdStream::download_invite(&device_id, &channel_id, &dst_ip, dst_port, stream_mode, &ssrc, st, et, speed, &InviteOption::default()).await
+        })
+    }
+
+    fn play_speed(&self, device_id: String, channel_id: String, call_id: String, from_tag: String, to_tag: String, seq: u32, speed: f32) -> CmdFuture<'_, f32> {
+        Box::pin(async move { CmdStream::play_speed(&device_id, &channel_id, speed, &from_tag, &to_tag, seq, call_id).await })
+    }
+
+    fn play_seek(&self, device_id: String, channel_id: String, call_id: String, from_tag: String, to_tag: String, seq: u32, seek: u32) -> CmdFuture<'_, ()> {
+        Box::pin(async move { CmdStream::play_seek(&device_id, &channel_id, seek, &from_tag, &to_tag, seq, call_id).await })
+    }
+
+    fn bye(&self, device_id: String, channel_id: String, call_id: String, from_tag: String, to_tag: String, seq: u32) -> CmdFuture<'_, ()> {
+        Box::pin(async move { CmdStream::play_bye(seq, call_id, &device_id, &channel_id, &from_tag, &to_tag).await })
+    }
+}
+
+/// 国标信令执行端
+pub struct GbCommandHandler;
+
+impl CommandHandler for GbCommandHandler {}
+
+impl Command {
+    pub async fn execute(self) -> GlobalResult<CommandOutput> {
+        self.execute_on(&GbCommandHandler).await
+    }
+
+    pub async fn execute_on<H: CommandHandler>(self, handler: &H) -> GlobalResult<CommandOutput> {
+        self.validate()?;
+        match self {
+            Command::Ptz(model) => handler.control_ptz(model).await.map(|_| CommandOutput::Sent),
+            Command::SetOsd { device_id, channel_id, text, show_time } => {
+                handler.set_osd(device_id, channel_id, text, show_time).await.map(|_| CommandOutput::Sent)
+            }
+            Command::Snapshot { device_id, channel_id, timeout_ms } => {
+                handler.snapshot(device_id, channel_id, Duration::from_millis(timeout_ms)).await.map(CommandOutput::Image)
+            }
+            Command::PlayLive { device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc } => {
+                let stream_mode = StreamMode::build(stream_mode)?;
+                handler.play_live(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc).await.map(CommandOutput::Invite)
+            }
+            Command::PlayBack { device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et } => {
+                let stream_mode = StreamMode::build(stream_mode)?;
+                handler.play_back(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et).await.map(CommandOutput::Invite)
+            }
+            Command::Download { device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, speed } => {
+                let stream_mode = StreamMode::build(stream_mode)?;
+                handler.download(device_id, channel_id, dst_ip, dst_port, stream_mode, ssrc, st, et, speed).await.map(CommandOutput::Invite)
+            }
+            Command::PlaySpeed { device_id, channel_id, call_id, from_tag, to_tag, seq, speed } => {
+                handler.play_speed(device_id, channel_id, call_id, from_tag, to_tag, seq, speed).await.map(CommandOutput::Speed)
+            }
+            Command::PlaySeek { device_id, channel_id, call_id, from_tag, to_tag, seq, seek } => {
+                handler.play_seek(device_id, channel_id, call_id, from_tag, to_tag, seq, seek).await.map(|_| CommandOutput::Sent)
+            }
+            Command::Bye { device_id, channel_id, call_id, from_tag, to_tag, seq } => {
+                handler.bye(device_id, channel_id, call_id, from_tag, to_tag, seq).await.map(|_| CommandOutput::Sent)
+            }
+            query => handler.query(query).await.map(|_| CommandOutput::Sent),
+        }
+    }
+
+    //统一参数校验：国标编码20位数字
+    fn validate(&self) -> GlobalResult<()> {
+        let (device_id, channel_id) = self.ids();
+        for id in std::iter::once(device_id).chain(channel_id) {
+            if id.len() != 20 || !id.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(CmdErrorCode::InvalidParam.error(&format!("无效国标编码: {id}")));
+            }
+        }
+        Ok(())
+    }
+
+    //(device_id,channel_id)
+    fn ids(&self) -> (&String, Option<&String>) {
+        match self {
+            Command::QueryDeviceInfo { device_id } | Command::QueryCatalog { device_id } | Command::SubscribeCatalog { device_id } => (device_id, None),
+            Command::QueryDeviceStatus { device_id, channel_id } | Command::QueryPreset { device_id, channel_id } => (device_id, channel_id.as_ref()),
+            Command::QueryStorageStatus { device_id, channel_id }
+            | Command::QueryOsd { device_id, channel_id }
+            | Command::QueryStreamProfiles { device_id, channel_id }
+            | Command::QueryPositionTrack { device_id, channel_id, .. }
+            | Command::SetOsd { device_id, channel_id, .. }
+            | Command::Snapshot { device_id, channel_id, .. }
+            | Command::PlayLive { device_id, channel_id, .. }
+            | Command::PlayBack { device_id, channel_id, .. }
+            | Command::Download { device_id, channel_id, .. }
+            | Command::PlaySpeed { device_id, channel_id, .. }
+            | Command::PlaySeek { device_id, channel_id, .. }
+            | Command::Bye { device_id, channel_id, .. } => (device_id, Some(channel_id)),
+            Command::Ptz(model) => (&model.deviceId, Some(&model.channelId)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+    use rsip::SipMessage;
+
+    use common::tokio;
+    use common::tokio::sync::oneshot;
+
+    use crate::gb::handler::cmd::InviteOutcome;
+    use crate::gb::handler::error_code::CmdErrorCode;
+    use crate::gb::shared::dialog::StreamKey;
+    use crate::general::model::{PtzControlModel, StreamMode};
+
+    use super::{CmdFuture, Command, CommandHandler, CommandOutput, CommandRequest, IdempotencyCache};
+
+    #[derive(Default)]
+    struct MockHandler {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl CommandHandler for MockHandler {
+        fn control_ptz(&self, model: PtzControlModel) -> CmdFuture<'_, ()> {
+            self.calls.lock().push(format!("ptz:{}:{}", model.channelId, model.leftRight));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn snapshot(&self, _device_id: String, _channel_id: String, _timeout: Duration) -> CmdFuture<'_, Vec<u8>> {
+            Box::pin(async { Ok(vec![0xFF, 0xD8]) })
+        }
+
+        fn play_speed(&self, _device_id: String, _channel_id: String, call_id: String, _from_tag: String, _to_tag: String, seq: u32, speed: f32) -> CmdFuture<'_, f32> {
+            self.calls.lock().push(format!("speed:{call_id}:{seq}:{speed}"));
+            Box::pin(async move { Ok(speed) })
+        }
+
+        fn play_live(&self, _device_id: String, channel_id: String, _dst_ip: String, dst_port: u16, stream_mode: StreamMode, ssrc: String) -> CmdFuture<'_, InviteOutcome> {
+            self.calls.lock().push(format!("live:{channel_id}:{dst_port}:{}:{ssrc}", stream_mode.name()));
+            Box::pin(async move {
+                let ok = "SIP/2.0 200 OK\r\nCall-ID: live\r\nCSeq: 1 INVITE\r\nContent-Length: 0\r\n\r\n";
+                let response = match SipMessage::try_from(ok).unwrap() {
+                    SipMessage::Response(res) => res,
+                    SipMessage::Request(_) => unreachable!(),
+                };
+                let (_tx, bye_rx) = oneshot::channel();
+                Ok(InviteOutcome {
+                    response,
+                    media_map: HashMap::new(),
+                    payload_order: vec![96],
+                    media: None,
+                    from_tag: "local".to_string(),
+                    to_tag: "device".to_string(),
+                    rtcp_mux: false,
+                    origin_addr: None,
+                    media_port: Some(5514),
+                    answer_ssrc: None,
+                    time_range: None,
+                    fmtp: HashMap::new(),
+                    crypto: None,
+                    rtcp_port: None,
+                    dst_port,
+                    key: StreamKey { device_id: "34020000001110000001".to_string(), channel_id: channel_id.clone(), ssrc: ssrc.clone(), call_id: "live".to_string() },
+                    ssrc,
+                    bye_rx,
+                })
+            })
+        }
+    }
+
+    #[test]
+    fn test_deserialize_and_dispatch() {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let handler = MockHandler::default();
+            let ptz: Command = common::serde_json::from_str(r#"{"cmd":"ptz","deviceId":"34020000001110000001","channelId":"34020000001320000101",
+                "leftRight":1,"upDown":0,"inOut":0,"horizonSpeed":5,"verticalSpeed":0,"zoomSpeed":0}"#).unwrap();
+            assert!(matches!(ptz.execute_on(&handler).await.unwrap(), CommandOutput::Sent));
+
+            let live: Command = common::serde_json::from_str(r#"{"cmd":"play_live","device_id":"34020000001110000001","channel_id":"34020000001320000101",
+                "dst_ip":"10.0.0.2","dst_port":30000,"stream_mode":0,"ssrc":"0100000001"}"#).unwrap();
+            match live.execute_on(&handler).await.unwrap() {
+                CommandOutput::Invite(outcome) => assert_eq!(outcome.ssrc, "0100000001"),
+                _ => panic!("expect invite outcome"),
+            }
+            assert_eq!(*handler.calls.lock(), vec![
+                "ptz:34020000001320000101:1".to_string(),
+                "live:34020000001320000101:30000:UDP:0100000001".to_string(),
+            ]);
+
+            //参数校验失败不分发
+            let bad: Command = common::serde_json::from_str(r#"{"cmd":"snapshot","device_id":"3402","channel_id":"34020000001320000101","timeout_ms":1000}"#).unwrap();
+            assert!(bad.execute_on(&handler).await.is_err());
+            let bad_mode: Command = common::serde_json::from_str(r#"{"cmd":"play_live","device_id":"34020000001110000001","channel_id":"34020000001320000101",
+                "dst_ip":"10.0.0.2","dst_port":30000,"stream_mode":9,"ssrc":"0100000001"}"#).unwrap();
+            assert!(bad_mode.execute_on(&handler).await.is_err());
+            assert_eq!(handler.calls.lock().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_dispatch_playback_control() {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let handler = MockHandler::default();
+            let speed: Command = common::serde_json::from_str(r#"{"cmd":"play_speed","device_id":"34020000001110000001","channel_id":"34020000001320000101",
+                "call_id":"back-140","from_tag":"local","to_tag":"device","seq":3,"speed":2.0}"#).unwrap();
+            assert!(matches!(speed.execute_on(&handler).await.unwrap(), CommandOutput::Speed(speed) if speed == 2.0));
+            assert_eq!(*handler.calls.lock(), vec!["speed:back-140:3:2".to_string()]);
+            //预置位查询可不带通道
+            let preset: Command = common::serde_json::from_str(r#"{"cmd":"query_preset","device_id":"34020000001110000001"}"#).unwrap();
+            assert!(matches!(preset, Command::QueryPreset { channel_id: None, .. }));
+            let download: Command = common::serde_json::from_str(r#"{"cmd":"download","device_id":"34020000001110000001","channel_id":"3402",
+                "dst_ip":"10.0.0.2","dst_port":30000,"stream_mode":0,"ssrc":"1100000001","st":1704038400,"et":1704042000,"speed":4}"#).unwrap();
+            assert!(download.execute_on(&handler).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let handler = MockHandler::default();
+            let cache = Mutex::new(IdempotencyCache::new(Duration::from_secs(60)));
+            let ptz = |key: &str| -> CommandRequest {
+                common::serde_json::from_str(&format!(r#"{{"idempotency_key":"{key}","cmd":"ptz","deviceId":"34020000001110000001","channelId":"34020000001320000101",
+                "leftRight":2,"upDown":0,"inOut":0,"horizonSpeed":5,"verticalSpeed":0,"zoomSpeed":0}}"#)).unwrap()
+            };
+            assert!(matches!(ptz("k1").execute_with(&handler, &cache).await.unwrap(), CommandOutput::Sent));
+            //相同键：不再下发
+            assert!(matches!(ptz("k1").execute_with(&handler, &cache).await.unwrap(), CommandOutput::Sent));
+            assert_eq!(handler.calls.lock().len(), 1);
+            //不同键：下发
+            ptz("k2").execute_with(&handler, &cache).await.unwrap();
+            assert_eq!(handler.calls.lock().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_idempotency_window_expire() {
+        use std::time::Instant;
+        let mut cache = IdempotencyCache::new(Duration::from_millis(100));
+        let key = ("34020000001110000001".to_string(), "k1".to_string());
+        let now = Instant::now();
+        assert!(cache.begin(&key, now).is_none());
+        //首次执行未结束时的重复指令
+        assert!(matches!(cache.begin(&key, now + Duration::from_millis(10)), Some(Ok(CommandOutput::InFlight))));
+        cache.finish(&key, &Ok(CommandOutput::Sent));
+        assert!(matches!(cache.begin(&key, now + Duration::from_millis(20)), Some(Ok(CommandOutput::Sent))));
+        assert!(cache.begin(&key, now + Duration::from_millis(50)).is_some());
+        //窗口过后视为新指令
+        assert!(cache.begin(&key, now + Duration::from_millis(200)).is_none());
+        //失败后清除,允许重试
+        cache.finish(&key, &Err(CmdErrorCode::ResponseTimeout.warn("timeout")));
+        assert!(cache.begin(&key, now + Duration::from_millis(210)).is_none());
+    }
+}
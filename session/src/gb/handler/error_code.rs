@@ -0,0 +1,115 @@
+use common::exception::GlobalError;
+use common::log::{error, warn};
+
+/// 命令层业务错误码：数值对外稳定,新的失败类型分配新值,不复用已有值
+/// 1000-1099 超时; 1100-1199 参数; 1200-1299 资源; 3000-3099 媒体协商
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CmdErrorCode {
+    //设备未响应请求(点播INVITE、查询等待应答)
+    ResponseTimeout = 1000,
+    //倍速INFO未响应
+    SpeedTimeout = 1001,
+    //拖动INFO未响应
+    SeekTimeout = 1002,
+    //BYE未响应
+    ByeTimeout = 1003,
+    //点播成功但媒体流未到达
+    MediaTimeout = 1004,
+    //抓图上传或上传完成通知超时
+    SnapshotTimeout = 1005,
+    //设备未ACK本端200 OK(设备发起的会话)
+    AckTimeout = 1006,
+    //点播超出调用方指定的总时限(含重定向等内部多次交互)
+    DeadlineExceeded = 1007,
+    //参数无效或设备能力不支持
+    InvalidParam = 1100,
+    //幂等键重复的点播指令
+    DuplicateCommand = 1101,
+    //同一事件标识(Call-ID)已有在途请求
+    IdentConflict = 1102,
+    //SUBSCRIBE最终应答>=300,订阅未建立
+    SubscribeRejected = 1103,
+    //在途命令达上限
+    ServerBusy = 1200,
+    //rtp端口池已用完
+    RtpPortExhausted = 1201,
+    //通知缓冲已满(error策略)
+    NotifyOverflow = 1202,
+    //INVITE最终应答>=300
+    InviteRejected = 3000,
+    //设备已无此对话(481)
+    DialogGone = 3001,
+    //应答媒体源地址不在设备地址范围内
+    SourceMismatch = 3002,
+    //应答SSRC与offer不一致
+    SsrcMismatch = 3003,
+    //应答SDP超出上限
+    SdpTooLarge = 3004,
+    //应答SDP无可用媒体行
+    MediaRejected = 3005,
+    //发起SRTP但应答无可用a=crypto
+    SrtpRejected = 3006,
+    //200 OK但应答媒体为a=inactive
+    MediaInactive = 3007,
+}
+
+impl CmdErrorCode {
+    pub const ALL: [CmdErrorCode; 23] = [
+        CmdErrorCode::ResponseTimeout, CmdErrorCode::SpeedTimeout, CmdErrorCode::SeekTimeout, CmdErrorCode::ByeTimeout,
+        CmdErrorCode::MediaTimeout, CmdErrorCode::SnapshotTimeout, CmdErrorCode::InvalidParam, CmdErrorCode::DuplicateCommand,
+        CmdErrorCode::ServerBusy, CmdErrorCode::RtpPortExhausted, CmdErrorCode::InviteRejected, CmdErrorCode::DialogGone,
+        CmdErrorCode::SourceMismatch, CmdErrorCode::SsrcMismatch, CmdErrorCode::SdpTooLarge, CmdErrorCode::MediaRejected,
+        CmdErrorCode::SrtpRejected, CmdErrorCode::AckTimeout, CmdErrorCode::DeadlineExceeded,
+        CmdErrorCode::MediaInactive, CmdErrorCode::IdentConflict, CmdErrorCode::SubscribeRejected,
+        CmdErrorCode::NotifyOverflow,
+    ];
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.code() == code)
+    }
+
+    /// 取业务错误的错误码;系统错误或非本表错误码为None
+    pub fn of(err: &GlobalError) -> Option<Self> {
+        match err {
+            GlobalError::BizErr(e) => Self::from_code(e.code),
+            GlobalError::SysErr(_) => None,
+        }
+    }
+
+    /// 构造业务错误,按error级别记录
+    pub fn error(self, msg: &str) -> GlobalError {
+        GlobalError::new_biz_error(self.code(), msg, |msg| error!("{msg}"))
+    }
+
+    /// 构造业务错误,按warn级别记录(可预期的失败,如参数校验)
+    pub fn warn(self, msg: &str) -> GlobalError {
+        GlobalError::new_biz_error(self.code(), msg, |msg| warn!("{msg}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::CmdErrorCode;
+
+    #[test]
+    fn test_codes_unique_and_stable() {
+        let codes: HashSet<u16> = CmdErrorCode::ALL.iter().map(|c| c.code()).collect();
+        assert_eq!(codes.len(), CmdErrorCode::ALL.len());
+        //已对外使用的数值
+        assert_eq!(CmdErrorCode::ResponseTimeout.code(), 1000);
+        assert_eq!(CmdErrorCode::InvalidParam.code(), 1100);
+        assert_eq!(CmdErrorCode::InviteRejected.code(), 3000);
+        assert_eq!(CmdErrorCode::DialogGone.code(), 3001);
+        for code in CmdErrorCode::ALL {
+            assert_eq!(CmdErrorCode::from_code(code.code()), Some(code));
+            assert_eq!(CmdErrorCode::of(&code.warn("test")), Some(code));
+        }
+        assert_eq!(CmdErrorCode::from_code(999), None);
+    }
+}
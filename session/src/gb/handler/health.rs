@@ -0,0 +1,71 @@
+use crate::gb::handler::limiter::CmdLimiter;
+use crate::gb::handler::position::PositionSubscription;
+use crate::gb::shared::dialog::DialogSession;
+use crate::gb::shared::event::EventSession;
+
+/// 会话运行概况：各项均为当前计数
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct HealthSnapshot {
+    //进行中的媒体对话
+    pub active_streams: usize,
+    //已发出、等待应答的INVITE
+    pub pending_invites: usize,
+    //待触发的延迟事件(如懒查询、订阅续订)
+    pub scheduled_events: usize,
+    //位置订阅
+    pub active_subscriptions: usize,
+    //EventSession登记的事件总数
+    pub event_session_size: usize,
+    //在途命令(全局限流许可)
+    pub in_flight_cmds: usize,
+}
+
+/// 汇总运行概况：仅读取各注册表规模,不做遍历外的额外开销,可频繁调用
+pub fn health_snapshot() -> HealthSnapshot {
+    let (event_session_size, pending_invites) = EventSession::event_counts();
+    HealthSnapshot {
+        active_streams: DialogSession::count(),
+        pending_invites,
+        scheduled_events: EventSession::scheduled_count(),
+        active_subscriptions: PositionSubscription::active_count(),
+        event_session_size,
+        in_flight_cmds: CmdLimiter::global_in_flight(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rsip::SipMessage;
+
+    use common::tokio::time::Instant;
+
+    use crate::gb::shared::dialog::{Dialog, DialogSession};
+    use crate::gb::shared::event::{Container, EventSession, Ident};
+
+    use super::health_snapshot;
+
+    #[test]
+    fn test_health_snapshot() {
+        common::tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let dialog = Dialog::new("34020000001110000198".to_string(), "34020000001320000198".to_string(), "health-stream-198".to_string(), "local".to_string(), "device".to_string());
+            let _bye_rx = DialogSession::insert_stream(dialog, None);
+            let query = "MESSAGE sip:34020000001110000198@3402000000 SIP/2.0\r\n\
+Call-ID: health-lazy-198\r\n\
+CSeq: 20 MESSAGE\r\n\
+Content-Length: 0\r\n\r\n";
+            let ident = Ident::new("34020000001110000198".to_string(), "health-lazy-198".to_string(), "20 MESSAGE".to_string());
+            let msg = SipMessage::try_from(query).unwrap();
+            EventSession::listen_event(&ident, Instant::now() + Duration::from_secs(60), Container::build_actor(ident.clone(), msg, None)).unwrap();
+
+            let snapshot = health_snapshot();
+            assert!(snapshot.active_streams >= 1);
+            assert!(snapshot.scheduled_events >= 1);
+            assert!(snapshot.event_session_size >= snapshot.scheduled_events);
+
+            EventSession::remove_event(&ident);
+            DialogSession::remove("health-stream-198");
+        });
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::exception::GlobalResult;
+use common::once_cell::sync::Lazy;
+use common::serde::Deserialize;
+use common::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use common::tokio::time;
+
+use crate::gb::SessionConf;
+use crate::gb::handler::error_code::CmdErrorCode;
+
+static CMD_LIMITER: Lazy<CmdLimiter> = Lazy::new(|| {
+    let conf = SessionConf::get_session_by_conf();
+    CmdLimiter::new(*conf.get_max_in_flight_cmds(), *conf.get_cmd_busy_policy(), Duration::from_millis(*conf.get_cmd_queue_wait_ms()))
+});
+
+/// 并发命令达上限时的处理策略
+/// queue : 排队等待,超出等待时长返回繁忙
+/// reject : 立即返回繁忙
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Deserialize)]
+#[serde(crate = "common::serde", rename_all = "lowercase")]
+pub enum BusyPolicy {
+    #[default]
+    Queue,
+    Reject,
+}
+
+/// 全局在途命令限制：命令执行期间持有许可,结束(含出错)时自动归还
+pub struct CmdLimiter {
+    semaphore: Arc<Semaphore>,
+    capacity: usize,
+    policy: BusyPolicy,
+    wait: Duration,
+}
+
+impl CmdLimiter {
+    /// capacity = 0 表示不限制
+    pub fn new(capacity: usize, policy: BusyPolicy, wait: Duration) -> Self {
+        let capacity = if capacity == 0 { Semaphore::MAX_PERMITS } else { capacity };
+        Self { semaphore: Arc::new(Semaphore::new(capacity)), capacity, policy, wait }
+    }
+
+    pub async fn acquire(&self) -> GlobalResult<OwnedSemaphorePermit> {
+        let permit = match self.policy {
+            BusyPolicy::Reject => self.semaphore.clone().try_acquire_owned().ok(),
+            BusyPolicy::Queue => time::timeout(self.wait, self.semaphore.clone().acquire_owned()).await.ok().and_then(|res| res.ok()),
+        };
+        permit.ok_or_else(|| CmdErrorCode::ServerBusy.warn(&format!("服务繁忙: 在途命令已达上限 {}", self.capacity)))
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.capacity - self.semaphore.available_permits()
+    }
+
+    /// 命令入口调用：取得全局许可后再下发
+    pub async fn admit() -> GlobalResult<OwnedSemaphorePermit> {
+        CMD_LIMITER.acquire().await
+    }
+
+    /// 当前在途命令数,用于监控
+    pub fn global_in_flight() -> usize {
+        CMD_LIMITER.in_flight()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use common::exception::GlobalError;
+    use common::tokio;
+
+    use super::{BusyPolicy, CmdLimiter};
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn test_reject_over_capacity() {
+        block_on(async {
+            let limiter = CmdLimiter::new(2, BusyPolicy::Reject, Duration::from_millis(50));
+            let first = limiter.acquire().await.unwrap();
+            let _second = limiter.acquire().await.unwrap();
+            assert_eq!(limiter.in_flight(), 2);
+            match limiter.acquire().await {
+                Err(GlobalError::BizErr(ref e)) => assert!(e.msg.contains("服务繁忙")),
+                _ => panic!("expect busy"),
+            }
+            drop(first);
+            assert!(limiter.acquire().await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_queue_over_capacity() {
+        block_on(async {
+            let limiter = CmdLimiter::new(1, BusyPolicy::Queue, Duration::from_millis(50));
+            let first = limiter.acquire().await.unwrap();
+            //等待超时仍无许可
+            assert!(limiter.acquire().await.is_err());
+            //等待期间许可归还,排队命令得到执行
+            let release = async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(first);
+            };
+            let (queued, _) = tokio::join!(limiter.acquire(), release);
+            assert!(queued.is_ok());
+            assert_eq!(limiter.in_flight(), 1);
+        });
+    }
+}
@@ -2,4 +2,22 @@ pub mod requester;
 mod events;
 mod builder;
 pub mod parser;
-pub mod cmd;
\ No newline at end of file
+pub mod cmd;
+pub mod catalog;
+pub mod alarm;
+pub mod ptz;
+pub mod status;
+pub mod snapshot;
+pub mod osd;
+pub mod profile;
+pub mod position;
+pub mod record;
+pub mod subscription;
+pub mod command;
+pub mod limiter;
+pub mod error_code;
+pub mod quirks;
+pub mod buffer;
+mod health;
+
+pub use health::{health_snapshot, HealthSnapshot};
@@ -0,0 +1,105 @@
+use common::exception::GlobalResult;
+
+use crate::gb::handler::parser::xml::*;
+use crate::gb::handler::error_code::CmdErrorCode;
+
+//OSD文字上限：按GB18030编码计字节(汉字2字节)
+pub const OSD_TEXT_MAX_BYTES: usize = 64;
+
+/// OSD叠加配置：对应 ConfigDownload 响应的 OSDConfig
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OsdConfig {
+    pub device_id: String,
+    //通道标题文字,多项时取首项
+    pub text: Option<String>,
+    pub text_enable: bool,
+    pub time_enable: bool,
+}
+
+impl OsdConfig {
+    /// 校验标题文字：非空且不超过OSD_TEXT_MAX_BYTES
+    pub fn check_text(text: &str) -> GlobalResult<()> {
+        if text.trim().is_empty() {
+            return Err(CmdErrorCode::InvalidParam.error("OSD文字不能为空"));
+        }
+        let len: usize = text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum();
+        if len > OSD_TEXT_MAX_BYTES {
+            return Err(CmdErrorCode::InvalidParam.error(&format!("OSD文字过长: {len} > {OSD_TEXT_MAX_BYTES}字节")));
+        }
+        Ok(())
+    }
+
+    pub fn parse(body: &[u8]) -> GlobalResult<Self> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Self {
+        let mut osd = OsdConfig::default();
+        for (k, v) in vs {
+            match &k[..] {
+                RESPONSE_DEVICE_ID => { osd.device_id = v; }
+                RESPONSE_OSD_CONFIG_TEXT_ENABLE => { osd.text_enable = v.trim() == "1"; }
+                RESPONSE_OSD_CONFIG_TIME_ENABLE => { osd.time_enable = v.trim() == "1"; }
+                RESPONSE_OSD_CONFIG_ITEM_TEXT => {
+                    if osd.text.is_none() {
+                        osd.text = Some(v);
+                    }
+                }
+                _ => {}
+            }
+        }
+        osd
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OsdConfig;
+
+    #[test]
+    fn test_check_text() {
+        assert!(OsdConfig::check_text("Gate 01").is_ok());
+        assert!(OsdConfig::check_text(&"通".repeat(32)).is_ok());
+        assert!(OsdConfig::check_text(&"通".repeat(33)).is_err());
+        assert!(OsdConfig::check_text(&"a".repeat(65)).is_err());
+        assert!(OsdConfig::check_text("  ").is_err());
+    }
+
+    #[test]
+    fn test_parse_osd_config() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>ConfigDownload</CmdType>
+<SN>17440</SN>
+<DeviceID>34020000001320000101</DeviceID>
+<Result>OK</Result>
+<OSDConfig>
+<Length>1920</Length>
+<Width>1080</Width>
+<TimeX>10</TimeX>
+<TimeY>10</TimeY>
+<TimeEnable>1</TimeEnable>
+<TimeType>0</TimeType>
+<TextEnable>1</TextEnable>
+<SumNum>2</SumNum>
+<Item>
+<Text>Gate 01</Text>
+<X>10</X>
+<Y>1000</Y>
+</Item>
+<Item>
+<Text>Second</Text>
+<X>10</X>
+<Y>900</Y>
+</Item>
+</OSDConfig>
+</Response>
+"#;
+        let osd = OsdConfig::parse(xml.as_bytes()).unwrap();
+        assert_eq!(osd.device_id, "34020000001320000101");
+        assert_eq!(osd.text.as_deref(), Some("Gate 01"));
+        assert!(osd.text_enable);
+        assert!(osd.time_enable);
+    }
+}
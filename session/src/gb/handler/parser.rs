@@ -1,7 +1,7 @@
 pub mod header {
     use rsip::headers::Via;
-    use rsip::prelude::{HasHeaders, HeadersExt};
-    use rsip::{Header, Request, Response};
+    use rsip::prelude::{HasHeaders, HeadersExt, UntypedHeader};
+    use rsip::{Header, Method, Request, Response};
     use common::anyhow::anyhow;
     use common::exception::{GlobalResult, TransError};
     use common::exception::GlobalError::{SysErr};
@@ -65,6 +65,29 @@ pub mod header {
         Ok(to_uri.host_with_port.to_string())
     }
 
+    /// 请求Require中本端不支持的option-tag;ACK/CANCEL不处理Require(RFC3261 8.2.2.3)
+    pub fn unsupported_required(req: &Request, supported: &[String]) -> Vec<String> {
+        if matches!(req.method, Method::Ack | Method::Cancel) {
+            return Vec::new();
+        }
+        req.headers().iter()
+            .filter_map(|header| match header { Header::Require(require) => Some(require.value()), _ => None })
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty() && !supported.iter().any(|s| s.eq_ignore_ascii_case(tag)))
+            .map(|tag| tag.to_string())
+            .collect()
+    }
+
+    /// INVITE的通道：取Subject中媒体流发送者ID(发送者ID:序列号,接收者ID:序列号),无Subject时取Request-URI用户部分
+    pub fn get_channel_id(req: &Request) -> Option<String> {
+        let sender = req.headers().iter().find_map(|header| match header {
+            Header::Subject(subject) => subject.value().split(',').next()?.split(':').next().map(|id| id.trim().to_string()),
+            _ => None,
+        });
+        sender.filter(|id| !id.is_empty()).or_else(|| req.uri.auth.as_ref().map(|auth| auth.user.clone()))
+    }
+
     pub fn get_gb_version(req: &Request) -> Option<String> {
         for header in req.headers().iter() {
             match header {
@@ -111,12 +134,19 @@ pub mod xml {
     pub const MESSAGE_DEVICE_CONTROL: &str = "DeviceControl";
     pub const MESSAGE_DEVICE_CONFIG: &str = "DeviceConfig";
     pub const MESSAGE_PRESET_QUERY: &str = "PresetQuery";
+    pub const MESSAGE_UPLOAD_SNAPSHOT_FINISHED: &str = "UploadSnapShotFinished";
+    pub const MESSAGE_MOBILE_POSITION: &str = "MobilePosition";
     pub const RESPONSE_DEVICE_ID: &str = "Response,DeviceID";
+    pub const RESPONSE_SN: &str = "Response,SN";
+    pub const QUERY_SN: &str = "Query,SN";
+    pub const RESPONSE_SUM_NUM: &str = "Response,SumNum";
+    pub const RESPONSE_DEVICE_NAME: &str = "Response,DeviceName";
     pub const RESPONSE_MANUFACTURER: &str = "Response,Manufacturer";
     pub const RESPONSE_MODEL: &str = "Response,Model";
     pub const RESPONSE_FIRMWARE: &str = "Response,Firmware";
     pub const RESPONSE_DEVICE_TYPE: &str = "Response,DeviceType";
     pub const RESPONSE_MAX_CAMERA: &str = "Response,MaxCamera";
+    pub const RESPONSE_CHANNEL: &str = "Response,Channel";
     pub const RESPONSE_DEVICE_LIST_ITEM_DEVICE_ID: &str = "Response,DeviceList,Item,DeviceID";
     pub const RESPONSE_DEVICE_LIST_ITEM_NAME: &str = "Response,DeviceList,Item,Name";
     pub const RESPONSE_DEVICE_LIST_ITEM_MANUFACTURER: &str = "Response,DeviceList,Item,Manufacturer";
@@ -135,16 +165,81 @@ pub mod xml {
     pub const RESPONSE_DEVICE_LIST_ITEM_PORT: &str = "Response,DeviceList,Item,Port";
     pub const RESPONSE_DEVICE_LIST_ITEM_PASSWORD: &str = "Response,DeviceList,Item,Password";
     pub const RESPONSE_DEVICE_LIST_ITEM_STATUS: &str = "Response,DeviceList,Item,Status";
+    pub const RESPONSE_RESULT: &str = "Response,Result";
+    pub const RESPONSE_ONLINE: &str = "Response,Online";
+    pub const RESPONSE_STATUS: &str = "Response,Status";
+    pub const RESPONSE_ENCODE: &str = "Response,Encode";
+    pub const RESPONSE_RECORD: &str = "Response,Record";
+    pub const RESPONSE_DEVICE_TIME: &str = "Response,DeviceTime";
+    pub const RESPONSE_ALARM_LIST_ITEM_DEVICE_ID: &str = "Response,AlarmList,Item,DeviceID";
+    pub const RESPONSE_ALARM_LIST_ITEM_ALARM_PRIORITY: &str = "Response,AlarmList,Item,AlarmPriority";
+    pub const RESPONSE_ALARM_LIST_ITEM_ALARM_METHOD: &str = "Response,AlarmList,Item,AlarmMethod";
+    pub const RESPONSE_ALARM_LIST_ITEM_ALARM_TIME: &str = "Response,AlarmList,Item,AlarmTime";
+    pub const RESPONSE_ALARM_LIST_ITEM_ALARM_DESCRIPTION: &str = "Response,AlarmList,Item,AlarmDescription";
+    pub const RESPONSE_ALARM_LIST_ITEM_LONGITUDE: &str = "Response,AlarmList,Item,Longitude";
+    pub const RESPONSE_ALARM_LIST_ITEM_LATITUDE: &str = "Response,AlarmList,Item,Latitude";
+    pub const RESPONSE_ALARM_LIST_ITEM_INFO_ALARM_TYPE: &str = "Response,AlarmList,Item,Info,AlarmType";
+    pub const RESPONSE_SD_CARD_LIST_ITEM_STATUS: &str = "Response,SDCardList,Item,Status";
+    pub const RESPONSE_SD_CARD_LIST_ITEM_CAPACITY: &str = "Response,SDCardList,Item,Capacity";
+    pub const RESPONSE_SD_CARD_LIST_ITEM_TOTAL_SPACE: &str = "Response,SDCardList,Item,TotalSpace";
+    pub const RESPONSE_SD_CARD_LIST_ITEM_USED_SPACE: &str = "Response,SDCardList,Item,UsedSpace";
+    pub const RESPONSE_SD_CARD_LIST_ITEM_FREE_SPACE: &str = "Response,SDCardList,Item,FreeSpace";
+    pub const RESPONSE_OSD_CONFIG_TEXT_ENABLE: &str = "Response,OSDConfig,TextEnable";
+    pub const RESPONSE_OSD_CONFIG_TIME_ENABLE: &str = "Response,OSDConfig,TimeEnable";
+    pub const RESPONSE_OSD_CONFIG_ITEM_TEXT: &str = "Response,OSDConfig,Item,Text";
+    pub const RESPONSE_VIDEO_PARAM_OPT_ITEM_STREAM_NUMBER: &str = "Response,VideoParamOpt,Item,StreamNumber";
+    pub const RESPONSE_VIDEO_PARAM_OPT_ITEM_RESOLUTION: &str = "Response,VideoParamOpt,Item,Resolution";
+    pub const RESPONSE_VIDEO_PARAM_OPT_ITEM_FRAME_RATE: &str = "Response,VideoParamOpt,Item,FrameRate";
+    pub const RESPONSE_VIDEO_PARAM_OPT_ITEM_BIT_RATE: &str = "Response,VideoParamOpt,Item,BitRate";
+    pub const RESPONSE_RECORD_LIST_ITEM_DEVICE_ID: &str = "Response,RecordList,Item,DeviceID";
+    pub const RESPONSE_RECORD_LIST_ITEM_NAME: &str = "Response,RecordList,Item,Name";
+    pub const RESPONSE_RECORD_LIST_ITEM_FILE_PATH: &str = "Response,RecordList,Item,FilePath";
+    pub const RESPONSE_RECORD_LIST_ITEM_TYPE: &str = "Response,RecordList,Item,Type";
+    pub const RESPONSE_RECORD_LIST_ITEM_START_TIME: &str = "Response,RecordList,Item,StartTime";
+    pub const RESPONSE_RECORD_LIST_ITEM_END_TIME: &str = "Response,RecordList,Item,EndTime";
+    pub const RESPONSE_POSITION_LIST_ITEM_TIME: &str = "Response,PositionList,Item,Time";
+    pub const RESPONSE_POSITION_LIST_ITEM_LONGITUDE: &str = "Response,PositionList,Item,Longitude";
+    pub const RESPONSE_POSITION_LIST_ITEM_LATITUDE: &str = "Response,PositionList,Item,Latitude";
+    pub const RESPONSE_POSITION_LIST_ITEM_SPEED: &str = "Response,PositionList,Item,Speed";
+    pub const RESPONSE_POSITION_LIST_ITEM_DIRECTION: &str = "Response,PositionList,Item,Direction";
     pub const SPLIT_CLASS: &str = "?<-0_0->?";
     pub const NOTIFY_DEVICE_ID: &str = "Notify,DeviceID";
+    pub const NOTIFY_SN: &str = "Notify,SN";
     pub const NOTIFY_STATUS: &str = "Notify,Status";
     pub const NOTIFY_TYPE: &str = "Notify,NotifyType";
+    pub const NOTIFY_TIME: &str = "Notify,Time";
+    pub const NOTIFY_LONGITUDE: &str = "Notify,Longitude";
+    pub const NOTIFY_LATITUDE: &str = "Notify,Latitude";
+    pub const NOTIFY_SPEED: &str = "Notify,Speed";
+    pub const NOTIFY_DIRECTION: &str = "Notify,Direction";
 
 
     pub const NOTIFY_ALARM_PRIORITY: &str = "Notify,AlarmPriority";
     pub const NOTIFY_ALARM_TIME: &str = "Notify,AlarmTime";
     pub const NOTIFY_ALARM_METHOD: &str = "Notify,AlarmMethod";
     pub const NOTIFY_INFO_ALARM_TYPE: &str = "Notify,Info,AlarmType";
+    pub const NOTIFY_ALARM_DESCRIPTION: &str = "Notify,AlarmDescription";
+    pub const NOTIFY_SESSION_ID: &str = "Notify,SessionID";
+    pub const NOTIFY_SNAP_SHOT_LIST_SNAP_SHOT_FILE_ID: &str = "Notify,SnapShotList,SnapShotFileID";
+    pub const RESPONSE_DEVICE_LIST_ITEM: &str = "Response,DeviceList,Item";
+    pub const RESPONSE: &str = "Response";
+    //目录Item下的标准元素
+    pub const CATALOG_ITEM_ELEMENTS: &[&str] = &["DeviceID", "Name", "Manufacturer", "Model", "Owner", "CivilCode", "Block", "Address",
+        "Parental", "ParentID", "SafetyWay", "RegisterWay", "CertNum", "Certifiable", "ErrCode", "EndTime", "Secrecy", "IPAddress",
+        "Port", "Password", "Status", "Longitude", "Latitude", "Info", "BusinessGroupID"];
+    //设备状态响应的标准元素
+    pub const DEVICE_STATUS_ELEMENTS: &[&str] = &["CmdType", "SN", "DeviceID", "Result", "Online", "Status", "Reason", "Encode",
+        "Record", "DeviceTime", "Alarmstatus"];
+
+    //厂商扩展元素：prefix下非标准元素,返回相对prefix的路径(如 "HikExt,Level")
+    pub fn extension_key(k: &str, prefix: &str, standard: &[&str]) -> Option<String> {
+        let rel = k.strip_prefix(prefix)?.strip_prefix(',')?;
+        let first = rel.split(',').next()?;
+        if standard.contains(&first) {
+            return None;
+        }
+        Some(rel.to_string())
+    }
 
     pub fn parse_xlm_to_vec(xml: &[u8]) -> GlobalResult<Vec<(String, String)>> {
         let mut xml_reader = Reader::from_reader(xml);
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::Stream;
+use parking_lot::Mutex;
+
+use common::exception::GlobalResult;
+use common::once_cell::sync::Lazy;
+
+use crate::gb::handler::buffer::{self, NotifyReceiver, NotifySender};
+use crate::gb::handler::error_code::CmdErrorCode;
+use crate::gb::handler::parser::xml::*;
+
+//位置订阅接收端：(device_id,channel_id)
+static POSITION_WATCHERS: Lazy<Mutex<HashMap<(String, String), Vec<NotifySender<PositionPoint>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 轨迹点：对应 Response/PositionList/Item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PositionPoint {
+    //yyyy-MM-ddTHH:mm:ss
+    pub time: String,
+    pub longitude: f64,
+    pub latitude: f64,
+    //km/h
+    pub speed: Option<f32>,
+    //方向角,正北为0顺时针
+    pub direction: Option<f32>,
+}
+
+impl PositionPoint {
+    /// 解析轨迹查询响应体,按时间升序
+    pub fn parse_track(body: &[u8]) -> GlobalResult<Vec<Self>> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Vec<Self> {
+        let mut points = Vec::new();
+        let mut point: Option<PositionPoint> = None;
+        for (k, v) in vs {
+            match &k[..] {
+                //以Item下Time作为新轨迹点起始
+                RESPONSE_POSITION_LIST_ITEM_TIME => {
+                    if let Some(pp) = point.take() {
+                        points.push(pp);
+                    }
+                    point = Some(PositionPoint { time: v, ..Default::default() });
+                }
+                _ => {
+                    if let Some(pp) = point.as_mut() {
+                        Self::fill_point(pp, &k, v);
+                    }
+                }
+            }
+        }
+        if let Some(pp) = point.take() {
+            points.push(pp);
+        }
+        //时间格式定长,字典序即时间序
+        points.sort_by(|a, b| a.time.cmp(&b.time));
+        points
+    }
+
+    /// 解析位置通知(Notify/MobilePosition),返回(通知中的DeviceID,位置点)
+    pub fn parse_notify(vs: &[(String, String)]) -> Option<(String, Self)> {
+        let mut channel_id = None;
+        let mut point = PositionPoint::default();
+        for (k, v) in vs {
+            match &k[..] {
+                NOTIFY_DEVICE_ID => { channel_id = Some(v.clone()); }
+                NOTIFY_TIME => { point.time = v.clone(); }
+                NOTIFY_LONGITUDE => { point.longitude = v.parse::<f64>().unwrap_or_default(); }
+                NOTIFY_LATITUDE => { point.latitude = v.parse::<f64>().unwrap_or_default(); }
+                NOTIFY_SPEED => { point.speed = v.parse::<f32>().ok(); }
+                NOTIFY_DIRECTION => { point.direction = v.parse::<f32>().ok(); }
+                _ => {}
+            }
+        }
+        Some((channel_id?, point))
+    }
+
+    /// 推送位置通知至该通道的订阅接收端,返回送达数
+    pub fn dispatch_notify(device_id: &str, vs: &[(String, String)]) -> usize {
+        let Some((channel_id, point)) = Self::parse_notify(vs) else { return 0; };
+        let mut guard = POSITION_WATCHERS.lock();
+        let key = (device_id.to_string(), channel_id);
+        let Some(watchers) = guard.get_mut(&key) else { return 0; };
+        watchers.retain(|tx| !tx.is_closed() && tx.send(point.clone()).is_ok());
+        let delivered = watchers.len();
+        if watchers.is_empty() {
+            guard.remove(&key);
+        }
+        delivered
+    }
+
+    fn fill_point(pp: &mut PositionPoint, k: &str, v: String) {
+        match k {
+            RESPONSE_POSITION_LIST_ITEM_LONGITUDE => { pp.longitude = v.parse::<f64>().unwrap_or_default(); }
+            RESPONSE_POSITION_LIST_ITEM_LATITUDE => { pp.latitude = v.parse::<f64>().unwrap_or_default(); }
+            RESPONSE_POSITION_LIST_ITEM_SPEED => { pp.speed = v.parse::<f32>().ok(); }
+            RESPONSE_POSITION_LIST_ITEM_DIRECTION => { pp.direction = v.parse::<f32>().ok(); }
+            _ => {}
+        }
+    }
+}
+
+/// 移动位置订阅：以Stream接收位置通知;丢弃时注销接收端并执行on_drop(如发送Expires为0的退订)
+pub struct PositionSubscription {
+    key: (String, String),
+    rx: NotifyReceiver<PositionPoint>,
+    on_drop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl PositionSubscription {
+    /// 当前位置订阅数,用于监控
+    pub fn active_count() -> usize {
+        POSITION_WATCHERS.lock().values().map(|txs| txs.len()).sum()
+    }
+
+    /// 上报间隔须大于0且不超过订阅时长(秒)
+    pub fn check(expires: u32, interval: u32) -> GlobalResult<()> {
+        if interval == 0 || interval > expires {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("位置订阅参数无效: interval = {interval}s,expires = {expires}s")));
+        }
+        Ok(())
+    }
+
+    pub fn watch(device_id: &str, channel_id: &str, on_drop: Option<Box<dyn FnOnce() + Send>>) -> Self {
+        let (tx, rx) = buffer::channel_by_conf();
+        let key = (device_id.to_string(), channel_id.to_string());
+        POSITION_WATCHERS.lock().entry(key.clone()).or_default().push(tx);
+        Self { key, rx, on_drop }
+    }
+
+    /// 消费过慢被丢弃的位置通知数
+    pub fn dropped(&self) -> u64 {
+        self.rx.dropped()
+    }
+}
+
+impl Stream for PositionSubscription {
+    type Item = PositionPoint;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+impl Drop for PositionSubscription {
+    fn drop(&mut self) {
+        self.rx.close();
+        let mut guard = POSITION_WATCHERS.lock();
+        if let Some(watchers) = guard.get_mut(&self.key) {
+            watchers.retain(|tx| !tx.is_closed());
+            if watchers.is_empty() {
+                guard.remove(&self.key);
+            }
+        }
+        drop(guard);
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PositionPoint;
+
+    #[test]
+    fn test_parse_track() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>MobilePosition</CmdType>
+<SN>17450</SN>
+<DeviceID>34020000001320000101</DeviceID>
+<SumNum>3</SumNum>
+<PositionList Num="3">
+<Item>
+<Time>2024-01-01T08:00:10</Time>
+<Longitude>116.397128</Longitude>
+<Latitude>39.916527</Latitude>
+<Speed>32.5</Speed>
+<Direction>90</Direction>
+</Item>
+<Item>
+<Time>2024-01-01T08:00:00</Time>
+<Longitude>116.396000</Longitude>
+<Latitude>39.916500</Latitude>
+<Speed>30</Speed>
+<Direction>88.5</Direction>
+</Item>
+<Item>
+<Time>2024-01-01T08:00:20</Time>
+<Longitude>116.398300</Longitude>
+<Latitude>39.916600</Latitude>
+</Item>
+</PositionList>
+</Response>
+"#;
+        let points = PositionPoint::parse_track(xml.as_bytes()).unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].time, "2024-01-01T08:00:00");
+        assert_eq!(points[0].direction, Some(88.5));
+        assert_eq!(points[1].longitude, 116.397128);
+        assert_eq!(points[1].speed, Some(32.5));
+        assert_eq!(points[2].latitude, 39.9166);
+        assert_eq!(points[2].speed, None);
+    }
+
+    #[test]
+    fn test_position_subscription_stream() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use futures_util::StreamExt;
+        use crate::gb::handler::parser::xml::parse_xlm_to_vec;
+        use super::PositionSubscription;
+
+        assert!(PositionSubscription::check(3600, 5).is_ok());
+        assert!(PositionSubscription::check(5, 10).is_err());
+        assert!(PositionSubscription::check(3600, 0).is_err());
+
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Notify>
+<CmdType>MobilePosition</CmdType>
+<SN>187</SN>
+<DeviceID>34020000001320000187</DeviceID>
+<Time>2024-01-01T08:00:10</Time>
+<Longitude>116.397128</Longitude>
+<Latitude>39.916527</Latitude>
+<Speed>32.5</Speed>
+<Direction>90</Direction>
+</Notify>
+"#;
+        let vs = parse_xlm_to_vec(xml.as_bytes()).unwrap();
+        let device_id = "34020000001110000187";
+        //未订阅时不投递
+        assert_eq!(PositionPoint::dispatch_notify(device_id, &vs), 0);
+
+        let unsubscribed = Arc::new(AtomicBool::new(false));
+        let flag = unsubscribed.clone();
+        let mut subscription = PositionSubscription::watch(device_id, "34020000001320000187", Some(Box::new(move || flag.store(true, Ordering::SeqCst))));
+        assert_eq!(PositionPoint::dispatch_notify(device_id, &vs), 1);
+        let point = common::tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(subscription.next()).unwrap();
+        assert_eq!(point.time, "2024-01-01T08:00:10");
+        assert_eq!(point.longitude, 116.397128);
+        assert_eq!(point.speed, Some(32.5));
+        assert_eq!(point.direction, Some(90.0));
+
+        drop(subscription);
+        assert!(unsubscribed.load(Ordering::SeqCst));
+        assert_eq!(PositionPoint::dispatch_notify(device_id, &vs), 0);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+
+use common::exception::GlobalResult;
+use common::once_cell::sync::Lazy;
+
+use crate::gb::handler::error_code::CmdErrorCode;
+use crate::gb::handler::parser::xml::*;
+
+//主码流编号,即设备缺省码流
+pub const MAIN_STREAM: u8 = 1;
+
+//channel_id:设备上报的码流能力
+static STREAM_PROFILES: Lazy<Mutex<HashMap<String, Vec<StreamProfile>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 码流能力：对应 ConfigDownload 响应的 VideoParamOpt/Item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamProfile {
+    //码流编号：1主码流,2子码流,依次类推
+    pub stream_number: u8,
+    //分辨率,原样保留设备上报值(如 6 或 1920x1080)
+    pub resolution: String,
+    pub frame_rate: Option<u32>,
+    //kbps
+    pub bitrate: Option<u32>,
+}
+
+impl StreamProfile {
+    /// 解析 VideoParamOpt 响应体,按码流编号升序
+    pub fn parse(body: &[u8]) -> GlobalResult<Vec<Self>> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Vec<Self> {
+        let mut profiles = Vec::new();
+        let mut profile: Option<StreamProfile> = None;
+        for (k, v) in vs {
+            match &k[..] {
+                //以Item下StreamNumber作为新码流起始
+                RESPONSE_VIDEO_PARAM_OPT_ITEM_STREAM_NUMBER => {
+                    if let Some(sp) = profile.take() {
+                        profiles.push(sp);
+                    }
+                    profile = v.trim().parse::<u8>().ok().map(|stream_number| StreamProfile { stream_number, ..Default::default() });
+                }
+                RESPONSE_VIDEO_PARAM_OPT_ITEM_RESOLUTION => {
+                    if let Some(sp) = profile.as_mut() { sp.resolution = v.trim().to_string(); }
+                }
+                RESPONSE_VIDEO_PARAM_OPT_ITEM_FRAME_RATE => {
+                    if let Some(sp) = profile.as_mut() { sp.frame_rate = v.trim().parse::<u32>().ok(); }
+                }
+                RESPONSE_VIDEO_PARAM_OPT_ITEM_BIT_RATE => {
+                    if let Some(sp) = profile.as_mut() { sp.bitrate = v.trim().parse::<u32>().ok(); }
+                }
+                _ => {}
+            }
+        }
+        if let Some(sp) = profile.take() {
+            profiles.push(sp);
+        }
+        profiles.sort_by_key(|sp| sp.stream_number);
+        profiles
+    }
+
+    /// 缓存通道码流能力,空列表不覆盖已有缓存
+    pub fn store(channel_id: &str, profiles: Vec<StreamProfile>) {
+        if profiles.is_empty() {
+            return;
+        }
+        STREAM_PROFILES.lock().insert(channel_id.to_string(), profiles);
+    }
+
+    /// 已知的通道码流能力;未查询或设备未上报时为None
+    pub fn known(channel_id: &str) -> Option<Vec<StreamProfile>> {
+        STREAM_PROFILES.lock().get(channel_id).cloned()
+    }
+
+    /// 校验码流编号：从1开始;已知通道码流能力时须在其中
+    pub fn check_stream_number(channel_id: &str, stream_number: u8) -> GlobalResult<()> {
+        if stream_number == 0 {
+            return Err(CmdErrorCode::InvalidParam.warn("码流编号无效: 0"));
+        }
+        match Self::known(channel_id) {
+            Some(profiles) if !profiles.iter().any(|sp| sp.stream_number == stream_number) => {
+                Err(CmdErrorCode::InvalidParam.warn(&format!("通道 {channel_id} 不支持码流编号 {stream_number}")))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamProfile;
+
+    #[test]
+    fn test_parse_video_param_opt() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>ConfigDownload</CmdType>
+<SN>17465</SN>
+<DeviceID>34020000001320000101</DeviceID>
+<Result>OK</Result>
+<VideoParamOpt>
+<SumNum>2</SumNum>
+<Item>
+<StreamNumber>2</StreamNumber>
+<Resolution>704x576</Resolution>
+<FrameRate>15</FrameRate>
+<BitRate>512</BitRate>
+</Item>
+<Item>
+<StreamNumber>1</StreamNumber>
+<Resolution>1920x1080</Resolution>
+<FrameRate>25</FrameRate>
+<BitRate>4096</BitRate>
+</Item>
+</VideoParamOpt>
+</Response>
+"#;
+        let profiles = StreamProfile::parse(xml.as_bytes()).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0], StreamProfile { stream_number: 1, resolution: "1920x1080".to_string(), frame_rate: Some(25), bitrate: Some(4096) });
+        assert_eq!(profiles[1].stream_number, 2);
+        assert_eq!(profiles[1].resolution, "704x576");
+        assert_eq!(profiles[1].bitrate, Some(512));
+    }
+
+    #[test]
+    fn test_check_stream_number() {
+        let channel_id = "34020000001320000166";
+        //未知能力时不限制
+        assert!(StreamProfile::check_stream_number(channel_id, 3).is_ok());
+        assert!(StreamProfile::check_stream_number(channel_id, 0).is_err());
+        StreamProfile::store(channel_id, vec![
+            StreamProfile { stream_number: 1, resolution: "1920x1080".to_string(), ..Default::default() },
+            StreamProfile { stream_number: 2, resolution: "704x576".to_string(), ..Default::default() },
+        ]);
+        assert!(StreamProfile::check_stream_number(channel_id, 2).is_ok());
+        assert!(StreamProfile::check_stream_number(channel_id, 3).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{stream, StreamExt};
+use parking_lot::Mutex;
+
+use common::exception::{GlobalResult, TransError};
+use common::log::{error, warn};
+use common::once_cell::sync::Lazy;
+use common::tokio;
+use common::tokio::sync::mpsc;
+use common::tokio::time::{self, Instant};
+
+use crate::gb::handler::cmd::CmdControl;
+use crate::general::model::PtzControlModel;
+
+//合并窗口：同一通道每个窗口至多下发一次
+const PTZ_DEBOUNCE_INTERVAL: Duration = Duration::from_millis(200);
+//无新输入超过该时长则下发停止
+const PTZ_IDLE_STOP: Duration = Duration::from_millis(500);
+//下发移动指令后仍视为转动中的时长,超过则急停时不再处理
+const PTZ_ACTIVE_TTL: Duration = Duration::from_secs(300);
+//急停时并发下发停止指令的上限
+pub const PTZ_STOP_ALL_CONCURRENCY: usize = 32;
+
+static PTZ_TRACKER: Lazy<PtzTracker> = Lazy::new(|| PtzTracker::new(PTZ_ACTIVE_TTL));
+
+static PTZ_DEBOUNCER: Lazy<PtzDebouncer> = Lazy::new(|| {
+    let (tx, mut rx) = mpsc::channel::<PtzControlModel>(64);
+    tokio::spawn(async move {
+        while let Some(model) = rx.recv().await {
+            let _ = CmdControl::control_ptz(&model).await.hand_log(|msg| warn!("ptz: {msg}"));
+        }
+    });
+    PtzDebouncer::new(PTZ_DEBOUNCE_INTERVAL, PTZ_IDLE_STOP, tx)
+});
+
+type Key = (String, String);
+
+/// 云台指令合并：按(device_id,channel_id)将高频输入合并为每个窗口至多一条最新指令,
+/// 输入停止后保证下发停止指令
+pub struct PtzDebouncer {
+    interval: Duration,
+    idle: Duration,
+    out: mpsc::Sender<PtzControlModel>,
+    inputs: Arc<Mutex<HashMap<Key, mpsc::Sender<PtzControlModel>>>>,
+}
+
+impl PtzDebouncer {
+    pub fn new(interval: Duration, idle: Duration, out: mpsc::Sender<PtzControlModel>) -> Self {
+        Self { interval, idle, out, inputs: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// 全局合并下发,需在tokio运行时内调用
+    pub fn submit_global(model: PtzControlModel) {
+        PTZ_DEBOUNCER.submit(model);
+    }
+
+    pub fn submit(&self, model: PtzControlModel) {
+        let key = (model.deviceId.clone(), model.channelId.clone());
+        let mut guard = self.inputs.lock();
+        let model = match guard.get(&key) {
+            Some(tx) => {
+                match tx.try_send(model) {
+                    Ok(()) => { return; }
+                    //窗口内积压：丢弃较旧输入不影响结果,但最新输入需保留
+                    Err(mpsc::error::TrySendError::Full(model)) => {
+                        let tx = tx.clone();
+                        tokio::spawn(async move { let _ = tx.send(model).await; });
+                        return;
+                    }
+                    Err(mpsc::error::TrySendError::Closed(model)) => { model }
+                }
+            }
+            None => { model }
+        };
+        let (tx, rx) = mpsc::channel(32);
+        let _ = tx.try_send(model);
+        guard.insert(key.clone(), tx);
+        drop(guard);
+        tokio::spawn(Self::run(rx, key, self.inputs.clone(), self.out.clone(), self.interval, self.idle));
+    }
+
+    async fn run(mut rx: mpsc::Receiver<PtzControlModel>, key: Key, inputs: Arc<Mutex<HashMap<Key, mpsc::Sender<PtzControlModel>>>>,
+                 out: mpsc::Sender<PtzControlModel>, interval: Duration, idle: Duration) {
+        let mut latest: Option<PtzControlModel> = None;
+        let mut last_input = Instant::now();
+        let mut ticker = time::interval(interval);
+        loop {
+            tokio::select! {
+                model = rx.recv() => {
+                    match model {
+                        Some(model) => {
+                            latest = Some(model);
+                            last_input = Instant::now();
+                        }
+                        None => { break; }
+                    }
+                }
+                _ = ticker.tick() => {
+                    if let Some(model) = latest.take() {
+                        if out.send(model).await.is_err() {
+                            error!("ptz: 指令下发通道已关闭");
+                        }
+                    } else if last_input.elapsed() >= idle {
+                        //持锁确认无新输入后再移除,避免输入丢失
+                        let mut guard = inputs.lock();
+                        match rx.try_recv() {
+                            Ok(model) => {
+                                latest = Some(model);
+                                last_input = Instant::now();
+                            }
+                            Err(_) => {
+                                guard.remove(&key);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let _ = out.send(stop_model(key)).await;
+    }
+}
+
+fn stop_model(key: Key) -> PtzControlModel {
+    let mut stop = PtzControlModel::default();
+    stop.deviceId = key.0;
+    stop.channelId = key.1;
+    stop
+}
+
+/// 记录最近下发过移动指令的通道,供急停使用;下发停止指令后移除
+pub struct PtzTracker {
+    ttl: Duration,
+    active: Mutex<HashMap<Key, Instant>>,
+}
+
+impl PtzTracker {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, active: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn record(&self, model: &PtzControlModel) {
+        let key = (model.deviceId.clone(), model.channelId.clone());
+        let stop = model.leftRight == 0 && model.upDown == 0 && model.inOut == 0;
+        let mut guard = self.active.lock();
+        if stop {
+            guard.remove(&key);
+        } else {
+            guard.insert(key, Instant::now());
+        }
+    }
+
+    /// 取出并清空转动中的通道,已超过保留时长的忽略
+    pub fn take_active(&self) -> Vec<Key> {
+        let ttl = self.ttl;
+        self.active.lock().drain().filter(|(_, at)| at.elapsed() < ttl).map(|(key, _)| key).collect()
+    }
+
+    pub fn record_global(model: &PtzControlModel) {
+        PTZ_TRACKER.record(model);
+    }
+
+    pub fn take_active_global() -> Vec<Key> {
+        PTZ_TRACKER.take_active()
+    }
+
+    /// 对每个通道下发停止指令,至多limit个并发;返回下发成功数
+    pub async fn stop_each<F, Fut>(keys: Vec<Key>, limit: usize, send: F) -> usize
+    where
+        F: Fn(PtzControlModel) -> Fut,
+        Fut: Future<Output=GlobalResult<()>>,
+    {
+        stream::iter(keys)
+            .map(|key| {
+                let (device_id, channel_id) = key.clone();
+                let fut = send(stop_model(key));
+                async move { fut.await.hand_log(|msg| warn!("ptz: 急停失败,device_id={device_id},channel_id={channel_id};{msg}")).is_ok() }
+            })
+            .buffer_unordered(limit.max(1))
+            .filter(|ok| std::future::ready(*ok))
+            .count()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use common::tokio;
+    use common::tokio::sync::mpsc;
+
+    use crate::general::model::PtzControlModel;
+
+    use super::{PtzDebouncer, PtzTracker};
+
+    #[test]
+    fn test_stop_all_moving_channels() {
+        use std::sync::Arc;
+        use parking_lot::Mutex;
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let tracker = PtzTracker::new(Duration::from_secs(60));
+            for (channel_id, left_right) in [("34020000001320000101", 1u8), ("34020000001320000102", 2), ("34020000001320000103", 1)] {
+                let mut model = PtzControlModel::default();
+                model.deviceId = "34020000001110000001".to_string();
+                model.channelId = channel_id.to_string();
+                model.leftRight = left_right;
+                model.horizonSpeed = 100;
+                tracker.record(&model);
+            }
+            //已停止的通道不再急停
+            let mut stopped = PtzControlModel::default();
+            stopped.deviceId = "34020000001110000001".to_string();
+            stopped.channelId = "34020000001320000103".to_string();
+            tracker.record(&stopped);
+
+            let sent = Arc::new(Mutex::new(Vec::new()));
+            let count = PtzTracker::stop_each(tracker.take_active(), 8, |model| {
+                let sent = sent.clone();
+                async move {
+                    sent.lock().push(model);
+                    Ok(())
+                }
+            }).await;
+            assert_eq!(count, 2);
+            let mut channels: Vec<String> = sent.lock().iter().map(|m| {
+                assert_eq!((m.leftRight, m.upDown, m.inOut, m.horizonSpeed), (0, 0, 0, 0));
+                m.channelId.clone()
+            }).collect();
+            channels.sort();
+            assert_eq!(channels, vec!["34020000001320000101".to_string(), "34020000001320000102".to_string()]);
+            assert!(tracker.take_active().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_debounce_burst_with_trailing_stop() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let (tx, mut rx) = mpsc::channel(64);
+            let debouncer = PtzDebouncer::new(Duration::from_millis(20), Duration::from_millis(50), tx);
+            for i in 0..30u8 {
+                let mut model = PtzControlModel::default();
+                model.deviceId = "34020000001110000001".to_string();
+                model.channelId = "34020000001320000101".to_string();
+                model.leftRight = 2;
+                model.horizonSpeed = i + 1;
+                debouncer.submit(model);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            let mut sent = Vec::new();
+            while let Ok(Some(model)) = tokio::time::timeout(Duration::from_millis(300), rx.recv()).await {
+                let stop = model.leftRight == 0;
+                sent.push(model);
+                if stop {
+                    break;
+                }
+            }
+            let last = sent.last().unwrap();
+            assert_eq!(last.leftRight, 0);
+            assert_eq!(last.horizonSpeed, 0);
+            //30次输入合并后有限次下发 + 停止
+            assert!(sent.len() >= 2 && sent.len() <= 6, "sent = {}", sent.len());
+            //最后一条移动指令为最新输入
+            assert_eq!(sent[sent.len() - 2].horizonSpeed, 30);
+        });
+    }
+}
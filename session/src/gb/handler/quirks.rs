@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+
+use common::once_cell::sync::Lazy;
+use common::serde::Deserialize;
+
+use crate::gb::SessionConf;
+use crate::gb::handler::builder::VIDEO_RTPMAPS;
+use crate::gb::shared::event::MatchMode;
+use crate::general::model::{InviteOption, SignalTransport, SsrcCheck};
+
+//device_id或编码前缀(如10位中心编码):兼容项;首次使用时自配置加载
+static DEVICE_QUIRKS: Lazy<RwLock<HashMap<String, DeviceQuirks>>> = Lazy::new(|| RwLock::new(SessionConf::device_quirks()));
+
+/// 设备兼容项：集中各类互通差异,按device_id或编码前缀配置;缺省均为标准行为
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(crate = "common::serde", default)]
+pub struct DeviceQuirks {
+    //最简offer：仅 RTP/AVP 96 PS/90000,见InviteOption::strict_offer
+    pub strict_offer: bool,
+    //offer不含H265(100)
+    pub no_h265: bool,
+    //INVITE经TCP下发
+    pub invite_over_tcp: bool,
+    //响应匹配不校验Via branch,见MatchMode::Lenient
+    pub lenient_match: bool,
+    //设备改写SSRC：采用应答y=行SSRC
+    pub accept_ssrc: bool,
+}
+
+impl DeviceQuirks {
+    /// 设置兼容项：key为device_id或编码前缀;None即移除
+    pub fn set(key: &str, quirks: Option<DeviceQuirks>) {
+        let mut guard = DEVICE_QUIRKS.write();
+        match quirks {
+            None => { guard.remove(key); }
+            Some(quirks) => { guard.insert(key.to_string(), quirks); }
+        }
+    }
+
+    /// 设备生效的兼容项：取最长匹配(device_id精确匹配即最长),均不匹配为缺省
+    pub fn of(device_id: &str) -> DeviceQuirks {
+        Self::lookup(&DEVICE_QUIRKS.read(), device_id)
+    }
+
+    fn lookup(quirks: &HashMap<String, DeviceQuirks>, device_id: &str) -> DeviceQuirks {
+        quirks.iter()
+            .filter(|(key, _)| device_id.starts_with(key.as_str()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, quirks)| *quirks)
+            .unwrap_or_default()
+    }
+
+    /// 兼容项指定的响应匹配模式;None则沿用设备设置或全局配置
+    pub fn match_mode(&self) -> Option<MatchMode> {
+        self.lenient_match.then_some(MatchMode::Lenient)
+    }
+
+    /// 按兼容项修正点播选项;调用方显式指定的信令传输与SSRC校验不覆盖
+    pub fn apply(&self, option: &InviteOption) -> InviteOption {
+        let mut option = option.clone();
+        option.strict_offer |= self.strict_offer;
+        if self.no_h265 {
+            if option.video_payloads.is_empty() {
+                option.video_payloads = VIDEO_RTPMAPS.iter().map(|(tp, _)| *tp).collect();
+            }
+            option.video_payloads.retain(|tp| *tp != 100);
+        }
+        if self.invite_over_tcp && option.signal_transport.is_none() {
+            option.signal_transport = Some(SignalTransport::Tcp);
+        }
+        if self.accept_ssrc && option.ssrc_check == SsrcCheck::Off {
+            option.ssrc_check = SsrcCheck::Accept;
+        }
+        option
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::general::model::{InviteOption, SignalTransport};
+
+    use super::DeviceQuirks;
+
+    #[test]
+    fn test_quirks_lookup_and_apply() {
+        let tcp = DeviceQuirks { invite_over_tcp: true, ..Default::default() };
+        let no_h265 = DeviceQuirks { no_h265: true, ..Default::default() };
+        let quirks = HashMap::from([("3402000000".to_string(), tcp), ("34020000001110000001".to_string(), no_h265)]);
+        //精确id优先于前缀
+        assert_eq!(DeviceQuirks::lookup(&quirks, "34020000001110000001"), no_h265);
+        assert_eq!(DeviceQuirks::lookup(&quirks, "34020000001110000002"), tcp);
+        assert_eq!(DeviceQuirks::lookup(&quirks, "44010000001110000001"), DeviceQuirks::default());
+
+        let option = no_h265.apply(&InviteOption::default());
+        assert_eq!(option.video_payloads, vec![96, 97, 98, 99]);
+        assert_eq!(tcp.apply(&InviteOption::default()).signal_transport, Some(SignalTransport::Tcp));
+        let explicit = InviteOption { signal_transport: Some(SignalTransport::Udp), ..Default::default() };
+        assert_eq!(tcp.apply(&explicit).signal_transport, Some(SignalTransport::Udp));
+    }
+}
@@ -0,0 +1,89 @@
+use common::chrono::{Local, NaiveDateTime, TimeZone};
+use common::exception::GlobalResult;
+
+use crate::gb::handler::error_code::CmdErrorCode;
+use crate::gb::handler::parser::xml::*;
+
+/// 设备录像段：对应 RecordInfo 响应 RecordList 的 Item
+/// 起止时间为设备时钟的秒级时间戳,按段回放时由CmdStream::play_back_segment换算
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordSegment {
+    pub channel_id: String,
+    pub name: Option<String>,
+    pub file_path: Option<String>,
+    //time/alarm/manual/all
+    pub record_type: Option<String>,
+    pub start_time: u32,
+    pub end_time: u32,
+}
+
+impl RecordSegment {
+    /// 校验录像段属于该通道且区间有效
+    pub fn check(&self, channel_id: &String) -> GlobalResult<()> {
+        if &self.channel_id != channel_id {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("录像段不属于该通道: segment = {},channel_id = {channel_id}", self.channel_id)));
+        }
+        if self.start_time == 0 || self.start_time >= self.end_time {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("录像段区间无效: st = {},et = {}", self.start_time, self.end_time)));
+        }
+        Ok(())
+    }
+}
+
+/// RecordInfo 响应：sum_num为设备端录像段总数,分包时items仅为本包所含
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordInfo {
+    pub device_id: String,
+    pub sum_num: Option<u32>,
+    pub items: Vec<RecordSegment>,
+}
+
+impl RecordInfo {
+    pub fn parse(body: &[u8]) -> GlobalResult<Self> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Self {
+        let mut info = RecordInfo::default();
+        let mut segment: Option<RecordSegment> = None;
+        for (k, v) in vs {
+            match &k[..] {
+                RESPONSE_DEVICE_ID => { info.device_id = v; }
+                RESPONSE_SUM_NUM => { info.sum_num = v.trim().parse::<u32>().ok(); }
+                //以Item下DeviceID作为新录像段起始
+                RESPONSE_RECORD_LIST_ITEM_DEVICE_ID => {
+                    info.items.extend(segment.take());
+                    segment = Some(RecordSegment { channel_id: v, ..Default::default() });
+                }
+                _ => {
+                    if let Some(seg) = segment.as_mut() {
+                        match &k[..] {
+                            RESPONSE_RECORD_LIST_ITEM_NAME => { seg.name = Some(v); }
+                            RESPONSE_RECORD_LIST_ITEM_FILE_PATH => { seg.file_path = Some(v); }
+                            RESPONSE_RECORD_LIST_ITEM_TYPE => { seg.record_type = Some(v); }
+                            RESPONSE_RECORD_LIST_ITEM_START_TIME => { seg.start_time = Self::parse_time(&v); }
+                            RESPONSE_RECORD_LIST_ITEM_END_TIME => { seg.end_time = Self::parse_time(&v); }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        info.items.extend(segment.take());
+        info
+    }
+
+    /// 是否有录像：SumNum为0且无条目即无录像
+    pub fn has_segments(&self) -> bool {
+        self.sum_num.map(|n| n > 0).unwrap_or(false) || !self.items.is_empty()
+    }
+
+    //设备时钟的本地时间,格式如2024-01-01T08:00:00;无法解析为0
+    fn parse_time(v: &str) -> u32 {
+        NaiveDateTime::parse_from_str(v.trim(), "%Y-%m-%dT%H:%M:%S").ok()
+            .and_then(|naive| Local.from_local_datetime(&naive).single())
+            .map(|time| time.timestamp() as u32)
+            .unwrap_or_default()
+    }
+}
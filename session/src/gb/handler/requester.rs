@@ -1,31 +1,58 @@
 use common::log::{debug, info, LevelFilter};
 use quick_xml::encoding;
 use encoding_rs::GB18030;
-use rsip::{Method, Request};
+use rsip::{Method, Request, SipMessage};
 use rsip::headers::ToTypedHeader;
 use rsip::message::HeadersExt;
+use rsip::prelude::UntypedHeader;
 use rsip::services::DigestGenerator;
 
 use common::anyhow::anyhow;
-use common::bytes::Bytes;
 use common::chrono::{Duration, Local};
 use common::exception::{GlobalResult, TransError};
 use common::exception::GlobalError::SysErr;
 use common::log::{error, warn};
-use common::net::state::{Association, Package, Zip};
+use common::net::state::{Association, Zip};
 use common::tokio::sync::mpsc::Sender;
 
-use crate::gb::handler::{cmd, parser};
-use crate::gb::handler::builder::ResponseBuilder;
+use crate::gb::handler::{catalog, cmd, parser};
+use crate::gb::handler::alarm::AlarmRecord;
+use crate::gb::handler::cmd::CmdResponse;
+use crate::gb::handler::catalog::{CatalogAssembler, CatalogPages, CatalogSnapshot};
 use crate::gb::handler::parser::xml::KV2Model;
+use crate::gb::handler::position::PositionPoint;
+use crate::gb::handler::profile::StreamProfile;
+use crate::gb::handler::record::RecordInfo;
+use crate::gb::handler::snapshot::SnapshotHandle;
+use crate::gb::handler::subscription::SubscriptionState;
+use crate::gb::handler::status::{DeviceClock, DeviceStatus};
+use crate::gb::shared::dialog::DialogSession;
+use crate::gb::shared::event::{EventSession, TapDirection};
+use crate::gb::shared::reply::ReplySession;
 use crate::gb::shared::rw::RWSession;
 use crate::general::model::AlarmInfo;
 use crate::service::callback;
 use crate::storage::entity::{GmvDevice, GmvDeviceChannel, GmvDeviceExt, GmvOauth};
 use crate::storage::mapper;
 
+/// 定时输出超时未收齐的分包目录(尾包丢失)
+pub async fn flush_catalog_task() {
+    let mut ticker = common::tokio::time::interval(catalog::FLUSH_INTERVAL);
+    loop {
+        ticker.tick().await;
+        for snapshot in CatalogAssembler::flush_expired() {
+            let device_id = snapshot.device_id.clone();
+            Message::deliver_catalog(&device_id, snapshot);
+        }
+    }
+}
+
 pub async fn hand_request(req: Request, tx: Sender<Zip>, bill: &Association) -> GlobalResult<()> {
     let device_id = parser::header::get_device_id_by_request(&req)?;
+    EventSession::tap(&device_id, TapDirection::Inbound, &SipMessage::Request(req.clone()));
+    if CmdResponse::reject_unsupported(&req, &tx, bill).await? {
+        return Ok(());
+    }
     //校验设备是否注册
     if req.method == Method::Register {
         let _ = Register::process(&device_id, req, tx, bill).await.hand_log(|msg| error!("设备 = [{}],注册失败;err={}",&device_id,msg));
@@ -34,8 +61,13 @@ pub async fn hand_request(req: Request, tx: Sender<Zip>, bill: &Association) ->
         match State::check_session(tx.clone(), bill, &device_id).await? {
             State::Usable | State::ReCache => {
                 match req.method {
-                    Method::Ack => { Ok(()) }
-                    Method::Bye => { Ok(()) }
+                    Method::Ack => {
+                        //设备对本端200 OK的确认(设备发起的会话)
+                        let call_id = req.call_id_header().hand_log(|msg| warn!("{msg}"))?.value().to_string();
+                        cmd::CmdResponse::on_ack(&call_id);
+                        Ok(())
+                    }
+                    Method::Bye => { Bye::process(req, tx.clone(), bill).await }
                     Method::Cancel => { Ok(()) }
                     Method::Info => { Ok(()) }
                     Method::Invite => { Ok(()) }
@@ -53,12 +85,7 @@ pub async fn hand_request(req: Request, tx: Sender<Zip>, bill: &Association) ->
                     }
                 }
             }
-            State::Expired => {
-                let unregister_response = ResponseBuilder::build_401_response(&req, bill.get_remote_addr())?;
-                let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(unregister_response)));
-                let _ = tx.clone().send(zip).await.hand_log(|msg| error!("{msg}"));
-                Ok(())
-            }
+            State::Expired => { CmdResponse::expired(&req, &tx, bill).await }
             State::Invalid => { Ok(()) }
         }
     }
@@ -102,6 +129,7 @@ impl State {
                         if reg_ts + Duration::seconds(expire as i64) > Local::now().naive_local() {
                             //刷新缓存
                             RWSession::insert(device_id, tx, heart, bill);
+                            Register::bye_orphans(device_id);
                             //如果设备是离线状态，则更新为在线
                             if on == 0 {
                                 GmvDevice::update_gmv_device_status_by_device_id(device_id, 1).await?;
@@ -174,11 +202,10 @@ impl Register {
     }
     async fn login_ok(device_id: &String, req: &Request, tx: Sender<Zip>, bill: &Association, oauth: GmvOauth) -> GlobalResult<()> {
         RWSession::insert(device_id, tx.clone(), *oauth.get_heartbeat_sec(), bill);
+        Self::bye_orphans(device_id);
         let gmv_device = GmvDevice::build_gmv_device(&req)?;
         gmv_device.insert_single_gmv_device_by_register().await?;
-        let ok_response = ResponseBuilder::build_register_ok_response(&req, bill.get_remote_addr())?;
-        let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(ok_response)));
-        let _ = tx.clone().send(zip).await.hand_log(|msg| warn!("{msg}"));
+        CmdResponse::ok(req, &tx, bill).await?;
 
         // query subscribe device msg
         cmd::CmdQuery::lazy_query_device_info(device_id).await?;
@@ -186,20 +213,26 @@ impl Register {
         cmd::CmdQuery::lazy_query_device_catalog(device_id).await
     }
 
+    //设备上线：关闭重启前遗留的媒体流
+    fn bye_orphans(device_id: &String) {
+        let device_id = device_id.clone();
+        common::tokio::spawn(async move {
+            let count = cmd::CmdStream::bye_orphaned_dialogs(&device_id).await;
+            if count > 0 {
+                info!("关闭遗留媒体流: device_id = {device_id},count = {count}");
+            }
+        });
+    }
+
     async fn logout_ok(device_id: &String, req: &Request, tx: Sender<Zip>, bill: &Association) -> GlobalResult<()> {
-        let ok_response = ResponseBuilder::build_logout_ok_response(&req, bill.get_remote_addr())?;
-        let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(ok_response)));
-        let _ = tx.clone().send(zip).await.hand_log(|msg| warn!("{msg}"));
+        CmdResponse::logout_ok(req, &tx, bill).await?;
         GmvDevice::update_gmv_device_status_by_device_id(device_id, 0).await?;
         RWSession::clean_rw_session_and_net(device_id).await;
         Ok(())
     }
 
     async fn unauthorized(req: &Request, tx: Sender<Zip>, bill: &Association) -> GlobalResult<()> {
-        let unauthorized_register_response = ResponseBuilder::unauthorized_register_response(&req, bill.get_remote_addr())?;
-        let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(unauthorized_register_response)));
-        let _ = tx.clone().send(zip).await.hand_log(|msg| error!("{msg}"));
-        Ok(())
+        CmdResponse::challenge(req, &tx, bill).await
     }
 }
 
@@ -210,28 +243,28 @@ impl Message {
         use parser::xml::*;
         match parse_xlm_to_vec(&req.body) {
             Ok(vs) => {
-                let response = ResponseBuilder::build_register_ok_response(&req, bill.get_remote_addr())?;
                 for (k, v) in &vs {
                     if MESSAGE_TYPE.contains(&&k[..]) {
                         match &v[..] {
                             MESSAGE_KEEP_ALIVE => { Self::keep_alive(device_id, vs, bill).await; }
-                            MESSAGE_CONFIG_DOWNLOAD => {}
+                            MESSAGE_CONFIG_DOWNLOAD => { Self::config_download(vs); }
                             MESSAGE_NOTIFY_CATALOG => { Self::device_catalog(device_id, vs).await; }
                             MESSAGE_DEVICE_INFO => { Self::device_info(vs).await; }
                             MESSAGE_ALARM => { let _ = Self::message_notify_alarm(device_id, vs).await; }
-                            MESSAGE_RECORD_INFO => {}
+                            MESSAGE_RECORD_INFO => { Self::record_info(vs); }
                             MESSAGE_MEDIA_STATUS => {}
                             MESSAGE_BROADCAST => {}
-                            MESSAGE_DEVICE_STATUS => {}
+                            MESSAGE_DEVICE_STATUS => { Self::device_status(vs); }
                             MESSAGE_DEVICE_CONTROL => {}
                             MESSAGE_DEVICE_CONFIG => {}
                             MESSAGE_PRESET_QUERY => {}
+                            MESSAGE_UPLOAD_SNAPSHOT_FINISHED => { SnapshotHandle::finish(&vs); }
+                            MESSAGE_MOBILE_POSITION => { PositionPoint::dispatch_notify(device_id, &vs); }
                             _ => {
                                 warn!("device_id = {};message -- > {} 不支持。", device_id,v)
                             }
                         }
-                        let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(response)));
-                        let _ = tx.clone().send(zip).await.hand_log(|msg| error!("{msg}"));
+                        CmdResponse::ok(&req, &tx, bill).await?;
                         break;
                     }
                 }
@@ -263,11 +296,46 @@ impl Message {
         RWSession::heart(device_id, bill.clone());
     }
 
+    //VideoParamOpt：缓存通道码流能力,供点播选择码流编号
+    fn config_download(vs: Vec<(String, String)>) {
+        use parser::xml::RESPONSE_DEVICE_ID;
+        let Some(channel_id) = vs.iter().find(|(k, _)| k == RESPONSE_DEVICE_ID).map(|(_, v)| v.clone()) else { return; };
+        StreamProfile::store(&channel_id, StreamProfile::from_kv(vs));
+    }
+
+    //DeviceTime用于设备时钟偏差补偿
+    fn device_status(vs: Vec<(String, String)>) {
+        let status = DeviceStatus::from_kv(vs);
+        if let Some(drift) = DeviceClock::observe(&status, Local::now().timestamp()) {
+            debug!("device_id = {},时钟偏差 = {drift}s", status.device_id);
+        }
+    }
+
+    fn record_info(vs: Vec<(String, String)>) {
+        let info = RecordInfo::from_kv(vs);
+        debug!("record_info: channel_id = {},sum_num = {:?},本包条目 = {}", info.device_id, info.sum_num, info.items.len());
+        ReplySession::reply_record_info(&info.device_id.clone(), info);
+    }
+
     async fn device_info(vs: Vec<(String, String)>) {
+        let ext = GmvDeviceExt::build(vs.clone());
+        ReplySession::reply_device_info(&ext.get_device_id().clone(), ext);
         let _ = GmvDeviceExt::update_gmv_device_ext_info(vs).await.hand_log(|msg| error!("{msg}"));
     }
 
+    fn deliver_catalog(device_id: &String, snapshot: CatalogSnapshot) {
+        info!("catalog: device_id = {},sn = {:?},通道数 = {}/{:?}", snapshot.device_id, snapshot.sn, snapshot.items.len(), snapshot.sum_num);
+        snapshot.remember();
+        ReplySession::reply_catalog(device_id, snapshot);
+    }
+
     async fn device_catalog(device_id: &String, vs: Vec<(String, String)>) {
+        //分包目录：逐包分发给分页订阅方,收齐或超时后输出
+        let page = CatalogSnapshot::from_kv(vs.clone());
+        CatalogPages::publish(device_id, &page);
+        for snapshot in CatalogAssembler::assemble(page) {
+            Self::deliver_catalog(device_id, snapshot);
+        }
         if let Ok(_arr) = GmvDeviceChannel::insert_gmv_device_channel(device_id, vs).await.hand_log(|msg| error!("{msg}")) {
             //通过预置位探测是否有云台可用
             // for dc in arr {
@@ -277,6 +345,7 @@ impl Message {
     }
 
     async fn message_notify_alarm(device_id: &String, vs: Vec<(String, String)>) -> GlobalResult<()> {
+        AlarmRecord::dispatch_notify(device_id, &vs);
         let mut info = AlarmInfo::kv_to_model(vs)?;
         info.deviceId = device_id.clone();
         callback::call_alarm_info(&info).await?;
@@ -289,25 +358,42 @@ struct Notify;
 impl Notify {
     async fn process(device_id: &String, req: Request, tx: Sender<Zip>, bill: &Association) -> GlobalResult<()> {
         use parser::xml::*;
+        if let Some(state @ SubscriptionState::Terminated { .. }) = SubscriptionState::of(&req) {
+            cmd::CmdQuery::on_subscription_terminated(device_id, Self::subscription_kind(&req), &state);
+            //终止通知可不带消息体
+            if req.body.is_empty() {
+                return CmdResponse::ok(&req, &tx, bill).await;
+            }
+        }
         match parse_xlm_to_vec(&req.body) {
             Ok(vs) => {
-                let response = ResponseBuilder::build_register_ok_response(&req, bill.get_remote_addr())?;
-                for (k, v) in &vs {
-                    if MESSAGE_TYPE.contains(&&**k) {
-                        match &v[..] {
-                            MESSAGE_NOTIFY_CATALOG => {
-                                GmvDeviceChannel::insert_gmv_device_channel(device_id, vs).await?;
-                            }
-                            _ => {
-                                debug!("cmdType暂不支持;{k} : {v}");
-                            }
+                let Some(cmd_type) = vs.iter().find(|(k, _)| MESSAGE_TYPE.contains(&&**k)).map(|(_, v)| v.clone()) else {
+                    return Ok(());
+                };
+                let sn = vs.iter().find(|(k, _)| k == NOTIFY_SN).map(|(_, v)| v.clone());
+                let cseq = req.cseq_header().hand_log(|msg| warn!("{msg}"))?.value().to_string();
+                let dispatch = async {
+                    match &cmd_type[..] {
+                        MESSAGE_NOTIFY_CATALOG => {
+                            GmvDeviceChannel::insert_gmv_device_channel(device_id, vs).await?;
+                        }
+                        MESSAGE_MOBILE_POSITION => {
+                            PositionPoint::dispatch_notify(device_id, &vs);
+                        }
+                        _ => {
+                            debug!("cmdType暂不支持;{cmd_type}");
                         }
-                        let zip = Zip::build_data(Package::new(bill.clone(), Bytes::from(response)));
-                        let _ = tx.clone().send(zip).await.hand_log(|msg| error!("{msg}"));
-                        break;
                     }
-                }
-                Ok(())
+                    GlobalResult::Ok(())
+                };
+                let ack = async {
+                    if cmd_type == MESSAGE_NOTIFY_CATALOG {
+                        CmdResponse::catalog_notify_ok(device_id, &req, &tx, bill).await
+                    } else {
+                        CmdResponse::ok(&req, &tx, bill).await
+                    }
+                };
+                cmd::CmdNotify::dispatch_once(device_id, &cseq, sn.as_deref(), dispatch, ack).await
             }
             Err(err) => {
                 let val = encoding::decode(&req.body, GB18030).hand_log(|msg| error!("{msg}"))?;
@@ -315,6 +401,42 @@ impl Notify {
             }
         }
     }
+
+    //订阅类型：按消息体CmdType,无消息体时视为目录订阅
+    fn subscription_kind(req: &Request) -> &'static str {
+        use parser::xml::*;
+        let cmd_type = parse_xlm_to_vec(&req.body).ok()
+            .and_then(|vs| vs.into_iter().find(|(k, _)| MESSAGE_TYPE.contains(&&**k)).map(|(_, v)| v));
+        match cmd_type.as_deref() {
+            Some(MESSAGE_ALARM) => MESSAGE_ALARM,
+            _ => MESSAGE_NOTIFY_CATALOG,
+        }
+    }
+}
+
+struct Bye;
+
+impl Bye {
+    //设备主动BYE：关联到已建立的对话则通知流持有方;无论是否命中均应答200
+    async fn process(req: Request, tx: Sender<Zip>, bill: &Association) -> GlobalResult<()> {
+        let (call_id, device_tag, local_tag) = Self::dialog_key(&req)?;
+        match DialogSession::device_bye(&call_id, device_tag.as_deref(), local_tag.as_deref()) {
+            None => { debug!("bye: call_id = {call_id},未匹配到媒体会话"); }
+            Some(dialog) => {
+                info!("bye: device_id = {},channel_id = {},call_id = {call_id},设备结束媒体会话", dialog.get_device_id(), dialog.get_channel_id());
+                cmd::CmdStream::release_rtp_port_by_call_id(&call_id);
+            }
+        }
+        CmdResponse::ok(&req, &tx, bill).await
+    }
+
+    //(call_id,设备端tag,本端tag)
+    fn dialog_key(req: &Request) -> GlobalResult<(String, Option<String>, Option<String>)> {
+        let call_id = req.call_id_header().hand_log(|msg| warn!("{msg}"))?.value().to_string();
+        let device_tag = req.from_header().hand_log(|msg| warn!("{msg}"))?.tag().hand_log(|msg| warn!("{msg}"))?.map(|tag| tag.to_string());
+        let local_tag = req.to_header().hand_log(|msg| warn!("{msg}"))?.tag().hand_log(|msg| warn!("{msg}"))?.map(|tag| tag.to_string());
+        Ok((call_id, device_tag, local_tag))
+    }
 }
 
 #[cfg(test)]
@@ -357,4 +479,31 @@ mod tests {
         let x = dsg.verify(&au.response);
         println!("{:?}", x);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_device_bye_notify_owner() {
+        use rsip::SipMessage;
+        use crate::gb::shared::dialog::{Dialog, DialogSession};
+        use super::Bye;
+
+        let bye = "BYE sip:34020000002000000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 192.168.1.64:5060;rport;branch=z9hG4bK1073741823\r\n\
+From: <sip:34020000001320000101@3402000000>;tag=device-tag\r\n\
+To: <sip:34020000002000000001@3402000000>;tag=local-tag\r\n\
+Call-ID: requester-bye-call-id\r\n\
+CSeq: 21 BYE\r\n\
+Max-Forwards: 70\r\n\
+Content-Length: 0\r\n\r\n";
+        let req = match SipMessage::try_from(bye).unwrap() {
+            SipMessage::Request(req) => req,
+            SipMessage::Response(_) => unreachable!(),
+        };
+        let dialog = Dialog::new("34020000001110000001".to_string(), "34020000001320000101".to_string(),
+                                 "requester-bye-call-id".to_string(), "local-tag".to_string(), "device-tag".to_string());
+        let mut rx = DialogSession::insert(dialog.clone());
+        let (call_id, device_tag, local_tag) = Bye::dialog_key(&req).unwrap();
+        assert_eq!(call_id, "requester-bye-call-id");
+        assert_eq!(DialogSession::device_bye(&call_id, device_tag.as_deref(), local_tag.as_deref()), Some(dialog.clone()));
+        assert_eq!(rx.try_recv().unwrap(), dialog);
+    }
+}
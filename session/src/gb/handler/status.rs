@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+
+use common::chrono::{Local, NaiveDateTime, TimeZone};
+use common::exception::GlobalResult;
+use common::once_cell::sync::Lazy;
+
+use crate::gb::handler::parser::xml::*;
+
+//设备时钟偏差(秒,设备快为正)：device_id:drift
+static CLOCK_DRIFT: Lazy<RwLock<HashMap<String, i64>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// 设备状态：对应 DeviceStatus 响应
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceStatus {
+    pub device_id: String,
+    pub result: Option<String>,
+    //ONLINE/OFFLINE
+    pub online: Option<String>,
+    //OK/ERROR
+    pub status: Option<String>,
+    //ON/OFF
+    pub encode: Option<String>,
+    //ON/OFF
+    pub record: Option<String>,
+    pub device_time: Option<String>,
+    //设备不区分通道,以设备级状态应答通道查询
+    pub device_level: bool,
+    //厂商扩展元素：相对Response的路径:值
+    pub extensions: HashMap<String, String>,
+}
+
+impl DeviceStatus {
+    pub fn parse(body: &[u8]) -> GlobalResult<Self> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    /// 通道状态：应答DeviceID非该通道时视为设备级状态
+    pub fn parse_channel(body: &[u8], channel_id: &str) -> GlobalResult<Self> {
+        let mut status = Self::parse(body)?;
+        status.device_level = status.device_id != channel_id;
+        Ok(status)
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Self {
+        let mut status = DeviceStatus::default();
+        for (k, v) in vs {
+            match &k[..] {
+                RESPONSE_DEVICE_ID => { status.device_id = v; }
+                RESPONSE_RESULT => { status.result = Some(v); }
+                RESPONSE_ONLINE => { status.online = Some(v); }
+                RESPONSE_STATUS => { status.status = Some(v); }
+                RESPONSE_ENCODE => { status.encode = Some(v); }
+                RESPONSE_RECORD => { status.record = Some(v); }
+                RESPONSE_DEVICE_TIME => { status.device_time = Some(v); }
+                _ => {
+                    if let Some(key) = extension_key(&k, RESPONSE, DEVICE_STATUS_ELEMENTS) {
+                        status.extensions.insert(key, v);
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.as_deref().map(|online| online.eq_ignore_ascii_case("ONLINE")).unwrap_or(false)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.record.as_deref().map(|record| record.eq_ignore_ascii_case("ON")).unwrap_or(false)
+    }
+
+    /// 设备时钟相对服务端的偏差(秒,设备快为正);DeviceTime缺失或格式不符时为None
+    pub fn clock_drift(&self, server_now: i64) -> Option<i64> {
+        let time = NaiveDateTime::parse_from_str(self.device_time.as_deref()?.trim(), "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+        let device_now = Local.from_local_datetime(&time).single()?.timestamp();
+        Some(device_now - server_now)
+    }
+}
+
+/// 设备时钟偏差补偿：带时间参数的指令(回放、下载、轨迹、告警查询)下发前将服务端时间换算为设备时间
+/// 拖动播放为相对起点的npt偏移,不做换算
+pub struct DeviceClock;
+
+impl DeviceClock {
+    /// 由DeviceStatus的DeviceTime记录偏差
+    pub fn observe(status: &DeviceStatus, server_now: i64) -> Option<i64> {
+        let drift = status.clock_drift(server_now)?;
+        Self::set_drift(&status.device_id, drift);
+        Some(drift)
+    }
+
+    pub fn set_drift(device_id: &String, drift: i64) {
+        CLOCK_DRIFT.write().insert(device_id.clone(), drift);
+    }
+
+    pub fn drift(device_id: &String) -> Option<i64> {
+        CLOCK_DRIFT.read().get(device_id).copied()
+    }
+
+    /// 设备离线时清理
+    pub fn forget(device_id: &String) {
+        CLOCK_DRIFT.write().remove(device_id);
+    }
+
+    /// 服务端秒级时间戳换算为设备时间;无已知偏差时原样返回
+    pub fn to_device_time(device_id: &String, server_epoch: u32) -> u32 {
+        Self::shift(server_epoch, Self::drift(device_id))
+    }
+
+    /// 设备时间(如录像检索结果)换算为服务端时间,与to_device_time互逆
+    pub fn to_server_time(device_id: &String, device_epoch: u32) -> u32 {
+        Self::shift(device_epoch, Self::drift(device_id).map(|drift| -drift))
+    }
+
+    fn shift(server_epoch: u32, drift: Option<i64>) -> u32 {
+        match drift {
+            //0为"未指定时间",不做换算
+            Some(drift) if server_epoch != 0 => (server_epoch as i64 + drift).clamp(1, u32::MAX as i64) as u32,
+            _ => server_epoch,
+        }
+    }
+}
+
+/// 存储卡状态：对应 SDCardStatus 响应,容量单位MB
+/// 各厂商字段不一,缺失字段为None;多张卡时容量累加,状态取首张卡
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageStatus {
+    pub device_id: String,
+    pub total_mb: Option<u64>,
+    pub used_mb: Option<u64>,
+    //如 formatted/unformatted/abnormal
+    pub status: Option<String>,
+}
+
+impl StorageStatus {
+    pub fn parse(body: &[u8]) -> GlobalResult<Self> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Self {
+        let mut storage = StorageStatus::default();
+        let mut free_mb: Option<u64> = None;
+        let add = |acc: &mut Option<u64>, v: &str| {
+            if let Ok(mb) = v.trim().parse::<u64>() {
+                *acc = Some(acc.unwrap_or(0) + mb);
+            }
+        };
+        for (k, v) in vs {
+            match &k[..] {
+                RESPONSE_DEVICE_ID => { storage.device_id = v; }
+                RESPONSE_SD_CARD_LIST_ITEM_STATUS => {
+                    if storage.status.is_none() {
+                        storage.status = Some(v);
+                    }
+                }
+                RESPONSE_SD_CARD_LIST_ITEM_CAPACITY | RESPONSE_SD_CARD_LIST_ITEM_TOTAL_SPACE => { add(&mut storage.total_mb, &v); }
+                RESPONSE_SD_CARD_LIST_ITEM_USED_SPACE => { add(&mut storage.used_mb, &v); }
+                RESPONSE_SD_CARD_LIST_ITEM_FREE_SPACE => { add(&mut free_mb, &v); }
+                _ => {}
+            }
+        }
+        //未直接给出已用容量时按 总量-剩余 计算
+        if storage.used_mb.is_none() {
+            if let (Some(total), Some(free)) = (storage.total_mb, free_mb) {
+                storage.used_mb = Some(total.saturating_sub(free));
+            }
+        }
+        storage
+    }
+}
+
+/// 设备信息：对应 DeviceInfo 响应,缺失或为空的字段为None
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub result: Option<String>,
+    pub device_name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    //视频输入通道数
+    pub channel: Option<u32>,
+}
+
+impl DeviceInfo {
+    pub fn parse(body: &[u8]) -> GlobalResult<Self> {
+        let vs = parse_xlm_to_vec(body)?;
+        Ok(Self::from_kv(vs))
+    }
+
+    pub fn from_kv(vs: Vec<(String, String)>) -> Self {
+        let mut info = DeviceInfo::default();
+        let text = |v: String| Some(v.trim().to_string()).filter(|v| !v.is_empty());
+        for (k, v) in vs {
+            match &k[..] {
+                RESPONSE_DEVICE_ID => { info.device_id = v; }
+                RESPONSE_RESULT => { info.result = text(v); }
+                RESPONSE_DEVICE_NAME => { info.device_name = text(v); }
+                RESPONSE_MANUFACTURER => { info.manufacturer = text(v); }
+                RESPONSE_MODEL => { info.model = text(v); }
+                RESPONSE_FIRMWARE => { info.firmware = text(v); }
+                RESPONSE_CHANNEL => { info.channel = v.trim().parse::<u32>().ok(); }
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeviceClock, DeviceInfo, DeviceStatus, StorageStatus};
+
+    #[test]
+    fn test_clock_drift() {
+        use common::chrono::{Local, TimeZone};
+        let server_now = Local.with_ymd_and_hms(2024, 1, 1, 8, 0, 0).unwrap().timestamp();
+        let status = DeviceStatus { device_id: "34020000001110000158".to_string(), device_time: Some("2024-01-01T08:00:30".to_string()), ..Default::default() };
+        assert_eq!(DeviceClock::observe(&status, server_now), Some(30));
+        assert_eq!(DeviceClock::to_device_time(&status.device_id, 1700000000), 1700000030);
+        DeviceClock::forget(&status.device_id);
+        assert_eq!(DeviceClock::to_device_time(&status.device_id, 1700000000), 1700000000);
+        assert_eq!(DeviceStatus { device_time: Some("bad".to_string()), ..Default::default() }.clock_drift(server_now), None);
+        assert_eq!(DeviceClock::shift(0, Some(30)), 0);
+    }
+
+    #[test]
+    fn test_parse_storage_status() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>SDCardStatus</CmdType>
+<SN>2201</SN>
+<DeviceID>34020000001320000101</DeviceID>
+<Result>OK</Result>
+<SDCardList Num="2">
+<Item>
+<ID>1</ID>
+<Status>formatted</Status>
+<Capacity>61440</Capacity>
+<FreeSpace>1024</FreeSpace>
+</Item>
+<Item>
+<ID>2</ID>
+<Status>unformatted</Status>
+<Capacity>30720</Capacity>
+<FreeSpace>30720</FreeSpace>
+</Item>
+</SDCardList>
+</Response>
+"#;
+        let storage = StorageStatus::parse(xml.as_bytes()).unwrap();
+        assert_eq!(storage.device_id, "34020000001320000101");
+        assert_eq!(storage.total_mb, Some(92160));
+        assert_eq!(storage.used_mb, Some(60416));
+        assert_eq!(storage.status.as_deref(), Some("formatted"));
+
+        //缺失容量字段
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>SDCardStatus</CmdType>
+<SN>2202</SN>
+<DeviceID>34020000001320000101</DeviceID>
+<SDCardList Num="1">
+<Item>
+<Status>abnormal</Status>
+</Item>
+</SDCardList>
+</Response>
+"#;
+        let storage = StorageStatus::parse(xml.as_bytes()).unwrap();
+        assert_eq!(storage.total_mb, None);
+        assert_eq!(storage.used_mb, None);
+        assert_eq!(storage.status.as_deref(), Some("abnormal"));
+    }
+
+    const CHANNEL_STATUS: &str = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>DeviceStatus</CmdType>
+<SN>17432</SN>
+<DeviceID>34020000001320000101</DeviceID>
+<Result>OK</Result>
+<Online>ONLINE</Online>
+<Status>OK</Status>
+<Encode>ON</Encode>
+<Record>OFF</Record>
+<DeviceTime>2024-01-01T08:00:00</DeviceTime>
+</Response>
+"#;
+
+    #[test]
+    fn test_parse_channel_status() {
+        let status = DeviceStatus::parse_channel(CHANNEL_STATUS.as_bytes(), "34020000001320000101").unwrap();
+        assert!(!status.device_level);
+        assert!(status.is_online());
+        assert!(!status.is_recording());
+        assert_eq!(status.encode.as_deref(), Some("ON"));
+    }
+
+    #[test]
+    fn test_parse_status_extensions() {
+        let xml = CHANNEL_STATUS.replace("<Record>OFF</Record>", "<Record>OFF</Record>\n<VendorExt><Temperature>46</Temperature></VendorExt>");
+        let status = DeviceStatus::parse(xml.as_bytes()).unwrap();
+        assert_eq!(status.extensions.get("VendorExt,Temperature").map(|s| s.as_str()), Some("46"));
+        //标准元素不计入扩展
+        assert!(!status.extensions.contains_key("SN"));
+        assert_eq!(status.extensions.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_channel_status_device_fallback() {
+        let xml = CHANNEL_STATUS.replace("34020000001320000101", "34020000001110000001");
+        let status = DeviceStatus::parse_channel(xml.as_bytes(), "34020000001320000101").unwrap();
+        assert!(status.device_level);
+        assert_eq!(status.device_id, "34020000001110000001");
+    }
+
+    #[test]
+    fn test_parse_device_info() {
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>DeviceInfo</CmdType>
+<SN>17430</SN>
+<DeviceID>34020000001110000001</DeviceID>
+<DeviceName>IP CAMERA</DeviceName>
+<Result>OK</Result>
+<Manufacturer>Hikvision</Manufacturer>
+<Model>DS-2CD2T47G2-L</Model>
+<Firmware>V5.7.3 build 220112</Firmware>
+<Channel>1</Channel>
+</Response>
+"#;
+        let info = DeviceInfo::parse(xml.as_bytes()).unwrap();
+        assert_eq!(info.device_id, "34020000001110000001");
+        assert_eq!(info.device_name.as_deref(), Some("IP CAMERA"));
+        assert_eq!(info.result.as_deref(), Some("OK"));
+        assert_eq!(info.manufacturer.as_deref(), Some("Hikvision"));
+        assert_eq!(info.model.as_deref(), Some("DS-2CD2T47G2-L"));
+        assert_eq!(info.firmware.as_deref(), Some("V5.7.3 build 220112"));
+        assert_eq!(info.channel, Some(1));
+
+        //NVR常省略DeviceName,Firmware为空
+        let xml = r#"<?xml version="1.0" encoding="GB2312"?>
+<Response>
+<CmdType>DeviceInfo</CmdType>
+<SN>17431</SN>
+<DeviceID>34020000001180000001</DeviceID>
+<Result>OK</Result>
+<Manufacturer>Dahua</Manufacturer>
+<Model>NVR4208</Model>
+<Firmware></Firmware>
+<Channel>8</Channel>
+</Response>
+"#;
+        let info = DeviceInfo::parse(xml.as_bytes()).unwrap();
+        assert_eq!(info.device_name, None);
+        assert_eq!(info.firmware, None);
+        assert_eq!(info.channel, Some(8));
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use rsip::Request;
+
+use common::log::{info, warn};
+use common::once_cell::sync::Lazy;
+use common::tokio::sync::mpsc;
+
+//续订周期占Expires的比例,留出余量保证在过期前完成续订
+const RENEW_RATIO: f64 = 0.8;
+//续订周期下限,避免Expires过小时频繁续订
+const MIN_RENEW_PERIOD: Duration = Duration::from_secs(10);
+
+/// 订阅续订节奏：续订周期取Expires的RENEW_RATIO,各设备按device_id确定周期内相位,
+/// 使全网续订均匀分布于周期内,而非集中在过期前同时发出
+pub struct RenewalPacer;
+
+impl RenewalPacer {
+    pub fn period(expires: Duration) -> Duration {
+        expires.mul_f64(RENEW_RATIO).max(MIN_RENEW_PERIOD)
+    }
+
+    /// 设备在续订周期内的相位：[0, period),同一设备与Expires恒定(跨进程重启不变)
+    pub fn phase(device_id: &str, expires: Duration) -> Duration {
+        let period = Self::period(expires).as_millis() as u64;
+        Duration::from_millis(Self::fnv1a(device_id.as_bytes()) % period)
+    }
+
+    /// 距下次续订的时长：按墙钟对齐周期,取now之后首个相位点
+    pub fn next_delay(device_id: &str, expires: Duration, now: Duration) -> Duration {
+        let period = Self::period(expires).as_millis() as u64;
+        let phase = Self::phase(device_id, expires).as_millis() as u64;
+        let offset = (now.as_millis() as u64) % period;
+        let delay = if phase > offset { phase - offset } else { period - offset + phase };
+        Duration::from_millis(delay)
+    }
+
+    pub fn next_delay_now(device_id: &str, expires: Duration) -> Duration {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self::next_delay(device_id, expires, now)
+    }
+
+    //FNV-1a：结果不随编译器版本变化,保证相位稳定
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+    }
+}
+
+//可立即重试的终止(deactivated/timeout等)也至少等待此时长,避免平台反复终止时形成订阅风暴
+const MIN_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+//probation/giveup未带retry-after时的等待时长
+const DEFAULT_RETRY_AFTER: u32 = 60;
+
+//订阅不可重试终止的接收端
+static TERMINATED_WATCHERS: Lazy<Mutex<Vec<mpsc::UnboundedSender<SubscriptionTerminated>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// NOTIFY的Subscription-State头域(RFC 6665 4.1.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionState {
+    Active,
+    Pending,
+    Terminated { reason: Option<String>, retry_after: Option<u32> },
+}
+
+impl SubscriptionState {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';').map(str::trim);
+        let state = parts.next()?.to_ascii_lowercase();
+        let (mut reason, mut retry_after) = (None, None);
+        for param in parts {
+            if let Some((k, v)) = param.split_once('=') {
+                match &k.trim().to_ascii_lowercase()[..] {
+                    "reason" => { reason = Some(v.trim().to_ascii_lowercase()); }
+                    "retry-after" => { retry_after = v.trim().parse::<u32>().ok(); }
+                    _ => {}
+                }
+            }
+        }
+        match &state[..] {
+            "active" => Some(SubscriptionState::Active),
+            "pending" => Some(SubscriptionState::Pending),
+            "terminated" => Some(SubscriptionState::Terminated { reason, retry_after }),
+            _ => None,
+        }
+    }
+
+    /// 请求中的Subscription-State;未携带或无法识别为None
+    pub fn of(req: &Request) -> Option<Self> {
+        req.headers.iter().find_map(|header| {
+            let header = header.to_string();
+            let (name, value) = header.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("Subscription-State") { Self::parse(value) } else { None }
+        })
+    }
+
+    /// 终止后重新订阅的等待时长;rejected/noresource/invariant不可重试,为None
+    pub fn resubscribe_delay(&self) -> Option<Duration> {
+        let SubscriptionState::Terminated { reason, retry_after } = self else {
+            return None;
+        };
+        let secs = match reason.as_deref() {
+            Some("rejected" | "noresource" | "invariant") => return None,
+            Some("probation" | "giveup") => retry_after.unwrap_or(DEFAULT_RETRY_AFTER),
+            _ => retry_after.unwrap_or(0),
+        };
+        Some(Duration::from_secs(secs as u64).max(MIN_RESUBSCRIBE_DELAY))
+    }
+}
+
+/// 平台不可重试地终止了订阅,需人工介入
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionTerminated {
+    pub device_id: String,
+    //订阅类型：Catalog/Alarm
+    pub kind: String,
+    pub reason: Option<String>,
+}
+
+impl SubscriptionTerminated {
+    pub fn watch() -> mpsc::UnboundedReceiver<SubscriptionTerminated> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        TERMINATED_WATCHERS.lock().push(tx);
+        rx
+    }
+
+    fn notify(event: SubscriptionTerminated) {
+        TERMINATED_WATCHERS.lock().retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// 订阅终止处理：可重试时以等待时长调用resubscribe并返回该时长,否则通知订阅方
+    pub fn on_terminated<F: FnOnce(Duration)>(device_id: &str, kind: &str, state: &SubscriptionState, resubscribe: F) -> Option<Duration> {
+        let SubscriptionState::Terminated { reason, .. } = state else {
+            return None;
+        };
+        match state.resubscribe_delay() {
+            Some(delay) => {
+                info!("device_id = {device_id},{kind}订阅被终止: reason = {reason:?},{}s后重新订阅", delay.as_secs());
+                resubscribe(delay);
+                Some(delay)
+            }
+            None => {
+                warn!("device_id = {device_id},{kind}订阅被终止且不可重试: reason = {reason:?}");
+                Self::notify(SubscriptionTerminated { device_id: device_id.to_string(), kind: kind.to_string(), reason: reason.clone() });
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{RenewalPacer, SubscriptionState, SubscriptionTerminated};
+
+    #[test]
+    fn test_renewal_phase_per_device() {
+        let expires = Duration::from_secs(3600);
+        let period = RenewalPacer::period(expires);
+        assert_eq!(period, Duration::from_secs(2880));
+        let a = RenewalPacer::phase("34020000001110000001", expires);
+        let b = RenewalPacer::phase("34020000001110000002", expires);
+        assert_ne!(a, b);
+        assert!(a < period && b < period);
+        //同一设备相位稳定
+        assert_eq!(a, RenewalPacer::phase("34020000001110000001", expires));
+
+        let now = Duration::from_secs(1_700_000_000);
+        for device_id in ["34020000001110000001", "34020000001110000002"] {
+            let delay = RenewalPacer::next_delay(device_id, expires, now);
+            assert!(delay > Duration::ZERO && delay <= period);
+            //续订时刻落在该设备的相位点上
+            let at = (now + delay).as_millis() % period.as_millis();
+            assert_eq!(at, RenewalPacer::phase(device_id, expires).as_millis());
+        }
+    }
+
+    #[test]
+    fn test_terminated_notify_resubscribe() {
+        use std::cell::Cell;
+        use rsip::SipMessage;
+        let notify = |state: &str| {
+            let text = format!("NOTIFY sip:34020000002000000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.199:5060;rport;branch=z9hG4bK199199199\r\n\
+From: <sip:34020000001110000199@3402000000>;tag=199\r\n\
+To: <sip:34020000002000000001@3402000000>;tag=local\r\n\
+Call-ID: catalog-subscribe-199\r\n\
+CSeq: 8 NOTIFY\r\n\
+Event: Catalog;id=1\r\n\
+Subscription-State: {state}\r\n\
+Content-Length: 0\r\n\r\n");
+            match SipMessage::try_from(text.as_str()).unwrap() {
+                SipMessage::Request(req) => req,
+                SipMessage::Response(_) => unreachable!(),
+            }
+        };
+        let device_id = "34020000001110000199";
+        let mut terminated = SubscriptionTerminated::watch();
+
+        //可重试：按retry-after重新订阅
+        let state = SubscriptionState::of(&notify("terminated;reason=timeout;retry-after=30")).unwrap();
+        assert_eq!(state, SubscriptionState::Terminated { reason: Some("timeout".to_string()), retry_after: Some(30) });
+        let resubscribed = Cell::new(None);
+        let delay = SubscriptionTerminated::on_terminated(device_id, "Catalog", &state, |delay| resubscribed.set(Some(delay)));
+        assert_eq!(delay, Some(Duration::from_secs(30)));
+        assert_eq!(resubscribed.get(), Some(Duration::from_secs(30)));
+        assert!(terminated.try_recv().is_err());
+
+        //不可重试：不重新订阅,上报事件
+        let state = SubscriptionState::of(&notify("terminated;reason=rejected")).unwrap();
+        let resubscribed = Cell::new(false);
+        assert_eq!(SubscriptionTerminated::on_terminated(device_id, "Catalog", &state, |_| resubscribed.set(true)), None);
+        assert!(!resubscribed.get());
+        let event = terminated.try_recv().unwrap();
+        assert_eq!(event.device_id, device_id);
+        assert_eq!(event.reason.as_deref(), Some("rejected"));
+
+        assert_eq!(SubscriptionState::of(&notify("active;expires=3600")), Some(SubscriptionState::Active));
+        assert_eq!(SubscriptionState::parse("terminated;reason=deactivated").unwrap().resubscribe_delay(), Some(Duration::from_secs(1)));
+    }
+}
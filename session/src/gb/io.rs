@@ -1,8 +1,9 @@
 use encoding_rs::GB18030;
 use rsip::message::HeadersExt;
-use rsip::SipMessage;
+use rsip::{Response, SipMessage};
 use common::tokio::sync::mpsc::{Receiver, Sender};
 
+use common::bytes::Bytes;
 use common::exception::{TransError};
 use common::log::{debug, error, info};
 use common::net::state::{Package, Zip};
@@ -11,11 +12,17 @@ use crate::gb::handler;
 use crate::gb::handler::parser;
 use crate::gb::shared::event::EventSession;
 pub use crate::gb::shared::rw::RWSession;
+use crate::gb::shared::rw::CRLF_PONG;
 
 pub async fn read(mut input: Receiver<Zip>, output_tx: Sender<Zip>) {
     while let Some(zip) = input.recv().await {
         match zip {
             Zip::Data(Package { association, data }) => {
+                //对端CRLF保活ping,回复pong
+                if RWSession::is_crlf_ping(&data) {
+                    let _ = output_tx.try_send(Zip::build_data(Package::new(association, Bytes::from_static(CRLF_PONG))));
+                    continue;
+                }
                 match SipMessage::try_from(data) {
                     Ok(msg) => {
                         match msg {
@@ -25,14 +32,7 @@ pub async fn read(mut input: Receiver<Zip>, output_tx: Sender<Zip>) {
                             }
                             SipMessage::Response(res) => {
                                 info!("接收:{:?}\nResponse:\n{} {}\n{}\n{}",&association,&res.version,&res.status_code,&res.headers,GB18030.decode(&res.body).0);
-                                match (res.call_id_header(), res.cseq_header(), parser::header::get_device_id_by_response(&res)) {
-                                    (Ok(call_id), Ok(cs_eq), Ok(to_device_id)) => {
-                                        let _ = EventSession::handle_response(to_device_id, call_id.clone().into(), cs_eq.clone().into(), res).await;
-                                    }
-                                    (call_res, cseq_res, device_id_res) => {
-                                        error!("call={:?},call={:?},call={:?}",call_res,cseq_res,device_id_res);
-                                    }
-                                }
+                                hand_response(res).await;
                             }
                         }
                     }
@@ -52,6 +52,18 @@ pub async fn read(mut input: Receiver<Zip>, output_tx: Sender<Zip>) {
     info!("gb read exit");
 }
 
+//响应交由事件会话匹配请求：UDP/TCP/WS共用
+pub async fn hand_response(res: Response) {
+    match (res.call_id_header(), res.cseq_header(), parser::header::get_device_id_by_response(&res)) {
+        (Ok(call_id), Ok(cs_eq), Ok(to_device_id)) => {
+            let _ = EventSession::handle_response(to_device_id, call_id.clone().into(), cs_eq.clone().into(), res).await;
+        }
+        (call_res, cseq_res, device_id_res) => {
+            error!("call={:?},call={:?},call={:?}",call_res,cseq_res,device_id_res);
+        }
+    }
+}
+
 pub async fn write(mut output_rx: Receiver<Zip>, output: Sender<Zip>) {
     while let Some(zip) = output_rx.recv().await {
         match &zip {
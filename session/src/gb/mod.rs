@@ -1,21 +1,32 @@
+use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
 use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
 
 use common::serde::Deserialize;
+use common::serde_default;
 use common::tokio::sync::mpsc;
 use common::cfg_lib::conf;
 use common::constructor::Get;
 
 use common::exception::{GlobalResult, TransError};
-use common::log::{error, info};
+use common::log::{error, info, Level};
 use common::net;
 use common::net::state::{CHANNEL_BUFFER_SIZE};
 
-pub use crate::gb::shared::rw::RWSession;
+pub use crate::gb::shared::rw::{DeviceOffline, RWSession};
+pub use crate::gb::shared::dialog::{Dialog, DialogSession, DialogState, DialogStore, MemoryDialogStore, StreamLifecycle, StreamListener};
+pub use crate::gb::shared::event::{ConflictPolicy, MatchMode};
+pub use crate::gb::shared::ws::WsSession;
+use crate::gb::handler::buffer::OverflowPolicy;
+use crate::gb::handler::cmd::PortParity;
+use crate::gb::handler::limiter::BusyPolicy;
+use crate::gb::handler::quirks::DeviceQuirks;
 
 mod shared;
 pub mod handler;
-mod io;
+pub(crate) mod io;
 
 #[derive(Debug, Get, Deserialize)]
 #[serde(crate = "common::serde")]
@@ -25,13 +36,132 @@ pub struct SessionConf {
     wan_ip: Ipv4Addr,
     lan_port: u16,
     wan_port: u16,
+    //对外宣告的Via/Contact端口(如负载均衡映射端口),未配置时取wan_port
+    #[serde(default)]
+    advertised_port: Option<u16>,
+    //TCP信令连接CRLF保活间隔(秒),0-关闭
+    #[serde(default = "default_crlf_keepalive")]
+    crlf_keepalive: u16,
+    //响应匹配模式：strict|lenient
+    #[serde(default)]
+    response_match: MatchMode,
+    //预期内的常规情况(如ACK头域提取)日志级别：trace|debug|info|warn|error
+    #[serde(default = "default_routine_log_level")]
+    routine_log_level: String,
+    //设备应答SDP上限(字节),超出时拒绝解析
+    #[serde(default = "default_max_sdp_size")]
+    max_sdp_size: usize,
+    //全局在途命令上限,0-不限制
+    #[serde(default = "default_max_in_flight_cmds")]
+    max_in_flight_cmds: usize,
+    //达上限时策略：queue|reject
+    #[serde(default)]
+    cmd_busy_policy: BusyPolicy,
+    //queue策略下最长排队等待(毫秒)
+    #[serde(default = "default_cmd_queue_wait_ms")]
+    cmd_queue_wait_ms: u64,
+    //ACK后看门狗宽限期(秒),期间对话未关闭且媒体未确认时自动BYE;0-关闭
+    #[serde(default)]
+    ack_watchdog_secs: u64,
+    //本端支持的SIP扩展option-tag：请求携带Supported,设备Require其他tag时应答420;缺省为空
+    #[serde(default)]
+    supported: Vec<String>,
+    //同Call-ID已有在途请求时策略：reject|queue
+    #[serde(default)]
+    ident_conflict: ConflictPolicy,
+    //连续丢失心跳次数达此值判定离线,缺省为3
+    #[serde(default = "default_heartbeat_miss_limit")]
+    heartbeat_miss_limit: u32,
+    //设备兼容项：key为device_id或编码前缀,取最长匹配,见DeviceQuirks
+    #[serde(default)]
+    device_quirks: HashMap<String, DeviceQuirks>,
+    //收流端口为奇数时策略：off|reject|adjust
+    #[serde(default)]
+    rtp_port_parity: PortParity,
+    //报警/位置通知流缓冲容量(条)
+    #[serde(default = "default_notify_buffer_capacity")]
+    notify_buffer_capacity: usize,
+    //通知缓冲已满时策略：drop_oldest|error
+    #[serde(default)]
+    notify_overflow: OverflowPolicy,
 }
+serde_default!(default_crlf_keepalive, u16, 30);
+serde_default!(default_heartbeat_miss_limit, u32, 3);
+serde_default!(default_routine_log_level, String, "debug".to_string());
+serde_default!(default_max_sdp_size, usize, 64 * 1024);
+serde_default!(default_max_in_flight_cmds, usize, 1024);
+serde_default!(default_cmd_queue_wait_ms, u64, 3000);
+serde_default!(default_notify_buffer_capacity, usize, 256);
+static ROUTINE_LOG_LEVEL: OnceLock<Level> = OnceLock::new();
+static MAX_SDP_SIZE: OnceLock<usize> = OnceLock::new();
+static ACK_WATCHDOG_GRACE: OnceLock<Option<Duration>> = OnceLock::new();
+static SUPPORTED_TAGS: OnceLock<Vec<String>> = OnceLock::new();
+static HEARTBEAT_MISS_LIMIT: OnceLock<u32> = OnceLock::new();
+static IDENT_CONFLICT: OnceLock<ConflictPolicy> = OnceLock::new();
+static RTP_PORT_PARITY: OnceLock<PortParity> = OnceLock::new();
+static NOTIFY_BUFFER_CAPACITY: OnceLock<usize> = OnceLock::new();
+static NOTIFY_OVERFLOW: OnceLock<OverflowPolicy> = OnceLock::new();
 
 impl SessionConf {
     pub fn get_session_by_conf() -> Self {
         SessionConf::conf()
     }
 
+    /// 常规情况日志级别,配置非法时取debug
+    pub fn routine_log_level() -> Level {
+        *ROUTINE_LOG_LEVEL.get_or_init(|| {
+            Level::from_str(SessionConf::conf().get_routine_log_level()).unwrap_or(Level::Debug)
+        })
+    }
+
+    pub fn advertised_port(&self) -> u16 {
+        self.advertised_port.unwrap_or(self.wan_port)
+    }
+
+    pub fn max_sdp_size() -> usize {
+        *MAX_SDP_SIZE.get_or_init(|| *SessionConf::conf().get_max_sdp_size())
+    }
+
+    /// ACK看门狗宽限期,未配置或为0时关闭
+    pub fn ack_watchdog_grace() -> Option<Duration> {
+        *ACK_WATCHDOG_GRACE.get_or_init(|| Some(*SessionConf::conf().get_ack_watchdog_secs()).filter(|secs| *secs > 0).map(Duration::from_secs))
+    }
+
+    /// 本端支持的SIP扩展option-tag
+    pub fn supported_tags() -> &'static [String] {
+        SUPPORTED_TAGS.get_or_init(|| SessionConf::conf().get_supported().iter().map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect())
+    }
+
+    /// 判定离线的连续心跳丢失次数
+    pub fn heartbeat_miss_limit() -> u32 {
+        *HEARTBEAT_MISS_LIMIT.get_or_init(|| *SessionConf::conf().get_heartbeat_miss_limit())
+    }
+
+    /// 事件标识冲突处理策略
+    pub fn ident_conflict() -> ConflictPolicy {
+        *IDENT_CONFLICT.get_or_init(|| *SessionConf::conf().get_ident_conflict())
+    }
+
+    /// 收流端口奇偶校验策略
+    pub fn rtp_port_parity() -> PortParity {
+        *RTP_PORT_PARITY.get_or_init(|| *SessionConf::conf().get_rtp_port_parity())
+    }
+
+    /// 通知流缓冲容量,至少为1
+    pub fn notify_buffer_capacity() -> usize {
+        *NOTIFY_BUFFER_CAPACITY.get_or_init(|| (*SessionConf::conf().get_notify_buffer_capacity()).max(1))
+    }
+
+    /// 通知缓冲溢出策略
+    pub fn notify_overflow() -> OverflowPolicy {
+        *NOTIFY_OVERFLOW.get_or_init(|| *SessionConf::conf().get_notify_overflow())
+    }
+
+    /// 配置的设备兼容项,运行期可经DeviceQuirks::set调整
+    pub fn device_quirks() -> HashMap<String, DeviceQuirks> {
+        SessionConf::conf().get_device_quirks().clone()
+    }
+
     pub fn listen_gb_server(&self) -> GlobalResult<(Option<TcpListener>, Option<UdpSocket>)> {
         let socket_addr = SocketAddr::from_str(&format!("0.0.0.0:{}", self.get_wan_port())).hand_log(|msg| error! {"{msg}"})?;
         let res = net::sdx::listen(net::state::Protocol::ALL, socket_addr);
@@ -48,6 +178,7 @@ impl SessionConf {
         let write_task = common::tokio::spawn(async move {
             io::write(output_rx, output).await;
         });
+        common::tokio::spawn(handler::requester::flush_catalog_task());
         read_task.await.hand_log(|msg| error!("读取数据异常:{msg}"))?;
         write_task.await.hand_log(|msg| error!("写出数据异常:{msg}"))?;
         Ok(())
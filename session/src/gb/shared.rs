@@ -1,6 +1,6 @@
 /// 数据读写会话：与网络协议交互
-/// UDP：三次心跳超时则移除会话
-/// TCP：连接断开或三次心跳超时则移除会话
+/// UDP：连续heartbeat_miss_limit次(默认三次)心跳超时则移除会话
+/// TCP：连接断开或连续heartbeat_miss_limit次心跳超时则移除会话
 pub mod rw {
     use std::collections::{BTreeSet, HashMap};
     use std::sync::Arc;
@@ -9,6 +9,7 @@ pub mod rw {
 
     use parking_lot::Mutex;
     use rsip::{Response, SipMessage};
+    use rsip::prelude::{HeadersExt, UntypedHeader};
 
     use common::anyhow::anyhow;
     use common::bytes::Bytes;
@@ -20,19 +21,40 @@ pub mod rw {
     use common::once_cell::sync::Lazy;
     use common::tokio;
     use common::tokio::sync::{mpsc, Notify};
-    use common::tokio::sync::mpsc::{Receiver, Sender};
+    use common::tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
     use common::tokio::time;
     use common::tokio::time::Instant;
 
-    use crate::gb::shared::event::{Container, EventSession, EXPIRES, Ident};
+    use crate::gb::handler::status::DeviceClock;
+    use crate::gb::shared::event::{Container, EventSession, Ident, TapDirection};
+    #[cfg(any(test, feature = "mem-transport"))]
+    use crate::gb::shared::mem::MemTransport;
+    use crate::gb::shared::route::RouteTable;
+    use crate::gb::shared::ws::WsSession;
+    use crate::gb::SessionConf;
+    use crate::general::model::SignalTransport;
+    use crate::general::SipTimerConf;
     use crate::storage::entity::GmvDevice;
 
+    //RFC5626 CRLF保活：ping为双CRLF,pong为单CRLF
+    pub const CRLF_PING: &[u8] = b"\r\n\r\n";
+    pub const CRLF_PONG: &[u8] = b"\r\n";
+
     static RW_SESSION: Lazy<RWSession> = Lazy::new(|| RWSession::init());
+    //设备离线事件订阅者,供UI等上层感知心跳超时
+    static OFFLINE_WATCHERS: Lazy<Mutex<Vec<UnboundedSender<DeviceOffline>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+    /// 心跳超时离线事件：last_seen为最后一次心跳(或注册)时刻
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct DeviceOffline {
+        pub device_id: String,
+        pub last_seen: Instant,
+    }
 
     pub struct RWSession {
         shared: Arc<Shared>,
         //更新设备状态
-        db_task: Sender<String>,
+        db_task: Sender<DeviceOffline>,
     }
 
     impl RWSession {
@@ -41,7 +63,7 @@ pub mod rw {
             let session = RWSession {
                 shared: Arc::new(
                     Shared {
-                        state: Mutex::new(State { sessions: HashMap::new(), expirations: BTreeSet::new(), bill_map: HashMap::new() }),
+                        state: Mutex::new(State { sessions: HashMap::new(), expirations: BTreeSet::new(), bill_map: HashMap::new(), alt_bills: HashMap::new() }),
                         background_task: Notify::new(),
                     }
                 ),
@@ -57,18 +79,42 @@ pub mod rw {
                     let clean_task = tokio::spawn(async move {
                         let _ = Self::purge_expired_task(shared).await;
                     });
+                    let keepalive = *SessionConf::get_session_by_conf().get_crlf_keepalive();
+                    if keepalive > 0 {
+                        tokio::spawn(Self::ping_at_cadence(Duration::from_secs(keepalive as u64), Self::crlf_ping_tcp_sessions));
+                    }
                     let _ = db_task.await.hand_log(|msg| error!("Session:{msg}"));
                     let _ = clean_task.await.hand_log(|msg| error!("WEB:{msg}"));
                 });
             }).expect("Shared:rw background thread create failed");
             session
         }
-        async fn do_update_device_status(mut rx: Receiver<String>) {
-            while let Some(device_id) = rx.recv().await {
-                let _ = GmvDevice::update_gmv_device_status_by_device_id(&device_id, 0).await;
+        async fn do_update_device_status(mut rx: Receiver<DeviceOffline>) {
+            while let Some(offline) = rx.recv().await {
+                //心跳超时离线：取消未触发的延迟查询;不在RW锁内调用,避免与事件锁交叉
+                EventSession::cancel_scheduled(&offline.device_id);
+                let _ = GmvDevice::update_gmv_device_status_by_device_id(&offline.device_id, 0).await;
+                Self::notify_offline(offline);
             }
         }
 
+        /// 订阅设备离线事件：心跳连续超时次数达heartbeat_miss_limit时推送
+        pub fn watch_offline() -> UnboundedReceiver<DeviceOffline> {
+            let (tx, rx) = mpsc::unbounded_channel();
+            OFFLINE_WATCHERS.lock().push(tx);
+            rx
+        }
+
+        //推送离线事件,同时移除已关闭的订阅者
+        pub(super) fn notify_offline(offline: DeviceOffline) {
+            OFFLINE_WATCHERS.lock().retain(|tx| tx.send(offline.clone()).is_ok());
+        }
+
+        /// 判定离线的静默时长：心跳周期 * 允许连续丢失次数(至少1次)
+        pub fn offline_after(heartbeat: u8, miss_limit: u32) -> Duration {
+            Duration::from_secs(heartbeat as u64 * miss_limit.max(1) as u64)
+        }
+
         async fn purge_expired_task(shared: Arc<Shared>) -> GlobalResult<()> {
             loop {
                 if let Some(when) = shared.purge_expired_state().await? {
@@ -82,8 +128,35 @@ pub mod rw {
             }
         }
 
+        //按固定间隔触发ping,首次在一个间隔之后
+        pub(super) async fn ping_at_cadence<F: FnMut()>(interval: Duration, mut ping: F) {
+            let mut ticker = time::interval_at(Instant::now() + interval, interval);
+            loop {
+                ticker.tick().await;
+                ping();
+            }
+        }
+
+        //向所有TCP会话发送CRLF保活，UDP无连接不处理
+        fn crlf_ping_tcp_sessions() {
+            let targets: Vec<(Sender<Zip>, Association)> = {
+                let guard = RW_SESSION.shared.state.lock();
+                guard.sessions.values()
+                    .filter(|(_, _, _, bill)| &Protocol::TCP == bill.get_protocol())
+                    .map(|(tx, _, _, bill)| (tx.clone(), bill.clone()))
+                    .collect()
+            };
+            for (tx, bill) in targets {
+                let _ = tx.try_send(Zip::build_data(Package::new(bill, Bytes::from_static(CRLF_PING)))).hand_log(|msg| warn!("{msg}"));
+            }
+        }
+
+        pub fn is_crlf_ping(data: &[u8]) -> bool {
+            data == CRLF_PING
+        }
+
         pub fn insert(device_id: &String, tx: Sender<Zip>, heartbeat: u8, bill: &Association) {
-            let expires = Duration::from_secs(heartbeat as u64 * 3);
+            let expires = Self::offline_after(heartbeat, SessionConf::heartbeat_miss_limit());
             let when = Instant::now() + expires;
 
             let mut state = RW_SESSION.shared.state.lock();
@@ -108,7 +181,10 @@ pub mod rw {
             let mut guard = RW_SESSION.shared.state.lock();
 
             let state = &mut *guard;
+            //备用信令通道断开：仅移除该通道
+            state.alt_bills.retain(|_, alt| alt != bill);
             state.bill_map.remove(bill).map(|device_id| {
+                state.alt_bills.remove(&device_id);
                 state.sessions.remove(&device_id).map(|(_tx, when, _expires, _bill)| {
                     state.expirations.remove(&(when, device_id));
                 });
@@ -130,6 +206,7 @@ pub mod rw {
                 let mut guard = RW_SESSION.shared.state.lock();
 
                 let state = &mut *guard;
+                state.alt_bills.remove(device_id);
                 if let Some((tx, when, _expires, bill)) = state.sessions.remove(device_id) {
                     state.expirations.remove(&(when, device_id.clone()));
                     state.bill_map.remove(&bill);
@@ -140,6 +217,8 @@ pub mod rw {
                 } else { None }
             };
 
+            EventSession::cancel_scheduled(device_id);
+            DeviceClock::forget(device_id);
             if let Some((tx, bill)) = res {
                 let _ = tx.try_send(Zip::build_event(Event::new(bill, 0))).hand_log(|msg| warn!("{msg}"));
             }
@@ -150,8 +229,11 @@ pub mod rw {
 
             let state = &mut *guard;
             state.sessions.get_mut(device_id).map(|(_tx, when, expires, bill)| {
-                //UDP的无连接状态，需根据心跳实时刷新其网络三元组
-                if &Protocol::UDP == bill.get_protocol() {
+                //设备经另一传输发送消息：记为备用信令通道,供按命令覆盖传输
+                if bill.get_protocol() != new_bill.get_protocol() {
+                    state.alt_bills.insert(device_id.clone(), new_bill);
+                } else if &Protocol::UDP == bill.get_protocol() {
+                    //UDP的无连接状态，需根据心跳实时刷新其网络三元组
                     state.bill_map.remove(bill);
                     state.bill_map.insert(bill.clone(), device_id.clone());
                     *bill = new_bill;
@@ -171,6 +253,12 @@ pub mod rw {
             option_bill
         }
 
+        /// 设备最后一次心跳(或注册)时刻
+        pub fn last_seen(device_id: &String) -> Option<Instant> {
+            let guard = RW_SESSION.shared.state.lock();
+            guard.sessions.get(device_id).map(|(_tx, when, expires, _bill)| *when - *expires)
+        }
+
         pub fn get_expires_by_device_id(device_id: &String) -> Option<Duration> {
             let guard = RW_SESSION.shared.state.lock();
             let option_expires = guard.sessions.get(device_id).map(|(_tx, _when, expires, _bill)| *expires);
@@ -179,9 +267,36 @@ pub mod rw {
             option_expires
         }
 
-        fn get_output_sender_by_device_id(device_id: &String) -> Option<(Sender<Zip>, Association)> {
+        /// 设备是否可经该传输下发信令：注册传输或已建立的备用通道
+        pub fn supports_transport(device_id: &String, transport: SignalTransport) -> bool {
             let guard = RW_SESSION.shared.state.lock();
-            let opt = guard.sessions.get(device_id).map(|(sender, _, _, bill)| (sender.clone(), bill.clone()));
+            guard.sessions.get(device_id).map(|(_, _, _, bill)| transport.matches(bill.get_protocol())).unwrap_or(false)
+                || guard.alt_bills.get(device_id).map(|alt| transport.matches(alt.get_protocol())).unwrap_or(false)
+        }
+
+        //指定传输与注册传输不同且备用通道传输一致时,走备用通道
+        pub(super) fn use_alt_bill(registered: &Protocol, alt: Option<&Protocol>, transport: Option<SignalTransport>) -> bool {
+            match (transport, alt) {
+                (Some(transport), Some(alt)) => !transport.matches(registered) && transport.matches(alt),
+                _ => false,
+            }
+        }
+
+        //设备无直连会话时,按设备域路由至上级平台的会话
+        fn get_output_sender_by_device_id(device_id: &String, transport: Option<SignalTransport>) -> Option<(Sender<Zip>, Association)> {
+            let guard = RW_SESSION.shared.state.lock();
+            let target = if guard.sessions.contains_key(device_id) { Some(device_id.clone()) } else { RouteTable::resolve(device_id) };
+            let opt = target.and_then(|target| {
+                let (sender, _, _, bill) = guard.sessions.get(&target)?;
+                let alt = guard.alt_bills.get(&target);
+                if Self::use_alt_bill(bill.get_protocol(), alt.map(|alt| alt.get_protocol()), transport) {
+                    return alt.map(|alt| (sender.clone(), alt.clone()));
+                }
+                if let Some(transport) = transport.filter(|transport| !transport.matches(bill.get_protocol())) {
+                    warn!("设备 {target},无{}信令通道,沿用注册传输",transport.via_name());
+                }
+                Some((sender.clone(), bill.clone()))
+            });
 
 
             opt
@@ -204,24 +319,70 @@ pub mod rw {
 
     impl RequestOutput {
         pub fn do_send_off(device_id: &String, msg: SipMessage) -> GlobalResult<()> {
-            let (request_sender, bill) = RWSession::get_output_sender_by_device_id(device_id).ok_or(SysErr(anyhow!("设备 {device_id},已下线")))?;
-            let _ = request_sender.try_send(Zip::build_data(Package::new(bill, Bytes::from(msg)))).hand_log(|msg| warn!("{msg}"));
-            Ok(())
+            Self::dispatch(device_id, msg)
         }
 
-        pub fn do_send(self) -> GlobalResult<()> {
+        pub async fn do_send(self) -> GlobalResult<()> {
             let device_id = self.ident.get_device_id();
-            let (request_sender, bill) = RWSession::get_output_sender_by_device_id(device_id).ok_or(SysErr(anyhow!("设备 {device_id},已下线")))?;
-            let when = Instant::now() + Duration::from_secs(EXPIRES);
-            EventSession::listen_event(&self.ident, when, Container::build_res(self.event_sender))?;
-            let _ = request_sender.try_send(Zip::build_data(Package::new(bill, Bytes::from(self.msg)))).hand_log(|msg| error!("{msg}"));
-            Ok(())
+            let transport = Self::transport_of(&self.msg);
+            let udp = match (WsSession::get_sender(device_id), RWSession::get_output_sender_by_device_id(device_id, transport)) {
+                (Some(_), _) => false,
+                (None, Some((_, bill))) => &Protocol::UDP == bill.get_protocol(),
+                #[cfg(any(test, feature = "mem-transport"))]
+                (None, None) if MemTransport::is_attached(device_id) => false,
+                (None, None) => Err(SysErr(anyhow!("设备 {device_id},已下线")))?,
+            };
+            let timer = SipTimerConf::get_sip_timer_conf();
+            let is_invite = EventSession::is_invite(&self.msg);
+            let branch = EventSession::branch_of_request(&self.msg);
+            EventSession::listen_event_queued(&self.ident, timer.transaction_timeout(is_invite), Container::build_res(self.event_sender), branch).await?;
+            //UDP不可靠：未收到任何响应前按T1倍增间隔重传
+            if udp {
+                Self::retransmit(self.ident.clone(), self.msg.clone(), timer.retransmit_intervals(is_invite));
+            }
+            Self::dispatch(device_id, self.msg)
+        }
+
+        fn retransmit(ident: Ident, msg: SipMessage, intervals: Vec<Duration>) {
+            if intervals.is_empty() {
+                return;
+            }
+            tokio::spawn(async move {
+                for interval in intervals {
+                    time::sleep(interval).await;
+                    if !EventSession::awaiting_response(&ident) {
+                        break;
+                    }
+                    let _ = Self::dispatch(ident.get_device_id(), msg.clone());
+                }
+            });
         }
 
         pub(super) fn _do_send(self) -> GlobalResult<()> {
-            let device_id = self.ident.get_device_id();
-            let (request_sender, bill) = RWSession::get_output_sender_by_device_id(device_id).ok_or(SysErr(anyhow!("设备 {device_id},已下线")))?;
-            let _ = request_sender.try_send(Zip::build_data(Package::new(bill, Bytes::from(self.msg)))).hand_log(|msg| error!("{msg}"));
+            Self::dispatch(self.ident.get_device_id(), self.msg)
+        }
+
+        //请求按Via传输选择出口;响应沿用注册传输
+        pub(super) fn transport_of(msg: &SipMessage) -> Option<SignalTransport> {
+            match msg {
+                SipMessage::Request(req) => req.via_header().ok().and_then(|via| SignalTransport::from_via(via.value())),
+                SipMessage::Response(_) => None,
+            }
+        }
+
+        //WS会话优先,否则经UDP/TCP网络出口下发
+        fn dispatch(device_id: &String, msg: SipMessage) -> GlobalResult<()> {
+            EventSession::tap(device_id, TapDirection::Outbound, &msg);
+            #[cfg(any(test, feature = "mem-transport"))]
+            if MemTransport::capture(device_id, &msg) {
+                return Ok(());
+            }
+            if let Some(ws_sender) = WsSession::get_sender(device_id) {
+                let _ = ws_sender.try_send(msg.to_string()).hand_log(|msg| error!("{msg}"));
+                return Ok(());
+            }
+            let (request_sender, bill) = RWSession::get_output_sender_by_device_id(device_id, Self::transport_of(&msg)).ok_or(SysErr(anyhow!("设备 {device_id},已下线")))?;
+            let _ = request_sender.try_send(Zip::build_data(Package::new(bill, Bytes::from(msg)))).hand_log(|msg| error!("{msg}"));
             Ok(())
         }
     }
@@ -238,17 +399,14 @@ pub mod rw {
 
             let state = &mut *guard;
             let now = Instant::now();
-            while let Some((when, device_id)) = state.expirations.iter().next() {
-                if when > &now {
-                    return Ok(Some(*when));
-                }
-                //放入队列中处理，避免阻塞导致锁长期占用:更新DB中设备状态为离线
-                let _ = RW_SESSION.db_task.clone().try_send(device_id.clone()).hand_log(|msg| warn!("{msg}"));
-                // GmvDevice::update_gmv_device_status_by_device_id(device_id, 0);
+            for (when, device_id) in take_expired(&mut state.expirations, now) {
                 //移除会话map
-                if let Some((tx, when, _dur, bill)) = state.sessions.remove(device_id) {
+                state.alt_bills.remove(&device_id);
+                if let Some((tx, _when, expires, bill)) = state.sessions.remove(&device_id) {
+                    //放入队列中处理，避免阻塞导致锁长期占用:更新DB中设备状态为离线并推送离线事件
+                    let offline = DeviceOffline { device_id: device_id.clone(), last_seen: when - expires };
+                    let _ = RW_SESSION.db_task.clone().try_send(offline).hand_log(|msg| warn!("{msg}"));
                     state.bill_map.remove(&bill);
-                    state.expirations.remove(&(when, device_id.to_string()));
                     //通知网络出口关闭TCP连接
                     if &Protocol::TCP == bill.get_protocol() {
                         let _ = tx.try_send(Zip::build_event(Event::new(bill, 0))).hand_log(|msg| warn!("{msg}"));
@@ -256,7 +414,7 @@ pub mod rw {
                 }
             }
 
-            Ok(None)
+            Ok(state.next_expiration())
         }
     }
 
@@ -267,6 +425,8 @@ pub mod rw {
         expirations: BTreeSet<(Instant, String)>,
         //映射网络三元组与设备ID，bill,device_id
         bill_map: HashMap<Association, String>,
+        //备用信令通道：设备经非注册传输建立的连接，device_id,bill
+        alt_bills: HashMap<String, Association>,
     }
 
     impl State {
@@ -275,47 +435,393 @@ pub mod rw {
             self.expirations.first().map(|expiration| expiration.0)
         }
     }
+
+    //取出now时已到期(心跳连续超时)的设备,按到期先后排列
+    pub(super) fn take_expired(expirations: &mut BTreeSet<(Instant, String)>, now: Instant) -> Vec<(Instant, String)> {
+        let mut expired = Vec::new();
+        while expirations.first().map(|(when, _)| when <= &now).unwrap_or(false) {
+            expired.extend(expirations.pop_first());
+        }
+        expired
+    }
+}
+
+/// WebSocket信令会话(RFC7118)：经网关以SIP over WS与设备交互
+/// 复用全部请求构建,按设备ID选择WS连接下发,Via传输为WS/WSS
+pub mod ws {
+    use std::collections::HashMap;
+
+    use futures_util::{SinkExt, StreamExt};
+    use parking_lot::RwLock;
+    use rsip::SipMessage;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use common::exception::{GlobalResult, TransError};
+    use common::log::{debug, error, info, warn};
+    use common::once_cell::sync::Lazy;
+    use common::tokio;
+    use common::tokio::sync::mpsc;
+    use common::tokio::sync::mpsc::{Receiver, Sender};
+
+    use crate::gb::handler::error_code::CmdErrorCode;
+
+    static WS_SESSION: Lazy<RwLock<HashMap<String, (Sender<String>, &'static str)>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+    const WS_BUFFER_SIZE: usize = 64;
+
+    pub struct WsSession;
+
+    impl WsSession {
+        /// 建立设备WS信令连接,收到的响应交由事件会话匹配
+        pub async fn connect(device_id: &String, url: &str) -> GlobalResult<()> {
+            let transport = Self::transport_of(url)?;
+            let (tx, mut rx) = WsTransport::connect(url).await?;
+            WS_SESSION.write().insert(device_id.clone(), (tx, transport));
+            let device_id = device_id.clone();
+            tokio::spawn(async move {
+                while let Some(text) = rx.recv().await {
+                    match SipMessage::try_from(text.as_str()) {
+                        Ok(SipMessage::Response(res)) => { crate::gb::io::hand_response(res).await; }
+                        Ok(SipMessage::Request(req)) => { warn!("WS信令暂不处理设备请求: device_id = {device_id},method = {}",req.method); }
+                        Err(err) => { debug!("接收WS:invalid data {err:?}"); }
+                    }
+                }
+                info!("WS信令连接关闭: device_id = {device_id}");
+                WS_SESSION.write().remove(&device_id);
+            });
+            Ok(())
+        }
+
+        pub fn remove(device_id: &String) {
+            WS_SESSION.write().remove(device_id);
+        }
+
+        /// WS会话的Via传输标识
+        pub fn get_transport(device_id: &String) -> Option<&'static str> {
+            WS_SESSION.read().get(device_id).map(|(_, transport)| *transport)
+        }
+
+        pub(super) fn get_sender(device_id: &String) -> Option<Sender<String>> {
+            WS_SESSION.read().get(device_id).map(|(tx, _)| tx.clone())
+        }
+
+        pub fn transport_of(url: &str) -> GlobalResult<&'static str> {
+            match url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase()).as_deref() {
+                Some("ws") => Ok("WS"),
+                Some("wss") => Ok("WSS"),
+                _ => Err(CmdErrorCode::InvalidParam.warn(&format!("无效的WS地址:{url}"))),
+            }
+        }
+    }
+
+    /// WS传输：一条SIP消息对应一个文本帧
+    pub struct WsTransport;
+
+    impl WsTransport {
+        //返回(发送端,接收端)
+        pub async fn connect(url: &str) -> GlobalResult<(Sender<String>, Receiver<String>)> {
+            let (stream, _res) = tokio_tungstenite::connect_async(url).await.hand_log(|msg| error!("WS连接失败:{msg}"))?;
+            let (mut sink, mut source) = stream.split();
+            let (out_tx, mut out_rx) = mpsc::channel::<String>(WS_BUFFER_SIZE);
+            let (in_tx, in_rx) = mpsc::channel::<String>(WS_BUFFER_SIZE);
+            tokio::spawn(async move {
+                while let Some(text) = out_rx.recv().await {
+                    if sink.send(Message::Text(text)).await.hand_log(|msg| error!("WS数据发送失败:{msg}")).is_err() {
+                        break;
+                    }
+                }
+                let _ = sink.close().await;
+            });
+            tokio::spawn(async move {
+                while let Some(Ok(msg)) = source.next().await {
+                    let text = match msg {
+                        Message::Text(text) => text,
+                        Message::Binary(data) => String::from_utf8_lossy(&data).to_string(),
+                        Message::Close(_) => break,
+                        _ => continue,
+                    };
+                    if in_tx.send(text).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok((out_tx, in_rx))
+        }
+    }
+}
+
+/// 内存信令通道(测试用)：替代网络出口,捕获下发消息,并按Ident注入响应
+/// 启用：测试内或 feature = "mem-transport"
+#[cfg(any(test, feature = "mem-transport"))]
+pub mod mem {
+    use std::collections::HashMap;
+
+    use parking_lot::RwLock;
+    use rsip::{Request, Response, SipMessage};
+    use rsip::prelude::{HeadersExt, UntypedHeader};
+
+    use common::exception::{GlobalResult, TransError};
+    use common::log::warn;
+    use common::once_cell::sync::Lazy;
+    use common::tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+    use crate::gb::shared::event::{EventSession, Ident};
+
+    static MEM_SESSION: Lazy<RwLock<HashMap<String, UnboundedSender<SipMessage>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+    //应答To缺少tag时补充
+    pub const MEM_TO_TAG: &str = "mem";
+
+    pub struct MemTransport;
+
+    impl MemTransport {
+        /// 为设备建立内存通道,已存在时替换;MemPeer drop时断开
+        pub fn attach(device_id: &String) -> MemPeer {
+            let (tx, rx) = mpsc::unbounded_channel();
+            MEM_SESSION.write().insert(device_id.clone(), tx);
+            MemPeer { device_id: device_id.clone(), rx }
+        }
+
+        pub fn detach(device_id: &String) {
+            MEM_SESSION.write().remove(device_id);
+        }
+
+        pub(super) fn is_attached(device_id: &String) -> bool {
+            MEM_SESSION.read().contains_key(device_id)
+        }
+
+        //已建立内存通道时捕获消息,返回true则不再经网络下发
+        pub(super) fn capture(device_id: &String, msg: &SipMessage) -> bool {
+            match MEM_SESSION.read().get(device_id) {
+                None => false,
+                Some(tx) => {
+                    let _ = tx.send(msg.clone());
+                    true
+                }
+            }
+        }
+    }
+
+    /// 内存通道中的对端设备
+    pub struct MemPeer {
+        device_id: String,
+        rx: UnboundedReceiver<SipMessage>,
+    }
+
+    impl MemPeer {
+        /// 等待下一条下发消息
+        pub async fn next_sent(&mut self) -> Option<SipMessage> {
+            self.rx.recv().await
+        }
+
+        /// 已下发但未取出的消息
+        pub fn try_sent(&mut self) -> Option<SipMessage> {
+            self.rx.try_recv().ok()
+        }
+
+        /// 以Ident注入响应,与网络响应经同一事件会话匹配
+        pub async fn respond(&self, ident: &Ident, res: Response) -> GlobalResult<()> {
+            EventSession::handle_response(self.device_id.clone(), ident.get_call_id().clone(), ident.get_cs_eq().clone(), res).await
+        }
+
+        /// 按请求构造响应：沿用Via/From/To/Call-ID/CSeq,To无tag时补MEM_TO_TAG
+        pub fn response_to(req: &Request, code: u16, body: &str) -> GlobalResult<Response> {
+            let mut headers: rsip::Headers = Default::default();
+            headers.push(req.via_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+            headers.push(req.from_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+            let to = req.to_header().hand_log(|msg| warn!("{msg}"))?;
+            match to.tag().hand_log(|msg| warn!("{msg}"))? {
+                Some(_) => headers.push(to.clone().into()),
+                None => headers.push(rsip::headers::To::new(format!("{};tag={MEM_TO_TAG}", to.value())).into()),
+            }
+            headers.push(req.call_id_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+            headers.push(req.cseq_header().hand_log(|msg| warn!("{msg}"))?.clone().into());
+            if !body.is_empty() {
+                headers.push(rsip::headers::ContentType::new("Application/SDP").into());
+            }
+            headers.push(rsip::headers::ContentLength::from(body.len() as u32).into());
+            Ok(Response {
+                status_code: code.into(),
+                headers,
+                version: rsip::Version::V2,
+                body: body.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    impl Drop for MemPeer {
+        fn drop(&mut self) {
+            MemTransport::detach(&self.device_id);
+        }
+    }
+}
+
+/// 设备域路由：级联多个平台时,按设备域(编码前10位)选择上级平台的连接下发
+/// 未配置路由时仍按设备自身会话下发
+pub mod route {
+    use std::collections::HashMap;
+
+    use parking_lot::RwLock;
+
+    use common::once_cell::sync::Lazy;
+
+    static ROUTE_TABLE: Lazy<RwLock<RouteTable>> = Lazy::new(|| RwLock::new(RouteTable::default()));
+
+    //设备域长度：中心编码(8位)+行业编码(2位)
+    const DOMAIN_LEN: usize = 10;
+
+    #[derive(Debug, Default)]
+    pub struct RouteTable {
+        //domain:目标平台ID(已注册会话的平台编码)
+        routes: HashMap<String, String>,
+    }
+
+    impl RouteTable {
+        pub fn domain_of(device_id: &str) -> Option<&str> {
+            device_id.get(0..DOMAIN_LEN)
+        }
+
+        pub fn insert(&mut self, domain: String, target_id: String) -> Option<String> {
+            self.routes.insert(domain, target_id)
+        }
+
+        pub fn remove(&mut self, domain: &str) -> Option<String> {
+            self.routes.remove(domain)
+        }
+
+        pub fn target(&self, device_id: &str) -> Option<&String> {
+            Self::domain_of(device_id).and_then(|domain| self.routes.get(domain))
+        }
+
+        pub fn add_route(domain: String, target_id: String) -> Option<String> {
+            ROUTE_TABLE.write().insert(domain, target_id)
+        }
+
+        pub fn remove_route(domain: &str) -> Option<String> {
+            ROUTE_TABLE.write().remove(domain)
+        }
+
+        pub fn resolve(device_id: &str) -> Option<String> {
+            ROUTE_TABLE.read().target(device_id).cloned()
+        }
+    }
 }
 
 /// 会话事件：与业务事件交互
 /// 定位：请求 <——> 回复
 pub mod event {
-    use std::collections::{BTreeSet, HashMap};
+    use std::collections::{BTreeSet, HashMap, HashSet};
     use std::collections::hash_map::Entry;
     use std::sync::Arc;
     use std::thread;
+    use std::time::Duration;
 
     use parking_lot::Mutex;
     use rsip::{Response, SipMessage};
+    use rsip::prelude::{HeadersExt, UntypedHeader};
 
     use common::anyhow::anyhow;
     use common::constructor::{Get, New};
     use common::exception::{GlobalResult, TransError};
     use common::exception::GlobalError::SysErr;
-    use common::log::{error, warn};
+    use common::log::{debug, error, warn};
+    use common::serde::Deserialize;
     use common::once_cell::sync::Lazy;
     use common::tokio;
-    use common::tokio::sync::mpsc::Sender;
+    use common::tokio::sync::mpsc;
+    use common::tokio::sync::mpsc::{Receiver, Sender};
     use common::tokio::sync::Notify;
     use common::tokio::time;
     use common::tokio::time::Instant;
 
+    use crate::gb::handler::error_code::CmdErrorCode;
+    use crate::gb::handler::quirks::DeviceQuirks;
     use crate::gb::shared::rw::RequestOutput;
+    use crate::gb::SessionConf;
+    use crate::general::SipTimerConf;
 
-    /// 会话超时 8s
-    pub const EXPIRES: u64 = 8;
     static EVENT_SESSION: Lazy<EventSession> = Lazy::new(|| EventSession::init());
+    //设备信令旁路：device_id -> 订阅端;接收端丢弃后于下次投递时清理
+    static TAPS: Lazy<Mutex<HashMap<String, Vec<Sender<SipFrame>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+    //旁路缓冲上限,接收端消费不及时则丢弃新帧,不阻塞信令收发
+    const TAP_BUFFER: usize = 256;
+
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum TapDirection {
+        Inbound,
+        Outbound,
+    }
+
+    /// 旁路捕获的一条原始SIP信令
+    #[derive(Debug, Clone)]
+    pub struct SipFrame {
+        pub device_id: String,
+        pub direction: TapDirection,
+        pub at: Instant,
+        pub msg: SipMessage,
+    }
 
     pub struct EventSession {
         shared: Arc<Shared>,
     }
 
+    /// 响应匹配模式
+    /// strict : Call-ID + CSeq + Via branch
+    /// lenient : Call-ID + CSeq,兼容不回传branch或篡改branch的设备
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Deserialize)]
+    #[serde(crate = "common::serde", rename_all = "lowercase")]
+    pub enum MatchMode {
+        #[default]
+        Strict,
+        Lenient,
+    }
+
+    /// 事件标识冲突(同Call-ID已有在途请求)时的处理策略
+    /// reject : 立即返回错误
+    /// queue : 等待在途请求结束后登记,至多等待一个事务超时
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Deserialize)]
+    #[serde(crate = "common::serde", rename_all = "lowercase")]
+    pub enum ConflictPolicy {
+        #[default]
+        Reject,
+        Queue,
+    }
+
+    impl MatchMode {
+        //未记录请求branch时不做branch比对
+        pub fn accept(&self, expected: Option<&str>, response: &Response) -> bool {
+            match (self, expected) {
+                (MatchMode::Lenient, _) | (_, None) => true,
+                (MatchMode::Strict, Some(branch)) => branch_of_response(response).as_deref() == Some(branch),
+            }
+        }
+    }
+
+    //从Via头中取branch参数
+    fn branch_param(via: &str) -> Option<String> {
+        via.split(';').skip(1)
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("branch"))
+            .map(|(_, value)| value.trim().split(|c: char| c == ',' || c.is_whitespace()).next().unwrap_or_default().to_string())
+    }
+
+    fn branch_of_response(response: &Response) -> Option<String> {
+        response.via_header().ok().and_then(|via| branch_param(via.value()))
+    }
+
+    fn branch_of_msg(msg: &SipMessage) -> Option<String> {
+        match msg {
+            SipMessage::Request(req) => req.via_header().ok().and_then(|via| branch_param(via.value())),
+            SipMessage::Response(res) => branch_of_response(res),
+        }
+    }
+
     impl EventSession {
         fn init() -> Self {
             let session = EventSession {
                 shared: Arc::new(
                     Shared {
-                        state: Mutex::new(State { expirations: BTreeSet::new(), ident_map: HashMap::new(), device_session: HashMap::new() }),
+                        state: Mutex::new(State::new(*SessionConf::get_session_by_conf().get_response_match())),
                         background_task: Notify::new(),
                     }
                 ),
@@ -341,37 +847,119 @@ pub mod event {
             }
         }
 
+        /// 旁路指定设备的收发信令,直至接收端被丢弃
+        pub fn tap_device(device_id: &String) -> Receiver<SipFrame> {
+            let (tx, rx) = mpsc::channel(TAP_BUFFER);
+            TAPS.lock().entry(device_id.clone()).or_default().push(tx);
+            rx
+        }
+
+        //投递信令副本至该设备的旁路;无旁路时不复制
+        pub(crate) fn tap(device_id: &String, direction: TapDirection, msg: &SipMessage) {
+            let mut taps = TAPS.lock();
+            if let Some(senders) = taps.get_mut(device_id) {
+                let frame = SipFrame { device_id: device_id.clone(), direction, at: Instant::now(), msg: msg.clone() };
+                senders.retain(|tx| !tx.is_closed());
+                for tx in senders.iter() {
+                    if tx.try_send(frame.clone()).is_err() {
+                        warn!("device_id={device_id},旁路缓冲已满,丢弃信令帧");
+                    }
+                }
+                if senders.is_empty() {
+                    taps.remove(device_id);
+                }
+            }
+        }
+
         //即时事件监听，延迟事件监听
         pub(crate) fn listen_event(ident: &Ident, when: Instant, container: Container) -> GlobalResult<()> {
-            let mut guard = EVENT_SESSION.shared.state.lock();
+            Self::listen_event_with_branch(ident, when, container, None)
+        }
 
-            let state = &mut *guard;
-            match state.device_session.entry(ident.call_id.clone()) {
-                Entry::Occupied(_o) => {
-                    Err(SysErr(anyhow!("new = {:?},事件重复-添加监听无效",ident)))
-                }
-                Entry::Vacant(en) => {
-                    en.insert(ident.device_id.clone());
-                    state.expirations.insert((when, ident.clone()));
-                    state.ident_map.insert(ident.clone(), (when, container));
+        //记录请求Via branch,供严格匹配响应
+        pub(crate) fn listen_event_with_branch(ident: &Ident, when: Instant, container: Container, branch: Option<String>) -> GlobalResult<()> {
+            EVENT_SESSION.shared.state.lock().listen(ident, when, container, branch)
+        }
 
-                    Ok(())
+        //已发请求的响应监听：标识冲突时按配置策略拒绝或排队
+        pub(crate) async fn listen_event_queued(ident: &Ident, expires: Duration, container: Container, branch: Option<String>) -> GlobalResult<()> {
+            Self::listen_with_policy(&EVENT_SESSION.shared.state, SessionConf::ident_conflict(), ident, expires, container, branch).await
+        }
+
+        //queue：占用方释放或到期后重试,总等待不超过expires;登记成功后事务超时自此刻起算
+        pub(super) async fn listen_with_policy(state: &Mutex<State>, policy: ConflictPolicy, ident: &Ident, expires: Duration,
+                                               mut container: Container, mut branch: Option<String>) -> GlobalResult<()> {
+            let deadline = Instant::now() + expires;
+            let released = state.lock().released.clone();
+            loop {
+                //先于尝试登记创建,避免错过期间的释放通知
+                let notified = released.notified();
+                let res = state.lock().try_listen(ident, Instant::now() + expires, container, branch);
+                let (back, back_branch, occupied_until) = match res {
+                    Ok(()) => return Ok(()),
+                    Err(occupied) => occupied,
+                };
+                if policy == ConflictPolicy::Reject || Instant::now() >= deadline {
+                    return Err(CmdErrorCode::IdentConflict.warn(&format!("{:?},事件标识冲突:同Call-ID请求尚在途", ident)));
+                }
+                debug!("{:?},事件标识冲突,排队等待在途请求结束", ident);
+                container = back;
+                branch = back_branch;
+                tokio::select! {
+                    _ = notified => {},
+                    _ = time::sleep_until(occupied_until.min(deadline)) => {},
                 }
             }
         }
 
+        /// 取消设备尚未触发的延迟事件(如设备离线),返回取消数量
+        pub fn cancel_scheduled(device_id: &String) -> usize {
+            let cancelled = EVENT_SESSION.shared.state.lock().cancel_scheduled(device_id);
+            if cancelled > 0 {
+                debug!("device_id={device_id},取消延迟事件{cancelled}个");
+            }
+            cancelled
+        }
+
+        /// 待触发的延迟事件数量,用于监控
+        pub fn scheduled_count() -> usize {
+            EVENT_SESSION.shared.state.lock().scheduled_count()
+        }
+
+        /// (登记的事件总数,其中等待应答的INVITE数),用于监控
+        pub fn event_counts() -> (usize, usize) {
+            let guard = EVENT_SESSION.shared.state.lock();
+            (guard.ident_map.len(), guard.pending_invite_count())
+        }
+
         pub fn remove_event(ident: &Ident) {
-            let mut guard = EVENT_SESSION.shared.state.lock();
+            EVENT_SESSION.shared.state.lock().release(ident);
+        }
 
-            let state = &mut *guard;
-            state.ident_map.remove(ident).map(|(when, _container)| {
-                state.expirations.remove(&(when, ident.clone()));
+        //请求已登记且尚未收到任何响应(含1xx)
+        pub fn awaiting_response(ident: &Ident) -> bool {
+            EVENT_SESSION.shared.state.lock().unanswered.contains(ident)
+        }
 
-                state.device_session.remove(ident.get_call_id())
-            });
+        pub(crate) fn is_invite(msg: &SipMessage) -> bool {
+            matches!(msg, SipMessage::Request(req) if req.method == rsip::Method::Invite)
+        }
+
+        pub(crate) fn branch_of_request(msg: &SipMessage) -> Option<String> {
+            branch_of_msg(msg)
+        }
+
+        /// 按设备设置响应匹配模式,未设置的设备使用全局配置
+        pub fn set_device_match_mode(device_id: &String, mode: Option<MatchMode>) {
+            let mut guard = EVENT_SESSION.shared.state.lock();
+            match mode {
+                None => { guard.device_modes.remove(device_id); }
+                Some(mode) => { guard.device_modes.insert(device_id.clone(), mode); }
+            }
         }
 
         pub async fn handle_response(to_device_id: String, call_id: String, cs_eq: String, response: Response) -> GlobalResult<()> {
+            Self::tap(&to_device_id, TapDirection::Inbound, &SipMessage::Response(response.clone()));
             let res = {
                 let mut guard = EVENT_SESSION.shared.state.lock();
 
@@ -390,7 +978,12 @@ pub mod event {
                                 warn!("{:?},超时或未知响应",&ident);
                                 None
                             }
+                            Some(_) if !state.accept(&ident, &response) => {
+                                warn!("{:?},branch不一致,严格匹配丢弃响应",&ident);
+                                None
+                            }
                             Some((when, container)) => {
+                                state.unanswered.remove(&ident);
                                 match container {
                                     Container::Res(res) => {
                                         //当tx为some时发送响应结果，不清理会话，由相应rx接收端根据自身业务清理
@@ -402,10 +995,7 @@ pub mod event {
                                             // let _ = sender.send((Some(response), when)).await.hand_log(|msg| error!("{msg}"));
                                         } else {
                                             //清理会话
-                                            state.ident_map.remove(&ident).map(|(when, _container)| {
-                                                state.expirations.remove(&(when, ident.clone()));
-                                                state.device_session.remove(ident.get_call_id())
-                                            });
+                                            state.release(&ident);
                                             None
                                         }
                                     }
@@ -443,9 +1033,9 @@ pub mod event {
                 if when > &now {
                     return Ok(Some(*when));
                 }
-                if let Some((ident, (when, container))) = state.ident_map.remove_entry(expire_ident) {
-                    state.expirations.remove(&(when, expire_ident.clone()));
-                    state.device_session.remove(ident.get_call_id());
+                let (when, ident) = (*when, expire_ident.clone());
+                state.expirations.remove(&(when, ident.clone()));
+                if let Some((when, container)) = state.release(&ident) {
                     match container {
                         Container::Res(res) => {
                             warn!("{:?},响应超时。",&ident);
@@ -461,9 +1051,12 @@ pub mod event {
                                 //插入事件监听
                                 Entry::Vacant(en) => {
                                     en.insert(new_ident.device_id.clone());
-                                    let expires = time::Duration::from_secs(EXPIRES);
+                                    let expires = SipTimerConf::get_sip_timer_conf().transaction_timeout(EventSession::is_invite(&msg));
                                     let new_when = Instant::now() + expires;
                                     state.expirations.insert((new_when, new_ident.clone()));
+                                    if let Some(branch) = branch_of_msg(&msg) {
+                                        state.branch_map.insert(new_ident.clone(), branch);
+                                    }
                                     state.ident_map.insert(new_ident, (new_when, Container::build_res(sender)));
                                 }
                             }
@@ -505,11 +1098,556 @@ pub mod event {
         }
     }
 
-    struct State {
+    pub(super) struct State {
         expirations: BTreeSet<(Instant, Ident)>,
         ident_map: HashMap<Ident, (Instant, Container)>,
         //call_id:device_id
         device_session: HashMap<String, String>,
+        //请求Via branch
+        branch_map: HashMap<Ident, String>,
+        //未收到任何响应的请求,用于UDP重传判断
+        unanswered: HashSet<Ident>,
+        //事件移除时通知,唤醒排队等待同Call-ID的登记
+        released: Arc<Notify>,
+        //全局匹配模式
+        match_mode: MatchMode,
+        //device_id:设备匹配模式
+        device_modes: HashMap<String, MatchMode>,
+    }
+
+    impl State {
+        pub(super) fn new(match_mode: MatchMode) -> Self {
+            State {
+                expirations: BTreeSet::new(),
+                ident_map: HashMap::new(),
+                device_session: HashMap::new(),
+                branch_map: HashMap::new(),
+                unanswered: HashSet::new(),
+                released: Arc::new(Notify::new()),
+                match_mode,
+                device_modes: HashMap::new(),
+            }
+        }
+
+        pub(super) fn listen(&mut self, ident: &Ident, when: Instant, container: Container, branch: Option<String>) -> GlobalResult<()> {
+            self.try_listen(ident, when, container, branch)
+                .map_err(|_| CmdErrorCode::IdentConflict.warn(&format!("new = {:?},事件重复-添加监听无效", ident)))
+        }
+
+        //Call-ID已被占用时交还container与branch,并返回占用方到期时刻
+        fn try_listen(&mut self, ident: &Ident, when: Instant, container: Container, branch: Option<String>) -> Result<(), (Container, Option<String>, Instant)> {
+            match self.device_session.entry(ident.call_id.clone()) {
+                Entry::Occupied(_o) => {
+                    let occupied_until = self.ident_map.iter()
+                        .find(|(occupant, _)| occupant.call_id == ident.call_id)
+                        .map(|(_, (when, _))| *when)
+                        .unwrap_or(when);
+                    Err((container, branch, occupied_until))
+                }
+                Entry::Vacant(en) => {
+                    en.insert(ident.device_id.clone());
+                    self.expirations.insert((when, ident.clone()));
+                    self.ident_map.insert(ident.clone(), (when, container));
+                    self.unanswered.insert(ident.clone());
+                    if let Some(branch) = branch {
+                        self.branch_map.insert(ident.clone(), branch);
+                    }
+
+                    Ok(())
+                }
+            }
+        }
+
+        //移除事件及其索引,并唤醒排队登记
+        pub(super) fn release(&mut self, ident: &Ident) -> Option<(Instant, Container)> {
+            let (when, container) = self.ident_map.remove(ident)?;
+            self.expirations.remove(&(when, ident.clone()));
+            self.branch_map.remove(ident);
+            self.unanswered.remove(ident);
+            self.device_session.remove(ident.get_call_id());
+            self.released.notify_waiters();
+            Some((when, container))
+        }
+
+        //仅移除Actor:已发出请求的响应监听仍按超时处理
+        pub(super) fn cancel_scheduled(&mut self, device_id: &String) -> usize {
+            let idents: Vec<Ident> = self.ident_map.iter()
+                .filter(|(ident, (_, container))| &ident.device_id == device_id && matches!(container, Container::Actor(..)))
+                .map(|(ident, _)| ident.clone())
+                .collect();
+            for ident in &idents {
+                self.release(ident);
+            }
+            idents.len()
+        }
+
+        pub(super) fn scheduled_count(&self) -> usize {
+            self.ident_map.values().filter(|(_, container)| matches!(container, Container::Actor(..))).count()
+        }
+
+        pub(super) fn pending_invite_count(&self) -> usize {
+            self.ident_map.iter()
+                .filter(|(ident, (_, container))| matches!(container, Container::Res(..)) && ident.get_cs_eq().ends_with("INVITE"))
+                .count()
+        }
+
+        fn accept(&self, ident: &Ident, response: &Response) -> bool {
+            let expected = self.branch_map.get(ident).map(|b| b.as_str());
+            //设备设置优先,其次设备兼容项,均无则为全局配置
+            let mode = self.device_modes.get(ident.get_device_id()).copied()
+                .or_else(|| DeviceQuirks::of(ident.get_device_id()).match_mode())
+                .unwrap_or(self.match_mode);
+            let accepted = mode.accept(expected, response);
+            if accepted && !MatchMode::Strict.accept(expected, response) {
+                debug!("{:?},branch不一致,宽松匹配接收响应:expected={:?},got={:?}",ident,expected,branch_of_response(response));
+            }
+            accepted
+        }
+    }
+}
+
+/// 媒体会话：INVITE 成功后建立的对话
+/// 定位：对话登记 <——> BYE(本端/设备端)结束
+pub mod dialog {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use parking_lot::{Mutex, RwLock};
+
+    use common::chrono::{DateTime, Local};
+    use common::constructor::{Get, New};
+    use common::exception::{GlobalResult, TransError};
+    use common::log::{info, warn};
+    use common::once_cell::sync::Lazy;
+    use common::serde::{Deserialize, Serialize};
+    use common::tokio::sync::oneshot;
+
+    use crate::general::model::StreamMode;
+
+    static DIALOG_SESSION: Lazy<DialogSession> = Lazy::new(|| DialogSession::init());
+    static DIALOG_STORE: Lazy<RwLock<Arc<dyn DialogStore>>> = Lazy::new(|| RwLock::new(Arc::new(MemoryDialogStore::default())));
+    static STREAM_LISTENERS: Lazy<RwLock<Vec<Arc<dyn StreamListener>>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+    #[derive(New, Get, Debug, Clone, Eq, PartialEq)]
+    pub struct Dialog {
+        device_id: String,
+        channel_id: String,
+        call_id: String,
+        //本端tag
+        from_tag: String,
+        //设备端tag
+        to_tag: String,
+    }
+
+    /// 持久化的对话状态：进程重启后据此向设备BYE遗留的媒体流
+    #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+    #[serde(crate = "common::serde")]
+    pub struct DialogState {
+        pub device_id: String,
+        pub channel_id: String,
+        pub call_id: String,
+        pub from_tag: String,
+        pub to_tag: String,
+        //INVITE的CSeq序号,BYE使用seq+1
+        pub seq: u32,
+    }
+
+    impl DialogState {
+        pub fn build(dialog: &Dialog, seq: u32) -> Self {
+            Self {
+                device_id: dialog.device_id.clone(),
+                channel_id: dialog.channel_id.clone(),
+                call_id: dialog.call_id.clone(),
+                from_tag: dialog.from_tag.clone(),
+                to_tag: dialog.to_tag.clone(),
+                seq,
+            }
+        }
+    }
+
+    /// 对话状态存储：默认内存实现,可替换为文件/redis等持久化实现
+    pub trait DialogStore: Send + Sync {
+        fn save(&self, state: &DialogState) -> GlobalResult<()>;
+        fn remove(&self, call_id: &str) -> GlobalResult<()>;
+        fn load_all(&self) -> GlobalResult<Vec<DialogState>>;
+    }
+
+    #[derive(Default)]
+    pub struct MemoryDialogStore {
+        //call_id:对话状态
+        states: Mutex<HashMap<String, DialogState>>,
+    }
+
+    impl DialogStore for MemoryDialogStore {
+        fn save(&self, state: &DialogState) -> GlobalResult<()> {
+            self.states.lock().insert(state.call_id.clone(), state.clone());
+            Ok(())
+        }
+
+        fn remove(&self, call_id: &str) -> GlobalResult<()> {
+            self.states.lock().remove(call_id);
+            Ok(())
+        }
+
+        fn load_all(&self) -> GlobalResult<Vec<DialogState>> {
+            Ok(self.states.lock().values().cloned().collect())
+        }
+    }
+
+    /// 媒体流信息
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct StreamMedia {
+        pub stream_mode: StreamMode,
+        pub ssrc: String,
+    }
+
+    /// 媒体流标识：收流端据到达的SSRC解析所属设备通道与对话
+    #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+    pub struct StreamKey {
+        pub device_id: String,
+        pub channel_id: String,
+        pub ssrc: String,
+        pub call_id: String,
+    }
+
+    impl StreamKey {
+        pub fn build(dialog: &Dialog, ssrc: &str) -> Self {
+            Self {
+                device_id: dialog.device_id.clone(),
+                channel_id: dialog.channel_id.clone(),
+                ssrc: ssrc.to_string(),
+                call_id: dialog.call_id.clone(),
+            }
+        }
+    }
+
+    /// 进行中的对话快照：dialog与seq即BYE所需标识(BYE使用seq+1);seq为None表示尚未ACK
+    /// 码流字节数由流媒体服务统计,此处不跟踪
+    #[derive(Debug, Clone)]
+    pub struct ActiveStream {
+        pub dialog: Dialog,
+        pub seq: Option<u32>,
+        pub media: Option<StreamMedia>,
+        pub started_at: DateTime<Local>,
+    }
+
+    /// 媒体流生命周期：开始于点播成功登记对话,结束于本端BYE或设备BYE
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub struct StreamLifecycle {
+        pub dialog: Dialog,
+        pub ssrc: String,
+        pub started_at: DateTime<Local>,
+        //开始事件为None
+        pub stopped_at: Option<DateTime<Local>>,
+        //是否由设备BYE结束
+        pub by_device: bool,
+    }
+
+    /// 媒体流生命周期监听：码流由调用方统计,此处仅提供开始/结束信号(计费、QoS)
+    /// 回调在对话锁外同步执行,不宜阻塞
+    pub trait StreamListener: Send + Sync {
+        fn on_start(&self, stream: &StreamLifecycle);
+        fn on_stop(&self, stream: &StreamLifecycle);
+    }
+
+    struct DialogEntry {
+        dialog: Dialog,
+        //设备BYE通知
+        tx: oneshot::Sender<Dialog>,
+        seq: Option<u32>,
+        media: Option<StreamMedia>,
+        started_at: DateTime<Local>,
+    }
+
+    pub struct DialogSession {
+        state: Mutex<State>,
+    }
+
+    impl DialogSession {
+        fn init() -> Self {
+            DialogSession { state: Mutex::new(State { dialogs: HashMap::new(), orphans: HashMap::new(), reusable: HashMap::new(), by_ssrc: HashMap::new() }) }
+        }
+
+        /// 注册媒体流生命周期监听
+        pub fn add_listener(listener: Arc<dyn StreamListener>) {
+            STREAM_LISTENERS.write().push(listener);
+        }
+
+        //仅媒体流对话触发
+        fn notify_lifecycle(entry: &DialogEntry, stopped: Option<bool>) {
+            let Some(media) = &entry.media else { return; };
+            let listeners = STREAM_LISTENERS.read().clone();
+            if listeners.is_empty() {
+                return;
+            }
+            let stream = StreamLifecycle {
+                dialog: entry.dialog.clone(),
+                ssrc: media.ssrc.clone(),
+                started_at: entry.started_at,
+                stopped_at: stopped.map(|_| Local::now()),
+                by_device: stopped.unwrap_or(false),
+            };
+            for listener in listeners {
+                match stopped {
+                    None => listener.on_start(&stream),
+                    Some(_) => listener.on_stop(&stream),
+                }
+            }
+        }
+
+        /// 替换对话状态存储,需在启动时(rehydrate前)设置;返回原存储
+        pub fn set_store(store: Arc<dyn DialogStore>) -> Arc<dyn DialogStore> {
+            std::mem::replace(&mut *DIALOG_STORE.write(), store)
+        }
+
+        //ACK后对话确立,持久化BYE所需状态
+        pub fn persist(dialog: &Dialog, seq: u32) {
+            if let Some(entry) = DIALOG_SESSION.state.lock().dialogs.get_mut(&dialog.call_id) {
+                entry.seq = Some(seq);
+            }
+            let store = DIALOG_STORE.read().clone();
+            let _ = store.save(&DialogState::build(dialog, seq)).hand_log(|msg| warn!("对话状态持久化失败:{msg}"));
+        }
+
+        fn unpersist(call_id: &str) {
+            let store = DIALOG_STORE.read().clone();
+            let _ = store.remove(call_id).hand_log(|msg| warn!("对话状态清理失败:{msg}"));
+        }
+
+        /// 启动时加载存储中遗留的对话(非当前进程建立),按设备归集待其上线后BYE
+        pub fn rehydrate() -> GlobalResult<usize> {
+            let store = DIALOG_STORE.read().clone();
+            let states = store.load_all()?;
+            let mut guard = DIALOG_SESSION.state.lock();
+            let state = &mut *guard;
+            let mut count = 0;
+            for dialog_state in states {
+                if state.dialogs.contains_key(&dialog_state.call_id) {
+                    continue;
+                }
+                count += 1;
+                state.orphans.entry(dialog_state.device_id.clone()).or_insert_with(Vec::new).push(dialog_state);
+            }
+            info!("加载遗留媒体对话: count = {count}");
+            Ok(count)
+        }
+
+        //取出设备的遗留对话
+        pub fn take_orphans(device_id: &String) -> Vec<DialogState> {
+            DIALOG_SESSION.state.lock().orphans.remove(device_id).unwrap_or_default()
+        }
+
+        //登记对话,返回设备主动BYE时的通知接收端;本端移除对话时发送端释放,接收端收到Err
+        pub fn insert(dialog: Dialog) -> oneshot::Receiver<Dialog> {
+            Self::insert_stream(dialog, None)
+        }
+
+        //登记媒体流对话,附带流模式与ssrc供运维查看
+        pub fn insert_stream(dialog: Dialog, media: Option<StreamMedia>) -> oneshot::Receiver<Dialog> {
+            let (tx, rx) = oneshot::channel();
+            let entry = DialogEntry { dialog, tx, seq: None, media, started_at: Local::now() };
+            Self::notify_lifecycle(&entry, None);
+            let mut guard = DIALOG_SESSION.state.lock();
+            if let Some(ssrc) = entry.media.as_ref().and_then(|media| media.ssrc.parse::<u32>().ok()) {
+                guard.by_ssrc.insert(ssrc, entry.dialog.call_id.clone());
+            }
+            guard.dialogs.insert(entry.dialog.call_id.clone(), entry);
+            rx
+        }
+
+        /// 按RTP包SSRC查找进行中的媒体流
+        pub fn lookup_by_ssrc(ssrc: u32) -> Option<StreamKey> {
+            let guard = DIALOG_SESSION.state.lock();
+            let entry = guard.dialogs.get(guard.by_ssrc.get(&ssrc)?)?;
+            entry.media.as_ref().map(|media| StreamKey::build(&entry.dialog, &media.ssrc))
+        }
+
+        //移除对话时同步清理SSRC索引(仅当索引仍指向该对话)
+        fn unindex(state: &mut State, entry: &DialogEntry) {
+            state.reusable.retain(|_, id| id != &entry.dialog.call_id);
+            if let Some(ssrc) = entry.media.as_ref().and_then(|media| media.ssrc.parse::<u32>().ok()) {
+                if state.by_ssrc.get(&ssrc) == Some(&entry.dialog.call_id) {
+                    state.by_ssrc.remove(&ssrc);
+                }
+            }
+        }
+
+        pub fn get(call_id: &str) -> Option<Dialog> {
+            let guard = DIALOG_SESSION.state.lock();
+            guard.dialogs.get(call_id).map(|entry| entry.dialog.clone())
+        }
+
+        pub fn get_stream(call_id: &str) -> Option<ActiveStream> {
+            let guard = DIALOG_SESSION.state.lock();
+            guard.dialogs.get(call_id).map(Self::snapshot)
+        }
+
+        /// 当前对话数,用于监控
+        pub fn count() -> usize {
+            DIALOG_SESSION.state.lock().dialogs.len()
+        }
+
+        /// 当前进行中的全部对话(只读快照)
+        pub fn active() -> Vec<ActiveStream> {
+            let guard = DIALOG_SESSION.state.lock();
+            guard.dialogs.values().map(Self::snapshot).collect()
+        }
+
+        fn snapshot(entry: &DialogEntry) -> ActiveStream {
+            ActiveStream {
+                dialog: entry.dialog.clone(),
+                seq: entry.seq,
+                media: entry.media.clone(),
+                started_at: entry.started_at,
+            }
+        }
+
+        /// 登记可复用对话：按(设备,通道,收流地址)索引,同键重复登记以新对话为准
+        pub fn register_reusable(device_id: &str, channel_id: &str, dst: &str, call_id: &str) -> bool {
+            let mut guard = DIALOG_SESSION.state.lock();
+            if !guard.dialogs.contains_key(call_id) {
+                return false;
+            }
+            guard.reusable.insert((device_id.to_string(), channel_id.to_string(), dst.to_string()), call_id.to_string());
+            true
+        }
+
+        /// 查找可复用对话：返回tags/call_id/seq,供后续拖动、倍速、BYE使用
+        pub fn reusable(device_id: &str, channel_id: &str, dst: &str) -> Option<ActiveStream> {
+            let guard = DIALOG_SESSION.state.lock();
+            let call_id = guard.reusable.get(&(device_id.to_string(), channel_id.to_string(), dst.to_string()))?;
+            guard.dialogs.get(call_id).map(Self::snapshot)
+        }
+
+        //本端结束对话
+        pub fn remove(call_id: &str) -> Option<Dialog> {
+            Self::unpersist(call_id);
+            let entry = {
+                let mut guard = DIALOG_SESSION.state.lock();
+                let entry = guard.dialogs.remove(call_id)?;
+                Self::unindex(&mut guard, &entry);
+                entry
+            };
+            Self::notify_lifecycle(&entry, Some(false));
+            Some(entry.dialog)
+        }
+
+        //设备端主动BYE：按call_id匹配,tag存在时需一致(设备from_tag对应本端to_tag);命中则移除并通知
+        pub fn device_bye(call_id: &str, device_tag: Option<&str>, local_tag: Option<&str>) -> Option<Dialog> {
+            let mut guard = DIALOG_SESSION.state.lock();
+            let matched = guard.dialogs.get(call_id).map(|DialogEntry { dialog, .. }| {
+                device_tag.map(|tag| dialog.to_tag.eq(tag)).unwrap_or(true)
+                    && local_tag.map(|tag| dialog.from_tag.eq(tag)).unwrap_or(true)
+            }).unwrap_or(false);
+            if !matched {
+                return None;
+            }
+            let entry = guard.dialogs.remove(call_id)?;
+            Self::unindex(&mut guard, &entry);
+            drop(guard);
+            Self::unpersist(call_id);
+            Self::notify_lifecycle(&entry, Some(true));
+            let DialogEntry { dialog, tx, .. } = entry;
+            let _ = tx.send(dialog.clone());
+            Some(dialog)
+        }
+    }
+
+    struct State {
+        //call_id:对话
+        dialogs: HashMap<String, DialogEntry>,
+        //device_id:重启前遗留的对话
+        orphans: HashMap<String, Vec<DialogState>>,
+        //(device_id,channel_id,dst):call_id 可复用的媒体对话
+        reusable: HashMap<(String, String, String), String>,
+        //ssrc:call_id 媒体流SSRC索引
+        by_ssrc: HashMap<u32, String>,
+    }
+}
+
+/// 查询应答：查询类MESSAGE的数据由设备另以MESSAGE返回,按device_id关联等待方
+pub mod reply {
+    use std::collections::HashMap;
+
+    use parking_lot::Mutex;
+
+    use common::once_cell::sync::Lazy;
+    use common::tokio::sync::oneshot;
+
+    use crate::gb::handler::catalog::CatalogSnapshot;
+    use crate::gb::handler::record::RecordInfo;
+    use crate::storage::entity::GmvDeviceExt;
+
+    static REPLY_SESSION: Lazy<ReplySession> = Lazy::new(|| ReplySession::init());
+
+    pub struct ReplySession {
+        device_info: Waiters<GmvDeviceExt>,
+        catalog: Waiters<CatalogSnapshot>,
+        //按通道(应答中的DeviceID)等待
+        record_info: Waiters<RecordInfo>,
+    }
+
+    impl ReplySession {
+        fn init() -> Self {
+            ReplySession { device_info: Waiters::new(), catalog: Waiters::new(), record_info: Waiters::new() }
+        }
+
+        pub fn wait_device_info(device_id: &String) -> oneshot::Receiver<GmvDeviceExt> {
+            REPLY_SESSION.device_info.wait(device_id)
+        }
+
+        pub fn reply_device_info(device_id: &String, info: GmvDeviceExt) -> bool {
+            REPLY_SESSION.device_info.reply(device_id, info)
+        }
+
+        //目录为重组后的完整(或超时部分)结果
+        pub fn wait_catalog(device_id: &String) -> oneshot::Receiver<CatalogSnapshot> {
+            REPLY_SESSION.catalog.wait(device_id)
+        }
+
+        pub fn reply_catalog(device_id: &String, snapshot: CatalogSnapshot) -> bool {
+            REPLY_SESSION.catalog.reply(device_id, snapshot)
+        }
+
+        //分包时为首包,SumNum为录像段总数
+        pub fn wait_record_info(channel_id: &String) -> oneshot::Receiver<RecordInfo> {
+            REPLY_SESSION.record_info.wait(channel_id)
+        }
+
+        pub fn reply_record_info(channel_id: &String, info: RecordInfo) -> bool {
+            REPLY_SESSION.record_info.reply(channel_id, info)
+        }
+    }
+
+    struct Waiters<T> {
+        //device_id:等待方
+        state: Mutex<HashMap<String, Vec<oneshot::Sender<T>>>>,
+    }
+
+    impl<T: Clone> Waiters<T> {
+        fn new() -> Self {
+            Waiters { state: Mutex::new(HashMap::new()) }
+        }
+
+        fn wait(&self, device_id: &String) -> oneshot::Receiver<T> {
+            let (tx, rx) = oneshot::channel();
+            let mut guard = self.state.lock();
+            let txs = guard.entry(device_id.clone()).or_default();
+            //清理已超时放弃的等待方
+            txs.retain(|tx| !tx.is_closed());
+            txs.push(tx);
+            rx
+        }
+
+        //有等待方接收时返回true
+        fn reply(&self, device_id: &String, data: T) -> bool {
+            let txs = self.state.lock().remove(device_id).unwrap_or_default();
+            let mut received = false;
+            for tx in txs {
+                received |= tx.send(data.clone()).is_ok();
+            }
+            received
+        }
     }
 }
 
@@ -554,5 +1692,362 @@ mod tests {
         }
         println!("{map:?}");
     }
-}
 
+    #[test]
+    fn test_device_bye_notify() {
+        use super::dialog::{Dialog, DialogSession};
+        let dialog = Dialog::new("34020000001110000001".to_string(), "34020000001320000101".to_string(),
+                                 "bye-notify-call-id".to_string(), "111".to_string(), "222".to_string());
+        let mut rx = DialogSession::insert(dialog.clone());
+        //tag不匹配：不处理
+        assert!(DialogSession::device_bye("bye-notify-call-id", Some("333"), Some("111")).is_none());
+        assert!(rx.try_recv().is_err());
+        assert!(DialogSession::get("bye-notify-call-id").is_some());
+
+        assert_eq!(DialogSession::device_bye("bye-notify-call-id", Some("222"), Some("111")), Some(dialog.clone()));
+        assert_eq!(rx.try_recv().unwrap(), dialog);
+        assert!(DialogSession::get("bye-notify-call-id").is_none());
+    }
+
+    #[test]
+    fn test_local_bye_release() {
+        use super::dialog::{Dialog, DialogSession};
+        let dialog = Dialog::new("34020000001110000001".to_string(), "34020000001320000101".to_string(),
+                                 "local-bye-call-id".to_string(), "111".to_string(), "222".to_string());
+        let mut rx = DialogSession::insert(dialog);
+        assert!(DialogSession::remove("local-bye-call-id").is_some());
+        assert!(rx.try_recv().is_err());
+        assert!(DialogSession::device_bye("local-bye-call-id", None, None).is_none());
+    }
+
+    #[test]
+    fn test_route_by_domain() {
+        use super::route::RouteTable;
+        let mut table = RouteTable::default();
+        assert!(table.target("34020000001320000101").is_none());
+        table.insert("3402000000".to_string(), "34020000002000000009".to_string());
+        table.insert("4401000000".to_string(), "44010000002000000009".to_string());
+        assert_eq!(table.target("34020000001320000101").map(|s| s.as_str()), Some("34020000002000000009"));
+        assert_eq!(table.target("44010000001320000101").map(|s| s.as_str()), Some("44010000002000000009"));
+        assert!(table.target("11010000001320000101").is_none());
+        assert!(table.target("short").is_none());
+    }
+
+    #[test]
+    fn test_crlf_ping_cadence() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+        use common::tokio;
+        use super::rw::RWSession;
+
+        assert!(RWSession::is_crlf_ping(b"\r\n\r\n"));
+        assert!(!RWSession::is_crlf_ping(b"\r\n"));
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async move {
+            let ping = RWSession::ping_at_cadence(Duration::from_millis(50), move || { counter.fetch_add(1, Ordering::SeqCst); });
+            let _ = tokio::time::timeout(Duration::from_millis(275), ping).await;
+        });
+        //50ms间隔,275ms内至多在50/100/150/200/250各触发一次;调度繁忙时允许少于5次,但不应提前或多发
+        let count = count.load(Ordering::SeqCst);
+        assert!((3..=5).contains(&count), "count = {count}");
+    }
+
+    #[test]
+    fn test_ident_collision_policy() {
+        use std::time::Duration;
+        use parking_lot::Mutex;
+        use common::tokio;
+        use common::tokio::time::Instant;
+        use crate::gb::handler::error_code::CmdErrorCode;
+        use super::event::{ConflictPolicy, Container, EventSession, Ident, MatchMode, State};
+
+        let device_id = "34020000001320000001".to_string();
+        //同Call-ID不同CSeq
+        let first = Ident::new(device_id.clone(), "collide".to_string(), "1".to_string());
+        let second = Ident::new(device_id.clone(), "collide".to_string(), "2".to_string());
+        let third = Ident::new(device_id, "collide".to_string(), "3".to_string());
+        let state = Mutex::new(State::new(MatchMode::Strict));
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let expires = Duration::from_secs(5);
+            EventSession::listen_with_policy(&state, ConflictPolicy::Reject, &first, expires, Container::build_res(None), None).await.unwrap();
+            //reject：不覆盖在途监听
+            let err = EventSession::listen_with_policy(&state, ConflictPolicy::Reject, &second, expires, Container::build_res(None), None).await.unwrap_err();
+            assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::IdentConflict));
+
+            //queue：在途请求结束后登记
+            let release = async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                assert!(state.lock().release(&first).is_some());
+            };
+            let (queued, _) = tokio::join!(EventSession::listen_with_policy(&state, ConflictPolicy::Queue, &second, expires, Container::build_res(None), None), release);
+            assert!(queued.is_ok());
+            assert!(state.lock().release(&second).is_some());
+
+            //queue：占用方始终不释放,至多等待expires
+            EventSession::listen_with_policy(&state, ConflictPolicy::Reject, &first, expires, Container::build_res(None), None).await.unwrap();
+            let started = Instant::now();
+            let err = EventSession::listen_with_policy(&state, ConflictPolicy::Queue, &third, Duration::from_millis(50), Container::build_res(None), None).await.unwrap_err();
+            assert_eq!(CmdErrorCode::of(&err), Some(CmdErrorCode::IdentConflict));
+            assert!(started.elapsed() >= Duration::from_millis(50));
+            assert!(state.lock().release(&third).is_none());
+        });
+    }
+
+    #[test]
+    fn test_missed_heartbeats_offline() {
+        use std::collections::BTreeSet;
+        use common::tokio::time::{Duration, Instant};
+        use super::rw::{take_expired, DeviceOffline, RWSession};
+
+        //心跳60秒,允许连续丢失3次
+        let expires = RWSession::offline_after(60, 3);
+        assert_eq!(expires, Duration::from_secs(180));
+        assert_eq!(RWSession::offline_after(60, 0), Duration::from_secs(60));
+
+        let base = Instant::now();
+        let device_a = "34020000001320000001".to_string();
+        let device_b = "34020000001320000002".to_string();
+        let mut expirations = BTreeSet::new();
+        expirations.insert((base + expires, device_a.clone()));
+        expirations.insert((base + expires, device_b.clone()));
+        //B在第2个周期收到心跳,刷新到期时刻
+        let heart_b = base + Duration::from_secs(120);
+        expirations.remove(&(base + expires, device_b.clone()));
+        expirations.insert((heart_b + expires, device_b.clone()));
+
+        //未满3个周期不判定离线
+        assert!(take_expired(&mut expirations, base + Duration::from_secs(179)).is_empty());
+        let expired = take_expired(&mut expirations, base + expires);
+        assert_eq!(expired, vec![(base + expires, device_a.clone())]);
+        assert_eq!(expirations.first().map(|(when, _)| *when), Some(heart_b + expires));
+
+        let mut rx = RWSession::watch_offline();
+        for (when, device_id) in expired {
+            RWSession::notify_offline(DeviceOffline { device_id, last_seen: when - expires });
+        }
+        assert_eq!(rx.try_recv().unwrap(), DeviceOffline { device_id: device_a, last_seen: base });
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_branch_mismatch_match_mode() {
+        use rsip::SipMessage;
+        use super::event::MatchMode;
+
+        let text = "SIP/2.0 200 OK\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:25600;rport=25600;branch=z9hG4bK-changed\r\n\
+From: <sip:34020000002000000001@3402000000>;tag=local\r\n\
+To: <sip:34020000001320000001@3402000000>;tag=device\r\n\
+Call-ID: 1001@172.18.38.186\r\n\
+CSeq: 1 MESSAGE\r\n\
+Content-Length: 0\r\n\r\n";
+        let res = match SipMessage::try_from(text).unwrap() {
+            SipMessage::Response(res) => res,
+            SipMessage::Request(_) => panic!("expect response"),
+        };
+        assert!(!MatchMode::Strict.accept(Some("z9hG4bK-origin"), &res));
+        assert!(MatchMode::Lenient.accept(Some("z9hG4bK-origin"), &res));
+        assert!(MatchMode::Strict.accept(Some("z9hG4bK-changed"), &res));
+        //未记录请求branch
+        assert!(MatchMode::Strict.accept(None, &res));
+    }
+
+    #[test]
+    fn test_ws_transport_echo() {
+        use futures_util::{SinkExt, StreamExt};
+        use rsip::SipMessage;
+        use rsip::prelude::{HeadersExt, UntypedHeader};
+        use common::tokio;
+        use common::tokio::net::TcpListener;
+        use super::ws::{WsSession, WsTransport};
+
+        assert_eq!(WsSession::transport_of("ws://127.0.0.1:8080/sip").unwrap(), "WS");
+        assert_eq!(WsSession::transport_of("WSS://gateway.example/sip").unwrap(), "WSS");
+        assert!(WsSession::transport_of("tcp://127.0.0.1:8080").is_err());
+
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            //模拟WS回显服务：仅回显文本帧
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                while let Some(Ok(msg)) = ws.next().await {
+                    if msg.is_text() {
+                        ws.send(msg).await.unwrap();
+                    }
+                }
+            });
+
+            let (tx, mut rx) = WsTransport::connect(&format!("ws://127.0.0.1:{port}/sip")).await.unwrap();
+            let text = "MESSAGE sip:34020000001320000001@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/WS 172.18.38.186:25600;rport;branch=z9hG4bK123456789\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=123456789\r\n\
+To: <sip:34020000001320000001@3402000000.spvmn.cn>\r\n\
+Call-ID: 1001@172.18.38.186\r\n\
+CSeq: 1 MESSAGE\r\n\
+Content-Length: 0\r\n\r\n";
+            tx.send(text.to_string()).await.unwrap();
+            let echo = tokio::time::timeout(std::time::Duration::from_secs(3), rx.recv()).await.unwrap().unwrap();
+            assert_eq!(echo, text);
+            match SipMessage::try_from(echo.as_str()).unwrap() {
+                SipMessage::Request(req) => assert!(req.via_header().unwrap().value().starts_with("SIP/2.0/WS ")),
+                SipMessage::Response(_) => panic!("expect request"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_dialog_state_rehydrate() {
+        use std::sync::Arc;
+        use common::serde_json;
+        use super::dialog::{Dialog, DialogSession, DialogState, DialogStore, MemoryDialogStore};
+
+        let dialog = Dialog::new("34020000001320000125".to_string(), "34020000001310000001".to_string(),
+                                 "125@172.18.38.186".to_string(), "local".to_string(), "device".to_string());
+        let state = DialogState::build(&dialog, 20);
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<DialogState>(&json).unwrap(), state);
+
+        //测试结束(含断言失败)时恢复全局存储,避免影响其他对话测试
+        struct RestoreStore(Option<Arc<dyn DialogStore>>);
+        impl Drop for RestoreStore {
+            fn drop(&mut self) {
+                if let Some(store) = self.0.take() {
+                    DialogSession::set_store(store);
+                }
+            }
+        }
+
+        //模拟重启前持久化的对话
+        let store = Arc::new(MemoryDialogStore::default());
+        store.save(&state).unwrap();
+        let _restore = RestoreStore(Some(DialogSession::set_store(store.clone())));
+        assert!(DialogSession::rehydrate().unwrap() >= 1);
+        let orphans = DialogSession::take_orphans(&"34020000001320000125".to_string());
+        assert_eq!(orphans, vec![state]);
+        assert!(DialogSession::take_orphans(&"34020000001320000125".to_string()).is_empty());
+        //BYE时移除对话同时清理存储
+        DialogSession::remove("125@172.18.38.186");
+        assert!(store.load_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_signal_transport_override() {
+        use rsip::SipMessage;
+        use common::net::state::Protocol;
+        use crate::general::model::SignalTransport;
+        use super::rw::{RequestOutput, RWSession};
+
+        //UDP注册设备,INVITE覆盖为TCP:Via为TCP,出口为TCP备用通道
+        let text = "INVITE sip:34020000001320000101@3402000000.spvmn.cn SIP/2.0\r\n\
+Via: SIP/2.0/TCP 172.18.38.186:15060;rport;branch=z9hG4bK123456789\r\n\
+From: <sip:34020000002000000001@3402000000.spvmn.cn>;tag=123456789\r\n\
+To: <sip:34020000001320000101@3402000000.spvmn.cn>\r\n\
+Call-ID: tcp-invite@172.18.38.186\r\n\
+CSeq: 1 INVITE\r\n\
+Content-Length: 0\r\n\r\n";
+        let msg = SipMessage::try_from(text).unwrap();
+        let transport = RequestOutput::transport_of(&msg);
+        assert_eq!(transport, Some(SignalTransport::Tcp));
+        assert!(RWSession::use_alt_bill(&Protocol::UDP, Some(&Protocol::TCP), transport));
+        //无备用通道或传输与注册一致时沿用注册会话
+        assert!(!RWSession::use_alt_bill(&Protocol::UDP, None, transport));
+        assert!(!RWSession::use_alt_bill(&Protocol::TCP, Some(&Protocol::UDP), transport));
+        assert!(!RWSession::use_alt_bill(&Protocol::UDP, Some(&Protocol::TCP), None));
+        let udp = msg.to_string().replace("SIP/2.0/TCP", "SIP/2.0/UDP");
+        let udp = SipMessage::try_from(udp.as_str()).unwrap();
+        assert!(!RWSession::use_alt_bill(&Protocol::UDP, Some(&Protocol::TCP), RequestOutput::transport_of(&udp)));
+        assert_eq!(SignalTransport::from_via("SIP/2.0/WSS gateway.example;branch=z9hG4bK1"), None);
+    }
+
+    #[test]
+    fn test_cancel_scheduled() {
+        use rsip::SipMessage;
+        use common::tokio::time::{Duration, Instant};
+        use super::event::{Container, Ident, MatchMode, State};
+
+        let text = "MESSAGE sip:34020000001320000001@3402000000 SIP/2.0\r\n\
+Via: SIP/2.0/UDP 172.18.38.186:25600;rport;branch=z9hG4bK-lazy\r\n\
+From: <sip:34020000002000000001@3402000000>;tag=local\r\n\
+To: <sip:34020000001320000001@3402000000>\r\n\
+Call-ID: lazy@172.18.38.186\r\n\
+CSeq: 1 MESSAGE\r\n\
+Content-Length: 0\r\n\r\n";
+        let msg = SipMessage::try_from(text).unwrap();
+        let when = Instant::now() + Duration::from_secs(2);
+        let device_a = "34020000001320000001".to_string();
+        let device_b = "34020000001320000002".to_string();
+        let lazy_a = Ident::new(device_a.clone(), "lazy-a".to_string(), "1".to_string());
+        let lazy_b = Ident::new(device_b.clone(), "lazy-b".to_string(), "1".to_string());
+        let res_a = Ident::new(device_a.clone(), "res-a".to_string(), "2".to_string());
+
+        let mut state = State::new(MatchMode::Strict);
+        state.listen(&lazy_a, when, Container::build_actor(lazy_a.clone(), msg.clone(), None), None).unwrap();
+        state.listen(&lazy_b, when, Container::build_actor(lazy_b.clone(), msg, None), None).unwrap();
+        state.listen(&res_a, when, Container::build_res(None), None).unwrap();
+        assert_eq!(state.scheduled_count(), 2);
+
+        //仅取消设备A的延迟事件,已发请求的响应监听保留
+        assert_eq!(state.cancel_scheduled(&device_a), 1);
+        assert_eq!(state.scheduled_count(), 1);
+        assert_eq!(state.cancel_scheduled(&device_a), 0);
+        assert!(state.listen(&res_a, when, Container::build_res(None), None).is_err());
+        //已取消的call_id可重新登记,说明会话已清理、到期不再触发
+        assert!(state.listen(&lazy_a, when, Container::build_res(None), None).is_ok());
+    }
+
+    #[test]
+    fn test_stream_lifecycle_events() {
+        use std::sync::Arc;
+        use parking_lot::Mutex;
+        use crate::general::model::StreamMode;
+        use super::dialog::{Dialog, DialogSession, StreamLifecycle, StreamListener, StreamMedia};
+
+        //同进程其他用例也会登记对话,按call_id过滤
+        struct Recorder(Mutex<Vec<(&'static str, StreamLifecycle)>>);
+        impl StreamListener for Recorder {
+            fn on_start(&self, stream: &StreamLifecycle) {
+                if stream.dialog.get_call_id().starts_with("lifecycle-") {
+                    self.0.lock().push(("start", stream.clone()));
+                }
+            }
+            fn on_stop(&self, stream: &StreamLifecycle) {
+                if stream.dialog.get_call_id().starts_with("lifecycle-") {
+                    self.0.lock().push(("stop", stream.clone()));
+                }
+            }
+        }
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        DialogSession::add_listener(recorder.clone());
+
+        let media = StreamMedia { stream_mode: StreamMode::Udp, ssrc: "0100000001".to_string() };
+        let local = Dialog::new("34020000001110000001".to_string(), "34020000001320000101".to_string(),
+                                "lifecycle-local".to_string(), "111".to_string(), "222".to_string());
+        let _rx = DialogSession::insert_stream(local.clone(), Some(media.clone()));
+        DialogSession::remove("lifecycle-local");
+        let device = Dialog::new("34020000001110000001".to_string(), "34020000001320000102".to_string(),
+                                 "lifecycle-device".to_string(), "333".to_string(), "444".to_string());
+        let _rx = DialogSession::insert_stream(device.clone(), Some(media));
+        DialogSession::device_bye("lifecycle-device", Some("444"), None);
+        //非媒体流对话不触发
+        let _rx = DialogSession::insert(Dialog::new("34020000001110000001".to_string(), "34020000001320000103".to_string(),
+                                                    "lifecycle-plain".to_string(), "555".to_string(), "666".to_string()));
+        DialogSession::remove("lifecycle-plain");
+
+        let events = recorder.0.lock().clone();
+        assert_eq!(events.len(), 4);
+        assert_eq!((events[0].0, &events[0].1.dialog), ("start", &local));
+        assert_eq!((events[1].0, &events[1].1.dialog), ("stop", &local));
+        assert_eq!(events[1].1.ssrc, "0100000001");
+        assert_eq!(events[1].1.started_at, events[0].1.started_at);
+        assert!(events[0].1.stopped_at.is_none() && events[1].1.stopped_at.is_some());
+        assert!(!events[1].1.by_device);
+        assert_eq!((events[2].0, &events[2].1.dialog), ("start", &device));
+        assert_eq!((events[3].0, &events[3].1.dialog), ("stop", &device));
+        assert!(events[3].1.by_device);
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::{HashMap};
 use std::fs;
 use std::net::Ipv4Addr;
 use std::sync::OnceLock;
+use std::time::Duration;
 use common::{serde_default};
 use common::cfg_lib::conf;
 use common::cfg_lib::conf::{CheckFromConf, FieldCheckError};
@@ -95,6 +96,36 @@ impl StreamConf {
     }
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[serde(crate = "common::serde")]
+#[conf(prefix = "server.rtp", check)]
+pub struct RtpPortConf {
+    #[serde(default = "default_port_start")]
+    pub port_start: u16,
+    #[serde(default = "default_port_end")]
+    pub port_end: u16,
+}
+serde_default!(default_port_start, u16, 30000);
+serde_default!(default_port_end, u16, 30999);
+static RTP_PORT_CONF: OnceLock<RtpPortConf> = OnceLock::new();
+
+impl RtpPortConf {
+    pub fn get_rtp_port_conf() -> &'static Self {
+        RTP_PORT_CONF.get_or_init(|| {
+            RtpPortConf::conf()
+        })
+    }
+}
+
+impl CheckFromConf for RtpPortConf {
+    fn _field_check(&self) -> Result<(), FieldCheckError> {
+        if self.port_start >= self.port_end {
+            return Err(FieldCheckError::BizError("server.rtp: port_start must be less than port_end".to_string()));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(crate = "common::serde")]
 #[conf(prefix = "server.videos", check)]
@@ -115,8 +146,109 @@ impl DownloadConf {
     }
 }
 
+/// SIP事务定时器(RFC3261 17.1),单位ms
+/// t1:RTT估计,UDP重传初始间隔;t2:非INVITE重传最大间隔
+/// timer_b:INVITE事务超时;timer_f:非INVITE事务超时
+#[derive(Debug, Deserialize, Clone)]
+#[serde(crate = "common::serde")]
+#[conf(prefix = "server.timer", check)]
+pub struct SipTimerConf {
+    #[serde(default = "default_t1")]
+    pub t1: u64,
+    #[serde(default = "default_t2")]
+    pub t2: u64,
+    #[serde(default = "default_timer_b")]
+    pub timer_b: u64,
+    #[serde(default = "default_timer_f")]
+    pub timer_f: u64,
+}
+serde_default!(default_t1, u64, 500);
+serde_default!(default_t2, u64, 4000);
+serde_default!(default_timer_b, u64, 64 * 500);
+serde_default!(default_timer_f, u64, 64 * 500);
+static SIP_TIMER_CONF: OnceLock<SipTimerConf> = OnceLock::new();
+
+impl Default for SipTimerConf {
+    fn default() -> Self {
+        SipTimerConf { t1: default_t1(), t2: default_t2(), timer_b: default_timer_b(), timer_f: default_timer_f() }
+    }
+}
+
+impl SipTimerConf {
+    pub fn get_sip_timer_conf() -> &'static Self {
+        SIP_TIMER_CONF.get_or_init(|| {
+            SipTimerConf::conf()
+        })
+    }
+
+    //事务超时：INVITE取Timer B,其余取Timer F
+    pub fn transaction_timeout(&self, is_invite: bool) -> Duration {
+        Duration::from_millis(if is_invite { self.timer_b } else { self.timer_f })
+    }
+
+    //UDP重传间隔序列：自T1起倍增,非INVITE封顶T2;累计不超过事务超时
+    pub fn retransmit_intervals(&self, is_invite: bool) -> Vec<Duration> {
+        let timeout = if is_invite { self.timer_b } else { self.timer_f };
+        let mut intervals = Vec::new();
+        let mut interval = self.t1;
+        let mut elapsed = 0;
+        while self.t1 > 0 && elapsed + interval < timeout {
+            elapsed += interval;
+            intervals.push(Duration::from_millis(interval));
+            interval = if is_invite { interval * 2 } else { (interval * 2).min(self.t2) };
+        }
+        intervals
+    }
+}
+
+impl CheckFromConf for SipTimerConf {
+    fn _field_check(&self) -> Result<(), FieldCheckError> {
+        if self.t1 == 0 || self.t1 > self.t2 {
+            return Err(FieldCheckError::BizError("server.timer: t1必须大于0且不大于t2".to_string()));
+        }
+        if self.timer_b < self.t1 || self.timer_f < self.t1 {
+            return Err(FieldCheckError::BizError("server.timer: timer_b/timer_f不能小于t1".to_string()));
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+    use super::SipTimerConf;
+
+    #[test]
+    fn test_sip_timer_intervals() {
+        let conf = SipTimerConf::default();
+        assert_eq!(conf.transaction_timeout(true), Duration::from_millis(32000));
+        let ms = |v: Vec<Duration>| v.into_iter().map(|d| d.as_millis() as u64).collect::<Vec<_>>();
+        assert_eq!(ms(conf.retransmit_intervals(true)), vec![500, 1000, 2000, 4000, 8000, 16000]);
+        assert_eq!(ms(conf.retransmit_intervals(false))[..5], [500, 1000, 2000, 4000, 4000]);
+    }
+
+    #[test]
+    fn test_shorter_timer_b_fails_sooner() {
+        use common::tokio;
+        use common::tokio::sync::mpsc;
+        use common::tokio::time::Instant;
+        let lan = SipTimerConf { t1: 50, t2: 400, timer_b: 200, timer_f: 200 };
+        assert!(lan.transaction_timeout(true) < SipTimerConf::default().transaction_timeout(true));
+        assert_eq!(lan.retransmit_intervals(true), vec![Duration::from_millis(50), Duration::from_millis(100)]);
+        //无响应的INVITE：等待方在Timer B到期时收到超时
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+            let (tx, mut rx) = mpsc::channel::<Option<()>>(1);
+            let start = Instant::now();
+            let when = start + lan.transaction_timeout(true);
+            tokio::spawn(async move {
+                tokio::time::sleep_until(when).await;
+                let _ = tx.send(None).await;
+            });
+            assert_eq!(rx.recv().await, Some(None));
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(2));
+        });
+    }
 
     // #[test]
     // fn test_map_conf() {
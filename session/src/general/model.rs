@@ -1,16 +1,26 @@
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+
 use poem_openapi::{self, Object};
 use poem_openapi::types::{ParseFromJSON, ToJSON, Type};
 use common::serde::{Deserialize, Serialize};
 
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use common::anyhow::anyhow;
 use common::exception::GlobalError::SysErr;
 use common::exception::{GlobalResult, TransError};
 use common::constructor::Get;
 use common::log::error;
+use common::net::state::Protocol;
+use common::rand::{Rng, thread_rng};
+use common::tokio::time::Instant;
 use crate::gb::handler::parser::xml::KV2Model;
 
 use crate::general;
+use crate::gb::handler::error_code::CmdErrorCode;
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum StreamMode {
     Udp,
     TcpActive,
@@ -26,6 +36,216 @@ impl StreamMode {
             _ => { Err(SysErr(anyhow!("无效流模式"))) }
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamMode::Udp => { "UDP" }
+            StreamMode::TcpActive => { "TCP-ACTIVE" }
+            StreamMode::TcpPassive => { "TCP-PASSIVE" }
+        }
+    }
+
+    /// 校验设备是否支持该流模式;supported为None(能力未知)时跳过
+    pub fn check_supported(&self, supported: Option<&[StreamMode]>) -> GlobalResult<()> {
+        match supported {
+            Some(modes) if !modes.contains(self) => {
+                let names = modes.iter().map(|mode| mode.name()).collect::<Vec<&str>>().join(",");
+                Err(CmdErrorCode::InvalidParam.error(&format!("设备不支持流模式 {},支持: [{}]", self.name(), names)))
+            }
+            _ => { Ok(()) }
+        }
+    }
+}
+
+/// 信令传输：可覆盖设备注册时的传输方式,如UDP注册设备经TCP下发INVITE以避免大SDP分片
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "common::serde", rename_all = "lowercase")]
+pub enum SignalTransport {
+    Udp,
+    Tcp,
+}
+
+impl SignalTransport {
+    pub fn via_name(&self) -> &'static str {
+        match self {
+            SignalTransport::Udp => { "UDP" }
+            SignalTransport::Tcp => { "TCP" }
+        }
+    }
+
+    pub fn matches(&self, protocol: &Protocol) -> bool {
+        match self {
+            SignalTransport::Udp => { &Protocol::UDP == protocol }
+            SignalTransport::Tcp => { &Protocol::TCP == protocol }
+        }
+    }
+
+    /// 从Via值(SIP/2.0/TCP host:port;...)取传输,WS/WSS等返回None
+    pub fn from_via(via: &str) -> Option<Self> {
+        let sent_protocol = via.split_whitespace().next()?;
+        match sent_protocol.rsplit('/').next()? {
+            t if t.eq_ignore_ascii_case("UDP") => Some(SignalTransport::Udp),
+            t if t.eq_ignore_ascii_case("TCP") => Some(SignalTransport::Tcp),
+            _ => None,
+        }
+    }
+}
+
+/// 媒体传输规范：AVP 默认；AVPF 支持 RTCP 反馈(NACK/PLI)
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum TransportProfile {
+    #[default]
+    Avp,
+    Avpf,
+}
+
+impl TransportProfile {
+    pub fn proto(&self) -> &'static str {
+        match self {
+            TransportProfile::Avp => { "RTP/AVP" }
+            TransportProfile::Avpf => { "RTP/AVPF" }
+        }
+    }
+
+    //SRTP对应的安全规范
+    pub fn secure_proto(&self) -> &'static str {
+        match self {
+            TransportProfile::Avp => { "RTP/SAVP" }
+            TransportProfile::Avpf => { "RTP/SAVPF" }
+        }
+    }
+}
+
+/// SRTP密钥参数(RFC4568 SDES)：a=crypto:<tag> <crypto-suite> inline:<base64(主密钥||盐)>
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SrtpCrypto {
+    pub tag: u32,
+    pub suite: String,
+    pub key_params: String,
+}
+
+impl SrtpCrypto {
+    pub const DEFAULT_SUITE: &'static str = "AES_CM_128_HMAC_SHA1_80";
+    //AES_CM_128：16字节主密钥 + 14字节盐
+    const KEY_LEN: usize = 16;
+    const SALT_LEN: usize = 14;
+
+    /// 随机生成本端主密钥与盐
+    pub fn generate() -> Self {
+        let mut key_salt = [0u8; Self::KEY_LEN + Self::SALT_LEN];
+        thread_rng().fill(&mut key_salt[..]);
+        Self { tag: 1, suite: Self::DEFAULT_SUITE.to_string(), key_params: STANDARD.encode(key_salt) }
+    }
+
+    /// a=crypto:属性值
+    pub fn to_attr(&self) -> String {
+        format!("{} {} inline:{}", self.tag, self.suite, self.key_params)
+    }
+
+    /// 解析a=crypto:属性值;inline后的|lifetime|MKI忽略
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.split_whitespace();
+        let tag = fields.next()?.parse::<u32>().ok()?;
+        let suite = fields.next()?.to_string();
+        let key_params = fields.next()?.strip_prefix("inline:")?.split('|').next()?.to_string();
+        Some(Self { tag, suite, key_params })
+    }
+
+    /// (主密钥,盐),供中继派生会话密钥;长度不符时为None
+    pub fn master_key_salt(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let bytes = STANDARD.decode(&self.key_params).ok()?;
+        if bytes.len() != Self::KEY_LEN + Self::SALT_LEN {
+            return None;
+        }
+        let (key, salt) = bytes.split_at(Self::KEY_LEN);
+        Some((key.to_vec(), salt.to_vec()))
+    }
+}
+
+/// 应答SSRC校验：设备y=行SSRC与offer不一致时的处理
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum SsrcCheck {
+    //不校验,沿用offer中的SSRC
+    #[default]
+    Off,
+    //采用设备SSRC
+    Accept,
+    //拒绝点播
+    Reject,
+}
+
+/// IPv4地址段：network/prefix_len
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AddrRange {
+    network: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl AddrRange {
+    pub fn new(network: Ipv4Addr, prefix_len: u8) -> Self {
+        Self { network, prefix_len: prefix_len.min(32) }
+    }
+
+    //非IPv4地址视为不在范围内
+    pub fn contains(&self, addr: &str) -> bool {
+        match addr.trim().parse::<Ipv4Addr>() {
+            Ok(ip) => {
+                let mask = if self.prefix_len == 0 { 0 } else { u32::MAX << (32 - self.prefix_len) };
+                u32::from(ip) & mask == u32::from(self.network) & mask
+            }
+            Err(_) => { false }
+        }
+    }
+}
+
+impl Display for AddrRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// 点播可选项：缺省即为原有行为
+#[derive(Debug, Clone, Default)]
+pub struct InviteOption {
+    pub profile: TransportProfile,
+    //设备支持的流模式(缓存的设备能力);None表示未知,不校验
+    pub supported_modes: Option<Vec<StreamMode>>,
+    //RTP/RTCP复用同一端口：a=rtcp-mux
+    pub rtcp_mux: bool,
+    //RTCP指定端口：a=rtcp:<port>;None不声明,设备按RTP端口+1发送
+    pub rtcp_port: Option<u16>,
+    //媒体源地址校验：应答o=/c=地址须在设备地址范围内;None不校验
+    pub source_range: Option<AddrRange>,
+    //调用方指定的Call-ID(如链路追踪ID),需符合SIP token语法;None则自动生成
+    pub call_id: Option<String>,
+    pub ssrc_check: SsrcCheck,
+    //按payload附加格式参数：a=fmtp:<pt> <params>,如 (98,"profile-level-id=42e01f")
+    pub fmtp: Vec<(u8, String)>,
+    //INVITE信令传输;None则沿用设备注册传输
+    pub signal_transport: Option<SignalTransport>,
+    //SRTP：Some时以RTP/SAVP发起并携带a=crypto(密钥由SrtpCrypto::generate生成);None为明文RTP/AVP
+    pub srtp: Option<SrtpCrypto>,
+    //点播成功后登记到可复用池(设备,通道,收流地址),见DialogSession::reusable
+    pub reuse: bool,
+    //调用方总时限：覆盖排队、重定向等全部内部交互,超出即中止并CANCEL在途INVITE;None不限制
+    pub deadline: Option<Instant>,
+    //offer中m=video的payload及顺序,如 [96,98] 同时offer PS与H264裸流,由设备择一;为空则offer全部(96 97 98 99 100)
+    pub video_payloads: Vec<u8>,
+    //兼容老旧设备的最简offer：仅 RTP/AVP 96 PS/90000,不带fmtp/rtcp等附加属性;忽略video_payloads与profile,不可与srtp同用
+    pub strict_offer: bool,
+}
+
+/// 批量实时点播中的单路请求,参数同CmdStream::play_live_invite
+#[derive(Debug, Clone)]
+pub struct InviteRequest {
+    pub device_id: String,
+    pub channel_id: String,
+    pub dst_ip: String,
+    pub dst_port: u16,
+    pub stream_mode: StreamMode,
+    pub ssrc: String,
+    pub stream_number: u8,
+    pub option: InviteOption,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Object)]
@@ -140,6 +360,175 @@ pub struct PtzControlModel {
     pub zoomSpeed: u8,
 }
 
+/// 云台方向：pan取Left/Right,tilt取Up/Down
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// 镜头变倍方向
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ZoomDir {
+    //放大
+    In,
+    //缩小
+    Out,
+}
+
+impl PtzControlModel {
+    /// 水平转动
+    pub fn pan(direction: Direction, speed: u8) -> GlobalResult<Self> {
+        let left_right = match direction {
+            Direction::Left => 1,
+            Direction::Right => 2,
+            _ => return Err(CmdErrorCode::InvalidParam.error("水平转动仅支持左/右")),
+        };
+        Ok(Self { leftRight: left_right, horizonSpeed: speed, ..Default::default() })
+    }
+
+    /// 垂直转动
+    pub fn tilt(direction: Direction, speed: u8) -> GlobalResult<Self> {
+        let up_down = match direction {
+            Direction::Up => 1,
+            Direction::Down => 2,
+            _ => return Err(CmdErrorCode::InvalidParam.error("垂直转动仅支持上/下")),
+        };
+        Ok(Self { upDown: up_down, verticalSpeed: speed, ..Default::default() })
+    }
+
+    /// 变倍：速度0-15
+    pub fn zoom(dir: ZoomDir, speed: u8) -> GlobalResult<Self> {
+        if speed > 15 {
+            return Err(CmdErrorCode::InvalidParam.error("变倍速度范围0-15"));
+        }
+        let in_out = match dir {
+            ZoomDir::Out => 1,
+            ZoomDir::In => 2,
+        };
+        Ok(Self { inOut: in_out, zoomSpeed: speed, ..Default::default() })
+    }
+
+    /// 合并同时动作的轴(如 水平+垂直 为斜向)
+    /// 同一轴不可重复指定：指令字节中同轴两个方向位同时置位为非法指令
+    pub fn combine(mut self, other: PtzControlModel) -> GlobalResult<Self> {
+        if (self.leftRight != 0 && other.leftRight != 0)
+            || (self.upDown != 0 && other.upDown != 0)
+            || (self.inOut != 0 && other.inOut != 0) {
+            return Err(CmdErrorCode::InvalidParam.error("云台同一轴不可同时指定多个方向"));
+        }
+        if other.leftRight != 0 {
+            self.leftRight = other.leftRight;
+            self.horizonSpeed = other.horizonSpeed;
+        }
+        if other.upDown != 0 {
+            self.upDown = other.upDown;
+            self.verticalSpeed = other.verticalSpeed;
+        }
+        if other.inOut != 0 {
+            self.inOut = other.inOut;
+            self.zoomSpeed = other.zoomSpeed;
+        }
+        Ok(self)
+    }
+
+    /// 指定控制目标
+    pub fn target(mut self, device_id: String, channel_id: String) -> Self {
+        self.deviceId = device_id;
+        self.channelId = channel_id;
+        self
+    }
+
+    /// 可读描述,用于日志与审计,如 "pan left speed 5 + tilt up speed 3";无动作为 "stop"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        match self.leftRight {
+            1 => parts.push(format!("pan left speed {}", self.horizonSpeed)),
+            2 => parts.push(format!("pan right speed {}", self.horizonSpeed)),
+            _ => {}
+        }
+        match self.upDown {
+            1 => parts.push(format!("tilt up speed {}", self.verticalSpeed)),
+            2 => parts.push(format!("tilt down speed {}", self.verticalSpeed)),
+            _ => {}
+        }
+        match self.inOut {
+            1 => parts.push(format!("zoom out speed {}", self.zoomSpeed)),
+            2 => parts.push(format!("zoom in speed {}", self.zoomSpeed)),
+            _ => {}
+        }
+        if parts.is_empty() { "stop".to_string() } else { parts.join(" + ") }
+    }
+
+    /// 由PTZCmd指令串(8字节十六进制)还原控制模型,校验头字节与校验码
+    /// 同一轴两个方向位同时置位视为非法指令
+    pub fn from_cmd_line(cmd_line: &str) -> GlobalResult<Self> {
+        let cmd_line = cmd_line.trim();
+        let bytes: Vec<u8> = (0..cmd_line.len()).step_by(2)
+            .filter_map(|i| cmd_line.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+            .collect();
+        if cmd_line.len() != 16 || bytes.len() != 8 || bytes[0] != 0xA5 {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("PTZCmd格式无效: {cmd_line}")));
+        }
+        let sum = (bytes[..7].iter().map(|b| *b as u16).sum::<u16>() % 256) as u8;
+        if sum != bytes[7] {
+            return Err(CmdErrorCode::InvalidParam.warn(&format!("PTZCmd校验码错误: {cmd_line}")));
+        }
+        //bit0/1:右/左 bit2/3:下/上 bit4/5:放大/缩小
+        let axis = |bits: u8| match (bits & 0x01 != 0, bits & 0x02 != 0) {
+            (false, false) => Ok(0),
+            (true, false) => Ok(2),
+            (false, true) => Ok(1),
+            (true, true) => Err(CmdErrorCode::InvalidParam.warn(&format!("PTZCmd同一轴方向冲突: {cmd_line}"))),
+        };
+        Ok(Self {
+            leftRight: axis(bytes[3])?,
+            upDown: axis(bytes[3] >> 2)?,
+            inOut: axis(bytes[3] >> 4)?,
+            horizonSpeed: bytes[4],
+            verticalSpeed: bytes[5],
+            zoomSpeed: bytes[6] >> 4,
+            ..Default::default()
+        })
+    }
+}
+
+impl Display for PtzControlModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.describe())
+    }
+}
+
+/// Web播放描述：浏览器播放器所需的媒体端口、编码、SSRC,及关闭点播所需的对话标识
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Object, Serialize)]
+#[serde(crate = "common::serde")]
+#[allow(non_snake_case)]
+pub struct WebStreamDescriptor {
+    pub mediaPort: u16,
+    pub codec: String,
+    pub ssrc: String,
+    //对话Call-ID
+    pub dialogId: String,
+    //对话令牌：由设备、通道、Call-ID确定,可经parse_dialog_token还原
+    pub dialogToken: String,
+}
+
+impl WebStreamDescriptor {
+    pub fn dialog_token(device_id: &str, channel_id: &str, call_id: &str) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{device_id}:{channel_id}:{call_id}"))
+    }
+
+    /// 还原令牌中的(device_id,channel_id,call_id)
+    pub fn parse_dialog_token(token: &str) -> Option<(String, String, String)> {
+        let text = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+        let mut fields = text.splitn(3, ':');
+        let (device_id, channel_id, call_id) = (fields.next()?, fields.next()?, fields.next()?);
+        Some((device_id.to_string(), channel_id.to_string(), call_id.to_string()))
+    }
+}
+
 #[derive(Debug, Deserialize, Object, Serialize)]
 #[serde(crate = "common::serde")]
 #[allow(non_snake_case)]
@@ -217,7 +606,7 @@ mod test {
     use poem_openapi::payload::Json;
     use poem_openapi::types::ToJSON;
 
-    use crate::general::model::{ResultMessageData, StreamInfo};
+    use crate::general::model::{ResultMessageData, StreamInfo, StreamMode};
 
     #[test]
     fn t1() {
@@ -229,4 +618,13 @@ mod test {
         let data = ResultMessageData::build_success(m);
         println!("{:#?}", Json(data).to_json_string());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stream_mode_supported() {
+        let udp_only = [StreamMode::Udp];
+        assert!(StreamMode::TcpPassive.check_supported(Some(&udp_only)).is_err());
+        assert!(StreamMode::Udp.check_supported(Some(&udp_only)).is_ok());
+        //能力未知时放行
+        assert!(StreamMode::TcpActive.check_supported(None).is_ok());
+    }
+}
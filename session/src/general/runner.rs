@@ -7,6 +7,7 @@ use common::exception::{GlobalResult, TransError};
 use common::log::error;
 use common::tokio::time::sleep;
 use crate::gb::handler::cmd;
+use crate::gb::handler::snapshot::SnapshotUri;
 use crate::general::schedule;
 use crate::general::schedule::ScheduleTask;
 use crate::storage::{mapper};
@@ -33,7 +34,7 @@ impl PicsRunner {
 
             for item in arr {
                 let (token, session_id) = se_token::build_token_session_id(&item.0, &item.1)?;
-                let url = format!("{}?token={}", pics_conf.push_url.clone().unwrap(), token);
+                let url = SnapshotUri::try_from(format!("{}?token={}", pics_conf.push_url.clone().unwrap(), token).as_str())?;
                 cmd::CmdControl::snapshot_image(&item.0, &item.1, pics_conf.num, pics_conf.interval, &url, &session_id).await?;
             }
             //图片上传延迟3秒，缓解带宽瓶颈
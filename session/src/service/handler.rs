@@ -6,17 +6,18 @@ use std::time::Duration;
 use common::bytes::Bytes;
 use common::chrono::{Local, TimeZone};
 use common::exception::{GlobalError, GlobalResult, TransError};
-use common::log::{error};
+use common::log::{error, info};
 use common::serde_json;
-use common::tokio::sync::mpsc;
+use common::tokio::sync::{mpsc, oneshot};
 use common::tokio::time::{Instant, sleep};
 
 use crate::gb::handler::cmd::{CmdControl, CmdStream};
-use crate::gb::RWSession;
+use crate::gb::handler::profile::MAIN_STREAM;
+use crate::gb::{Dialog, RWSession};
 use crate::general;
 use crate::general::cache::PlayType;
 use crate::general::{DownloadConf, StreamConf};
-use crate::general::model::{PlayBackModel, PlayLiveModel, PlaySeekModel, PlaySpeedModel, PtzControlModel, StreamInfo, StreamMode};
+use crate::general::model::{InviteOption, PlayBackModel, PlayLiveModel, PlaySeekModel, PlaySpeedModel, PtzControlModel, StreamInfo, StreamMode};
 use crate::service::{BaseStreamInfo, callback, EXPIRES, RELOAD_EXPIRES, StreamPlayInfo, StreamRecordInfo, StreamState};
 use crate::service::callback::{Download, MediaAction, Play};
 use crate::storage::entity::{GmvFileInfo, GmvRecord};
@@ -293,27 +294,28 @@ async fn start_invite_stream(device_id: &String, channel_id: &String, token: &St
         let stream_node = conf.get_node_map().get(&node_name).unwrap();
         //next 将sdp支持从session固定的，转为stream支持的
         if let Ok(true) = callback::call_listen_ssrc(stream_id.clone(), &ssrc, token, stream_node.get_local_ip(), stream_node.get_local_port(), media_action.clone()).await {
-            let (res, media_map, from_tag, to_tag) = match play_type {
+            let outcome = match play_type {
                 PlayType::Live => {
-                    CmdStream::play_live_invite(device_id, channel_id, &stream_node.get_pub_ip().to_string(), *stream_node.get_pub_port(), StreamMode::Udp, &ssrc).await?
+                    CmdStream::play_live_invite(device_id, channel_id, &stream_node.get_pub_ip().to_string(), *stream_node.get_pub_port(), StreamMode::Udp, &ssrc, MAIN_STREAM, &InviteOption::default()).await?
                 }
                 PlayType::Back => {
-                    CmdStream::play_back_invite(device_id, channel_id, &stream_node.get_pub_ip().to_string(), *stream_node.get_pub_port(), StreamMode::Udp, &ssrc, st, et).await?
+                    CmdStream::play_back_invite(device_id, channel_id, &stream_node.get_pub_ip().to_string(), *stream_node.get_pub_port(), StreamMode::Udp, &ssrc, st, et, &InviteOption::default()).await?
                 }
                 PlayType::Down => {
-                    CmdStream::download_invite(device_id, channel_id, &stream_node.get_pub_ip().to_string(), *stream_node.get_pub_port(), StreamMode::Udp, &ssrc, st, et, 1).await?
+                    CmdStream::download_invite(device_id, channel_id, &stream_node.get_pub_ip().to_string(), *stream_node.get_pub_port(), StreamMode::Udp, &ssrc, st, et, 1, &InviteOption::default()).await?
                 }
             };
 
             //回调给gmv-stream 使其确认媒体类型
-            let _ = callback::ident_rtp_media_info(&ssrc, media_map, token, stream_node.get_local_ip(), stream_node.get_local_port()).await;
-            let (call_id, seq) = CmdStream::invite_ack(device_id, &res)?;
+            let _ = callback::ident_rtp_media_info(&ssrc, outcome.media_map, token, stream_node.get_local_ip(), stream_node.get_local_port()).await;
+            let (call_id, seq) = CmdStream::invite_ack(device_id, &outcome.response)?;
             return if let Some(_base_stream_info) = listen_stream_by_stream_id(&stream_id, RELOAD_EXPIRES).await {
-                general::cache::Cache::stream_map_insert_info(stream_id.clone(), node_name.clone(), call_id, seq, play_type, from_tag, to_tag);
-                general::cache::Cache::device_map_insert(device_id.to_string(), channel_id.to_string(), ssrc, stream_id.clone(), play_type);
+                general::cache::Cache::stream_map_insert_info(stream_id.clone(), node_name.clone(), call_id, seq, play_type, outcome.from_tag, outcome.to_tag);
+                general::cache::Cache::device_map_insert(device_id.to_string(), channel_id.to_string(), ssrc.clone(), stream_id.clone(), play_type);
+                listen_device_bye(outcome.bye_rx, stream_id.clone(), device_id.clone(), channel_id.clone(), ssrc, play_type);
                 Ok((stream_id, node_name))
             } else {
-                CmdStream::play_bye(seq + 1, call_id, device_id, channel_id, &from_tag, &to_tag).await?;
+                CmdStream::play_bye(seq + 1, call_id, device_id, channel_id, &outcome.from_tag, &outcome.to_tag).await?;
                 Err(GlobalError::new_biz_error(1100, "未接收到监控推流", |msg| error!("{msg}")))
             };
         }
@@ -321,6 +323,19 @@ async fn start_invite_stream(device_id: &String, channel_id: &String, token: &St
     Err(GlobalError::new_biz_error(1100, "无可用流媒体服务", |msg| error!("{msg}")))
 }
 
+//设备主动BYE(如重启)：清理流缓存并释放ssrc
+fn listen_device_bye(bye_rx: oneshot::Receiver<Dialog>, stream_id: String, device_id: String, channel_id: String, ssrc: String, play_type: PlayType) {
+    common::tokio::spawn(async move {
+        if let Ok(dialog) = bye_rx.await {
+            info!("设备主动结束媒体会话: device_id = {},channel_id = {},call_id = {}", dialog.get_device_id(), dialog.get_channel_id(), dialog.get_call_id());
+            general::cache::Cache::device_map_remove(&device_id, Some((&channel_id, Some((play_type, &ssrc)))));
+            general::cache::Cache::stream_map_remove(&stream_id, None);
+            if let Ok(ssrc) = ssrc.parse::<u32>() {
+                general::cache::Cache::ssrc_sn_set((ssrc % 10000) as u16);
+            }
+        }
+    });
+}
 
 //首先查看session缓存中是否有映射关系,然后看stream中是否有相应数据:都为true时返回数据
 //当session有,stream无时：session调用stream->使其重新监听ssrc
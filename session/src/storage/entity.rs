@@ -213,7 +213,7 @@ impl GmvDeviceExt {
         Ok(())
     }
 
-    fn build(vs: Vec<(String, String)>) -> GmvDeviceExt {
+    pub fn build(vs: Vec<(String, String)>) -> GmvDeviceExt {
         use crate::gb::handler::parser::xml::*;
 
         let mut de = GmvDeviceExt::default();